@@ -2,10 +2,12 @@ use anyhow::Result;
 use chrono::Local;
 use std::path::Path;
 
+use crate::compress::Algorithm;
+
 pub struct ArchiveNamer {
     base_name: Option<String>,
     destination: String,
-    algorithm: String,
+    algorithm: Algorithm,
     date_format: String,
     source_path: Option<String>,
 }
@@ -14,7 +16,7 @@ impl ArchiveNamer {
     pub fn new(
         base_name: Option<String>,
         destination: String,
-        algorithm: String,
+        algorithm: Algorithm,
         date_format: String,
     ) -> Self {
         Self {
@@ -67,8 +69,8 @@ impl ArchiveNamer {
         let mut result = template.to_string();
 
         result = result.replace("{date}", &Local::now().format(&self.date_format).to_string());
-        result = result.replace("{algo}", &self.algorithm);
-        result = result.replace("{algorithm}", &self.algorithm);
+        result = result.replace("{algo}", self.algorithm.as_str());
+        result = result.replace("{algorithm}", self.algorithm.as_str());
 
         if let Some(ref source) = self.source_path {
             let source_name = Path::new(source)
@@ -92,12 +94,7 @@ impl ArchiveNamer {
     }
 
     fn get_extension(&self) -> &str {
-        match self.algorithm.as_str() {
-            "tar.gz" => "tar.gz",
-            "tar.zst" => "tar.zst",
-            "zip" => "zip",
-            _ => "archive",
-        }
+        self.algorithm.extension()
     }
 
     pub fn preview(&self, name: &str) -> String {
@@ -135,7 +132,7 @@ mod tests {
         let namer = ArchiveNamer::new(
             Some("{source}_{algo}_{date}".to_string()),
             "/tmp".to_string(),
-            "tar.zst".to_string(),
+            Algorithm::TarZst,
             "%Y%m%d".to_string(),
         )
         .with_source_path("/home/user/Music".to_string());
@@ -149,7 +146,7 @@ mod tests {
         let namer = ArchiveNamer::new(
             None,
             "/tmp".to_string(),
-            "zip".to_string(),
+            Algorithm::Zip,
             "%Y%m%d".to_string(),
         );
 
@@ -0,0 +1,117 @@
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::compress::{Algorithm, Archiver};
+
+/// Source data compressed per combination, so a bench of a large library
+/// finishes in seconds rather than hours.
+pub const DEFAULT_SAMPLE_BYTES: u64 = 64 * 1024 * 1024;
+
+/// Algorithm and level pairs `zencore bench` tries by default.
+pub fn default_combos() -> Vec<(Algorithm, i32)> {
+    vec![
+        (Algorithm::TarZst, 3),
+        (Algorithm::TarZst, 9),
+        (Algorithm::TarZst, 19),
+        (Algorithm::TarGz, 6),
+        (Algorithm::TarGz, 9),
+        (Algorithm::Zip, 6),
+    ]
+}
+
+/// Result of compressing the sample with one combination.
+#[derive(Debug, Clone)]
+pub struct BenchRow {
+    pub algorithm: Algorithm,
+    pub level: i32,
+    pub input_bytes: u64,
+    pub output_bytes: u64,
+    pub duration: Duration,
+}
+
+impl BenchRow {
+    /// Output size as a share of the sampled input.
+    pub fn ratio(&self) -> f64 {
+        if self.input_bytes == 0 {
+            1.0
+        } else {
+            self.output_bytes as f64 / self.input_bytes as f64
+        }
+    }
+}
+
+/// Compresses up to `sample_bytes` of `source` with each combination into a
+/// scratch folder under `scratch`, which is removed afterwards.
+pub fn run(source: &Path, combos: &[(Algorithm, i32)], sample_bytes: u64, scratch: &Path) -> Result<Vec<BenchRow>> {
+    let out_dir = scratch.join(format!("zencore-bench-{}", std::process::id()));
+    fs::create_dir_all(&out_dir).with_context(|| format!("Failed to create {}", out_dir.display()))?;
+
+    let rows = combos
+        .iter()
+        .map(|&(algorithm, level)| bench_one(source, &out_dir, algorithm, level, sample_bytes))
+        .collect();
+
+    fs::remove_dir_all(&out_dir).ok();
+    rows
+}
+
+fn bench_one(source: &Path, out_dir: &Path, algorithm: Algorithm, level: i32, sample_bytes: u64) -> Result<BenchRow> {
+    let name = format!("bench_{}.{}", level, algorithm.as_str());
+    let result = Archiver::new(source, out_dir, name, algorithm)
+        .with_compression_level(level)
+        .with_sample_limit(sample_bytes)
+        .with_size_sorting(false)
+        .with_progress_callback(|_| {})
+        .compress()?;
+
+    let input_bytes = result
+        .files
+        .iter()
+        .map(|name| fs::metadata(source.join(name)).map(|m| m.len()).unwrap_or(0))
+        .sum();
+    fs::remove_file(&result.path).ok();
+
+    Ok(BenchRow {
+        algorithm,
+        level,
+        input_bytes,
+        output_bytes: result.bytes_written,
+        duration: result.duration,
+    })
+}
+
+/// The smallest output among combinations that took at most twice as long
+/// as the fastest one.
+pub fn recommend(rows: &[BenchRow]) -> Option<&BenchRow> {
+    let fastest = rows.iter().map(|row| row.duration).min()?;
+    rows.iter()
+        .filter(|row| row.duration <= fastest * 2)
+        .min_by_key(|row| row.output_bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bench_produces_a_row_per_combo() -> Result<()> {
+        let source = tempfile::tempdir()?;
+        let scratch = tempfile::tempdir()?;
+        for i in 0..5 {
+            fs::write(source.path().join(format!("{}.txt", i)), "la la la ".repeat(2000))?;
+        }
+
+        let combos = default_combos();
+        let rows = run(source.path(), &combos, DEFAULT_SAMPLE_BYTES, scratch.path())?;
+
+        let tried: Vec<(Algorithm, i32)> = rows.iter().map(|row| (row.algorithm, row.level)).collect();
+        assert_eq!(tried, combos);
+        assert!(rows.iter().all(|row| row.input_bytes == 5 * 18000 && row.ratio() < 1.0));
+        assert!(recommend(&rows).is_some());
+        assert_eq!(fs::read_dir(scratch.path())?.count(), 0);
+
+        Ok(())
+    }
+}
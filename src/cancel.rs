@@ -0,0 +1,211 @@
+use anyhow::Result;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Child, ExitStatus};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// How long cancelled work gets to stop on its own before it is abandoned.
+const CANCEL_GRACE: Duration = Duration::from_secs(3);
+
+/// How often `TrackedChild::wait` checks whether the process has exited.
+const CHILD_POLL: Duration = Duration::from_millis(50);
+
+/// Shared between long-running work and whoever may stop it. The work calls
+/// `check` at safe points and names its current stage for error reports.
+#[derive(Clone, Default)]
+pub struct CancelToken {
+    cancelled: Arc<AtomicBool>,
+    stage: Arc<Mutex<String>>,
+    partial_files: Arc<Mutex<Vec<PathBuf>>>,
+    children: Arc<Mutex<Vec<Arc<Mutex<Child>>>>>,
+}
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Errors once the token has been cancelled.
+    pub fn check(&self) -> Result<()> {
+        if self.is_cancelled() {
+            return Err(anyhow::anyhow!("Cancelled during {}", self.stage()));
+        }
+        Ok(())
+    }
+
+    pub fn set_stage(&self, stage: &str) {
+        *self.stage.lock().unwrap() = stage.to_string();
+        tracing::debug!(stage, "stage started");
+    }
+
+    pub fn stage(&self) -> String {
+        let stage = self.stage.lock().unwrap();
+        if stage.is_empty() {
+            "setup".to_string()
+        } else {
+            stage.clone()
+        }
+    }
+
+    /// Marks a file being written; it is deleted if the work is cancelled
+    /// before `finished_file` is called for it.
+    pub fn track_partial(&self, path: &Path) {
+        self.partial_files.lock().unwrap().push(path.to_path_buf());
+    }
+
+    pub fn finished_file(&self, path: &Path) {
+        self.partial_files.lock().unwrap().retain(|p| p != path);
+    }
+
+    /// Hands over a spawned uploader (rclone, scp, aws) so a timeout kills
+    /// it instead of leaving it running after zencore gives up.
+    pub fn track_child(&self, child: Child) -> TrackedChild {
+        let child = Arc::new(Mutex::new(child));
+        self.children.lock().unwrap().push(child.clone());
+        TrackedChild { child, token: self.clone() }
+    }
+
+    fn kill_children(&self) {
+        for child in self.children.lock().unwrap().drain(..) {
+            let mut child = child.lock().unwrap();
+            if child.kill().is_ok() {
+                let _ = child.wait();
+            }
+        }
+    }
+
+    fn remove_partial_files(&self) {
+        for path in self.partial_files.lock().unwrap().drain(..) {
+            if fs::remove_file(&path).is_ok() {
+                crate::utils::print_info(&format!("Removed partial file {}", path.display()));
+            }
+        }
+    }
+}
+
+/// Runs `work` on its own thread and gives up after `timeout`: the token is
+/// cancelled, the work gets a short grace period to stop, and any partial
+/// files it registered are removed. The error names the stage that was running.
+pub fn run_with_timeout<T: Send + 'static>(
+    timeout: Duration,
+    work: impl FnOnce(CancelToken) -> Result<T> + Send + 'static,
+) -> Result<T> {
+    let token = CancelToken::new();
+    let (sender, receiver) = mpsc::channel();
+
+    let worker_token = token.clone();
+    thread::spawn(move || {
+        let _ = sender.send(work(worker_token));
+    });
+
+    match receiver.recv_timeout(timeout) {
+        Ok(result) => return result,
+        Err(mpsc::RecvTimeoutError::Disconnected) => {
+            return Err(anyhow::anyhow!("Worker thread panicked"));
+        }
+        Err(mpsc::RecvTimeoutError::Timeout) => {}
+    }
+
+    let stage = token.stage();
+    token.cancel();
+    token.kill_children();
+    // Stopping at a safe point keeps the partial files closed before removal.
+    let _ = receiver.recv_timeout(CANCEL_GRACE);
+    token.remove_partial_files();
+
+    Err(anyhow::anyhow!(
+        "Timed out after {} during {}",
+        crate::utils::format_duration(timeout),
+        stage
+    ))
+}
+
+/// A child process registered with `CancelToken::track_child`.
+pub struct TrackedChild {
+    child: Arc<Mutex<Child>>,
+    token: CancelToken,
+}
+
+impl TrackedChild {
+    /// `Child::wait` that doesn't hold the process while blocked, so the
+    /// token can kill it meanwhile.
+    pub fn wait(self) -> io::Result<ExitStatus> {
+        loop {
+            if let Some(status) = self.child.lock().unwrap().try_wait()? {
+                return Ok(status);
+            }
+            thread::sleep(CHILD_POLL);
+        }
+    }
+}
+
+impl Drop for TrackedChild {
+    fn drop(&mut self) {
+        self.token.children.lock().unwrap().retain(|child| !Arc::ptr_eq(child, &self.child));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_slow_stage_is_aborted() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let partial = temp_dir.path().join("music.tar.zst");
+        let partial_in_work = partial.clone();
+
+        let result = run_with_timeout::<()>(Duration::from_millis(100), move |token| {
+            token.set_stage("compress");
+            fs::write(&partial_in_work, b"half an archive")?;
+            token.track_partial(&partial_in_work);
+            loop {
+                token.check()?;
+                thread::sleep(Duration::from_millis(10));
+            }
+        });
+
+        let err = result.unwrap_err();
+        assert!(err.to_string().contains("during compress"), "{}", err);
+        assert!(!partial.exists());
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_timeout_kills_child_processes() -> Result<()> {
+        let started = std::time::Instant::now();
+        let result = run_with_timeout::<()>(Duration::from_millis(100), |token| {
+            token.set_stage("upload");
+            let child = std::process::Command::new("sleep").arg("30").spawn()?;
+            token.track_child(child).wait()?;
+            Ok(())
+        });
+
+        assert!(result.unwrap_err().to_string().contains("during upload"));
+        // The worker saw its child die within the grace period.
+        assert!(started.elapsed() < CANCEL_GRACE);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_fast_work_returns_its_result() -> Result<()> {
+        let value = run_with_timeout(Duration::from_secs(5), |_| Ok(42))?;
+        assert_eq!(value, 42);
+        Ok(())
+    }
+}
@@ -0,0 +1,250 @@
+use anyhow::{Context, Result};
+use rusqlite::{params, Connection, OptionalExtension};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use crate::state::{ArchiveMetadata, StateTracker};
+
+/// The archive catalog in SQLite, for `state_backend = "sqlite"`. Each row
+/// keeps the full metadata as JSON next to the columns queries sort on;
+/// `created_epoch` is `created_at` in UTC seconds, since the RFC 3339 text
+/// doesn't sort across UTC offsets.
+pub struct CatalogDb {
+    conn: Connection,
+}
+
+impl CatalogDb {
+    pub fn open(path: &Path) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let conn = Connection::open(path).with_context(|| format!("Failed to open catalog {}", path.display()))?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS archives (
+                name TEXT PRIMARY KEY,
+                created_at TEXT NOT NULL,
+                created_epoch INTEGER NOT NULL,
+                source TEXT NOT NULL,
+                metadata TEXT NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS archives_created_epoch ON archives (created_epoch);
+            CREATE TABLE IF NOT EXISTS last_destinations (
+                source TEXT PRIMARY KEY,
+                destination TEXT NOT NULL
+            );",
+        )?;
+        Ok(Self { conn })
+    }
+
+    /// Imports `json_path` (or its `.zst` form) once, when the database is
+    /// still empty, and renames the file to `*.migrated`. Returns how many
+    /// archives were imported.
+    pub fn migrate_from_json(&mut self, json_path: &Path) -> Result<Option<usize>> {
+        if self.count()? > 0 {
+            return Ok(None);
+        }
+
+        let mut zst_path = json_path.as_os_str().to_owned();
+        zst_path.push(".zst");
+        let Some(source) = [json_path.to_path_buf(), zst_path.into()].into_iter().find(|path| path.exists()) else {
+            return Ok(None);
+        };
+
+        let imported = StateTracker::read_catalog(&source)?;
+        let archives: Vec<&ArchiveMetadata> = imported.list_archives();
+        self.apply_changes(&archives, &[])?;
+        self.replace_last_destinations(imported.last_destinations())?;
+
+        let mut migrated = source.as_os_str().to_owned();
+        migrated.push(".migrated");
+        fs::rename(&source, &migrated).with_context(|| format!("Failed to move {} aside", source.display()))?;
+        Ok(Some(archives.len()))
+    }
+
+    pub fn add_archive(&self, metadata: &ArchiveMetadata) -> Result<()> {
+        Self::upsert(&self.conn, metadata)
+    }
+
+    pub fn get_archive(&self, name: &str) -> Result<Option<ArchiveMetadata>> {
+        self.conn
+            .query_row("SELECT metadata FROM archives WHERE name = ?1", [name], |row| row.get::<_, String>(0))
+            .optional()?
+            .map(|json| Ok(serde_json::from_str(&json)?))
+            .transpose()
+    }
+
+    /// Every archive, newest first.
+    pub fn list_archives(&self) -> Result<Vec<ArchiveMetadata>> {
+        self.query("SELECT metadata FROM archives ORDER BY created_epoch DESC, name", [])
+    }
+
+    /// Archives whose source contains `source`, ignoring ASCII case, and
+    /// made at or after `since` (UTC seconds), newest first.
+    pub fn search(&self, source: Option<&str>, since: Option<i64>) -> Result<Vec<ArchiveMetadata>> {
+        let pattern = source.map(|term| {
+            format!("%{}%", term.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_"))
+        });
+        self.query(
+            "SELECT metadata FROM archives
+             WHERE (?1 IS NULL OR (source != '' AND source LIKE ?1 ESCAPE '\\'))
+               AND (?2 IS NULL OR created_epoch >= ?2)
+             ORDER BY created_epoch DESC, name",
+            params![pattern, since],
+        )
+    }
+
+    pub fn remove_archive(&self, name: &str) -> Result<Option<ArchiveMetadata>> {
+        let removed = self.get_archive(name)?;
+        self.conn.execute("DELETE FROM archives WHERE name = ?1", [name])?;
+        Ok(removed)
+    }
+
+    pub fn count(&self) -> Result<usize> {
+        Ok(self.conn.query_row("SELECT COUNT(*) FROM archives", [], |row| row.get(0))?)
+    }
+
+    /// Writes `changed` and deletes `removed` in one transaction.
+    pub fn apply_changes(&mut self, changed: &[&ArchiveMetadata], removed: &[&str]) -> Result<()> {
+        let tx = self.conn.transaction()?;
+        for metadata in changed {
+            Self::upsert(&tx, metadata)?;
+        }
+        for name in removed {
+            tx.execute("DELETE FROM archives WHERE name = ?1", [name])?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Source folder to the destination of its last successful backup.
+    pub fn last_destinations(&self) -> Result<HashMap<String, String>> {
+        let mut statement = self.conn.prepare("SELECT source, destination FROM last_destinations")?;
+        let rows = statement.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    pub fn replace_last_destinations(&mut self, destinations: &HashMap<String, String>) -> Result<()> {
+        let tx = self.conn.transaction()?;
+        tx.execute("DELETE FROM last_destinations", [])?;
+        for (source, destination) in destinations {
+            tx.execute(
+                "INSERT INTO last_destinations (source, destination) VALUES (?1, ?2)",
+                params![source, destination],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn upsert(conn: &Connection, metadata: &ArchiveMetadata) -> Result<()> {
+        conn.execute(
+            "INSERT OR REPLACE INTO archives (name, created_at, created_epoch, source, metadata)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                metadata.name,
+                metadata.created_at,
+                metadata.created_epoch(),
+                metadata.source,
+                serde_json::to_string(metadata)?
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn query(&self, sql: &str, args: impl rusqlite::Params) -> Result<Vec<ArchiveMetadata>> {
+        let mut statement = self.conn.prepare(sql)?;
+        let rows = statement.query_map(args, |row| row.get::<_, String>(0))?;
+        rows.map(|json| Ok(serde_json::from_str(&json?)?)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn archive(name: &str, created_at: &str, source: &str) -> ArchiveMetadata {
+        serde_json::from_value(serde_json::json!({
+            "name": name,
+            "created_at": created_at,
+            "algorithm": "tar.zst",
+            "size_bytes": 1024,
+            "file_count": 1,
+            "encrypted": false,
+            "source": source,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_crud_and_queries() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let db = CatalogDb::open(&temp_dir.path().join("archives.db"))?;
+
+        db.add_archive(&archive("music_1.tar.zst", "2024-01-01T10:00:00+00:00", "/home/user/Music"))?;
+        db.add_archive(&archive("music_2.tar.zst", "2024-03-01T10:00:00+00:00", "/home/user/Music"))?;
+        db.add_archive(&archive("podcasts.tar.zst", "2024-02-01T10:00:00+00:00", "/home/user/Podcasts"))?;
+
+        let mut updated = archive("music_1.tar.zst", "2024-01-01T10:00:00+00:00", "/home/user/Music");
+        updated.file_count = 42;
+        db.add_archive(&updated)?;
+        assert_eq!(db.get_archive("music_1.tar.zst")?.unwrap().file_count, 42);
+        assert_eq!(db.count()?, 3);
+
+        let names = |archives: Vec<ArchiveMetadata>| archives.into_iter().map(|a| a.name).collect::<Vec<_>>();
+        assert_eq!(names(db.list_archives()?), ["music_2.tar.zst", "podcasts.tar.zst", "music_1.tar.zst"]);
+
+        // 10:00 at +05:00 is 05:00 UTC, so it is older than 08:00 UTC even
+        // though its text sorts later.
+        db.add_archive(&archive("east.tar.zst", "2024-04-01T10:00:00+05:00", "/home/user/Music"))?;
+        db.add_archive(&archive("west.tar.zst", "2024-04-01T08:00:00+00:00", "/home/user/Music"))?;
+        assert_eq!(names(db.list_archives()?)[..2], ["west.tar.zst", "east.tar.zst"]);
+        assert_eq!(names(db.search(None, Some(1711958400))?), ["west.tar.zst"]);
+        db.remove_archive("east.tar.zst")?;
+        db.remove_archive("west.tar.zst")?;
+
+        assert!(db.remove_archive("podcasts.tar.zst")?.is_some());
+        assert!(db.remove_archive("podcasts.tar.zst")?.is_none());
+        assert_eq!(db.count()?, 2);
+
+        let since_february = chrono::DateTime::parse_from_rfc3339("2024-02-01T00:00:00+00:00")?.timestamp();
+        assert_eq!(names(db.search(None, Some(since_february))?), ["music_2.tar.zst"]);
+        assert_eq!(names(db.search(Some("MUSIC"), None)?), ["music_2.tar.zst", "music_1.tar.zst"]);
+        assert!(db.search(Some("Mus_c"), None)?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_state_tracker_migrates_json_catalog_once() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let json_path = temp_dir.path().join("archives.json");
+        let db_path = temp_dir.path().join("archives.db");
+
+        let mut legacy = StateTracker::default();
+        legacy.add_archive(archive("music.tar.zst", "2024-01-01T10:00:00+00:00", "/home/user/Music"));
+        legacy.save_to(&json_path)?;
+
+        let mut state = StateTracker::load_from_db(&db_path, &json_path)?;
+        assert!(state.get_archive("music.tar.zst").is_some());
+        assert!(!json_path.exists());
+        assert!(temp_dir.path().join("archives.json.migrated").exists());
+
+        state.add_archive(archive("later.tar.zst", "2024-05-01T10:00:00+00:00", "/home/user/Music"));
+        state.remove_archive("music.tar.zst");
+        state.remember_destination("/home/user/Music", "/mnt/nas");
+
+        // Saving only writes the entries changed since loading.
+        CatalogDb::open(&db_path)?.add_archive(&archive("other.tar.zst", "2024-06-01T10:00:00+00:00", "/podcasts"))?;
+        state.save_to_db(&db_path)?;
+
+        let reloaded = StateTracker::load_from_db(&db_path, &json_path)?;
+        assert_eq!(reloaded.archive_count(), 2);
+        assert!(reloaded.get_archive("other.tar.zst").is_some());
+        assert_eq!(reloaded.last_destination("/home/user/Music"), Some("/mnt/nas"));
+        assert!(reloaded.get_archive("later.tar.zst").is_some());
+
+        Ok(())
+    }
+}
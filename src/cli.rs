@@ -1,21 +1,35 @@
 use anyhow::{Context, Result};
 use chrono::Local;
-use clap::{Parser, Subcommand};
+use clap::{CommandFactory, Parser, Subcommand};
+use colored::Colorize;
 use dialoguer::{theme::ColorfulTheme, Confirm, Password, Select};
 use std::collections::HashMap;
 use std::fs;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
 
 use crate::{
     archive_name::{ArchiveNamer, NamingPresets},
-    compress::Archiver,
-    config::Config,
-    crypto::{Checker, HashAlgorithm},
-    fuzzer::Fuzzer,
-    remote::RemoteTransfer,
-    state::{ArchiveMetadata, StateTracker},
+    bench,
+    cancel::{self, CancelToken},
+    compress::{Algorithm, Archiver, CollisionPolicy, CompressResult, CompressionLevel, SortOrder, TarFormat},
+    config::{BackupProfile, Config, ConfigChange},
+    crypto::{Checker, HashAlgorithm, PasswordPolicy},
+    dictionary, dupes,
+    error::ErrorKind,
+    extract::ArchiveReader,
+    fuzzer::{FolderInfo, Fuzzer},
+    path_utils::{PathUtils, SymlinkPolicy},
+    remote::{RemoteDestination, RemoteTransfer},
+    resume::ResumeState,
+    run_log::RunLog,
+    state::{ArchiveMetadata, ContentsMode, MergeStrategy, StateTracker},
     utils,
 };
 
+/// How often `--json-progress` reports while archiving.
+const JSON_PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+
 #[derive(Parser)]
 #[command(name = "zencore")]
 #[command(author = "Blues24")]
@@ -24,66 +38,729 @@ use crate::{
 pub struct Cli {
     #[command(subcommand)]
     command: Option<Commands>,
+
+    /// Log diagnostics to stderr (-v info, -vv debug, -vvv trace)
+    #[arg(short, long, action = clap::ArgAction::Count, global = true)]
+    verbose: u8,
+
+    /// Print nothing when everything succeeds; any warning or error prints
+    /// the full output and makes the exit status nonzero
+    #[arg(long, global = true)]
+    quiet_success: bool,
+
+    /// Report progress as newline-delimited JSON on stderr instead of bars,
+    /// for frontends wrapping zencore
+    #[arg(long, global = true)]
+    json_progress: bool,
 }
 
 #[derive(Subcommand)]
 enum Commands {
-    Backup {
-        #[arg(short, long)]
+    Backup(Box<BackupArgs>),
+    
+    List {
+        /// Only archives made from this source (folder name or part of its path)
+        #[arg(long)]
         source: Option<String>,
-        
-        #[arg(short, long)]
-        destination: Option<String>,
-        
-        #[arg(short, long)]
-        name: Option<String>,
-        
-        #[arg(short, long)]
-        algorithm: Option<String>,
-        
+
+        /// Only archives made on or after this date (YYYY-MM-DD or RFC 3339)
+        #[arg(long, value_parser = utils::parse_since)]
+        since: Option<i64>,
+    },
+    
+    Show {
+        name: String,
+
+        /// Also re-check the stored checksums against the archive file
+        #[arg(long)]
+        verify: bool,
+
+        /// Only list stored file names containing this text (ignoring case),
+        /// all of them rather than the first 50
+        #[arg(long, value_name = "PATTERN")]
+        grep: Option<String>,
+
+        /// Read the --grep pattern as a regular expression
+        #[arg(long, requires = "grep")]
+        regex: bool,
+
+        /// How many stored file names to list
+        #[arg(long, default_value_t = 50)]
+        limit: usize,
+
+        /// Skip this many file names first, to page through large archives
+        #[arg(long, default_value_t = 0)]
+        offset: usize,
+
+        /// List every stored file name
+        #[arg(long, conflicts_with = "limit")]
+        all: bool,
+    },
+
+    /// List the files inside an archive
+    Contents { archive: String },
+
+    /// Extract an archive into a folder
+    Decompress {
+        archive: String,
+
+        /// Folder to extract into (default: the archive name without extension)
         #[arg(short, long)]
-        encrypt: bool,
-        
+        output: Option<String>,
+
+        /// Read the zip password from the first line of stdin
         #[arg(long)]
-        upload: bool,
+        password_stdin: bool,
 
-        /// Compression level (tar.gz: 0-9, tar.zst: 1-22, zip: 0-9)
-        #[arg(short = 'l', long)]
-        level: Option<i32>,
+        /// Read the zip password from this environment variable
+        #[arg(long, value_name = "VARNAME", conflicts_with = "password_stdin")]
+        password_from_env: Option<String>,
 
-        /// Number of threads (0 = auto)
-        #[arg(short = 't', long, default_value = "0")]
-        threads: usize,
+        /// zstd dictionary the archive was compressed with (default: the one
+        /// recorded in the catalog)
+        #[arg(long, value_name = "PATH")]
+        zstd_dict: Option<String>,
 
-        /// Checksum algorithms (comma-separated: sha256,blake3,sha3)
-        #[arg(long, value_delimiter = ',')]
-        checksums: Option<Vec<String>>,
+        /// Only extract entries matching this glob, e.g. "*.flac" or
+        /// "Album/**" (repeatable)
+        #[arg(long, value_name = "GLOB")]
+        include: Vec<String>,
+    },
+
+    /// Drop old archives from the catalog
+    Prune {
+        /// Keep the newest N archives of each source folder name
+        #[arg(long, value_name = "N")]
+        keep_last_n_per_source: usize,
+
+        /// Show what would be removed without changing anything
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Also delete the pruned archives and their sidecar files from DIR
+        #[arg(long, value_name = "DIR")]
+        delete_files_in: Option<String>,
     },
-    
-    List,
-    
-    Show { name: String },
     
     Verify {
         archive: String,
         
-        /// Checksum algorithm to verify
+        /// Checksum algorithm to verify, or `all` to re-check every checksum
+        /// stored for the archive
         #[arg(short, long, default_value = "sha256")]
         algorithm: Option<String>,
+
+        /// Re-hash each file inside the archive against its content manifest
+        #[arg(long)]
+        contents: bool,
+
+        /// For large archives: check file by file against the content
+        /// manifest when there is one, stopping at the first corrupted file
+        #[arg(long, conflicts_with = "contents")]
+        checksum_verify_chunked: bool,
     },
     
-    Config,
+    /// Print checksums for an existing file without touching the catalog
+    Checksum {
+        file: String,
+
+        /// Algorithms to compute (comma-separated)
+        #[arg(short, long, value_delimiter = ',', default_value = "sha256")]
+        algorithm: Vec<String>,
+
+        /// Also write a `<file>.<algorithm>` sidecar for each checksum
+        #[arg(long)]
+        write: bool,
+    },
+
+    /// Find the catalog entry for an archive file by its SHA-256
+    Whereis {
+        file: String,
+    },
+
+    /// Compress a sample of a folder with several algorithms and levels and
+    /// compare the results, without keeping any archive
+    Bench {
+        source: String,
+
+        /// How much source data to compress per combination (e.g. 64M, 1G)
+        #[arg(long, value_parser = utils::parse_size, default_value = "64M")]
+        sample_size: u64,
+    },
+
+    /// Train a zstd dictionary from sample files, for `backup --zstd-dict`
+    TrainDict {
+        source: String,
+
+        /// Where to write the dictionary
+        #[arg(short, long, default_value = "zencore.dict")]
+        output: String,
+
+        /// Maximum dictionary size (e.g. 110K)
+        #[arg(long, value_parser = utils::parse_size, default_value_t = dictionary::DEFAULT_DICT_SIZE as u64)]
+        size: u64,
+    },
+
+    /// List audio files that look like the same track in several copies or
+    /// qualities (read-only)
+    AnalyzeDupes {
+        source: String,
+
+        /// How alike two names must be to count as the same track (0-1)
+        #[arg(long, default_value_t = dupes::DEFAULT_SIMILARITY)]
+        similarity: f64,
+    },
+
+    /// Merge another machine's archives.json into the local catalog
+    ImportState {
+        file: String,
+
+        /// What to do when an imported archive has the same name as a local one
+        #[arg(long, value_enum, default_value_t = MergeStrategy::KeepNewer)]
+        strategy: MergeStrategy,
+    },
+
+    /// Reconcile the catalog with the archives actually on disk
+    RepairState {
+        /// Also look here for moved archives and ones missing from the catalog
+        #[arg(long)]
+        scan_dir: Option<String>,
+
+        /// Save the changes (otherwise only report them)
+        #[arg(long)]
+        apply: bool,
+    },
+
+    /// Show the configuration, or change one setting
+    Config {
+        #[command(subcommand)]
+        action: Option<ConfigAction>,
+    },
     
     Upload {
         archive: String,
+
+        /// Where to upload: s3://bucket/prefix, sftp://user@host/dir,
+        /// mysql://user@host/database or an rclone remote:path
+        #[arg(conflicts_with = "to")]
+        destination: Option<String>,
+
         #[arg(long)]
         to: Option<String>,
+
+        /// Don't ask before uploading archives above the size threshold
+        #[arg(short = 'y', long)]
+        yes: bool,
+
+        /// Skip the upload if the remote already has this archive with the same SHA-256
+        #[arg(long)]
+        compare_remote: bool,
+
+        /// Upload even if the archive no longer matches the checksum recorded at backup time
+        #[arg(long)]
+        force: bool,
     },
     
     Remote {
         #[command(subcommand)]
         action: RemoteAction,
     },
+
+    /// Manage saved backup profiles
+    Profile {
+        #[command(subcommand)]
+        action: ProfileAction,
+    },
+
+    /// Print a tab-completion script for bash, zsh, fish, elvish or powershell
+    Completions {
+        shell: clap_complete::Shell,
+    },
+}
+
+/// Options for `zencore backup`; the interactive menu fills in the defaults.
+#[derive(clap::Args, Clone, Default)]
+struct BackupArgs {
+    #[arg(short, long)]
+    source: Option<String>,
+    
+    #[arg(short, long)]
+    destination: Option<String>,
+    
+    #[arg(short, long)]
+    name: Option<String>,
+    
+    #[arg(short, long)]
+    algorithm: Option<String>,
+    
+    #[arg(short, long)]
+    encrypt: bool,
+    
+    #[arg(long)]
+    upload: bool,
+
+    /// Compression level (tar.gz: 0-9, tar.zst: 1-22, zip: 0-9, or 'auto'
+    /// to pick one from the content mix)
+    #[arg(short = 'l', long, visible_alias = "compression-level")]
+    level: Option<CompressionLevel>,
+
+    /// Number of threads (0 = auto)
+    #[arg(short = 't', long, default_value = "0")]
+    threads: usize,
+
+    /// Checksum algorithms (comma-separated: sha256,sha512,sha3,blake2b,blake3,
+    /// or the faster non-cryptographic crc32,xxh3)
+    #[arg(long, value_delimiter = ',')]
+    checksums: Option<Vec<String>>,
+
+    /// Glob patterns to skip, relative to the source (repeatable).
+    /// Combined with the source's .zencoreignore file.
+    #[arg(long)]
+    exclude: Vec<String>,
+
+    /// Skip files with this extension, ignoring case (repeatable)
+    #[arg(long, value_name = "EXT")]
+    exclude_extension: Vec<String>,
+
+    /// Read more exclude patterns from a file, one per line (repeatable).
+    /// Same syntax as .zencoreignore
+    #[arg(long, value_name = "FILE")]
+    exclude_from: Vec<String>,
+
+    /// How much of the file list to keep in the catalog
+    #[arg(long, value_enum)]
+    contents: Option<ContentsMode>,
+
+    /// Write tar archives in parts so an interrupted run can pick up
+    /// where it stopped when re-run with the same source and destination
+    #[arg(long)]
+    resume_backup: bool,
+
+    /// Warn about source files that change while the backup runs
+    #[arg(long)]
+    verify_source: bool,
+
+    /// Abort if a source file changes while the backup runs
+    #[arg(long)]
+    strict_source: bool,
+
+    /// Refuse to start unless the destination has room for the estimated
+    /// archive plus this margin (e.g. 500M, 2G)
+    #[arg(long, value_parser = utils::parse_size)]
+    min_free_space: Option<u64>,
+
+    /// Continue despite failed preflight checks, with a warning, and skip
+    /// the confirmation for unusually large sources
+    #[arg(long)]
+    force: bool,
+
+    /// Overwrite an existing `<archive>.sha256` with different contents
+    /// (also implied by --force)
+    #[arg(long)]
+    replace_existing_checksum_file: bool,
+
+    /// Show the finished archive in the file manager
+    #[arg(long)]
+    open_after: bool,
+
+    /// Create the archive even if no files are left to back up
+    #[arg(long)]
+    allow_empty: bool,
+
+    /// Without --destination, reuse this source's last destination without
+    /// asking; only the first backup of a source prompts for one
+    #[arg(long)]
+    ask_destination_once: bool,
+
+    /// Back up from a read-only Btrfs, ZFS or APFS snapshot of the source,
+    /// falling back to the live source where snapshots aren't available
+    #[arg(long)]
+    source_snapshot: bool,
+
+    /// Suggest storing instead when the archive ends up bigger than this
+    /// fraction of the source (default from config, 0.98)
+    #[arg(long, value_name = "RATIO")]
+    min_compression_ratio: Option<f64>,
+
+    /// Pick the strongest level projected to finish compressing within
+    /// this many minutes, measured on a sample of the source
+    #[arg(long, value_name = "MINUTES", conflicts_with = "level")]
+    time_budget: Option<f64>,
+
+    /// Minimum encryption password length (default from config, 8)
+    #[arg(long)]
+    password_min_length: Option<usize>,
+
+    /// Read the encryption password from the first line of stdin
+    #[arg(long)]
+    password_stdin: bool,
+
+    /// Check the configured age recipients (and identity file, with a test
+    /// roundtrip) before starting, aborting if any is wrong
+    #[arg(long)]
+    verify_identity: bool,
+
+    /// Read the encryption password from this environment variable
+    #[arg(long, value_name = "VARNAME", conflicts_with = "password_stdin")]
+    password_from_env: Option<String>,
+
+    /// Embed the backup's name, date and source, plus an optional note,
+    /// as a comment inside zip and tar.gz archives
+    #[arg(long, num_args = 0..=1, default_missing_value = "", value_name = "NOTE")]
+    comment: Option<String>,
+
+    /// Hash every file as it is archived and write a per-file
+    /// `<archive>.manifest.sha256`
+    #[arg(long)]
+    content_checksums: bool,
+
+    /// Put a ZENCORE_README.txt describing the backup, and how to verify
+    /// and extract it, first in the archive
+    #[arg(long)]
+    embed_readme: bool,
+
+    /// With --content-checksums, hash files on the --threads worker threads
+    /// while they are archived (faster for many small files)
+    #[arg(long, requires = "content_checksums")]
+    checksum_parallel_files: bool,
+
+    /// Don't ask before uploading archives above the size threshold
+    #[arg(short = 'y', long)]
+    yes: bool,
+
+    /// Write one archive per immediate subfolder of the source (e.g. one
+    /// per artist) instead of a single archive
+    #[arg(long)]
+    group_by_toplevel: bool,
+
+    /// Upload to this destination after the backup (s3://, sftp://,
+    /// mysql:// or an rclone remote:path) instead of the configured remote
+    #[arg(long, value_name = "DEST")]
+    upload_to: Option<String>,
+
+    /// With --upload, send this run's archive to an rclone remote:path
+    /// instead of the configured remote
+    #[arg(long, value_name = "REMOTE:PATH", requires = "upload", conflicts_with_all = ["upload_to", "s3", "sftp"])]
+    rclone_remote: Option<String>,
+
+    /// With --upload, send this run's archive to s3://bucket/prefix instead
+    /// of the configured remote
+    #[arg(long, value_name = "URI", requires = "upload", conflicts_with_all = ["upload_to", "sftp"])]
+    s3: Option<String>,
+
+    /// With --upload, send this run's archive to sftp://user@host/dir
+    /// instead of the configured remote
+    #[arg(long, value_name = "URI", requires = "upload", conflicts_with = "upload_to")]
+    sftp: Option<String>,
+
+    /// Fill in options not given on the command line from a saved profile
+    #[arg(long)]
+    profile: Option<String>,
+
+    /// Pick which top-level folders of the source to include from a list
+    #[arg(long)]
+    interactive_exclude: bool,
+
+    /// Skip the upload if the remote already has this archive with the same SHA-256
+    #[arg(long)]
+    compare_remote: bool,
+
+    /// Re-check the archive against its checksum just before uploading, and
+    /// don't upload it if it no longer matches
+    #[arg(long)]
+    verify_before_upload: bool,
+
+    /// Append a timestamped record of each stage and the result to this file
+    #[arg(long, value_name = "PATH")]
+    log_file: Option<String>,
+
+    /// What to do when two files would get the same name inside the archive
+    #[arg(long, value_enum, default_value_t = CollisionPolicy::Suffix)]
+    on_collision: CollisionPolicy,
+
+    /// What to do with symbolic links (default from config: follow)
+    #[arg(long, value_enum)]
+    symlinks: Option<SymlinkPolicy>,
+
+    /// Order of entries in the archive (default from config: size-desc)
+    #[arg(long, value_enum)]
+    sort_by: Option<SortOrder>,
+
+    /// Store files in scan order, same as --sort-by none
+    #[arg(long, conflicts_with = "sort_by")]
+    no_sort: bool,
+
+    /// Store identical files once, later copies as hard links (tar only)
+    #[arg(long)]
+    dedup: bool,
+
+    /// Compress with a zstd dictionary from `zencore train-dict` (tar.zst
+    /// only); many small, similar files compress much better with one
+    #[arg(long, value_name = "PATH")]
+    zstd_dict: Option<String>,
+
+    /// Tar header format; PAX keeps long and non-ASCII paths intact
+    #[arg(long, value_enum, default_value_t = TarFormat::Pax)]
+    tar_format: TarFormat,
+
+    /// Only include files changed since the last backup of the same source
+    #[arg(long)]
+    since_last: bool,
+
+    /// Write checksum and manifest sidecars here instead of next to the archive
+    #[arg(long, value_name = "DIR")]
+    meta_dir: Option<String>,
+
+    /// Abort the whole backup after this many seconds, removing partial files
+    #[arg(long, value_name = "SECS")]
+    timeout: Option<u64>,
+}
+
+impl BackupArgs {
+    /// Where this run uploads instead of the configured remote: the ad-hoc
+    /// `--rclone-remote`/`--s3`/`--sftp` flag, else `--upload-to`.
+    fn upload_destination(&self) -> Result<Option<RemoteDestination>> {
+        let adhoc = [
+            ("--rclone-remote", "rclone", &self.rclone_remote, ""),
+            ("--s3", "s3", &self.s3, "s3://"),
+            ("--sftp", "sftp", &self.sftp, "sftp://"),
+        ];
+        for (flag, kind, value, scheme) in adhoc {
+            let Some(value) = value else {
+                continue;
+            };
+            let uri = if value.contains("://") {
+                value.clone()
+            } else {
+                format!("{}{}", scheme, value)
+            };
+            let destination = RemoteDestination::parse(&uri)?;
+            if destination.kind() != kind {
+                return Err(ErrorKind::Usage.error(format!("{} expects a {} destination, got '{}'", flag, kind, value)));
+            }
+            return Ok(Some(destination));
+        }
+
+        self.upload_to.as_deref().map(RemoteDestination::parse).transpose()
+    }
+
+    /// Options given on the command line win; the rest come from `profile`.
+    fn with_profile(&self, profile: &BackupProfile) -> Result<Self> {
+        let mut args = self.clone();
+
+        args.source = args.source.or_else(|| profile.source.clone());
+        args.destination = args.destination.or_else(|| profile.destination.clone());
+        args.algorithm = args.algorithm.or_else(|| profile.algorithm.clone());
+        args.checksums = args.checksums.or_else(|| profile.checksums.clone());
+        args.upload_to = args.upload_to.or_else(|| profile.remote.clone());
+
+        if args.level.is_none()
+            && let Some(level) = &profile.level
+        {
+            args.level = Some(level.parse().map_err(|e| anyhow::anyhow!("Invalid level in profile: {}", e))?);
+        }
+
+        if args.threads == 0 {
+            args.threads = profile.threads.unwrap_or(0);
+        }
+
+        if args.exclude.is_empty() {
+            args.exclude = profile.exclude.clone();
+        }
+
+        args.encrypt |= profile.encrypt.unwrap_or(false);
+        args.upload |= profile.upload.unwrap_or(false);
+
+        Ok(args)
+    }
+
+    /// These options with the source, destination and algorithm as resolved.
+    fn to_profile(&self, source: &str, destination: &str, algo: Algorithm) -> BackupProfile {
+        BackupProfile {
+            source: Some(source.to_string()),
+            destination: Some(destination.to_string()),
+            algorithm: Some(algo.as_str().to_string()),
+            level: self.level.map(|level| match level {
+                CompressionLevel::Fixed(n) => n.to_string(),
+                CompressionLevel::Auto => "auto".to_string(),
+            }),
+            threads: (self.threads > 0).then_some(self.threads),
+            encrypt: Some(self.encrypt),
+            upload: Some(self.upload),
+            remote: self.upload_to.clone(),
+            checksums: self.checksums.clone(),
+            exclude: self.exclude.clone(),
+        }
+    }
+}
+
+/// Backup settings that have a config default, layered in one place:
+/// config, then the profile, then command-line flags, each overriding the
+/// one before.
+#[derive(Debug, Clone, PartialEq)]
+struct ResolvedBackupOptions {
+    level: Option<CompressionLevel>,
+    /// 0 means one per CPU.
+    threads: usize,
+    checksums: Vec<String>,
+    contents: ContentsMode,
+    symlinks: SymlinkPolicy,
+    sort_order: SortOrder,
+}
+
+impl ResolvedBackupOptions {
+    /// The options, and `args` with `profile` filled in for the settings
+    /// that have no config default.
+    fn resolve(config: &Config, profile: Option<&BackupProfile>, args: &BackupArgs) -> Result<(Self, BackupArgs)> {
+        let args = match profile {
+            Some(profile) => args.with_profile(profile)?,
+            None => args.clone(),
+        };
+
+        let config_checksum = if config.default_hash_algorithm.is_empty() {
+            "sha256".to_string()
+        } else {
+            config.default_hash_algorithm.clone()
+        };
+        let config_sort = if config.sort_files_by_size { SortOrder::SizeDesc } else { SortOrder::None };
+
+        let options = Self {
+            level: args.level.or(config.compression_level.map(CompressionLevel::Fixed)),
+            threads: if args.threads > 0 { args.threads } else { config.num_threads },
+            checksums: args.checksums.clone().unwrap_or_else(|| vec![config_checksum]),
+            contents: args.contents.unwrap_or(config.contents_mode),
+            symlinks: args.symlinks.unwrap_or(config.symlinks),
+            sort_order: if args.no_sort { SortOrder::None } else { args.sort_by.unwrap_or(config_sort) },
+        };
+        Ok((options, args))
+    }
+}
+
+/// `show --limit/--offset/--all`: which slice of the stored file names to list.
+#[derive(Debug, Clone, Copy)]
+struct ContentsPage {
+    offset: usize,
+    /// `None` with `--all`.
+    limit: Option<usize>,
+}
+
+impl Default for ContentsPage {
+    fn default() -> Self {
+        Self { offset: 0, limit: Some(50) }
+    }
+}
+
+impl ContentsPage {
+    /// Indices to show out of `len` names, clamped to the list.
+    fn range(&self, len: usize) -> std::ops::Range<usize> {
+        let start = self.offset.min(len);
+        let end = self.limit.map_or(len, |limit| start.saturating_add(limit).min(len));
+        start..end
+    }
+}
+
+/// `show --grep`: which stored file names to list.
+struct ContentFilter {
+    pattern: String,
+    regex: Option<regex_automata::meta::Regex>,
+}
+
+impl ContentFilter {
+    fn new(pattern: &str, regex: bool) -> Result<Self> {
+        let regex = if regex {
+            let compiled = regex_automata::meta::Regex::new(pattern)
+                .map_err(|e| ErrorKind::Usage.error(format!("Invalid --grep regex {:?}: {}", pattern, e)))?;
+            Some(compiled)
+        } else {
+            None
+        };
+        Ok(Self {
+            pattern: pattern.to_string(),
+            regex,
+        })
+    }
+
+    /// Names containing the pattern, ignoring case, or matching the regex.
+    fn apply<'a>(&self, contents: &'a [String]) -> Vec<&'a String> {
+        let needle = self.pattern.to_lowercase();
+        contents
+            .iter()
+            .filter(|name| match self.regex {
+                Some(ref regex) => regex.is_match(name.as_str()),
+                None => name.to_lowercase().contains(&needle),
+            })
+            .collect()
+    }
+}
+
+/// Settings resolved once per `backup` run and shared by every archive it writes.
+struct BackupPlan {
+    algo: Algorithm,
+    compression_level: Option<i32>,
+    adaptive_level: bool,
+    /// Set when the archive should be encrypted.
+    password: Option<String>,
+    resume_dir: Option<std::path::PathBuf>,
+    options: ResolvedBackupOptions,
+    /// Checked between stages; cancelled when `--timeout` runs out.
+    cancel: CancelToken,
+    log: RunLog,
+    /// `--zstd-dict`: where the dictionary was loaded from, and its bytes.
+    zstd_dictionary: Option<(String, Vec<u8>)>,
+}
+
+/// What `archive_one` produced.
+struct BackupOutcome {
+    metadata: ArchiveMetadata,
+    archive_path: std::path::PathBuf,
+    changed: Vec<String>,
+    timings: StageTimings,
+}
+
+/// Wall time spent in each backup stage.
+#[derive(Debug, Default, Clone, Copy)]
+struct StageTimings {
+    scan: Duration,
+    compress: Duration,
+    checksum: Duration,
+    encrypt: Duration,
+    verify: Duration,
+    upload: Duration,
+}
+
+impl StageTimings {
+    fn stages(&self) -> [(&'static str, Duration); 6] {
+        [
+            ("Scan", self.scan),
+            ("Compress", self.compress),
+            ("Checksum", self.checksum),
+            ("Encrypt", self.encrypt),
+            ("Verify", self.verify),
+            ("Upload", self.upload),
+        ]
+    }
+
+    fn total(&self) -> Duration {
+        self.stages().iter().map(|(_, duration)| *duration).sum()
+    }
+
+    /// Stages that ran, with their share of the total.
+    fn print(&self) {
+        let total_ms = self.total().as_millis() as u64;
+
+        utils::print_info("\nTime by stage:");
+        for (stage, duration) in self.stages() {
+            if duration.is_zero() {
+                continue;
+            }
+            utils::print_line(&format!(
+                "  {:<10} {:>8}  {}",
+                stage,
+                utils::format_duration(duration),
+                utils::format_percentage(duration.as_millis() as u64, total_ms)
+            ));
+        }
+    }
 }
 
 #[derive(Subcommand)]
@@ -92,58 +769,191 @@ enum RemoteAction {
     Test { remote: String },
 }
 
+#[derive(Subcommand)]
+enum ConfigAction {
+    /// Set a config key (dotted for sections, e.g. remote.enabled)
+    Set {
+        key: String,
+        value: String,
+
+        /// Show the change without saving it
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Print the effective config as TOML, with every default filled in
+    Dump,
+}
+
+#[derive(Subcommand)]
+enum ProfileAction {
+    /// Save the options of the last successful backup under a name
+    Save { name: String },
+    List,
+}
+
 impl Cli {
     pub fn run(&self) -> Result<()> {
+        crate::logging::init(self.verbose);
+        // The script goes to stdout as-is, so no banner in front of it.
+        if let Some(Commands::Completions { shell }) = &self.command {
+            return Self::write_completions(*shell, &mut std::io::stdout());
+        }
+        if self.quiet_success {
+            utils::hold_output_until_problem();
+        }
+        if self.json_progress {
+            utils::enable_json_progress();
+        }
+        utils::show_banner();
+
+        let result = self.run_command();
+        if !self.quiet_success {
+            return result;
+        }
+        if result.is_err() {
+            utils::release_held_output();
+            return result;
+        }
+        if utils::problem_reported() {
+            return Err(anyhow::anyhow!("Completed with warnings (--quiet-success)"));
+        }
+        result
+    }
+
+    fn write_completions(shell: clap_complete::Shell, out: &mut dyn std::io::Write) -> Result<()> {
+        clap_complete::generate(shell, &mut Self::command(), "zencore", out);
+        Ok(())
+    }
+
+    fn run_command(&self) -> Result<()> {
         match &self.command {
-            Some(Commands::Backup {
-                source,
-                destination,
-                name,
-                algorithm,
-                encrypt,
-                upload,
-                level,
-                threads,
-                checksums,
-            }) => self.run_backup(
-                source,
-                destination,
-                name,
-                algorithm,
-                *encrypt,
-                *upload,
-                *level,
-                *threads,
-                checksums,
+            Some(Commands::Backup(args)) => self.run_backup(args),
+            Some(Commands::List { source, since }) => self.run_list(source.as_deref(), *since),
+            Some(Commands::Show { name, verify, grep, regex, limit, offset, all }) => {
+                let filter = grep.as_deref().map(|pattern| ContentFilter::new(pattern, *regex)).transpose()?;
+                let page = ContentsPage {
+                    offset: *offset,
+                    limit: (!all).then_some(*limit),
+                };
+                self.run_show(name, *verify, filter.as_ref(), page)
+            }
+            Some(Commands::Contents { archive }) => self.run_contents(archive),
+            Some(Commands::Decompress {
+                archive,
+                output,
+                password_stdin,
+                password_from_env,
+                zstd_dict,
+                include,
+            }) => self.run_decompress(
+                archive,
+                output,
+                *password_stdin,
+                password_from_env.as_deref(),
+                zstd_dict.as_deref(),
+                include,
             ),
-            Some(Commands::List) => self.run_list(),
-            Some(Commands::Show { name }) => self.run_show(name),
-            Some(Commands::Verify { archive, algorithm }) => self.run_verify(archive, algorithm),
-            Some(Commands::Config) => self.run_config(),
-            Some(Commands::Upload { archive, to }) => self.run_upload(archive, to),
+            Some(Commands::Prune {
+                keep_last_n_per_source,
+                dry_run,
+                delete_files_in,
+            }) => self.run_prune(*keep_last_n_per_source, *dry_run, delete_files_in),
+            Some(Commands::Verify { archive, algorithm, contents, checksum_verify_chunked }) => {
+                if *contents {
+                    let manifest = Self::content_manifest(archive)?
+                        .context("No content manifest found; back up with --content-checksums first")?;
+                    self.run_verify_contents(archive, &manifest, false)
+                } else if *checksum_verify_chunked
+                    && let Some(manifest) = Self::content_manifest(archive)?
+                {
+                    self.run_verify_contents(archive, &manifest, true)
+                } else if algorithm.as_deref().is_some_and(|a| a.eq_ignore_ascii_case("all")) {
+                    self.run_verify_all(archive)
+                } else {
+                    self.run_verify(archive, algorithm)
+                }
+            }
+            Some(Commands::Checksum { file, algorithm, write }) => {
+                self.run_checksum(file, algorithm, *write)
+            }
+            Some(Commands::Whereis { file }) => self.run_whereis(file),
+            Some(Commands::Bench { source, sample_size }) => self.run_bench(source, *sample_size),
+            Some(Commands::TrainDict { source, output, size }) => self.run_train_dict(source, output, *size),
+            Some(Commands::AnalyzeDupes { source, similarity }) => self.run_analyze_dupes(source, *similarity),
+            Some(Commands::ImportState { file, strategy }) => self.run_import_state(file, *strategy),
+            Some(Commands::RepairState { scan_dir, apply }) => self.run_repair_state(scan_dir.as_deref(), *apply),
+            Some(Commands::Config { action: None }) => self.run_config(),
+            Some(Commands::Config {
+                action: Some(ConfigAction::Set { key, value, dry_run }),
+            }) => {
+                let config_path = Config::config_path()?;
+                if !config_path.exists() {
+                    Config::load()?;
+                }
+                Self::run_config_set(&config_path, key, value, *dry_run).map(|_| ())
+            }
+            Some(Commands::Config {
+                action: Some(ConfigAction::Dump),
+            }) => {
+                utils::print_line(&Self::effective_config(&Config::config_path()?)?);
+                Ok(())
+            }
+            Some(Commands::Upload { archive, destination, to, yes, compare_remote, force }) => {
+                let checksum = compare_remote.then(|| Checker::generate_checksum(archive)).transpose()?;
+                self.run_upload(archive, destination.as_ref().or(to.as_ref()), *yes, checksum.as_deref(), *force)
+            }
             Some(Commands::Remote { action }) => self.run_remote(action),
+            Some(Commands::Profile { action }) => self.run_profile(action),
+            Some(Commands::Completions { shell }) => Self::write_completions(*shell, &mut std::io::stdout()),
             None => self.run_interactive(),
         }
     }
 
-    fn run_backup(
-        &self,
-        source: &Option<String>,
-        destination: &Option<String>,
-        name: &Option<String>,
-        algorithm: &Option<String>,
-        encrypt: bool,
-        upload: bool,
-        level: Option<i32>,
-        threads: usize,
-        checksums: &Option<Vec<String>>,
-    ) -> Result<()> {
+    fn run_backup(&self, args: &BackupArgs) -> Result<()> {
+        let log = match &args.log_file {
+            Some(path) => RunLog::open(std::path::Path::new(&PathUtils::expand_path(path)?))?,
+            None => RunLog::default(),
+        };
+        log.record("backup started");
+
+        let result = match args.timeout {
+            Some(secs) => {
+                let args = args.clone();
+                let log = log.clone();
+                cancel::run_with_timeout(Duration::from_secs(secs), move |token| Self::backup(&args, &token, &log))
+            }
+            None => Self::backup(args, &CancelToken::new(), &log),
+        };
+
+        match &result {
+            Ok(()) => log.record("backup finished"),
+            Err(e) => log.record(&format!("backup failed: {:#}", e)),
+        }
+        result
+    }
+
+    fn backup(args: &BackupArgs, cancel: &CancelToken, log: &RunLog) -> Result<()> {
         let config = Config::load()?;
 
+        let profile = match &args.profile {
+            Some(name) => {
+                let profile = config.profiles.get(name).with_context(|| {
+                    let mut names: Vec<&String> = config.profiles.keys().collect();
+                    names.sort();
+                    format!("Unknown profile '{}' (saved profiles: {:?})", name, names)
+                })?;
+                utils::print_info(&format!("Using profile: {}", name));
+                Some(profile)
+            }
+            None => None,
+        };
+        let (options, profiled) = ResolvedBackupOptions::resolve(&config, profile, args)?;
+        let args = &profiled;
+
         // SOURCE SELECTION
-        let source_path = match source {
+        let source_path = match &args.source {
             Some(path) => {
-                let expanded = shellexpand::tilde(path).to_string();
+                let expanded = PathUtils::expand_path(path)?;
                 if !std::path::Path::new(&expanded).exists() {
                     utils::print_warning(&format!("Path not found: {}", path));
                     utils::print_info("Falling back to interactive selection...");
@@ -175,9 +985,9 @@ impl Cli {
                             .with_prompt("Source folder")
                             .interact_text()?;
 
-                        let expanded = shellexpand::tilde(&manual_path).to_string();
+                        let expanded = PathUtils::expand_path(&manual_path)?;
                         if !std::path::Path::new(&expanded).exists() {
-                            return Err(anyhow::anyhow!("Path does not exist: {}", expanded));
+                            return Err(ErrorKind::Usage.error(format!("Path does not exist: {}", expanded)));
                         }
                         expanded
                     }
@@ -185,15 +995,28 @@ impl Cli {
             }
         };
 
+        let source_path = PathUtils::canonical(&source_path);
+
         // Show folder info
-        if let Ok(info) = Fuzzer::get_folder_info(&source_path) {
+        if let Ok(info) = Fuzzer::get_folder_info(&source_path, options.symlinks) {
             info.display();
+
+            let proceed = Self::confirm_large_source(&info, &config, args.force, |prompt| {
+                Ok(Confirm::with_theme(&ColorfulTheme::default())
+                    .with_prompt(prompt)
+                    .default(false)
+                    .interact()?)
+            })?;
+            if !proceed {
+                utils::print_info("Backup cancelled");
+                return Ok(());
+            }
         }
 
         // DESTINATION SELECTION
-        let dest_path = match destination {
+        let dest_path = match &args.destination {
             Some(path) => {
-                let expanded = shellexpand::tilde(path).to_string();
+                let expanded = PathUtils::expand_path(path)?;
                 if !std::path::Path::new(&expanded).exists() {
                     utils::print_warning(&format!("Path not found: {}", path));
 
@@ -207,16 +1030,31 @@ impl Cli {
                         utils::print_success(&format!("Created: {}", expanded));
                         expanded
                     } else {
-                        return Err(anyhow::anyhow!("Destination folder required"));
+                        return Err(ErrorKind::Usage.error("Destination folder required"));
                     }
                 } else {
                     expanded
                 }
             }
             None => {
-                if !config.default_backup_destination.is_empty() {
+                let remembered = StateTracker::load()
+                    .ok()
+                    .and_then(|state| Self::remembered_destination(&state, &source_path));
+                let reuse = match &remembered {
+                    Some(_) if args.ask_destination_once => true,
+                    Some(last) => Confirm::with_theme(&ColorfulTheme::default())
+                        .with_prompt(format!("Save to {} again (last used for this source)?", last))
+                        .default(true)
+                        .interact()?,
+                    None => false,
+                };
+
+                if let (true, Some(last)) = (reuse, remembered) {
+                    utils::print_info(&format!("💾 Destination: {}", last));
+                    last
+                } else if !config.default_backup_destination.is_empty() {
                     let default_dest =
-                        shellexpand::tilde(&config.default_backup_destination).to_string();
+                        PathUtils::expand_path(&config.default_backup_destination)?;
 
                     let use_default = Confirm::with_theme(&ColorfulTheme::default())
                         .with_prompt(format!(
@@ -241,59 +1079,102 @@ impl Cli {
             }
         };
 
+        let dest_path = PathUtils::canonical(&dest_path);
+
         // ALGORITHM SELECTION
-        let algo = match algorithm {
-            Some(a) => {
-                let normalized = a.to_lowercase();
-                if !["tar.gz", "tar.zst", "zip"].contains(&normalized.as_str()) {
-                    utils::print_warning(&format!("Unknown algorithm: {}", a));
+        let algo = match &args.algorithm {
+            Some(a) => match a.parse::<Algorithm>() {
+                Ok(parsed) => parsed,
+                Err(e) => {
+                    utils::print_warning(&e);
                     Self::select_algorithm_interactive()?
-                } else {
-                    normalized
                 }
-            }
+            },
             None => Self::select_algorithm_interactive()?,
         };
 
         // COMPRESSION LEVEL VALIDATION
-        let compression_level = if let Some(lvl) = level {
-            match algo.as_str() {
-                "tar.gz" | "zip" if lvl < 0 || lvl > 9 => {
-                    utils::print_warning(&format!(
-                        "Invalid level {} for {}, using default",
-                        lvl, algo
-                    ));
-                    None
-                }
-                "tar.zst" if lvl < 1 || lvl > 22 => {
-                    utils::print_warning(&format!(
-                        "Invalid level {} for tar.zst, using default",
-                        lvl
-                    ));
-                    None
-                }
-                _ => Some(lvl),
+        let adaptive_level = options.level == Some(CompressionLevel::Auto);
+        let compression_level = if let Some(CompressionLevel::Fixed(lvl)) = options.level {
+            let (level, note) = algo.clamp_level(lvl);
+            if let Some(note) = note {
+                utils::print_warning(&note);
+            }
+            Some(level)
+        } else {
+            None
+        };
+        let zstd_dictionary = Self::load_zstd_dictionary(args, algo)?;
+
+        let picked;
+        let args = if args.interactive_exclude {
+            if console::user_attended() {
+                let mut with_picks = args.clone();
+                with_picks.exclude.extend(Self::pick_excluded_folders(&source_path)?);
+                picked = with_picks;
+                &picked
+            } else {
+                utils::print_warning("--interactive-exclude needs a terminal, ignoring it");
+                args
             }
         } else {
-            config.compression_level
+            args
         };
 
-        // ARCHIVE NAMING (Interactive or CLI)
-        let archive_name_input = if name.is_some() {
-            name.clone()
+        if args.group_by_toplevel {
+            let plan = BackupPlan {
+                algo,
+                compression_level,
+                adaptive_level,
+                password: None,
+                resume_dir: None,
+                options,
+                cancel: cancel.clone(),
+                log: log.clone(),
+                zstd_dictionary,
+            };
+            return Self::run_grouped_backup(&config, args, &source_path, &dest_path, plan);
+        }
+
+        // RESUME LOOKUP
+        let resume_dir = Config::state_dir()?.join("resume");
+        let pending = if args.resume_backup {
+            ResumeState::find(
+                &resume_dir,
+                std::path::Path::new(&source_path),
+                std::path::Path::new(&dest_path),
+                algo.as_str(),
+            )?
         } else {
-            Self::select_archive_name_interactive(&source_path, &dest_path, &algo, &config.date_format)?
+            None
         };
 
-        let namer = ArchiveNamer::new(
-            archive_name_input,
-            dest_path.clone(),
-            algo.clone(),
-            config.date_format.clone(),
-            )
-            .with_source_path(source_path.clone());
+        // ARCHIVE NAMING (Interactive or CLI)
+        let archive_name = if let Some(ref state) = pending {
+            utils::print_info(&format!(
+                "Found interrupted backup {} ({}/{} parts done)",
+                state.archive_name,
+                state.completed_parts,
+                state.part_count()
+            ));
+            state.archive_name.clone()
+        } else {
+            let archive_name_input = if args.name.is_some() {
+                args.name.clone()
+            } else {
+                Self::select_archive_name_interactive(&source_path, &dest_path, algo, &config.date_format)?
+            };
 
-        let archive_name = namer.generate()?;
+            let namer = ArchiveNamer::new(
+                archive_name_input,
+                dest_path.clone(),
+                algo,
+                config.date_format.clone(),
+                )
+                .with_source_path(source_path.clone());
+
+            namer.generate()?
+        };
 
 
         // DISPLAY CONFIGURATION
@@ -302,111 +1183,539 @@ impl Cli {
             ("Archive name", archive_name.clone()),
             ("Source", source_path.clone()),
             ("Destination", dest_path.clone()),
-            ("Algorithm", algo.clone()),
+            ("Algorithm", algo.to_string()),
         ]);
+        Self::print_level_and_threads(compression_level, adaptive_level, options.threads);
 
-        if let Some(lvl) = compression_level {
-            utils::print_info(&format!("⚙️  Compression level: {}", lvl));
+        // ENCRYPTION SETUP
+        if args.verify_identity {
+            utils::print_info("🔑 Checking age recipients...");
+            let identity_file = config.age_identity_file.as_deref().map(PathUtils::expand_path).transpose()?;
+            crate::encrypt_tar::TarEncryptor::verify_recipients(
+                &config.age_recipients,
+                identity_file.as_deref().map(std::path::Path::new),
+            )?;
         }
+        let password = Self::select_encryption(&config, args, algo)?;
 
-        if threads > 0 {
-            utils::print_info(&format!("🧵 Threads: {}", threads));
-        } else {
-            utils::print_info(&format!("🧵 Threads: auto ({})", num_cpus::get()));
+        if !Self::confirm_proceed()? {
+            utils::print_info("Backup cancelled");
+            return Ok(());
         }
 
-        // ENCRYPTION SETUP
-        let (should_encrypt, password) = if encrypt || config.encrypt_by_default {
-            if algo == "zip" {
-                let do_encrypt = if encrypt {
-                    utils::print_info("Encrypting ZIP archive");
-                    true 
-                } else {
-                    Confirm::with_theme(&ColorfulTheme::default())
-                        .with_prompt("Encrypt archive? (configured as default)")
-                        .default(true)
-                        .interact()?
-                };
+        let plan = BackupPlan {
+            algo,
+            compression_level,
+            adaptive_level,
+            password,
+            resume_dir: args.resume_backup.then_some(resume_dir),
+            options,
+            cancel: cancel.clone(),
+            log: log.clone(),
+            zstd_dictionary,
+        };
 
-                if do_encrypt {
-                    let pwd = Password::with_theme(&ColorfulTheme::default())
-                        .with_prompt("Enter encryption password")
-                        .with_confirmation("Confirm password", "Passwords don't match")
-                        .interact()?;
-                    (true, Some(pwd))
-                } else {
-                    (false, None)
-                }
-            } else {
-                utils::print_warning(&format!(
-                    "{} doesn't support built-in encryption",
-                    algo
-                ));
-                utils::print_info("💡 Tip: Use 'zip' format for native encryption");
+        let start_time = Instant::now();
+        let outcome = Self::archive_one(&config, args, &plan, &source_path, &dest_path, archive_name)?;
+        let metadata = outcome.metadata;
 
-                let use_post_encrypt = Confirm::with_theme(&ColorfulTheme::default())
-                    .with_prompt("Encrypt after compression? (AES-256-GCM wrapper)")
-                    .default(encrypt)
-                    .interact()?;
+        StateTracker::update(|state| {
+            state.add_archive(metadata.clone());
+            state.remember_destination(&source_path, &dest_path);
+        })?;
+        Self::remember_options(args, &source_path, &dest_path, algo);
 
-                if use_post_encrypt {
-                    let pwd = Password::with_theme(&ColorfulTheme::default())
-                        .with_prompt("Enter encryption password")
-                        .with_confirmation("Confirm password", "Passwords don't match")
-                        .interact()?;
-                    (true, Some(pwd))
-                } else {
-                    (false, None)
-                }
-            }
+        // FINAL SUMMARY
+        let total_duration = start_time.elapsed();
+        let file_size = metadata.size_bytes;
+        let original_size = if let Ok(info) = Fuzzer::get_folder_info(&source_path, plan.options.symlinks) {
+            info.total_size
         } else {
-            (false, None)
+            file_size
         };
 
-        let proceed = Confirm::with_theme(&ColorfulTheme::default())
-            .with_prompt("Proceed with backup?")
-            .default(true)
-            .interact()?;
+        utils::print_header("Backup Complete");
+        utils::print_summary(&[
+            ("Files backed up", utils::format_number(metadata.file_count)),
+            ("Original size", utils::format_bytes(original_size)),
+            ("Archive size", utils::format_bytes(file_size)),
+            ("Compression ratio", utils::format_compression_ratio(original_size, file_size)),
+            ("Total time", utils::format_duration(total_duration)),
+            ("Average speed", utils::format_speed(file_size, total_duration)),
+        ]);
 
-        if !proceed {
+        outcome.timings.print();
+
+        if !metadata.checksums.is_empty() {
+            utils::print_info("\nChecksums:");
+            for (algo_name, hash) in metadata.list_checksums() {
+                utils::print_line(&format!("  {} = {}", algo_name, hash));
+            }
+        }
+
+        if !outcome.changed.is_empty() {
+            utils::print_warning(&format!(
+                "\n{} file(s) changed during backup and may be inconsistent:",
+                outcome.changed.len()
+            ));
+            for file in &outcome.changed {
+                utils::print_line(&format!("  • {}", file));
+            }
+        }
+
+        if args.open_after
+            && let Err(e) = crate::reveal::reveal_archive(&crate::reveal::SystemLauncher, &outcome.archive_path)
+        {
+            utils::print_warning(&format!("Could not open file manager: {}", e));
+        }
+
+        Ok(())
+    }
+
+    /// `--group-by-toplevel`: one archive per immediate subfolder of the
+    /// source, all sharing the same settings and catalogued separately.
+    fn run_grouped_backup(
+        config: &Config,
+        args: &BackupArgs,
+        source_path: &str,
+        dest_path: &str,
+        mut plan: BackupPlan,
+    ) -> Result<()> {
+        let algo = plan.algo;
+        if args.resume_backup {
+            utils::print_warning("--resume-backup is not supported with --group-by-toplevel, ignoring it");
+        }
+
+        let root_excludes = Self::root_excludes(std::path::Path::new(source_path), args)?;
+        let groups = Self::toplevel_groups(std::path::Path::new(source_path), &root_excludes)?;
+        if groups.is_empty() {
+            return Err(anyhow::anyhow!("No subfolders to group in {}", source_path));
+        }
+
+        utils::print_header("Backup Configuration");
+        utils::print_summary(&[
+            ("Groups", groups.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>().join(", ")),
+            ("Source", source_path.to_string()),
+            ("Destination", dest_path.to_string()),
+            ("Algorithm", algo.to_string()),
+        ]);
+        Self::print_level_and_threads(plan.compression_level, plan.adaptive_level, plan.options.threads);
+
+        plan.password = Self::select_encryption(config, args, algo)?;
+
+        if !Self::confirm_proceed()? {
             utils::print_info("Backup cancelled");
             return Ok(());
         }
 
-        // CREATE ARCHIVER WITH ALL OPTIONS
-        let start_time = std::time::Instant::now();
+        let mut added = StateTracker::default();
+        let results = Self::backup_groups(config, args, &plan, &root_excludes, &groups, dest_path, &mut added);
+        StateTracker::update(|state| {
+            state.merge(added, MergeStrategy::Overwrite);
+            state.remember_destination(source_path, dest_path);
+        })?;
+        Self::remember_options(args, source_path, dest_path, algo);
+
+        utils::print_header("Grouped Backup Complete");
+        let mut failed = 0;
+        let rows: Vec<(&str, String)> = results
+            .iter()
+            .map(|(group, result)| {
+                let status = match result {
+                    Ok(metadata) => format!(
+                        "{} ({} files, {})",
+                        metadata.name,
+                        utils::format_number(metadata.file_count),
+                        utils::format_bytes(metadata.size_bytes)
+                    ),
+                    Err(e) => {
+                        failed += 1;
+                        format!("failed: {}", e)
+                    }
+                };
+                (group.as_str(), status)
+            })
+            .collect();
+        utils::print_summary(&rows);
+
+        if failed > 0 {
+            return Err(anyhow::anyhow!("{} of {} group(s) failed", failed, results.len()));
+        }
+
+        Ok(())
+    }
 
-        let mut archiver = Archiver::new(&source_path, &dest_path, archive_name.clone(), algo.clone())
-            .with_size_sorting(config.sort_files_by_size);
+    /// Lets the user untick top-level folders of the source and returns
+    /// exclude patterns for the unticked ones.
+    fn pick_excluded_folders(source_path: &str) -> Result<Vec<String>> {
+        let mut folders: Vec<String> = fs::read_dir(source_path)?
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_type().map(|t| t.is_dir()).unwrap_or(false))
+            .map(|entry| entry.file_name().to_string_lossy().to_string())
+            .collect();
+        folders.sort();
 
-        if threads > 0 {
-            archiver = archiver.with_threads(threads);
+        if folders.is_empty() {
+            utils::print_info("No folders to choose from, backing up everything");
+            return Ok(Vec::new());
         }
 
-        if let Some(lvl) = compression_level {
-            archiver = archiver.with_compression_level(lvl);
+        let defaults = vec![true; folders.len()];
+        let selected = dialoguer::MultiSelect::with_theme(&ColorfulTheme::default())
+            .with_prompt("Folders to back up (space to toggle, enter to confirm)")
+            .items(&folders)
+            .defaults(&defaults)
+            .interact()?;
+
+        let excludes = Self::folder_excludes(&folders, &selected);
+        if !excludes.is_empty() {
+            utils::print_info(&format!("Skipping {} folder(s)", excludes.len()));
+        }
+        Ok(excludes)
+    }
+
+    /// Anchored exclude patterns for every folder whose index isn't in
+    /// `selected`, with gitignore special characters escaped.
+    fn folder_excludes(folders: &[String], selected: &[usize]) -> Vec<String> {
+        folders
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| !selected.contains(index))
+            .map(|(_, folder)| {
+                let mut escaped = String::with_capacity(folder.len());
+                for c in folder.chars() {
+                    if matches!(c, '\\' | '*' | '?' | '[' | ']' | '!' | '#') {
+                        escaped.push('\\');
+                    }
+                    escaped.push(c);
+                }
+                format!("/{}/", escaped)
+            })
+            .collect()
+    }
+
+    /// The excludes that apply to the whole source, in order: its ignore
+    /// file, `--exclude-from` files, then `--exclude` patterns (which hold
+    /// the folders unticked by `--interactive-exclude`).
+    fn root_excludes(source: &std::path::Path, args: &BackupArgs) -> Result<Vec<String>> {
+        let mut patterns = Vec::new();
+        if let Ok(root_ignore) = fs::read_to_string(source.join(crate::compress::IGNORE_FILE_NAME)) {
+            patterns.extend(root_ignore.lines().map(str::to_string));
+        }
+        for file in &args.exclude_from {
+            let file = PathUtils::expand_path(file)?;
+            let contents = fs::read_to_string(&file).with_context(|| format!("Exclude file not found: {}", file))?;
+            patterns.extend(contents.lines().map(str::to_string));
+        }
+        patterns.extend(args.exclude.iter().cloned());
+        Ok(patterns)
+    }
+
+    /// `pattern`, a root exclude, rewritten relative to the `group` folder,
+    /// or `None` when it can't match inside it. Patterns without a slash
+    /// match at any depth and are kept as they are; anchored ones only
+    /// apply when their first component matches the group, which is then
+    /// stripped.
+    fn group_exclude(pattern: &str, group: &str) -> Option<String> {
+        let (negation, body) = match pattern.strip_prefix('!') {
+            Some(body) => ("!", body),
+            None => ("", pattern),
+        };
+        if body.trim().is_empty() || body.starts_with('#') {
+            return None;
+        }
+        if !body.trim_end_matches('/').contains('/') {
+            return Some(pattern.to_string());
         }
 
-        if algo == "zip" && password.is_some() {
-            archiver = archiver.with_password(password.clone().unwrap());
+        let anchored = body.strip_prefix('/').unwrap_or(body);
+        if anchored.starts_with("**/") {
+            return Some(format!("{}{}", negation, anchored));
+        }
+        let (first, rest) = anchored.split_once('/').unwrap_or((anchored, ""));
+        let mut builder = ignore::gitignore::GitignoreBuilder::new("");
+        builder.add_line(None, &format!("/{}", first)).ok()?;
+        if !builder.build().ok()?.matched(group, true).is_ignore() || rest.trim_end_matches('/').is_empty() {
+            return None;
         }
+        Some(format!("{}/{}", negation, rest))
+    }
 
-        let (archive_path, file_list) = archiver.compress()?;
+    /// Immediate subfolders of `source`, sorted by name. Hidden folders and
+    /// those matched by `root_excludes` are skipped, and loose files in the
+    /// root are reported since no group holds them.
+    fn toplevel_groups(source: &std::path::Path, root_excludes: &[String]) -> Result<Vec<(String, std::path::PathBuf)>> {
+        let mut builder = ignore::gitignore::GitignoreBuilder::new(source);
+        for pattern in root_excludes {
+            builder
+                .add_line(None, pattern)
+                .with_context(|| format!("Invalid exclude pattern: {}", pattern))?;
+        }
+        let excluded = builder.build().context("Failed to build exclude rules")?;
 
-        let compress_duration = start_time.elapsed();
+        let mut groups = Vec::new();
+        let mut loose_files = 0;
 
-        utils::print_success(&format!("Compressed to: {}", archive_path.display()));
-        utils::print_info(&format!("Time: {}", utils::format_duration(compress_duration)));
+        for entry in fs::read_dir(source)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            if name.starts_with('.') {
+                continue;
+            }
 
-        // MULTI-CHECKSUM GENERATION
-        let checksum_algos = if let Some(algos) = checksums {
-            algos.clone()
-        } else if !config.default_hash_algorithm.is_empty() {
-            vec![config.default_hash_algorithm.clone()]
+            if entry.file_type()?.is_dir() {
+                if excluded.matched(entry.path(), true).is_ignore() {
+                    continue;
+                }
+                groups.push((name, entry.path()));
+            } else {
+                loose_files += 1;
+            }
+        }
+
+        if loose_files > 0 {
+            utils::print_warning(&format!(
+                "{} file(s) directly in {} belong to no group and are not backed up",
+                loose_files,
+                source.display()
+            ));
+        }
+
+        groups.sort();
+        Ok(groups)
+    }
+
+    /// Archives each group and adds it to `state`. A failing group doesn't
+    /// stop the others; its error is returned in its slot instead.
+    fn backup_groups(
+        config: &Config,
+        args: &BackupArgs,
+        plan: &BackupPlan,
+        root_excludes: &[String],
+        groups: &[(String, std::path::PathBuf)],
+        dest_path: &str,
+        state: &mut StateTracker,
+    ) -> Vec<(String, Result<ArchiveMetadata>)> {
+        let template = match &args.name {
+            Some(name) if name.contains("{source}") => name.clone(),
+            Some(name) => format!("{}_{{source}}", name),
+            None => "{source}_{date}".to_string(),
+        };
+
+        groups
+            .iter()
+            .map(|(group, group_path)| {
+                utils::print_header(&format!("Group: {}", group));
+                let group_source = group_path.to_string_lossy().to_string();
+                // Each group is archived as its own source, so the root's
+                // excludes are re-rooted at it.
+                let group_args = BackupArgs {
+                    exclude: root_excludes.iter().filter_map(|pattern| Self::group_exclude(pattern, group)).collect(),
+                    exclude_from: Vec::new(),
+                    ..args.clone()
+                };
+
+                let result = ArchiveNamer::new(
+                    Some(template.clone()),
+                    dest_path.to_string(),
+                    plan.algo,
+                    config.date_format.clone(),
+                )
+                .with_source_path(group_source.clone())
+                .generate()
+                .and_then(|archive_name| {
+                    Self::archive_one(config, &group_args, plan, &group_source, dest_path, archive_name)
+                })
+                .map(|outcome| {
+                    state.add_archive(outcome.metadata.clone());
+                    outcome.metadata
+                });
+
+                if let Err(e) = &result {
+                    utils::print_error(&format!("Group {} failed: {}", group, e));
+                }
+
+                (group.clone(), result)
+            })
+            .collect()
+    }
+
+    /// Compresses, checksums, encrypts, verifies and uploads one archive,
+    /// returning its catalog entry. The caller adds it to the state.
+    fn archive_one(
+        config: &Config,
+        args: &BackupArgs,
+        plan: &BackupPlan,
+        source_path: &str,
+        dest_path: &str,
+        archive_name: String,
+    ) -> Result<BackupOutcome> {
+        let algo = plan.algo;
+        let _span = tracing::info_span!("backup", archive = %archive_name, source = source_path).entered();
+
+        let scan_started_at = Local::now();
+        let snapshot = if args.source_snapshot {
+            crate::snapshot::Snapshot::take(std::path::Path::new(source_path))?
         } else {
-            vec!["sha256".to_string()]
+            None
+        };
+        let read_from = snapshot.as_ref().map_or(std::path::Path::new(source_path), |s| s.source());
+
+        let mut archiver = Archiver::new(read_from, dest_path, archive_name.clone(), algo)
+            .with_display_source(source_path)
+            .with_sort_order(plan.options.sort_order)
+            .with_excludes(args.exclude.clone())
+            .with_exclude_files(
+                args.exclude_from
+                    .iter()
+                    .map(|file| PathUtils::expand_path(file).map(std::path::PathBuf::from))
+                    .collect::<Result<_>>()?,
+            )
+            .with_excluded_extensions(args.exclude_extension.clone())
+            .with_allow_empty(args.allow_empty)
+            .with_symlinks(plan.options.symlinks)
+            .with_collision_policy(args.on_collision)
+            .with_dedup(args.dedup)
+            .with_tar_format(args.tar_format)
+            .with_read_retries(config.read_retries)
+            .with_embedded_readme(args.embed_readme)
+            .with_cancel(plan.cancel.clone());
+
+        if plan.options.threads > 0 {
+            archiver = archiver.with_threads(plan.options.threads);
+        }
+
+        if utils::json_progress_enabled() {
+            archiver = archiver.with_progress_callback(crate::compress::ndjson_progress(
+                "compress",
+                std::io::stderr(),
+                JSON_PROGRESS_INTERVAL,
+            ));
+        }
+
+        if let Some(lvl) = plan.compression_level {
+            archiver = archiver.with_compression_level(lvl);
+        }
+
+        if let Some((_, ref dictionary)) = plan.zstd_dictionary {
+            archiver = archiver.with_zstd_dictionary(dictionary.clone());
+        }
+
+        if plan.adaptive_level {
+            archiver = archiver.with_adaptive_level(true);
+        }
+
+        if let Some(minutes) = args.time_budget {
+            let budget = std::time::Duration::try_from_secs_f64(minutes * 60.0)
+                .ok()
+                .filter(|budget| !budget.is_zero())
+                .ok_or_else(|| ErrorKind::Usage.error(format!("Invalid --time-budget: {}", minutes)))?;
+            archiver = archiver.with_time_budget(budget);
+        }
+
+        if let Some(resume_dir) = &plan.resume_dir {
+            archiver = archiver.with_resume(resume_dir.clone());
+        }
+
+        if args.verify_source || args.strict_source {
+            archiver = archiver.with_source_verification(args.verify_source, args.strict_source);
+        }
+
+        if let Some(margin) = args.min_free_space {
+            archiver = archiver.with_min_free_space(margin, args.force);
+        }
+
+        if let Some(note) = &args.comment {
+            archiver = archiver.with_comment(note);
+        }
+
+        if args.content_checksums {
+            archiver = archiver
+                .with_content_checksums(true)
+                .with_parallel_content_checksums(args.checksum_parallel_files);
+        }
+
+        let mut parent = None;
+        if args.since_last {
+            let state = StateTracker::load()?;
+            match state.latest_for_source(source_path) {
+                Some(previous) => {
+                    let since = previous.changes_since().with_context(|| {
+                        format!("Unreadable creation time on {}: {}", previous.name, previous.created_at)
+                    })?;
+                    utils::print_info(&format!(
+                        "Only files changed since {} ({})",
+                        previous.name,
+                        previous.scan_started_at.as_deref().unwrap_or(&previous.created_at)
+                    ));
+                    archiver = archiver.with_modified_since(since);
+                    parent = Some(previous.name.clone());
+                }
+                None => utils::print_warning("No earlier backup of this source, backing up everything"),
+            }
+        }
+
+        if algo.supports_encryption()
+            && let Some(password) = &plan.password
+        {
+            archiver = archiver.with_password(password.clone());
+        }
+
+        utils::print_info(&format!("Compressing with {} algorithm...", algo));
+        utils::print_info(&format!("Using {} threads", archiver.effective_threads()));
+        if let Some(lvl) = plan.compression_level {
+            utils::print_info(&format!("Compression level: {}", lvl));
+        }
+
+        let meta_dir = args.meta_dir.as_deref().map(PathUtils::expand_path).transpose()?;
+        let meta_path = meta_dir.as_deref().map(std::path::Path::new);
+        let replace_sidecar = args.force || args.replace_existing_checksum_file || config.replace_existing_checksum_file;
+        if config.generate_checksum_file && !replace_sidecar {
+            let planned = std::path::Path::new(dest_path).join(&archive_name);
+            Checker::check_sidecar_replaceable(planned.to_str().unwrap(), meta_path)?;
+        }
+
+        plan.log.record(&format!("archive {} started from {}", archive_name, source_path));
+        plan.cancel.set_stage("compress");
+        let result = archiver.compress()?;
+        drop(snapshot);
+        plan.log.record(&format!(
+            "compress finished: {} files, {} in {}",
+            result.files.len(),
+            utils::format_bytes(result.bytes_written),
+            utils::format_duration(result.duration)
+        ));
+        let mut timings = StageTimings {
+            scan: result.scan_duration,
+            compress: result.duration.saturating_sub(result.scan_duration),
+            ..Default::default()
         };
+        tracing::debug!(elapsed = ?result.duration, bytes = result.bytes_written, "compression finished");
+        Self::report_compression(&result, plan.password.is_some() && algo.supports_encryption());
+        if let Some(tip) =
+            Self::compression_tip(&result, args.min_compression_ratio.unwrap_or(config.min_compression_ratio))
+        {
+            utils::print_info(&tip);
+        }
+
+        let archive_path = result.path;
+        let file_list = result.files;
+
+        if let Some(ref dir) = meta_dir {
+            fs::create_dir_all(dir).with_context(|| format!("Failed to create meta dir {}", dir))?;
+        }
+
+        if args.content_checksums {
+            let manifest_path =
+                Checker::write_manifest(archive_path.to_str().unwrap(), meta_path, &result.content_checksums)?;
+            utils::print_success(&format!("Content manifest: {}", manifest_path));
+        }
+
+        // MULTI-CHECKSUM GENERATION
+        let checksum_algos = plan.options.checksums.clone();
 
+        plan.cancel.check()?;
+        plan.cancel.set_stage("checksum");
         utils::print_info(&format!("Generating checksums: {}", checksum_algos.join(", ")));
 
         let algorithms: Vec<HashAlgorithm> = checksum_algos
@@ -414,36 +1723,49 @@ impl Cli {
             .filter_map(|s| HashAlgorithm::from_str(s).ok())
             .collect();
 
+        let checksum_start = Instant::now();
         let checksum_results =
             Checker::generate_multiple_checksums(archive_path.to_str().unwrap(), &algorithms)?;
+        tracing::debug!(algorithms = ?checksum_algos, elapsed = ?checksum_start.elapsed(), "checksums generated");
 
         let mut checksums_map = HashMap::new();
 
         for (algo_enum, hash) in checksum_results {
             utils::print_success(&format!("{}: {}", algo_enum.name(), hash));
-            checksums_map.insert(algo_enum.name().to_string(), hash);
+            checksums_map.insert(algo_enum.key().to_string(), hash);
         }
 
         if config.generate_checksum_file {
-            Checker::generate_checksum_file(archive_path.to_str().unwrap())?;
+            // Any conflicting sidecar was caught before compressing.
+            Checker::generate_checksum_file(archive_path.to_str().unwrap(), meta_path, true)?;
         }
+        timings.checksum = checksum_start.elapsed();
+        plan.log.record(&format!("checksum finished in {}", utils::format_duration(timings.checksum)));
 
         // POST-COMPRESSION ENCRYPTION (TAR formats)
-        let encrypted = if should_encrypt && password.is_some() {
-            if algo == "zip" {
-                utils::print_info("✓ ZIP native encryption applied during compression");
+        plan.cancel.check()?;
+        plan.cancel.set_stage("encrypt");
+        let encrypt_start = Instant::now();
+        let encrypted = if let Some(pwd) = &plan.password {
+            if algo.supports_encryption() {
+                utils::print_info("✓ ZIP AES-256 encryption applied during compression");
                 true
             } else {
                 utils::print_info("Applying age encryption to TAR...");
 
-                let pwd = password.unwrap();
-                let encryptor = crate::encrypt_tar::TarEncryptor::new(pwd);
-        
-                match encryptor.encrypt_file(archive_path.to_str().unwrap()) {
+                let encryptor =
+                    crate::encrypt_tar::TarEncryptor::new(pwd.clone()).with_armor(config.age_armor);
+
+                let age_temp = crate::encrypt_tar::TarEncryptor::encrypted_temp_path(archive_path.to_str().unwrap());
+                plan.cancel.track_partial(std::path::Path::new(&age_temp));
+                let encrypted = encryptor.encrypt_file(archive_path.to_str().unwrap());
+                plan.cancel.finished_file(std::path::Path::new(&age_temp));
+                match encrypted {
                     Ok(_) => {
                         if config.generate_checksum_file {
                             utils::print_info("Updating checksum for encrypted archive...");
-                            let _ = Checker::generate_checksum_file(archive_path.to_str().unwrap());
+                            // Replaces the sidecar written above for the unencrypted archive.
+                            let _ = Checker::generate_checksum_file(archive_path.to_str().unwrap(), meta_path, true);
                         }
                         true
                     }
@@ -456,124 +1778,412 @@ impl Cli {
         } else {
             false
         };
+        timings.encrypt = encrypt_start.elapsed();
+        if plan.password.is_some() {
+            plan.log.record(&format!(
+                "encrypt {} in {}",
+                if encrypted { "finished" } else { "failed" },
+                utils::format_duration(timings.encrypt)
+            ));
+        }
+
         // VERIFY IF ENABLED
+        plan.cancel.check()?;
+        plan.cancel.set_stage("verify");
+        let verify_start = Instant::now();
         if config.verify_after_backup {
             utils::print_info("🔍 Verifying backup integrity...");
-            if Checker::auto_verify(archive_path.to_str().unwrap())? {
+            if Checker::auto_verify(archive_path.to_str().unwrap(), meta_path)? {
                 utils::print_success("✓ Backup verified successfully!");
             } else {
                 utils::print_error("✗ Backup verification failed!");
-                return Err(anyhow::anyhow!("Backup verification failed"));
+                return Err(ErrorKind::Verification.error("Backup verification failed"));
             }
         }
 
+        timings.verify = verify_start.elapsed();
+        if config.verify_after_backup {
+            plan.log.record(&format!("verify finished in {}", utils::format_duration(timings.verify)));
+        }
+
         // REMOTE UPLOAD
-        if upload || config.remote.as_ref().map(|r| r.auto_upload && r.enabled).unwrap_or(false) {
-            Self::handle_remote_upload(&config, archive_path.to_str().unwrap())?;
+        plan.cancel.check()?;
+        plan.cancel.set_stage("upload");
+        let upload_start = Instant::now();
+        let verify_before_upload = args.verify_before_upload || config.verify_before_upload;
+        // The checksums above describe the archive before age encryption rewrote it.
+        let expected_sha256 = checksums_map
+            .get(HashAlgorithm::Sha256.key())
+            .filter(|_| !encrypted || algo.supports_encryption())
+            .map(String::as_str);
+        let compare_checksum = match expected_sha256 {
+            Some(hash) if args.compare_remote => Some(hash.to_string()),
+            None if args.compare_remote => Some(Checker::generate_checksum(archive_path.to_str().unwrap())?),
+            _ => None,
+        };
+        let adhoc_destination = args.upload_destination()?;
+        if let Some(destination) = adhoc_destination.as_ref() {
+            let archive = archive_path.to_str().unwrap();
+            let present = compare_checksum.as_deref().is_some_and(|checksum| {
+                Self::already_on_remote(archive, destination, checksum, |remote, path, name, checksum| {
+                    RemoteTransfer::remote_has(remote, path, name, checksum, config.rclone_extra_args())
+                })
+            });
+            if !present && Self::confirm_upload(config, archive, &destination.target(), args.yes)? {
+                Self::upload_verified(verify_before_upload, archive, meta_path, expected_sha256, || {
+                    Self::upload_to_destination(archive, destination, config.rclone_extra_args(), &plan.cancel)
+                })?;
+            }
+        } else if args.upload || config.remote.as_ref().map(|r| r.auto_upload && r.enabled).unwrap_or(false) {
+            let archive = archive_path.to_str().unwrap();
+            Self::upload_verified(verify_before_upload, archive, meta_path, expected_sha256, || {
+                Self::handle_remote_upload(config, archive, args.yes, compare_checksum.as_deref(), &plan.cancel)
+            })?;
+        }
+        timings.upload = upload_start.elapsed();
+        if adhoc_destination.is_some() || args.upload {
+            plan.log.record(&format!("upload finished in {}", utils::format_duration(timings.upload)));
         }
 
-        // SAVE METADATA
+        // BUILD METADATA
         let file_size = fs::metadata(&archive_path)?.len();
         let mut metadata = ArchiveMetadata {
             name: archive_name,
             created_at: Local::now().to_rfc3339(),
             checksum: String::new(),
             checksums: HashMap::new(),
-            algorithm: algo,
+            algorithm: algo.to_string(),
             size_bytes: file_size,
             file_count: file_list.len(),
             encrypted,
-            contents: file_list,
+            contents: Vec::new(),
+            contents_summary: Default::default(),
+            contents_mode: ContentsMode::Full,
+            content_checksums: result.content_checksums.into_iter().collect(),
+            source: source_path.to_string(),
+            meta_dir,
+            path: archive_path.to_string_lossy().to_string(),
+            parent,
+            zstd_dictionary: plan.zstd_dictionary.as_ref().map(|(path, _)| path.clone()),
+            scan_started_at: Some(scan_started_at.to_rfc3339()),
+            file_name: None,
         };
 
+        metadata.set_contents(file_list, plan.options.contents);
+
         for (algo_name, hash) in checksums_map {
             metadata.add_checksum(&algo_name, hash);
         }
+        plan.log.record(&format!(
+            "archive {} written to {} ({})",
+            metadata.name,
+            archive_path.display(),
+            utils::format_bytes(file_size)
+        ));
+
+        Ok(BackupOutcome {
+            metadata,
+            archive_path,
+            changed: result.changed,
+            timings,
+        })
+    }
 
-        let mut state = StateTracker::load()?;
-        state.add_archive(metadata.clone());
-        state.save()?;
+    /// Keeps this run's options for `zencore profile save`.
+    fn remember_options(args: &BackupArgs, source_path: &str, dest_path: &str, algo: Algorithm) {
+        if let Err(e) = args.to_profile(source_path, dest_path, algo).save_last() {
+            utils::print_warning(&format!("Could not record backup options: {}", e));
+        }
+    }
 
-        // FINAL SUMMARY
-        let total_duration = start_time.elapsed();
-        let original_size = if let Ok(info) = Fuzzer::get_folder_info(&source_path) {
-            info.total_size
-        } else {
-            file_size
-        };
+    fn print_level_and_threads(compression_level: Option<i32>, adaptive_level: bool, threads: usize) {
+        if let Some(lvl) = compression_level {
+            utils::print_info(&format!("⚙️  Compression level: {}", lvl));
+        } else if adaptive_level {
+            utils::print_info("⚙️  Compression level: auto (chosen from content)");
+        }
 
-        utils::print_header("Backup Complete");
-        utils::print_summary(&[
-            ("Files backed up", utils::format_number(metadata.file_count)),
-            ("Original size", utils::format_bytes(original_size)),
-            ("Archive size", utils::format_bytes(file_size)),
-            ("Compression ratio", utils::format_compression_ratio(original_size, file_size)),
-            ("Total time", utils::format_duration(total_duration)),
-            ("Average speed", utils::format_speed(file_size, total_duration)),
-        ]);
+        if threads > 0 {
+            utils::print_info(&format!("🧵 Threads: {}", threads));
+        } else {
+            utils::print_info(&format!("🧵 Threads: auto ({})", num_cpus::get()));
+        }
+    }
 
-        if !metadata.checksums.is_empty() {
-            utils::print_info("\nChecksums:");
-            for (algo_name, hash) in metadata.list_checksums() {
-                println!("  {} = {}", algo_name, hash);
-            }
+    /// Loads `--zstd-dict`, which only tar.zst archives can use.
+    fn load_zstd_dictionary(args: &BackupArgs, algo: Algorithm) -> Result<Option<(String, Vec<u8>)>> {
+        let Some(ref path) = args.zstd_dict else {
+            return Ok(None);
+        };
+        if algo != Algorithm::TarZst {
+            utils::print_warning(&format!("--zstd-dict only applies to tar.zst, not {}, ignoring it", algo));
+            return Ok(None);
         }
 
-        Ok(())
+        let path = PathUtils::canonical(&PathUtils::expand_path(path)?);
+        let dictionary = dictionary::load(std::path::Path::new(&path))?;
+        utils::print_info(&format!(
+            "Using zstd dictionary {} (id {})",
+            path,
+            dictionary::dictionary_id(&dictionary)
+        ));
+        Ok(Some((path, dictionary)))
     }
 
-    fn handle_remote_upload(config: &Config, archive_path: &str) -> Result<()> {
-        if let Some(ref remote_config) = config.remote {
-            if let Some(ref rclone) = remote_config.rclone {
-                utils::print_info("📤 Uploading to remote storage...");
+    /// Decides whether to encrypt and reads the password if so.
+    fn select_encryption(config: &Config, args: &BackupArgs, algo: Algorithm) -> Result<Option<String>> {
+        Self::select_encryption_with(config, args, algo, |name| std::env::var(name))
+    }
 
-                RemoteTransfer::upload_to_rclone(
-                    archive_path,
-                    &rclone.remote_name,
-                    &rclone.remote_path,
-                )?;
+    /// `select_encryption`, looking up `--password-from-env` with `env`.
+    fn select_encryption_with(
+        config: &Config,
+        args: &BackupArgs,
+        algo: Algorithm,
+        env: impl Fn(&str) -> Result<String, std::env::VarError>,
+    ) -> Result<Option<String>> {
+        if !(args.encrypt || config.encrypt_by_default) {
+            return Ok(None);
+        }
 
-                if rclone.verify_after_upload {
-                    utils::print_info("Verifying remote upload...");
-                    utils::print_success("✓ Upload verified (rclone checksum)");
-                }
+        let policy = PasswordPolicy::new(args.password_min_length.unwrap_or(config.password_min_length));
 
-                return Ok(());
+        let do_encrypt = if algo.supports_encryption() {
+            if args.encrypt {
+                utils::print_info("Encrypting ZIP archive with AES-256");
+                true
+            } else {
+                Confirm::with_theme(&ColorfulTheme::default())
+                    .with_prompt("Encrypt archive? (configured as default)")
+                    .default(true)
+                    .interact()?
             }
+        } else {
+            utils::print_warning(&format!(
+                "{} doesn't support built-in encryption",
+                algo
+            ));
+            utils::print_info("💡 Tip: Use 'zip' format for native encryption");
+
+            if args.encrypt && (args.password_stdin || args.password_from_env.is_some()) {
+                // Nobody is there to answer the prompt.
+                utils::print_info("Encrypting after compression (AES-256-GCM wrapper)");
+                true
+            } else {
+                Confirm::with_theme(&ColorfulTheme::default())
+                    .with_prompt("Encrypt after compression? (AES-256-GCM wrapper)")
+                    .default(args.encrypt)
+                    .interact()?
+            }
+        };
 
-            if let Some(ref db) = remote_config.database {
-                utils::print_info("📤 Uploading to database...");
+        if do_encrypt {
+            Ok(Some(Self::read_encryption_password(
+                &policy,
+                args.password_stdin,
+                args.password_from_env.as_deref().map(|name| (name, env(name))),
+            )?))
+        } else {
+            Ok(None)
+        }
+    }
 
-                let password = if db.password.is_some() {
-                    db.password.clone().unwrap()
-                } else {
-                    Password::with_theme(&ColorfulTheme::default())
-                        .with_prompt("Database password")
-                        .interact()?
-                };
+    /// Asks before starting the backup. Runs without a terminal, such as
+    /// `--quiet-success` from cron, go ahead without asking.
+    fn confirm_proceed() -> Result<bool> {
+        if !console::user_attended() {
+            return Ok(true);
+        }
+        Ok(Confirm::with_theme(&ColorfulTheme::default())
+            .with_prompt("Proceed with backup?")
+            .default(true)
+            .interact()?)
+    }
 
-                RemoteTransfer::upload_to_database(
-                    archive_path,
-                    &db.host,
-                    db.port,
-                    &db.username,
-                    &password,
-                    &db.database,
-                    &db.table,
-                )?;
+    /// Prompts until the password meets the policy's minimum length; weak
+    /// ones need an explicit confirmation.
+    /// `from_env` is the `--password-from-env` variable and its value.
+    fn read_encryption_password(
+        policy: &PasswordPolicy,
+        from_stdin: bool,
+        from_env: Option<(&str, Result<String, std::env::VarError>)>,
+    ) -> Result<String> {
+        if let Some((name, value)) = from_env {
+            return policy.read_from_var(name, value);
+        }
+        if from_stdin {
+            return policy.read_from(std::io::stdin().lock());
+        }
 
-                return Ok(());
+        loop {
+            let pwd = Password::with_theme(&ColorfulTheme::default())
+                .with_prompt("Enter encryption password")
+                .with_confirmation("Confirm password", "Passwords don't match")
+                .interact()?;
+
+            if let Err(e) = policy.check(&pwd) {
+                utils::print_error(&e.to_string());
+                continue;
             }
-        }
 
-        utils::print_warning("No remote configured. Skipping upload.");
-        Ok(())
-    }
+            if let Some(reason) = policy.weakness(&pwd) {
+                utils::print_warning(&format!("Weak password: {}", reason));
 
-    fn select_archive_name_interactive(
+                let keep = Confirm::with_theme(&ColorfulTheme::default())
+                    .with_prompt("Use this password anyway?")
+                    .default(false)
+                    .interact()?;
+
+                if !keep {
+                    continue;
+                }
+            }
+
+            return Ok(pwd);
+        }
+    }
+
+    fn report_compression(result: &CompressResult, zip_encrypted: bool) {
+        if let Some(adaptive) = result.adaptive {
+            adaptive.report();
+        }
+
+        if let Some(budget) = result.budget {
+            budget.report();
+        }
+
+        for warning in &result.warnings {
+            utils::print_warning(warning);
+        }
+
+        if !result.skipped.is_empty() {
+            utils::print_warning(&format!("Skipped {} unreadable entries:", result.skipped.len()));
+            for entry in &result.skipped {
+                utils::print_line(&format!("  • {}", entry));
+            }
+        }
+
+        if !result.collisions.is_empty() {
+            utils::print_warning(&format!(
+                "{} files shared an entry name and were stored under a new one:",
+                result.collisions.len()
+            ));
+            for (path, stored) in &result.collisions {
+                utils::print_line(&format!("  • {} → {}", path, stored));
+            }
+        }
+
+        if result.dedup.files > 0 {
+            utils::print_info(&format!(
+                "Deduplicated {} identical files, saving {}",
+                utils::format_number(result.dedup.files),
+                utils::format_bytes(result.dedup.bytes_saved)
+            ));
+        }
+
+        if zip_encrypted {
+            utils::print_success("✓ Archive encrypted with password");
+        }
+
+        utils::print_success(&format!(
+            "Compressed {} files to: {}",
+            utils::format_number(result.files.len()),
+            result.path.display()
+        ));
+        utils::print_info(&format!(
+            "Time: {} ({})",
+            utils::format_duration(result.duration),
+            utils::format_bytes(result.bytes_written)
+        ));
+    }
+
+    /// A hint for archives that barely shrank, which usually means the
+    /// source is already-compressed media (MP3, AAC, JPEG, video).
+    fn compression_tip(result: &CompressResult, threshold: f64) -> Option<String> {
+        let ratio = result.ratio();
+        let already_stored = result.adaptive.is_some_and(|adaptive| adaptive.store_only);
+        (ratio > threshold && !already_stored).then(|| {
+            format!(
+                "💡 The archive is {:.0}% of the source size, so the files are most likely already compressed. \
+                 `--algorithm zip --level auto` stores them as they are, which is faster and just as small.",
+                ratio * 100.0
+            )
+        })
+    }
+
+    fn handle_remote_upload(
+        config: &Config,
+        archive_path: &str,
+        assume_yes: bool,
+        compare_checksum: Option<&str>,
+        cancel: &CancelToken,
+    ) -> Result<()> {
+        if let Some(ref remote_config) = config.remote {
+            if let Some(destination) = RemoteDestination::all_from_config(remote_config).first() {
+                if let Some(checksum) = compare_checksum
+                    && Self::already_on_remote(archive_path, destination, checksum, |remote, path, name, checksum| {
+                        RemoteTransfer::remote_has(remote, path, name, checksum, config.rclone_extra_args())
+                    })
+                {
+                    return Ok(());
+                }
+                if !Self::confirm_upload(config, archive_path, &destination.target(), assume_yes)? {
+                    return Ok(());
+                }
+            }
+
+            if let Some(ref rclone) = remote_config.rclone {
+                utils::print_info("📤 Uploading to remote storage...");
+
+                RemoteTransfer::upload_to_rclone(
+                    archive_path,
+                    &rclone.remote_name,
+                    &rclone.remote_path,
+                    &rclone.extra_args,
+                    cancel,
+                )?;
+
+                if rclone.verify_after_upload {
+                    utils::print_info("Verifying remote upload...");
+                    utils::print_success("✓ Upload verified (rclone checksum)");
+                }
+
+                return Ok(());
+            }
+
+            if let Some(ref db) = remote_config.database {
+                utils::print_info("📤 Uploading to database...");
+
+                let password = if db.password.is_some() {
+                    db.password.clone().unwrap()
+                } else {
+                    Password::with_theme(&ColorfulTheme::default())
+                        .with_prompt("Database password")
+                        .interact()?
+                };
+
+                RemoteTransfer::upload_to_database(
+                    archive_path,
+                    &db.host,
+                    db.port,
+                    &db.username,
+                    &password,
+                    &db.database,
+                    &db.table,
+                )?;
+
+                return Ok(());
+            }
+        }
+
+        utils::print_warning("No remote configured. Skipping upload.");
+        Ok(())
+    }
+
+    fn select_archive_name_interactive(
         source_path: &str,
         dest_path: &str,
-        algo: &str,
+        algo: Algorithm,
         date_format: &str,
     ) -> Result<Option<String>> {
         utils::print_info("📝 Archive Naming Options");
@@ -601,7 +2211,7 @@ impl Cli {
                         let namer = ArchiveNamer::new(
                             Some(template.to_string()),
                             dest_path.to_string(),
-                            algo.to_string(),
+                            algo,
                             date_format.to_string(),
                         )
                         .with_source_path(source_path.to_string());
@@ -633,16 +2243,16 @@ impl Cli {
             }
             3 => {
                 utils::print_info("\nAvailable variables:");
-                println!("  {{date}}      - Current date/time");
-                println!("  {{source}}    - Source folder name");
-                println!("  {{algo}}      - Compression algorithm");
-                println!("  {{year}}      - Current year (YYYY)");
-                println!("  {{month}}     - Current month (MM)");
-                println!("  {{day}}       - Current day (DD)");
-                println!("  {{hour}}      - Current hour (HH)");
-                println!("  {{minute}}    - Current minute (MM)");
-                println!("  {{timestamp}} - Unix timestamp");
-                println!("\nExample: backup_{{source}}_{{year}}{{month}}{{day}}");
+                utils::print_line("  {date}      - Current date/time");
+                utils::print_line("  {source}    - Source folder name");
+                utils::print_line("  {algo}      - Compression algorithm");
+                utils::print_line("  {year}      - Current year (YYYY)");
+                utils::print_line("  {month}     - Current month (MM)");
+                utils::print_line("  {day}       - Current day (DD)");
+                utils::print_line("  {hour}      - Current hour (HH)");
+                utils::print_line("  {minute}    - Current minute (MM)");
+                utils::print_line("  {timestamp} - Unix timestamp");
+                utils::print_line("\nExample: backup_{source}_{year}{month}{day}");
 
                 let template = dialoguer::Input::<String>::new()
                     .with_prompt("Enter template")
@@ -654,7 +2264,7 @@ impl Cli {
                     let namer = ArchiveNamer::new(
                         Some(template.clone()),
                         dest_path.to_string(),
-                        algo.to_string(),
+                        algo,
                         date_format.to_string(),
                     )
                     .with_source_path(source_path.to_string());
@@ -678,50 +2288,278 @@ impl Cli {
         }
     }
 
-    fn run_upload(&self, archive: &str, to: &Option<String>) -> Result<()> {
+    fn run_upload(
+        &self,
+        archive: &str,
+        to: Option<&String>,
+        assume_yes: bool,
+        compare_checksum: Option<&str>,
+        force: bool,
+    ) -> Result<()> {
         if !std::path::Path::new(archive).exists() {
-            return Err(anyhow::anyhow!("Archive not found: {}", archive));
+            return Err(ErrorKind::Usage.error(format!("Archive not found: {}", archive)));
         }
 
+        let state = StateTracker::load()?;
+        Self::check_unchanged_since_backup(archive, state.find_archive_file(std::path::Path::new(archive)), force)?;
+
         let config = Config::load()?;
 
         if let Some(destination) = to {
-            if destination.contains(':') {
-                let parts: Vec<&str> = destination.splitn(2, ':').collect();
-                let remote_name = parts[0];
-                let remote_path = parts.get(1).map(|s| s.to_string()).unwrap_or_default();
-
-                RemoteTransfer::upload_to_rclone(archive, remote_name, &remote_path)?;
-            } else {
-                return Err(anyhow::anyhow!(
-                    "Invalid destination format. Use 'remote:path'"
-                ));
+            let destination = RemoteDestination::parse(destination)?;
+            let present = compare_checksum.is_some_and(|checksum| {
+                Self::already_on_remote(archive, &destination, checksum, |remote, path, name, checksum| {
+                    RemoteTransfer::remote_has(remote, path, name, checksum, config.rclone_extra_args())
+                })
+            });
+            if !present && Self::confirm_upload(&config, archive, &destination.target(), assume_yes)? {
+                Self::upload_to_destination(archive, &destination, config.rclone_extra_args(), &CancelToken::new())?;
             }
         } else {
-            Self::handle_remote_upload(&config, archive)?;
+            Self::handle_remote_upload(&config, archive, assume_yes, compare_checksum, &CancelToken::new())?;
+        }
+
+        Ok(())
+    }
+
+    /// Re-hashes an archive the catalog knows about and refuses to upload it
+    /// (unless `force`) when it no longer matches the SHA-256 recorded at
+    /// backup time, so a corrupted or tampered file never reaches the remote.
+    fn check_unchanged_since_backup(archive: &str, recorded: Option<&ArchiveMetadata>, force: bool) -> Result<()> {
+        // After age encryption the recorded checksums describe the plain archive.
+        let comparable = |metadata: &&ArchiveMetadata| {
+            !metadata.encrypted || Algorithm::from_str(&metadata.algorithm).is_ok_and(|algo| algo.supports_encryption())
+        };
+        let Some(expected) = recorded.filter(comparable).and_then(|metadata| metadata.get_checksum(HashAlgorithm::Sha256.key())) else {
+            return Ok(());
+        };
+
+        if Checker::generate_checksum(archive)?.eq_ignore_ascii_case(expected) {
+            return Ok(());
         }
 
+        utils::print_warning(&format!("{} changed since it was backed up (SHA-256 differs)", archive));
+        if !force {
+            return Err(ErrorKind::Verification
+                .error(format!("{} no longer matches its recorded checksum; use --force to upload it anyway", archive)));
+        }
         Ok(())
     }
 
+    /// Shows the upload's size and target; archives over the configured
+    /// threshold need a yes from the user unless `assume_yes`.
+    fn confirm_upload(config: &Config, archive_path: &str, target: &str, assume_yes: bool) -> Result<bool> {
+        let size = fs::metadata(archive_path)?.len();
+        utils::print_info(&format!("📦 {} → {}", utils::format_bytes(size), target));
+
+        let proceed = Self::confirm_large_upload(size, config.large_upload_threshold, assume_yes, |prompt| {
+            Ok(Confirm::with_theme(&ColorfulTheme::default())
+                .with_prompt(prompt)
+                .default(false)
+                .interact()?)
+        })?;
+
+        if !proceed {
+            utils::print_info("Upload skipped");
+        }
+        Ok(proceed)
+    }
+
+    fn confirm_large_upload(
+        size: u64,
+        threshold: u64,
+        assume_yes: bool,
+        confirm: impl FnOnce(&str) -> Result<bool>,
+    ) -> Result<bool> {
+        if assume_yes || size <= threshold {
+            return Ok(true);
+        }
+
+        confirm(&format!(
+            "Archive is {} (over {}). Upload anyway?",
+            utils::format_bytes(size),
+            utils::format_bytes(threshold)
+        ))
+    }
+
+    /// Guards against picking the wrong folder (a whole home directory, say):
+    /// sources over the configured file count or size need a confirmation
+    /// unless `--force` is given.
+    fn confirm_large_source(
+        info: &FolderInfo,
+        config: &Config,
+        force: bool,
+        confirm: impl FnOnce(&str) -> Result<bool>,
+    ) -> Result<bool> {
+        let mut over = Vec::new();
+        if info.file_count > config.warn_file_count {
+            over.push(format!(
+                "{} files (over {})",
+                utils::format_number(info.file_count),
+                utils::format_number(config.warn_file_count)
+            ));
+        }
+        if info.total_size > config.warn_total_size {
+            over.push(format!(
+                "{} (over {})",
+                utils::format_bytes(info.total_size),
+                utils::format_bytes(config.warn_total_size)
+            ));
+        }
+
+        if over.is_empty() {
+            return Ok(true);
+        }
+        if force {
+            utils::print_warning(&format!("Source is unusually large: {}", over.join(", ")));
+            return Ok(true);
+        }
+
+        confirm(&format!("Source has {}. Is this the right folder?", over.join(" and ")))
+    }
+
+    /// Runs `upload`, first re-hashing the archive when `verify` is set and
+    /// refusing to upload one that no longer matches its sidecar, or failing
+    /// that, `expected_sha256`.
+    fn upload_verified(
+        verify: bool,
+        archive: &str,
+        meta_dir: Option<&std::path::Path>,
+        expected_sha256: Option<&str>,
+        upload: impl FnOnce() -> Result<()>,
+    ) -> Result<()> {
+        if verify {
+            utils::print_info("🔍 Re-checking archive before upload...");
+            let sidecar = Checker::sidecar_path(archive, meta_dir, HashAlgorithm::Sha256.sidecar_extension());
+            let intact = if std::path::Path::new(&sidecar).exists() {
+                Checker::verify_from_checksum_file(archive, meta_dir)?
+            } else if let Some(expected) = expected_sha256 {
+                Checker::generate_checksum(archive)?.eq_ignore_ascii_case(expected)
+            } else {
+                return Err(ErrorKind::Verification.error(format!(
+                    "No checksum to check {} against before upload; enable generate_checksum_file",
+                    archive
+                )));
+            };
+
+            if !intact {
+                return Err(ErrorKind::Verification
+                    .error(format!("{} no longer matches its checksum, not uploading it", archive)));
+            }
+            utils::print_success("✓ Archive intact, uploading");
+        }
+
+        upload()
+    }
+
+    /// `--compare-remote`: true when `destination` already holds the archive
+    /// with this SHA-256, so the upload can be skipped. Any doubt means upload.
+    fn already_on_remote(
+        archive: &str,
+        destination: &RemoteDestination,
+        checksum: &str,
+        remote_has: impl FnOnce(&str, &str, &str, &str) -> Result<bool>,
+    ) -> bool {
+        let RemoteDestination::Rclone { remote, path } = destination else {
+            utils::print_warning("--compare-remote only works with rclone destinations, uploading anyway");
+            return false;
+        };
+        let name = std::path::Path::new(archive)
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or(archive);
+
+        match remote_has(remote, path, name, checksum) {
+            Ok(true) => {
+                utils::print_success(&format!("{} already present on {}, skipping upload", name, destination.target()));
+                true
+            }
+            Ok(false) => false,
+            Err(e) => {
+                utils::print_warning(&format!("Couldn't compare with remote: {}. Uploading anyway", e));
+                false
+            }
+        }
+    }
+
+    fn upload_to_destination(
+        archive: &str,
+        destination: &RemoteDestination,
+        rclone_extra_args: &[String],
+        cancel: &CancelToken,
+    ) -> Result<()> {
+        match destination {
+            RemoteDestination::Rclone { remote, path } => {
+                RemoteTransfer::upload_to_rclone(archive, remote, path, rclone_extra_args, cancel)
+            }
+            RemoteDestination::Database {
+                host,
+                port,
+                username,
+                database,
+                table,
+            } => {
+                let password = Password::with_theme(&ColorfulTheme::default())
+                    .with_prompt("Database password")
+                    .interact()?;
+                RemoteTransfer::upload_to_database(archive, host, *port, username, &password, database, table)
+            }
+            RemoteDestination::S3 { bucket, prefix } => RemoteTransfer::upload_to_s3(archive, bucket, prefix, cancel),
+            RemoteDestination::Sftp { .. } => RemoteTransfer::upload_to_sftp(archive, destination, cancel),
+        }
+    }
+
+    /// Backup destinations from the config, marking the one auto-upload uses.
+    fn print_remote_catalog(config: &Config) {
+        utils::print_info("🗂️  Backup destinations (from config):");
+
+        let Some(ref remote) = config.remote else {
+            utils::print_warning("No remote destinations configured");
+            return;
+        };
+
+        let destinations = RemoteDestination::all_from_config(remote);
+        if destinations.is_empty() {
+            utils::print_warning("No remote destinations configured");
+            return;
+        }
+
+        for (index, destination) in destinations.iter().enumerate() {
+            let marker = if index == 0 && remote.enabled && remote.auto_upload {
+                "  (default for auto-upload)"
+            } else {
+                ""
+            };
+            utils::print_line(&format!("  • [{}] {}{}", destination.kind(), destination.target(), marker));
+        }
+
+        if !remote.enabled {
+            utils::print_warning("Remote uploads are disabled in the config");
+        }
+    }
+
     fn run_remote(&self, action: &RemoteAction) -> Result<()> {
         match action {
             RemoteAction::List => {
+                let config = Config::load()?;
+                Self::print_remote_catalog(&config);
+
+                utils::print_line("");
                 if !RemoteTransfer::check_rclone_installed()? {
                     utils::print_error("Rclone is not installed");
                     utils::print_info("Install: https://rclone.org/downloads/");
                     return Ok(());
                 }
 
-                utils::print_info("📡 Configured remotes:");
-                let remotes = RemoteTransfer::list_rclone_remotes()?;
+                utils::print_info("📡 Remotes known to rclone (rclone listremotes):");
+                let remotes = RemoteTransfer::list_rclone_remotes(config.rclone_extra_args())?;
 
                 if remotes.is_empty() {
                     utils::print_warning("No remotes configured");
                     utils::print_info("Run: rclone config");
                 } else {
                     for remote in remotes {
-                        println!("  • {}", remote);
+                        utils::print_line(&format!("  • {}", remote));
                     }
                 }
                 Ok(())
@@ -732,135 +2570,748 @@ impl Cli {
                     return Ok(());
                 }
 
-                RemoteTransfer::test_rclone_connection(remote)?;
-                Ok(())
+                RemoteTransfer::test_rclone_connection(remote, Config::load()?.rclone_extra_args())?;
+                Ok(())
+            }
+        }
+    }
+
+    fn run_profile(&self, action: &ProfileAction) -> Result<()> {
+        let mut config = Config::load()?;
+
+        match action {
+            ProfileAction::Save { name } => {
+                let profile = BackupProfile::load_last()?
+                    .context("No backup has been run yet, nothing to save")?;
+
+                config.profiles.insert(name.clone(), profile.clone());
+                config.save()?;
+
+                utils::print_success(&format!("Saved profile '{}'", name));
+                Self::print_profile(&profile);
+            }
+            ProfileAction::List => {
+                if config.profiles.is_empty() {
+                    utils::print_warning("No profiles saved");
+                    utils::print_info("Run a backup, then: zencore profile save <name>");
+                    return Ok(());
+                }
+
+                let mut names: Vec<&String> = config.profiles.keys().collect();
+                names.sort();
+                for name in names {
+                    utils::print_header(name);
+                    Self::print_profile(&config.profiles[name]);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn print_profile(profile: &BackupProfile) {
+        let fields = [
+            ("Source", profile.source.clone()),
+            ("Destination", profile.destination.clone()),
+            ("Algorithm", profile.algorithm.clone()),
+            ("Level", profile.level.clone()),
+            ("Threads", profile.threads.map(|t| t.to_string())),
+            ("Encrypt", profile.encrypt.map(|e| e.to_string())),
+            ("Upload", profile.upload.map(|u| u.to_string())),
+            ("Remote", profile.remote.clone()),
+            ("Checksums", profile.checksums.as_ref().map(|c| c.join(","))),
+            ("Exclude", (!profile.exclude.is_empty()).then(|| profile.exclude.join(", "))),
+        ];
+
+        for (label, value) in fields {
+            if let Some(value) = value {
+                utils::print_line(&format!("  {}: {}", label, value));
+            }
+        }
+    }
+
+    fn run_list(&self, source: Option<&str>, since: Option<i64>) -> Result<()> {
+        let archives = StateTracker::search(source, since)?;
+
+        if archives.is_empty() && (source.is_some() || since.is_some()) {
+            utils::print_warning(&match source {
+                Some(query) => format!("No archives found from source '{}'", query),
+                None => "No archives found in that time range".to_string(),
+            });
+            return Ok(());
+        }
+
+        if archives.is_empty() {
+            utils::print_warning("No archives found. Create one with 'zencore backup'");
+            return Ok(());
+        }
+
+        utils::print_header("Available Archives");
+
+        let widths = [35, 20, 15, 10];
+        utils::print_table_header(&["Name", "Created", "Size", "Files"], &widths);
+
+        for archive in archives {
+            let size_mb = archive.size_bytes as f64 / 1_048_576.0;
+            let created = archive.created_at.split('T').next().unwrap_or("unknown");
+
+            utils::print_line(&format!(
+                "{:<35} {:<20} {:>10.2} MB {:>10}",
+                utils::truncate_string(&archive.name, 35),
+                created,
+                size_mb,
+                archive.file_count
+            ));
+        }
+
+        utils::print_line("");
+        Ok(())
+    }
+
+    fn run_contents(&self, archive: &str) -> Result<()> {
+        let listing = Self::reader_for(std::path::Path::new(archive), None)?.list_contents_report()?;
+        let entries = &listing.entries;
+
+        utils::print_header(&format!("Contents of {}", archive));
+        for entry in entries {
+            let lock = if entry.encrypted { "🔒 " } else { "" };
+            utils::print_line(&format!("  {:>10}  {}{}", utils::format_bytes(entry.size), lock, entry.name));
+        }
+
+        let total: u64 = entries.iter().map(|entry| entry.size).sum();
+        utils::print_line("");
+        utils::print_info(&format!(
+            "{} file(s), {}",
+            utils::format_number(entries.len()),
+            utils::format_bytes(total)
+        ));
+        if listing.unreadable > 0 {
+            utils::print_warning(&format!("{} damaged entries could not be read", listing.unreadable));
+        }
+        if listing.truncated {
+            utils::print_warning("The archive breaks off after these entries; the rest could not be read");
+        }
+        Ok(())
+    }
+
+    fn run_decompress(
+        &self,
+        archive: &str,
+        output: &Option<String>,
+        password_stdin: bool,
+        password_from_env: Option<&str>,
+        zstd_dict: Option<&str>,
+        include: &[String],
+    ) -> Result<()> {
+        let archive_path = std::path::Path::new(archive);
+        let output = match output {
+            Some(dir) => PathUtils::expand_path(dir)?,
+            None => {
+                let file_name = archive_path.file_name().and_then(|n| n.to_str()).context("Invalid archive path")?;
+                let stem = Algorithm::from_archive_path(archive_path)
+                    .and_then(|algo| file_name.strip_suffix(&format!(".{}", algo.extension())))
+                    .unwrap_or(file_name);
+                stem.to_string()
+            }
+        };
+
+        let zstd_dict = zstd_dict.map(PathUtils::expand_path).transpose()?;
+        let mut reader = Self::reader_for(archive_path, zstd_dict.as_deref())?.with_includes(include.to_vec());
+        if reader.is_encrypted()? {
+            let password = if let Some(name) = password_from_env {
+                PasswordPolicy::new(0).read_from_env(name)?
+            } else if password_stdin {
+                PasswordPolicy::new(0).read_from(std::io::stdin().lock())?
+            } else {
+                Password::with_theme(&ColorfulTheme::default())
+                    .with_prompt("Archive password")
+                    .interact()?
+            };
+            reader = reader.with_password(password);
+        } else if password_stdin || password_from_env.is_some() {
+            utils::print_info("Archive is not encrypted, no password needed");
+        }
+
+        utils::print_info(&format!("📦 Extracting to {}...", output));
+        let summary = reader.extract(std::path::Path::new(&output))?;
+        if include.is_empty() {
+            utils::print_success(&format!("Extracted {} file(s) to {}", utils::format_number(summary.extracted), output));
+        } else {
+            utils::print_success(&format!(
+                "Extracted {} of {} file(s) to {}",
+                utils::format_number(summary.extracted),
+                utils::format_number(summary.total),
+                output
+            ));
+        }
+        Ok(())
+    }
+
+    /// A reader for `archive` with the zstd dictionary it needs: `zstd_dict`
+    /// if given, else the one the catalog recorded for it.
+    fn reader_for(archive: &std::path::Path, zstd_dict: Option<&str>) -> Result<ArchiveReader> {
+        let recorded_dict = || -> Option<String> {
+            StateTracker::load().ok()?.find_archive_file(archive)?.zstd_dictionary.clone()
+        };
+        let reader = ArchiveReader::new(archive);
+        Ok(match zstd_dict.map(str::to_string).or_else(recorded_dict) {
+            Some(dict_path) => reader.with_zstd_dictionary(dictionary::load(std::path::Path::new(&dict_path))?),
+            None => reader,
+        })
+    }
+
+    fn run_train_dict(&self, source: &str, output: &str, size: u64) -> Result<()> {
+        let source = PathUtils::expand_path(source)?;
+        let output = PathUtils::expand_path(output)?;
+        if !std::path::Path::new(&source).is_dir() {
+            return Err(ErrorKind::Usage.error(format!("Source folder not found: {}", source)));
+        }
+
+        utils::print_info(&format!("📚 Training a zstd dictionary from {}...", source));
+        let trained = dictionary::train(std::path::Path::new(&source), size as usize)?;
+        fs::write(&output, &trained).with_context(|| format!("Failed to write {}", output))?;
+
+        utils::print_success(&format!(
+            "Wrote {} dictionary {} (id {})",
+            utils::format_bytes(trained.len() as u64),
+            output,
+            dictionary::dictionary_id(&trained)
+        ));
+        utils::print_info(&format!("Use it with: zencore backup --zstd-dict {}", output));
+        Ok(())
+    }
+
+    fn run_analyze_dupes(&self, source: &str, similarity: f64) -> Result<()> {
+        let source = PathUtils::expand_path(source)?;
+        if !std::path::Path::new(&source).is_dir() {
+            return Err(ErrorKind::Usage.error(format!("Source folder not found: {}", source)));
+        }
+        if !(0.0..=1.0).contains(&similarity) {
+            return Err(ErrorKind::Usage.error("--similarity must be between 0 and 1"));
+        }
+
+        let symlinks = Config::load().map(|config| config.symlinks).unwrap_or_default();
+        utils::print_info(&format!("🔎 Looking for duplicate tracks in {}...", source));
+        let groups = dupes::find_duplicates(std::path::Path::new(&source), symlinks, similarity)?;
+        if groups.is_empty() {
+            utils::print_success("No likely duplicates found");
+            return Ok(());
+        }
+
+        for group in &groups {
+            utils::print_line(&format!("\n{} ({})", group.name, utils::format_bytes(group.total_size())));
+            for (path, size) in &group.files {
+                utils::print_line(&format!("  {:>10}  {}", utils::format_bytes(*size), path.display()));
+            }
+        }
+        utils::print_line("");
+        utils::print_info(&format!(
+            "{} likely duplicate group(s); use --exclude or --exclude-extension to leave copies out of a backup",
+            groups.len()
+        ));
+        Ok(())
+    }
+
+    fn run_import_state(&self, file: &str, strategy: MergeStrategy) -> Result<()> {
+        let imported = StateTracker::read_catalog(std::path::Path::new(&PathUtils::expand_path(file)?))?;
+        let report = StateTracker::update(|state| state.merge(imported, strategy))?;
+
+        for (original, renamed) in &report.renamed {
+            utils::print_info(&format!("{} imported as {}", original, renamed));
+        }
+        utils::print_success(&format!(
+            "Imported {} new, {} replaced, {} kept local, {} already present",
+            report.added + report.renamed.len(),
+            report.replaced,
+            report.kept,
+            report.unchanged
+        ));
+        Ok(())
+    }
+
+    fn run_repair_state(&self, scan_dir: Option<&str>, apply: bool) -> Result<()> {
+        let scan_dir = scan_dir.map(PathUtils::expand_path).transpose()?.map(std::path::PathBuf::from);
+        // Reading untracked archives hashes them, so do it before taking the lock.
+        let mut state = StateTracker::load()?;
+        let untracked = match scan_dir {
+            Some(ref dir) => state.read_untracked(dir)?,
+            None => Vec::new(),
+        };
+        let report = if apply {
+            StateTracker::update(|state| state.repair(scan_dir.as_deref(), untracked))??
+        } else {
+            state.repair(scan_dir.as_deref(), untracked)?
+        };
+
+        for name in &report.unreachable {
+            utils::print_info(&format!("{} left as is, its folder is missing (unmounted drive?)", name));
+        }
+        if report.is_empty() {
+            utils::print_success("Catalog matches the disk, nothing to repair");
+            return Ok(());
+        }
+
+        for name in &report.removed {
+            utils::print_line(&format!("  - {} (file missing)", name));
+        }
+        for (name, path) in &report.relocated {
+            utils::print_line(&format!("  ~ {} → {}", name, path));
+        }
+        for (name, before, after) in &report.resized {
+            utils::print_line(&format!(
+                "  ~ {} size {} → {}",
+                name,
+                utils::format_bytes(*before),
+                utils::format_bytes(*after)
+            ));
+        }
+        for name in &report.imported {
+            utils::print_line(&format!("  + {} (untracked)", name));
+        }
+
+        let summary = format!(
+            "{} removed, {} relocated, {} resized, {} imported",
+            report.removed.len(),
+            report.relocated.len(),
+            report.resized.len(),
+            report.imported.len()
+        );
+        if apply {
+            utils::print_success(&format!("Catalog repaired: {}", summary));
+        } else {
+            utils::print_info(&format!("Would repair: {} (run with --apply to save)", summary));
+        }
+        Ok(())
+    }
+
+    fn run_prune(&self, keep: usize, dry_run: bool, delete_files_in: &Option<String>) -> Result<()> {
+        let delete_dir = delete_files_in.as_deref().map(PathUtils::expand_path).transpose()?;
+
+        // A dry run prunes a copy of the catalog that is never saved.
+        let prune = |state: &mut StateTracker| -> Vec<ArchiveMetadata> {
+            let names: Vec<String> = state.prune_per_source(keep).into_iter().map(|a| a.name.clone()).collect();
+            names.iter().filter_map(|name| state.remove_archive(name)).collect()
+        };
+        let mut pruned = if dry_run { prune(&mut StateTracker::load()?) } else { StateTracker::update(prune)? };
+        pruned.sort_by(|a, b| a.source_label().cmp(&b.source_label()).then_with(|| b.created_at.cmp(&a.created_at)));
+
+        if pruned.is_empty() {
+            utils::print_success(&format!("Nothing to prune, every source has at most {} archive(s)", keep));
+            return Ok(());
+        }
+
+        utils::print_info(&format!("Keeping the newest {} archive(s) per source; removing:", keep));
+        for archive in &pruned {
+            utils::print_line(&format!("  • {} ({})", archive.name, archive.source_label().unwrap_or_default()));
+        }
+
+        if dry_run {
+            utils::print_info("Dry run, nothing removed");
+            return Ok(());
+        }
+
+        if let Some(ref dir) = delete_dir {
+            for archive in &pruned {
+                let archive_path = std::path::Path::new(dir).join(archive.file_name()).to_string_lossy().to_string();
+                let sidecars = HashAlgorithm::all()
+                    .into_iter()
+                    .map(|algo| Checker::sidecar_path(&archive_path, archive.meta_dir(), algo.sidecar_extension()))
+                    .chain([Checker::manifest_path(&archive_path, archive.meta_dir()), archive_path.clone()]);
+
+                for path in sidecars {
+                    match fs::remove_file(&path) {
+                        Ok(()) => utils::print_info(&format!("Deleted {}", path)),
+                        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                        Err(e) => utils::print_warning(&format!("Could not delete {}: {}", path, e)),
+                    }
+                }
+            }
+        }
+
+        utils::print_success(&format!("Pruned {} archive(s) from the catalog", pruned.len()));
+        Ok(())
+    }
+
+    fn run_show(&self, name: &str, verify: bool, filter: Option<&ContentFilter>, page: ContentsPage) -> Result<()> {
+        let state = StateTracker::load()?;
+        let archive = state
+            .get_archive(name)
+            .ok_or_else(|| ErrorKind::Usage.error("Archive not found in state"))?;
+
+        utils::print_header(&format!("Archive Details: {}", archive.name));
+        if archive.file_name() != archive.name {
+            utils::print_line(&format!("File:       {}", archive.file_name()));
+        }
+
+        utils::print_line(&format!("Created:    {}", archive.created_at));
+        if !archive.source.is_empty() {
+            utils::print_line(&format!("Source:     {}", archive.source));
+        }
+        utils::print_line(&format!("Algorithm:  {}", archive.algorithm));
+
+        let checksums = archive.list_checksums();
+        if !checksums.is_empty() {
+            utils::print_line("Checksums:");
+            for (algo, hash) in checksums {
+                utils::print_line(&format!("  {} = {}", algo, hash));
+            }
+        }
+
+        utils::print_line(&format!("Size:       {}", utils::format_bytes(archive.size_bytes)));
+        utils::print_line(&format!("Files:      {}", utils::format_number(archive.file_count)));
+        utils::print_line(&format!(
+            "Encrypted:  {}",
+            if archive.encrypted { "Yes" } else { "No" }
+        ));
+        match Self::encryption_on_disk(archive) {
+            Ok(Some(actual)) if actual != archive.encrypted => utils::print_warning(&format!(
+                "The file on disk is {}, unlike the catalog says",
+                if actual { "encrypted" } else { "not encrypted" }
+            )),
+            Ok(Some(_)) => {}
+            Ok(None) => utils::print_info("Archive file not found, encryption not checked on disk"),
+            Err(e) => utils::print_warning(&format!("Could not check encryption on disk: {:#}", e)),
+        }
+
+        match archive.contents_mode {
+            ContentsMode::None => {
+                utils::print_line("\n📄 File list not stored (contents = none)");
+            }
+            ContentsMode::Summary => {
+                utils::print_line(&format!(
+                    "\n📄 Contents by folder ({} folders):\n",
+                    archive.contents_summary.len()
+                ));
+                for (folder, count) in &archive.contents_summary {
+                    utils::print_line(&format!("  {:<40} {:>8} files", folder, utils::format_number(*count)));
+                }
+            }
+            ContentsMode::Full => match filter {
+                Some(filter) => {
+                    let matches = filter.apply(&archive.contents);
+                    utils::print_line(&format!(
+                        "\n📄 {} of {} files match {:?}:\n",
+                        matches.len(),
+                        archive.contents.len(),
+                        filter.pattern
+                    ));
+                    for (i, file) in matches.iter().enumerate() {
+                        utils::print_line(&format!("  {}. {}", i + 1, file));
+                    }
+                }
+                None => {
+                    utils::print_line(&format!("\n📄 Contents ({} files):\n", archive.contents.len()));
+
+                    let shown = page.range(archive.contents.len());
+                    for (i, file) in archive.contents[shown.clone()].iter().enumerate() {
+                        utils::print_line(&format!("  {}. {}", shown.start + i + 1, file));
+                    }
+
+                    if shown.end < archive.contents.len() {
+                        utils::print_line(&format!(
+                            "\n  ... and {} more files (--offset {} for the next page, --all for everything)",
+                            archive.contents.len() - shown.end,
+                            shown.end
+                        ));
+                    }
+                }
+            },
+        }
+        if filter.is_some() && archive.contents_mode != ContentsMode::Full {
+            utils::print_warning("--grep needs the full file list, which this archive's catalog entry doesn't keep");
+        }
+
+        utils::print_line("");
+
+        if verify {
+            let Some(path) = archive.archive_path().filter(|path| path.is_file()) else {
+                utils::print_warning("Archive file not found, nothing to verify");
+                return Ok(());
+            };
+
+            let failed: Vec<String> = archive
+                .verify_against(path)?
+                .into_iter()
+                .filter_map(|(algo, passed)| (!passed).then_some(algo))
+                .collect();
+            if !failed.is_empty() {
+                utils::print_error(&format!("Verify: FAIL ({})", failed.join(", ")));
+                return Err(ErrorKind::Verification.error(format!("{} no longer matches its stored checksums", path.display())));
+            }
+            utils::print_success("Verify: PASS");
+        }
+
+        Ok(())
+    }
+
+    /// Whether the archive file itself is encrypted, or None if it can't be
+    /// found.
+    fn encryption_on_disk(archive: &ArchiveMetadata) -> Result<Option<bool>> {
+        let Some(path) = archive.archive_path().filter(|path| path.is_file()) else {
+            return Ok(None);
+        };
+        ArchiveReader::new(path).is_encrypted().map(Some)
+    }
+
+    fn run_checksum(&self, file: &str, algorithms: &[String], write: bool) -> Result<()> {
+        if !std::path::Path::new(file).is_file() {
+            return Err(ErrorKind::Usage.error(format!("File not found: {}", file)));
+        }
+
+        let algorithms = algorithms
+            .iter()
+            .map(|a| HashAlgorithm::from_str(a))
+            .collect::<Result<Vec<_>>>()?;
+
+        for (algo, hash) in Checker::generate_multiple_checksums(file, &algorithms)? {
+            utils::print_success(&format!("{}: {}", algo.name(), hash));
+
+            if write {
+                Checker::write_checksum_file(file, None, algo, &hash)?;
             }
         }
+
+        Ok(())
     }
 
-    fn run_list(&self) -> Result<()> {
+    fn run_whereis(&self, file: &str) -> Result<()> {
+        if !std::path::Path::new(file).is_file() {
+            return Err(ErrorKind::Usage.error(format!("File not found: {}", file)));
+        }
+
+        let checksum = Checker::generate_checksum(file)?;
         let state = StateTracker::load()?;
-        let archives = state.list_archives();
 
-        if archives.is_empty() {
-            utils::print_warning("No archives found. Create one with 'zencore backup'");
+        let Some(archive) = state.find_by_checksum(&checksum) else {
+            utils::print_warning(&format!("Not tracked: no catalog entry has SHA-256 {}", checksum));
             return Ok(());
-        }
+        };
 
-        utils::print_header("Available Archives");
+        utils::print_success(&format!("{} is a catalogued archive", file));
+        utils::print_summary(&[
+            ("Name", archive.name.clone()),
+            ("Created", archive.created_at.clone()),
+            ("Source", if archive.source.is_empty() { "unknown".to_string() } else { archive.source.clone() }),
+            ("Algorithm", archive.algorithm.clone()),
+        ]);
 
-        let widths = [35, 20, 15, 10];
-        utils::print_table_header(&["Name", "Created", "Size", "Files"], &widths);
+        Ok(())
+    }
 
-        for archive in archives {
-            let size_mb = archive.size_bytes as f64 / 1_048_576.0;
-            let created = archive.created_at.split('T').next().unwrap_or("unknown");
+    fn run_bench(&self, source: &str, sample_size: u64) -> Result<()> {
+        let source = PathUtils::expand_path(source)?;
+        let source_path = std::path::Path::new(&source);
+        if !source_path.is_dir() {
+            return Err(anyhow::anyhow!("Source folder not found: {}", source));
+        }
 
-            println!(
-                "{:<35} {:<20} {:>10.2} MB {:>10}",
-                utils::truncate_string(&archive.name, 35),
-                created,
-                size_mb,
-                archive.file_count
-            );
+        let config = Config::load()?;
+        let total = Fuzzer::estimate_size(&source, config.symlinks)?;
+        utils::print_info(&format!(
+            "⏱️  Benchmarking on up to {} of {}...",
+            utils::format_bytes(sample_size.min(total)),
+            utils::format_bytes(total)
+        ));
+
+        let rows = bench::run(source_path, &bench::default_combos(), sample_size, &std::env::temp_dir())?;
+
+        utils::print_header("Benchmark");
+        utils::print_line(&format!("  {:<10} {:>5} {:>12} {:>7} {:>9} {:>14}", "Algorithm", "Level", "Sample out", "Ratio", "Time", "Projected"));
+        for row in &rows {
+            utils::print_line(&format!(
+                "  {:<10} {:>5} {:>12} {:>6.1}% {:>8.2}s {:>14}",
+                row.algorithm.as_str(),
+                row.level,
+                utils::format_bytes(row.output_bytes),
+                row.ratio() * 100.0,
+                row.duration.as_secs_f64(),
+                utils::format_bytes((total as f64 * row.ratio()) as u64)
+            ));
+        }
+
+        if let Some(best) = bench::recommend(&rows) {
+            utils::print_success(&format!(
+                "Recommended: --algorithm {} --level {} (smallest output within 2x of the fastest time)",
+                best.algorithm.as_str(),
+                best.level
+            ));
         }
 
-        println!();
         Ok(())
     }
 
-    fn run_show(&self, name: &str) -> Result<()> {
-        let state = StateTracker::load()?;
-        let archive = state
-            .get_archive(name)
-            .context("Archive not found in state")?;
+    /// The archive's content manifest: its sidecar, else the catalog's copy.
+    fn content_manifest(archive: &str) -> Result<Option<std::collections::BTreeMap<String, String>>> {
+        let meta_dir = Self::stored_meta_dir(archive)?;
+        let manifest_path = Checker::manifest_path(archive, meta_dir.as_deref());
+        if std::path::Path::new(&manifest_path).exists() {
+            utils::print_info(&format!("Using manifest: {}", manifest_path));
+            return Checker::read_manifest(&manifest_path).map(Some);
+        }
 
-        utils::print_header(&format!("Archive Details: {}", archive.name));
+        Ok(StateTracker::load()?
+            .find_archive_file(std::path::Path::new(archive))
+            .map(|m| m.content_checksums.clone())
+            .filter(|c| !c.is_empty()))
+    }
 
-        println!("Created:    {}", archive.created_at);
-        println!("Algorithm:  {}", archive.algorithm);
+    /// Checks each file inside the archive against its content manifest,
+    /// with one progress step per file. `stop_early` ends at the first
+    /// corrupted file.
+    fn run_verify_contents(
+        &self,
+        archive: &str,
+        manifest: &std::collections::BTreeMap<String, String>,
+        stop_early: bool,
+    ) -> Result<()> {
+        utils::print_info("🔍 Verifying archive contents file by file...");
+
+        let pb = utils::progress_bar(manifest.len() as u64);
+        pb.set_style(
+            indicatif::ProgressStyle::default_bar()
+                .template("{spinner:.green} [{bar:40.cyan/blue}] {pos}/{len} files ({eta}) {msg}")
+                .unwrap()
+                .progress_chars("█▓░"),
+        );
+        let reader = Self::reader_for(std::path::Path::new(archive), None)?;
+        let report = reader.verify_contents_streaming(manifest, stop_early, |name, _| {
+            pb.set_message(name.to_string());
+            pb.inc(1);
+        })?;
+        pb.finish_and_clear();
+
+        for name in &report.corrupted {
+            utils::print_error(&format!("✗ Corrupted: {}", name));
+        }
+        for name in &report.missing {
+            utils::print_error(&format!("✗ Missing: {}", name));
+        }
 
-        let checksums = archive.list_checksums();
-        if !checksums.is_empty() {
-            println!("Checksums:");
-            for (algo, hash) in checksums {
-                println!("  {} = {}", algo, hash);
-            }
+        if report.is_ok() {
+            utils::print_success(&format!("✓ All {} files match the manifest", report.checked));
+            Ok(())
+        } else if stop_early && !report.corrupted.is_empty() {
+            Err(ErrorKind::Verification.error(format!(
+                "Stopped at the first corrupted file, after checking {} of {} files",
+                report.checked,
+                manifest.len()
+            )))
+        } else {
+            Err(ErrorKind::Verification.error(format!(
+                "{} corrupted and {} missing of {} files",
+                report.corrupted.len(),
+                report.missing.len(),
+                manifest.len()
+            )))
         }
+    }
 
-        println!("Size:       {}", utils::format_bytes(archive.size_bytes));
-        println!("Files:      {}", utils::format_number(archive.file_count));
-        println!(
-            "Encrypted:  {}",
-            if archive.encrypted { "Yes" } else { "No" }
-        );
+    fn run_verify_all(&self, archive: &str) -> Result<()> {
+        let archive_name = std::path::Path::new(archive)
+            .file_name()
+            .and_then(|n| n.to_str())
+            .context("Invalid archive path")?;
+
+        let state = StateTracker::load()?;
+        let metadata = state
+            .find_archive_file(std::path::Path::new(archive))
+            .with_context(|| format!("{} is not in the catalog", archive_name))?;
 
-        println!("\n📄 Contents ({} files):\n", archive.contents.len());
+        utils::print_info(&format!("🔍 Re-checking {} stored checksum(s)...", metadata.list_checksums().len()));
+        let results = metadata.verify_against(std::path::Path::new(archive))?;
 
-        let limit = 50.min(archive.contents.len());
-        for (i, file) in archive.contents.iter().take(limit).enumerate() {
-            println!("  {}. {}", i + 1, file);
+        for (algo_name, passed) in &results {
+            if *passed {
+                utils::print_success(&format!("{}: pass", algo_name));
+            } else {
+                utils::print_error(&format!("{}: FAIL", algo_name));
+            }
         }
 
-        if archive.contents.len() > limit {
-            println!("\n  ... and {} more files", archive.contents.len() - limit);
+        let failed = results.iter().filter(|(_, passed)| !passed).count();
+        if failed > 0 {
+            return Err(ErrorKind::Verification.error(format!("{} of {} checksum(s) failed", failed, results.len())));
         }
 
-        println!();
+        utils::print_success("✓ All stored checksums match");
         Ok(())
     }
 
-    fn run_verify(&self, archive: &str, algorithm: &Option<String>) -> Result<()> {
-        utils::print_info("🔍 Verifying archive integrity...");
+    /// The `--meta-dir` recorded for this archive, if its sidecars were
+    /// written away from it.
+    fn stored_meta_dir(archive: &str) -> Result<Option<std::path::PathBuf>> {
+        let archive_name = std::path::Path::new(archive)
+            .file_name()
+            .and_then(|n| n.to_str())
+            .context("Invalid archive path")?;
 
-        let checksum_path = format!("{}.sha256", archive);
+        let state = StateTracker::load()?;
+        Ok(state
+            .get_archive(archive_name)
+            .and_then(|metadata| metadata.meta_dir())
+            .map(|dir| dir.to_path_buf()))
+    }
 
-        if std::path::Path::new(&checksum_path).exists() {
-            utils::print_info(&format!("Found checksum file: {}", checksum_path));
+    /// Checks `archive` against its `.sha256` sidecar in `meta_dir` (or next
+    /// to it). Returns false when there is no sidecar.
+    fn verify_sidecar(archive: &str, meta_dir: Option<&std::path::Path>) -> Result<bool> {
+        let checksum_path = Checker::sidecar_path(archive, meta_dir, "sha256");
+        if !std::path::Path::new(&checksum_path).exists() {
+            return Ok(false);
+        }
+        utils::print_info(&format!("Found checksum file: {}", checksum_path));
 
-            if Checker::verify_from_checksum_file(archive)? {
-                utils::print_success("✓ Checksum matches! Archive is intact.");
-            } else {
-                utils::print_error("✗ Checksum mismatch! Archive may be corrupted.");
-                return Err(anyhow::anyhow!("Checksum verification failed"));
-            }
+        if Checker::verify_from_checksum_file(archive, meta_dir)? {
+            utils::print_success("✓ Checksum matches! Archive is intact.");
+            Ok(true)
         } else {
-            let algo = if let Some(algo_str) = algorithm {
-                HashAlgorithm::from_str(algo_str)?
-            } else {
-                HashAlgorithm::Sha256
-            };
+            utils::print_error("✗ Checksum mismatch! Archive may be corrupted.");
+            Err(ErrorKind::Verification.error("Checksum verification failed"))
+        }
+    }
+
+    fn run_verify(&self, archive: &str, algorithm: &Option<String>) -> Result<()> {
+        utils::print_info("🔍 Verifying archive integrity...");
+
+        let meta_dir = Self::stored_meta_dir(archive)?;
+        if Self::verify_sidecar(archive, meta_dir.as_deref())? {
+            return Ok(());
+        }
+        let algo = if let Some(algo_str) = algorithm {
+            HashAlgorithm::from_str(algo_str)?
+        } else {
+            HashAlgorithm::Sha256
+        };
 
-            let checksum = Checker::generate_checksum_with_algorithm(archive, algo)?;
-            utils::print_success(&format!("{}: {}", algo.name(), checksum));
+        let checksum = Checker::generate_checksum_with_algorithm(archive, algo)?;
+        utils::print_success(&format!("{}: {}", algo.name(), checksum));
 
-            let archive_name = std::path::Path::new(archive)
-                .file_name()
-                .and_then(|n| n.to_str())
-                .context("Invalid archive path")?;
+        if !algo.is_cryptographic() {
+            utils::print_warning("This checksum detects corruption, not tampering");
+        }
 
-            let state = StateTracker::load()?;
-            if let Some(metadata) = state.get_archive(archive_name) {
-                if let Some(expected) = metadata.get_checksum(algo.name()) {
-                    if Checker::verify_checksum_with_algorithm(archive, expected, algo)? {
-                        utils::print_success(&format!("✓ {} matches state!", algo.name()));
-                    } else {
-                        utils::print_error(&format!("✗ {} mismatch with state!", algo.name()));
-                    }
+        let state = StateTracker::load()?;
+        if let Some(metadata) = state.find_archive_file(std::path::Path::new(archive)) {
+            if let Some(expected) = metadata.get_checksum(algo.key()) {
+                if Checker::hashes_match(&checksum, expected) {
+                    utils::print_success(&format!("✓ {} matches state!", algo.name()));
                 } else {
-                    utils::print_warning(&format!("No {} checksum in state", algo.name()));
+                    utils::print_error(&format!("✗ {} mismatch with state!", algo.name()));
+                    return Err(ErrorKind::Verification.error(format!("{} verification failed", algo.name())));
                 }
+            } else {
+                utils::print_warning(&format!("No {} checksum in state", algo.name()));
+            }
 
-                let all_checksums = metadata.list_checksums();
-                if !all_checksums.is_empty() {
-                    utils::print_info("\nAvailable checksums in state:");
-                    for (algo_name, hash) in all_checksums {
-                        println!("  {} = {}", algo_name, hash);
-                    }
+            let all_checksums = metadata.list_checksums();
+            if !all_checksums.is_empty() {
+                utils::print_info("\nAvailable checksums in state:");
+                for (algo_name, hash) in all_checksums {
+                    utils::print_line(&format!("  {} = {}", algo_name, hash));
                 }
-            } else {
-                utils::print_warning("No stored checksum found in state.");
             }
+        } else {
+            utils::print_warning("No stored checksum found in state.");
         }
 
         Ok(())
@@ -872,8 +3323,8 @@ impl Cli {
 
         utils::print_header("Configuration");
         
-        println!("Config file: {}", config_path.display());
-        println!("State dir:   {}", state_dir.display());
+        utils::print_line(&format!("Config file: {}", config_path.display()));
+        utils::print_line(&format!("State dir:   {}", state_dir.display()));
 
         if config_path.exists() {
             let config = Config::load()?;
@@ -885,6 +3336,8 @@ impl Cli {
                 ("Encrypt by default", config.encrypt_by_default.to_string()),
                 ("Generate checksum file", config.generate_checksum_file.to_string()),
                 ("Verify after backup", config.verify_after_backup.to_string()),
+                ("Compress state", config.compress_state.to_string()),
+                ("State backend", format!("{:?}", config.state_backend).to_lowercase()),
                 ("Compression level", 
                     config.compression_level.map(|l| l.to_string()).unwrap_or("auto".to_string())),
                 ("Threads", 
@@ -893,25 +3346,51 @@ impl Cli {
 
             if let Some(ref remote) = config.remote {
                 utils::print_info("\nRemote settings:");
-                println!("  Enabled: {}", remote.enabled);
-                println!("  Auto-upload: {}", remote.auto_upload);
+                utils::print_line(&format!("  Enabled: {}", remote.enabled));
+                utils::print_line(&format!("  Auto-upload: {}", remote.auto_upload));
 
                 if let Some(ref rclone) = remote.rclone {
-                    println!("  Rclone remote: {}:{}", rclone.remote_name, rclone.remote_path);
+                    utils::print_line(&format!("  Rclone remote: {}:{}", rclone.remote_name, rclone.remote_path));
                 }
 
                 if let Some(ref db) = remote.database {
-                    println!("  Database: {}:{}/{}", db.host, db.port, db.database);
+                    utils::print_line(&format!("  Database: {}:{}/{}", db.host, db.port, db.database));
                 }
             }
         } else {
             utils::print_warning("Config file doesn't exist yet. Will be created on first backup.");
         }
 
-        println!();
+        utils::print_line("");
         Ok(())
     }
 
+    /// The config Zencore actually runs with: the file at `config_path`
+    /// (or nothing, if it doesn't exist yet) with serde defaults filled in.
+    fn effective_config(config_path: &std::path::Path) -> Result<String> {
+        let config = if config_path.exists() { Config::load_from(config_path)? } else { Config::default() };
+        config.to_toml()
+    }
+
+    /// Applies one change to the config file and shows it as a diff. With
+    /// `dry_run` the file is left as it is.
+    fn run_config_set(config_path: &std::path::Path, key: &str, value: &str, dry_run: bool) -> Result<ConfigChange> {
+        let config = Config::load_from(config_path)?;
+        let (updated, change) = config.with_value(key, value)?;
+
+        utils::print_line(&format!("{}", format!("- {} = {}", key, change.before.as_deref().unwrap_or("(unset)")).red()));
+        utils::print_line(&format!("{}", format!("+ {} = {}", key, change.after).green()));
+
+        if dry_run {
+            utils::print_info("Dry run, config not changed");
+        } else {
+            updated.save_to(config_path)?;
+            utils::print_success(&format!("Updated {}", config_path.display()));
+        }
+
+        Ok(change)
+    }
+
     fn run_interactive(&self) -> Result<()> {
         let config = Config::load()?;
         
@@ -946,9 +3425,13 @@ impl Cli {
                     false
                 };
 
-                self.run_backup(&None, &None, &None, &None, encrypt, upload, None, 0, &None)
+                self.run_backup(&BackupArgs {
+                    encrypt,
+                    upload,
+                    ..Default::default()
+                })
             }
-            1 => self.run_list(),
+            1 => self.run_list(None, None),
             2 => {
                 let state = StateTracker::load()?;
                 let archives = state.list_archives();
@@ -965,7 +3448,7 @@ impl Cli {
                     .items(&names)
                     .interact()?;
 
-                self.run_show(&names[selection])
+                self.run_show(&names[selection], false, None, ContentsPage::default())
             }
             3 => {
                 utils::print_info("Enter archive path:");
@@ -973,7 +3456,7 @@ impl Cli {
                     .with_prompt("Archive")
                     .interact_text()?;
 
-                self.run_upload(&archive_path, &None)
+                self.run_upload(&archive_path, None, false, None, false)
             }
             4 => {
                 let remote_choices = vec!["List Remotes", "Test Connection", "Back"];
@@ -1000,6 +3483,14 @@ impl Cli {
         }
     }
 
+    /// Where `source` was last backed up to, if that folder still exists.
+    fn remembered_destination(state: &StateTracker, source: &str) -> Option<String> {
+        state
+            .last_destination(source)
+            .filter(|destination| std::path::Path::new(destination).is_dir())
+            .map(str::to_string)
+    }
+
     fn select_destination_interactive(config: &Config) -> Result<String> {
         utils::print_info("💾 Where do you want to save the backup?");
 
@@ -1014,7 +3505,7 @@ impl Cli {
                     .with_prompt("Destination folder")
                     .interact_text()?;
 
-                let expanded = shellexpand::tilde(&manual_path).to_string();
+                let expanded = PathUtils::expand_path(&manual_path)?;
 
                 if !std::path::Path::new(&expanded).exists() {
                     let create = Confirm::with_theme(&ColorfulTheme::default())
@@ -1026,7 +3517,7 @@ impl Cli {
                         fs::create_dir_all(&expanded)?;
                         utils::print_success(&format!("Created: {}", expanded));
                     } else {
-                        return Err(anyhow::anyhow!("Destination folder required"));
+                        return Err(ErrorKind::Usage.error("Destination folder required"));
                     }
                 }
 
@@ -1035,13 +3526,13 @@ impl Cli {
         }
     }
 
-    fn select_algorithm_interactive() -> Result<String> {
+    fn select_algorithm_interactive() -> Result<Algorithm> {
         utils::print_info("📦 Select compression algorithm:");
 
         let algorithms = vec![
-            ("tar.zst (Recommended)", "tar.zst", "⚡ Fast & High compression"),
-            ("tar.gz (Compatible)", "tar.gz", "🔧 Good compatibility"),
-            ("zip (Universal)", "zip", "🌍 Works everywhere"),
+            ("tar.zst (Recommended)", Algorithm::TarZst, "⚡ Fast & High compression"),
+            ("tar.gz (Compatible)", Algorithm::TarGz, "🔧 Good compatibility"),
+            ("zip (Universal)", Algorithm::Zip, "🌍 Works everywhere"),
         ];
 
         let choices: Vec<String> = algorithms
@@ -1055,6 +3546,710 @@ impl Cli {
             .default(0)
             .interact()?;
 
-        Ok(algorithms[selection].1.to_string())
+        Ok(algorithms[selection].1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_group_by_toplevel_archives_each_artist() -> Result<()> {
+        let source_dir = tempfile::tempdir()?;
+        let dest_dir = tempfile::tempdir()?;
+        for (artist, track) in [("Artist A", "01.flac"), ("Artist B", "01.flac")] {
+            fs::create_dir(source_dir.path().join(artist))?;
+            fs::write(source_dir.path().join(artist).join(track), artist)?;
+        }
+
+        let groups = Cli::toplevel_groups(source_dir.path(), &[])?;
+        assert_eq!(groups.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>(), ["Artist A", "Artist B"]);
+
+        let args = BackupArgs {
+            name: Some("music".to_string()),
+            ..Default::default()
+        };
+        let plan = BackupPlan {
+            algo: Algorithm::TarZst,
+            compression_level: None,
+            adaptive_level: false,
+            password: None,
+            resume_dir: None,
+            options: ResolvedBackupOptions::resolve(&Config::default(), None, &BackupArgs::default())?.0,
+            cancel: CancelToken::new(),
+            log: RunLog::default(),
+            zstd_dictionary: None,
+        };
+        let mut state = StateTracker::default();
+
+        let results = Cli::backup_groups(
+            &Config::default(),
+            &args,
+            &plan,
+            &[],
+            &groups,
+            dest_dir.path().to_str().unwrap(),
+            &mut state,
+        );
+
+        assert_eq!(results.len(), 2);
+        for (group, result) in results {
+            let metadata = result?;
+            assert_eq!(metadata.name, format!("music_{}.tar.zst", group));
+            assert_eq!(metadata.file_count, 1);
+            assert!(dest_dir.path().join(&metadata.name).exists());
+            assert!(state.get_archive(&metadata.name).is_some());
+        }
+        assert_eq!(state.list_archives().len(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_group_by_toplevel_applies_root_excludes() -> Result<()> {
+        let source_dir = tempfile::tempdir()?;
+        let dest_dir = tempfile::tempdir()?;
+        for artist in ["Artist A", "Artist B", "Bootlegs", "Podcasts"] {
+            fs::create_dir_all(source_dir.path().join(artist).join("Live"))?;
+            fs::write(source_dir.path().join(artist).join("01.flac"), artist)?;
+            fs::write(source_dir.path().join(artist).join("Live/01.flac"), artist)?;
+        }
+        fs::write(source_dir.path().join(crate::compress::IGNORE_FILE_NAME), "/Bootlegs/\n/Artist A/Live/\n")?;
+
+        // "/Podcasts/" is what an unticked folder in --interactive-exclude adds.
+        let args = BackupArgs {
+            name: Some("music".to_string()),
+            exclude: Cli::folder_excludes(&["Podcasts".to_string()], &[]),
+            ..Default::default()
+        };
+        let root_excludes = Cli::root_excludes(source_dir.path(), &args)?;
+        let groups = Cli::toplevel_groups(source_dir.path(), &root_excludes)?;
+        assert_eq!(groups.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>(), ["Artist A", "Artist B"]);
+
+        let plan = BackupPlan {
+            algo: Algorithm::TarZst,
+            compression_level: None,
+            adaptive_level: false,
+            password: None,
+            resume_dir: None,
+            options: ResolvedBackupOptions::resolve(&Config::default(), None, &BackupArgs::default())?.0,
+            cancel: CancelToken::new(),
+            log: RunLog::default(),
+            zstd_dictionary: None,
+        };
+        let results = Cli::backup_groups(
+            &Config::default(),
+            &args,
+            &plan,
+            &root_excludes,
+            &groups,
+            dest_dir.path().to_str().unwrap(),
+            &mut StateTracker::default(),
+        );
+
+        let file_counts: Vec<_> = results.into_iter().map(|(group, result)| Ok((group, result?.file_count))).collect::<Result<_>>()?;
+        assert_eq!(file_counts, [("Artist A".to_string(), 1), ("Artist B".to_string(), 2)]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_group_exclude_rewrites_root_patterns() {
+        assert_eq!(Cli::group_exclude("*.log", "Artist A").as_deref(), Some("*.log"));
+        assert_eq!(Cli::group_exclude("Scans/", "Artist A").as_deref(), Some("Scans/"));
+        assert_eq!(Cli::group_exclude("/Artist A/Live/", "Artist A").as_deref(), Some("/Live/"));
+        assert_eq!(Cli::group_exclude("!/Artist A/Live/keep.flac", "Artist A").as_deref(), Some("!/Live/keep.flac"));
+        assert_eq!(Cli::group_exclude("/Artist */Live", "Artist A").as_deref(), Some("/Live"));
+        assert_eq!(Cli::group_exclude("/**/Scans", "Artist A").as_deref(), Some("**/Scans"));
+        assert_eq!(Cli::group_exclude("/Artist B/Live/", "Artist A"), None);
+        assert_eq!(Cli::group_exclude("/Artist A/", "Artist A"), None);
+        assert_eq!(Cli::group_exclude("# comment", "Artist A"), None);
+    }
+
+    #[test]
+    fn test_stage_timings_add_up_to_total() -> Result<()> {
+        let source_dir = tempfile::tempdir()?;
+        let dest_dir = tempfile::tempdir()?;
+        for i in 0..20 {
+            fs::write(source_dir.path().join(format!("{:02}.flac", i)), vec![i as u8; 64 * 1024])?;
+        }
+
+        let plan = BackupPlan {
+            algo: Algorithm::TarGz,
+            compression_level: None,
+            adaptive_level: false,
+            password: None,
+            resume_dir: None,
+            options: ResolvedBackupOptions::resolve(&Config::default(), None, &BackupArgs::default())?.0,
+            cancel: CancelToken::new(),
+            log: RunLog::default(),
+            zstd_dictionary: None,
+        };
+
+        let start = Instant::now();
+        let outcome = Cli::archive_one(
+            &Config::default(),
+            &BackupArgs::default(),
+            &plan,
+            source_dir.path().to_str().unwrap(),
+            dest_dir.path().to_str().unwrap(),
+            "music.tar.gz".to_string(),
+        )?;
+        let total = start.elapsed();
+
+        let staged = outcome.timings.total();
+        assert!(staged <= total);
+        assert!(total - staged < Duration::from_millis(250).max(total / 10), "{:?} vs {:?}", staged, total);
+        assert!(!outcome.timings.compress.is_zero());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_profile_fills_unspecified_options() -> Result<()> {
+        let profile = BackupProfile {
+            source: Some("/music/lossless".to_string()),
+            destination: Some("/mnt/nas".to_string()),
+            algorithm: Some("tar.zst".to_string()),
+            level: Some("19".to_string()),
+            encrypt: Some(true),
+            exclude: vec!["*.log".to_string()],
+            ..Default::default()
+        };
+
+        let cli = Cli::try_parse_from(["zencore", "backup", "--profile", "lossless", "--algorithm", "zip"])?;
+        let Some(Commands::Backup(args)) = cli.command else {
+            panic!("expected the backup command");
+        };
+        let args = args.with_profile(&profile)?;
+
+        assert_eq!(args.algorithm.as_deref(), Some("zip"));
+        assert_eq!(args.source.as_deref(), Some("/music/lossless"));
+        assert_eq!(args.destination.as_deref(), Some("/mnt/nas"));
+        assert_eq!(args.level, Some(CompressionLevel::Fixed(19)));
+        assert!(args.encrypt);
+        assert_eq!(args.exclude, ["*.log"]);
+
+        let saved = args.to_profile("/music/lossless", "/mnt/nas", Algorithm::Zip);
+        assert_eq!(saved.algorithm.as_deref(), Some("zip"));
+        assert_eq!(saved.level.as_deref(), Some("19"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_adhoc_remote_flag_wins_over_configured_remote() -> Result<()> {
+        let profile = BackupProfile {
+            remote: Some("configured:Old".to_string()),
+            ..Default::default()
+        };
+        let parse = |flags: &[&str]| -> Result<BackupArgs> {
+            let cli = Cli::try_parse_from([&["zencore", "backup", "--upload"], flags].concat())?;
+            let Some(Commands::Backup(args)) = cli.command else {
+                panic!("expected the backup command");
+            };
+            args.with_profile(&profile)
+        };
+        let target = |args: BackupArgs| args.upload_destination().map(|d| d.map(|d| d.target()));
+
+        assert_eq!(target(parse(&[])?)?.as_deref(), Some("configured:Old"));
+        assert_eq!(target(parse(&["--rclone-remote", "gdrive:Backups"])?)?.as_deref(), Some("gdrive:Backups"));
+        assert_eq!(target(parse(&["--s3", "bucket/music"])?)?.as_deref(), Some("s3://bucket/music"));
+        assert_eq!(
+            target(parse(&["--sftp", "sftp://me@nas/backups"])?)?.as_deref(),
+            Some("sftp://me@nas/backups")
+        );
+        assert!(target(parse(&["--sftp", "s3://bucket"])?).is_err());
+        assert!(Cli::try_parse_from(["zencore", "backup", "--s3", "s3://bucket"]).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_options_layer_config_profile_and_flags() -> Result<()> {
+        let config = Config {
+            compression_level: Some(3),
+            num_threads: 2,
+            default_hash_algorithm: "blake3".to_string(),
+            contents_mode: ContentsMode::Summary,
+            ..Config::default()
+        };
+        let profile = BackupProfile {
+            level: Some("19".to_string()),
+            threads: Some(4),
+            ..Default::default()
+        };
+
+        let resolve = |flags: &[&str], profile: Option<&BackupProfile>| {
+            let cli = Cli::try_parse_from([&["zencore", "backup"], flags].concat()).unwrap();
+            let Some(Commands::Backup(args)) = cli.command else {
+                panic!("expected the backup command");
+            };
+            ResolvedBackupOptions::resolve(&config, profile, &args).map(|(options, _)| options)
+        };
+
+        let defaults = resolve(&[], None)?;
+        assert_eq!(defaults.level, Some(CompressionLevel::Fixed(3)));
+        assert_eq!(defaults.threads, 2);
+        assert_eq!(defaults.checksums, ["blake3"]);
+        assert_eq!(defaults.contents, ContentsMode::Summary);
+        assert_eq!(defaults.sort_order, SortOrder::SizeDesc);
+
+        let profiled = resolve(&[], Some(&profile))?;
+        assert_eq!(profiled.level, Some(CompressionLevel::Fixed(19)));
+        assert_eq!(profiled.threads, 4);
+        assert_eq!(profiled.checksums, ["blake3"]);
+
+        let flagged = resolve(&["--level", "9", "--threads", "8", "--contents", "none", "--no-sort"], Some(&profile))?;
+        assert_eq!(flagged.level, Some(CompressionLevel::Fixed(9)));
+        assert_eq!(flagged.threads, 8);
+        assert_eq!(flagged.contents, ContentsMode::None);
+        assert_eq!(flagged.sort_order, SortOrder::None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_config_set_dry_run_leaves_file_untouched() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let config_path = dir.path().join("config.toml");
+        Config::default().save_to(&config_path)?;
+        let original = fs::read_to_string(&config_path)?;
+
+        let change = Cli::run_config_set(&config_path, "compression_level", "19", true)?;
+        assert_eq!(change.before, None);
+        assert_eq!(change.after, "19");
+        assert_eq!(fs::read_to_string(&config_path)?, original);
+
+        let change = Cli::run_config_set(&config_path, "fuzzer.max_depth", "3", false)?;
+        assert_eq!(change.after, "3");
+        assert_eq!(Config::load_from(&config_path)?.fuzzer.max_depth, 3);
+
+        assert!(Cli::run_config_set(&config_path, "no_such_key", "1", true).is_err());
+        assert!(Cli::run_config_set(&config_path, "verify_after_backup", "maybe", true).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_config_dump_fills_in_defaults() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let config_path = dir.path().join("config.toml");
+        fs::write(&config_path, "compression_level = 19\n")?;
+
+        let dumped: toml::Table = toml::from_str(&Cli::effective_config(&config_path)?)?;
+        let defaults: toml::Table = toml::from_str(&Config::default().to_toml()?)?;
+        assert_eq!(dumped["compression_level"].as_integer(), Some(19));
+        for key in defaults.keys() {
+            assert!(dumped.contains_key(key), "{} missing from the dump", key);
+        }
+        assert_eq!(dumped["verify_after_backup"], defaults["verify_after_backup"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_deselected_folders_are_excluded() -> Result<()> {
+        let source_dir = tempfile::tempdir()?;
+        let dest_dir = tempfile::tempdir()?;
+        let folders = ["Albums", "Live [2019]", "Podcasts"].map(String::from);
+        for folder in &folders {
+            fs::create_dir(source_dir.path().join(folder))?;
+            fs::write(source_dir.path().join(folder).join("01.flac"), folder)?;
+        }
+
+        let excludes = Cli::folder_excludes(&folders, &[0]);
+        assert_eq!(excludes, ["/Live \\[2019\\]/", "/Podcasts/"]);
+
+        let result = Archiver::new(source_dir.path(), dest_dir.path(), "music.tar.zst".to_string(), Algorithm::TarZst)
+            .with_excludes(excludes)
+            .compress()?;
+        assert_eq!(result.files, ["Albums/01.flac"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_verbose_flag_is_counted() {
+        let cli = Cli::try_parse_from(["zencore", "-vv", "list"]).unwrap();
+        assert_eq!(cli.verbose, 2);
+
+        let cli = Cli::try_parse_from(["zencore", "backup", "-v", "--source", "/music"]).unwrap();
+        assert_eq!(cli.verbose, 1);
+    }
+
+    #[test]
+    fn test_large_upload_asks_for_confirmation() -> Result<()> {
+        let gb = 1024 * 1024 * 1024;
+        let mut prompts = Vec::new();
+
+        assert!(Cli::confirm_large_upload(gb / 2, gb, false, |_| unreachable!("small upload prompted"))?);
+        assert!(Cli::confirm_large_upload(3 * gb, gb, true, |_| unreachable!("--yes prompted"))?);
+
+        let proceed = Cli::confirm_large_upload(3 * gb, gb, false, |prompt| {
+            prompts.push(prompt.to_string());
+            Ok(false)
+        })?;
+        assert!(!proceed);
+        assert_eq!(prompts.len(), 1);
+        assert!(prompts[0].contains("Upload anyway?"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_password_from_env_encrypts_without_prompting() -> Result<()> {
+        let source_dir = tempfile::tempdir()?;
+        let dest_dir = tempfile::tempdir()?;
+        fs::write(source_dir.path().join("track.flac"), b"audio")?;
+
+        let args = BackupArgs {
+            encrypt: true,
+            password_from_env: Some("ZENCORE_TEST_BACKUP_PASSWORD".to_string()),
+            ..Default::default()
+        };
+        let err = Cli::select_encryption_with(&Config::default(), &args, Algorithm::TarZst, |_| {
+            Err(std::env::VarError::NotPresent)
+        })
+        .unwrap_err();
+        assert_eq!(err.to_string(), "Environment variable ZENCORE_TEST_BACKUP_PASSWORD is not set");
+
+        let password = Cli::select_encryption_with(&Config::default(), &args, Algorithm::TarZst, |name| {
+            assert_eq!(name, "ZENCORE_TEST_BACKUP_PASSWORD");
+            Ok("Correct-Horse-42".to_string())
+        })?;
+        assert_eq!(password.as_deref(), Some("Correct-Horse-42"));
+
+        let plan = BackupPlan {
+            algo: Algorithm::TarZst,
+            compression_level: None,
+            adaptive_level: false,
+            password,
+            resume_dir: None,
+            options: ResolvedBackupOptions::resolve(&Config::default(), None, &BackupArgs::default())?.0,
+            cancel: CancelToken::new(),
+            log: RunLog::default(),
+            zstd_dictionary: None,
+        };
+        let outcome = Cli::archive_one(
+            &Config::default(),
+            &args,
+            &plan,
+            source_dir.path().to_str().unwrap(),
+            dest_dir.path().to_str().unwrap(),
+            "music.tar.zst".to_string(),
+        )?;
+        assert!(outcome.metadata.encrypted);
+        assert!(ArchiveReader::new(&outcome.archive_path).is_encrypted()?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_large_source_asks_for_confirmation() -> Result<()> {
+        let config = Config {
+            warn_file_count: 10,
+            warn_total_size: 1024,
+            ..Config::default()
+        };
+        let small = FolderInfo {
+            path: "/music".to_string(),
+            file_count: 10,
+            total_size: 1024,
+        };
+        assert!(Cli::confirm_large_source(&small, &config, false, |_| unreachable!("small source prompted"))?);
+
+        let home = FolderInfo {
+            path: "/home/me".to_string(),
+            file_count: 11,
+            total_size: 2048,
+        };
+        assert!(Cli::confirm_large_source(&home, &config, true, |_| unreachable!("--force prompted"))?);
+
+        let mut prompts = Vec::new();
+        let proceed = Cli::confirm_large_source(&home, &config, false, |prompt| {
+            prompts.push(prompt.to_string());
+            Ok(false)
+        })?;
+        assert!(!proceed);
+        assert_eq!(prompts, ["Source has 11 files (over 10) and 2.00 KB (over 1.00 KB). Is this the right folder?"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_corrupted_archive_is_not_uploaded() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let archive = dir.path().join("music.tar.zst");
+        fs::write(&archive, b"archive bytes")?;
+        let archive = archive.to_str().unwrap();
+        Checker::generate_checksum_file(archive, None, false)?;
+
+        let uploads = std::cell::Cell::new(0);
+        let upload = || {
+            uploads.set(uploads.get() + 1);
+            Ok(())
+        };
+        Cli::upload_verified(true, archive, None, None, upload)?;
+        assert_eq!(uploads.get(), 1);
+
+        fs::write(archive, b"archive byte5")?;
+        let err = Cli::upload_verified(true, archive, None, None, upload).unwrap_err();
+        assert_eq!(crate::error::exit_code(&err), 3);
+        assert_eq!(uploads.get(), 1);
+
+        // Without a sidecar the checksum taken after compression is used.
+        fs::remove_file(format!("{}.sha256", archive))?;
+        let expected = Checker::generate_checksum(archive)?;
+        fs::write(archive, b"archive bytes")?;
+        assert!(Cli::upload_verified(true, archive, None, Some(&expected), upload).is_err());
+        assert_eq!(uploads.get(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_checksum_mismatch_exits_with_verification_code() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let archive = dir.path().join("music.tar.zst");
+        fs::write(&archive, b"archive bytes")?;
+        let archive = archive.to_str().unwrap();
+        Checker::write_checksum_file(archive, None, HashAlgorithm::Sha256, &"0".repeat(64))?;
+
+        let err = Cli::verify_sidecar(archive, None).unwrap_err();
+        assert_eq!(crate::error::exit_code(&err), 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_matching_remote_hash_skips_upload() {
+        let destination = RemoteDestination::from_rclone("gdrive", "Backups");
+
+        let present = Cli::already_on_remote("/tmp/music.tar.zst", &destination, "beef", |remote, path, name, checksum| {
+            assert_eq!((remote, path, name, checksum), ("gdrive", "Backups", "music.tar.zst", "beef"));
+            Ok(true)
+        });
+        assert!(present);
+
+        let missing = Cli::already_on_remote("/tmp/music.tar.zst", &destination, "beef", |_, _, _, _| Ok(false));
+        assert!(!missing);
+
+        let s3 = RemoteDestination::parse("s3://bucket/backups").unwrap();
+        assert!(!Cli::already_on_remote("/tmp/music.tar.zst", &s3, "beef", |_, _, _, _| {
+            unreachable!("s3 compared via rclone")
+        }));
+    }
+
+    #[test]
+    fn test_show_checks_encryption_on_disk() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let source = temp_dir.path().join("Music");
+        fs::create_dir_all(&source)?;
+        fs::write(source.join("track.flac"), b"audio")?;
+
+        let result = Archiver::new(&source, temp_dir.path(), "music.zip".to_string(), Algorithm::Zip).compress()?;
+        let mut archive: ArchiveMetadata = serde_json::from_value(serde_json::json!({
+            "name": "music.zip",
+            "created_at": "2024-01-01T00:00:00+00:00",
+            "algorithm": "zip",
+            "size_bytes": result.bytes_written,
+            "file_count": 1,
+            "encrypted": true,
+            "path": result.path.to_string_lossy(),
+        }))?;
+
+        assert_eq!(Cli::encryption_on_disk(&archive)?, Some(false));
+
+        archive.path = temp_dir.path().join("moved.zip").to_string_lossy().to_string();
+        assert_eq!(Cli::encryption_on_disk(&archive)?, None);
+
+        // A damaged zip is reported as such, not as missing.
+        fs::write(&archive.path, b"not a zip")?;
+        assert!(Cli::encryption_on_disk(&archive).is_err());
+
+        // Tar archives are judged without decompressing them.
+        archive.path = temp_dir.path().join("music.tar.zst").to_string_lossy().to_string();
+        fs::write(&archive.path, b"not zstd either")?;
+        assert_eq!(Cli::encryption_on_disk(&archive)?, Some(false));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_incompressible_source_gets_a_compression_tip() -> Result<()> {
+        use sha2::{Digest, Sha256};
+
+        let temp_dir = tempfile::tempdir()?;
+        let source = temp_dir.path().join("Music");
+        fs::create_dir_all(&source)?;
+        // Hash output looks like already-compressed audio to zstd.
+        let mut block = Sha256::digest(b"seed").to_vec();
+        let mut noise = Vec::new();
+        while noise.len() < 256 * 1024 {
+            block = Sha256::digest(&block).to_vec();
+            noise.extend_from_slice(&block);
+        }
+        fs::write(source.join("track.mp3"), &noise)?;
+
+        let archive = |name: &str| {
+            Archiver::new(&source, temp_dir.path(), name.to_string(), Algorithm::TarZst).compress()
+        };
+        let noisy = archive("noisy.tar.zst")?;
+        assert!(Cli::compression_tip(&noisy, 0.98).is_some(), "ratio {}", noisy.ratio());
+
+        fs::remove_file(source.join("track.mp3"))?;
+        fs::write(source.join("lyrics.txt"), "la la la ".repeat(4096))?;
+        let text = archive("text.tar.zst")?;
+        assert!(Cli::compression_tip(&text, 0.98).is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_show_grep_filters_contents() -> Result<()> {
+        let contents: Vec<String> = [
+            "Pink Floyd/Animals/01 Pigs on the Wing.flac",
+            "Pink Floyd/Animals/02 Dogs.flac",
+            "Pink Floyd/Animals/cover.jpg",
+            "Radiohead/OK Computer/07 Fitter Happier.flac",
+        ]
+        .map(String::from)
+        .to_vec();
+
+        let matches = ContentFilter::new("dogs", false)?.apply(&contents);
+        assert_eq!(matches, [&contents[1]]);
+
+        let matches = ContentFilter::new(r"Animals/\d+ .*\.flac$", true)?.apply(&contents);
+        assert_eq!(matches, [&contents[0], &contents[1]]);
+
+        let err = ContentFilter::new("(unclosed", true).err().unwrap();
+        assert_eq!(crate::error::exit_code(&err), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_show_pages_through_contents() {
+        let page = |offset, limit| ContentsPage { offset, limit };
+
+        assert_eq!(ContentsPage::default().range(120), 0..50);
+        assert_eq!(ContentsPage::default().range(10), 0..10);
+        assert_eq!(page(50, Some(50)).range(120), 50..100);
+        assert_eq!(page(100, Some(50)).range(120), 100..120);
+        assert_eq!(page(120, Some(50)).range(120), 120..120);
+        assert_eq!(page(500, Some(50)).range(120), 120..120);
+        assert_eq!(page(0, Some(0)).range(120), 0..0);
+        assert_eq!(page(0, None).range(120), 0..120);
+        assert_eq!(page(30, None).range(120), 30..120);
+        assert_eq!(page(1, Some(usize::MAX)).range(120), 1..120);
+
+        let cli = Cli::try_parse_from(["zencore", "show", "music.tar.zst", "--all", "--offset", "10"]).unwrap();
+        assert!(matches!(cli.command, Some(Commands::Show { all: true, offset: 10, .. })));
+        assert!(Cli::try_parse_from(["zencore", "show", "music.tar.zst", "--all", "--limit", "5"]).is_err());
+    }
+
+    #[test]
+    fn test_bash_completions_list_subcommands() -> Result<()> {
+        let mut script = Vec::new();
+        Cli::write_completions(clap_complete::Shell::Bash, &mut script)?;
+        let script = String::from_utf8(script)?;
+
+        assert!(script.contains("_zencore()"));
+        for subcommand in ["backup", "verify", "analyze-dupes", "completions"] {
+            assert!(script.contains(subcommand), "{} missing", subcommand);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_last_destination_is_offered_for_same_source() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let state_path = temp_dir.path().join("archives.json");
+        let dest_dir = tempfile::tempdir()?;
+        let dest = dest_dir.path().to_str().unwrap();
+
+        let mut state = StateTracker::default();
+        state.remember_destination("/home/user/Music", dest);
+        state.save_to(&state_path)?;
+
+        let state = StateTracker::load_from(&state_path)?;
+        assert_eq!(Cli::remembered_destination(&state, "/home/user/Music").as_deref(), Some(dest));
+        assert_eq!(Cli::remembered_destination(&state, "/home/user/Podcasts"), None);
+
+        drop(dest_dir);
+        assert_eq!(Cli::remembered_destination(&state, "/home/user/Music"), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_log_file_records_each_stage() -> Result<()> {
+        let source_dir = tempfile::tempdir()?;
+        let dest_dir = tempfile::tempdir()?;
+        fs::write(source_dir.path().join("track.flac"), b"audio")?;
+        let log_path = dest_dir.path().join("logs/zencore.log");
+
+        let plan = BackupPlan {
+            algo: Algorithm::TarZst,
+            compression_level: None,
+            adaptive_level: false,
+            password: None,
+            resume_dir: None,
+            options: ResolvedBackupOptions::resolve(&Config::default(), None, &BackupArgs::default())?.0,
+            cancel: CancelToken::new(),
+            log: RunLog::open(&log_path)?,
+            zstd_dictionary: None,
+        };
+        Cli::archive_one(
+            &Config::default(),
+            &BackupArgs::default(),
+            &plan,
+            source_dir.path().to_str().unwrap(),
+            dest_dir.path().to_str().unwrap(),
+            "music.tar.zst".to_string(),
+        )?;
+
+        let log = fs::read_to_string(&log_path)?;
+        // Each line is "YYYY-MM-DD HH:MM:SS <message>".
+        let messages: Vec<&str> = log.lines().map(|line| &line[20..]).collect();
+        let expected = [
+            "archive music.tar.zst started from",
+            "compress finished: 1 files",
+            "checksum finished in",
+            "verify finished in",
+            "archive music.tar.zst written to",
+        ];
+        assert_eq!(messages.len(), expected.len(), "{}", log);
+        for (message, prefix) in messages.iter().zip(expected) {
+            assert!(message.starts_with(prefix), "{:?} should start with {:?}", message, prefix);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_upload_refuses_archive_modified_after_backup() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let archive = temp_dir.path().join("music.tar.zst");
+        fs::write(&archive, b"archive bytes")?;
+        let archive = archive.to_str().unwrap();
+
+        let mut metadata: ArchiveMetadata = serde_json::from_value(serde_json::json!({
+            "name": "music.tar.zst",
+            "created_at": "2024-01-01T00:00:00+00:00",
+            "algorithm": "tar.zst",
+            "size_bytes": 13,
+            "file_count": 1,
+            "encrypted": false,
+        }))?;
+        metadata.add_checksum(HashAlgorithm::Sha256.key(), Checker::generate_checksum(archive)?);
+        Cli::check_unchanged_since_backup(archive, Some(&metadata), false)?;
+
+        fs::write(archive, b"tampered bytes")?;
+        let err = Cli::check_unchanged_since_backup(archive, Some(&metadata), false).unwrap_err();
+        assert_eq!(crate::error::exit_code(&err), 3);
+        Cli::check_unchanged_since_backup(archive, Some(&metadata), true)?;
+        Cli::check_unchanged_since_backup(archive, None, false)?;
+
+        Ok(())
     }
 }
@@ -1,259 +1,3089 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use flate2::write::GzEncoder;
-use flate2::Compression;
+use flate2::{Compression, GzBuilder};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
 use indicatif::{ProgressBar, ProgressStyle};
 use rayon::prelude::*;
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
 use std::fs::{self, File};
-use std::io;
+use std::io::{self, Read, Write};
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::mpsc;
+use std::time::{Duration, Instant, SystemTime};
 use tar::Builder;
-use walkdir::WalkDir;
 use zip::write::{FileOptions, ExtendedFileOptions};
-use zip::unstable::write::FileOptionsExt;
+
+use crate::cancel::CancelToken;
+use crate::path_utils::SymlinkPolicy;
+use crate::resume::{ResumeState, TrailerStripper, TAR_TRAILER_LEN};
 use zstd::stream::write::Encoder as ZstdEncoder;
 
-pub struct Archiver {
-    source: PathBuf,
-    destination: PathBuf,
-    archive_name: String,
-    algorithm: String,
-    num_threads: usize,
-    compression_level: Option<i32>,
-    password: Option<String>,
-    sort_by_size: bool,
+/// Name of the per-source ignore file, read from the source root.
+pub const IGNORE_FILE_NAME: &str = ".zencoreignore";
+
+/// Entry written first by `with_embedded_readme`.
+pub const README_ENTRY_NAME: &str = "ZENCORE_README.txt";
+
+/// File extensions whose content is already compressed; recompressing them
+/// costs CPU time for almost no size gain.
+const INCOMPRESSIBLE_EXTENSIONS: &[&str] = &[
+    "mp3", "aac", "m4a", "ogg", "opus", "wma", "flac", "ape", "wv", "jpg", "jpeg", "png", "gif",
+    "webp", "mp4", "mkv", "avi", "mov", "webm", "zip", "gz", "zst", "xz", "bz2", "7z", "rar",
+];
+
+/// Target uncompressed size of each part in `--resume-backup` mode.
+const RESUME_PART_SIZE: u64 = 1 << 30;
+
+/// Share of incompressible bytes above which adaptive mode stops compressing hard.
+const INCOMPRESSIBLE_THRESHOLD: f64 = 0.9;
+
+/// Wait before the first retry of a failed source read; each further retry
+/// waits one step longer.
+const READ_RETRY_BACKOFF: Duration = Duration::from_millis(200);
+
+/// Source bytes `--time-budget` compresses per candidate level to measure
+/// throughput.
+const BUDGET_SAMPLE_BYTES: u64 = 4 * 1024 * 1024;
+
+/// Share of the time budget a level's projection may use, leaving room for
+/// scanning, I/O and a slower-than-sampled tail.
+const BUDGET_HEADROOM: f64 = 0.8;
+
+/// Expected output/input ratio for compressible data, used by the free-space
+/// preflight. Deliberately pessimistic so the estimate errs on the large side.
+const COMPRESSIBLE_OUTPUT_RATIO: f64 = 0.7;
+
+/// Archive formats the Archiver can write.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Algorithm {
+    TarGz,
+    #[default]
+    TarZst,
+    Zip,
 }
 
-impl Archiver {
-    pub fn new(
-        source: impl AsRef<Path>,
-        destination: impl AsRef<Path>,
-        archive_name: String,
-        algorithm: String,
-    ) -> Self {
-        Self {
-            source: source.as_ref().to_path_buf(),
-            destination: destination.as_ref().to_path_buf(),
-            archive_name,
-            algorithm,
-            num_threads: 0,
-            compression_level: None,
-            password: None,
-            sort_by_size: true,
+impl Algorithm {
+    pub fn all() -> [Self; 3] {
+        [Self::TarZst, Self::TarGz, Self::Zip]
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::TarGz => "tar.gz",
+            Self::TarZst => "tar.zst",
+            Self::Zip => "zip",
         }
     }
 
-    pub fn with_threads(mut self, threads: usize) -> Self {
-        self.num_threads = threads;
-        self
+    /// Format of an existing archive, judged by its file name.
+    pub fn from_archive_path(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_string_lossy().to_lowercase();
+
+        if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(Self::TarGz)
+        } else if name.ends_with(".tar.zst") || name.ends_with(".tzst") {
+            Some(Self::TarZst)
+        } else if name.ends_with(".zip") {
+            Some(Self::Zip)
+        } else {
+            None
+        }
     }
 
-    pub fn with_compression_level(mut self, level: i32) -> Self {
-        self.compression_level = Some(level);
-        self
+    /// File extension used for archives of this format.
+    pub fn extension(&self) -> &'static str {
+        self.as_str()
     }
 
-    pub fn with_password(mut self, password: String) -> Self {
-        self.password = Some(password);
-        self
+    /// Valid `--level` values for this format.
+    pub fn level_range(&self) -> std::ops::RangeInclusive<i32> {
+        match self {
+            Self::TarGz | Self::Zip => 0..=9,
+            Self::TarZst => 1..=22,
+        }
     }
 
-    pub fn with_size_sorting(mut self, enabled: bool) -> Self {
-        self.sort_by_size = enabled;
-        self
+    /// `level` brought into `level_range`, with a note for the user when it
+    /// had to be changed.
+    pub fn clamp_level(&self, level: i32) -> (i32, Option<String>) {
+        let range = self.level_range();
+        if level > *range.end() {
+            let max = *range.end();
+            (max, Some(format!("level {} exceeds {} max {}, using {}", level, self, max, max)))
+        } else if level < *range.start() {
+            let min = *range.start();
+            (min, Some(format!("level {} is below {} min {}, using {}", level, self, min, min)))
+        } else {
+            (level, None)
+        }
     }
 
-    pub fn compress(&self) -> Result<(PathBuf, Vec<String>)> {
-        let archive_path = self.destination.join(&self.archive_name);
+    pub fn default_level(&self) -> i32 {
+        match self {
+            Self::TarGz | Self::Zip => 6,
+            Self::TarZst => 3,
+        }
+    }
 
-        crate::utils::print_info(&format!(
-            "Compressing with {} algorithm...",
-            self.algorithm
-        ));
+    /// Whether the format has native password protection.
+    pub fn supports_encryption(&self) -> bool {
+        matches!(self, Self::Zip)
+    }
 
-        let num_threads = if self.num_threads == 0 {
-            num_cpus::get()
+    pub fn is_tar(&self) -> bool {
+        matches!(self, Self::TarGz | Self::TarZst)
+    }
+
+    fn aliases(&self) -> &'static [&'static str] {
+        match self {
+            Self::TarGz => &["tar.gz", "gz", "gzip", "tgz"],
+            Self::TarZst => &["tar.zst", "zst", "zstd", "tzst"],
+            Self::Zip => &["zip"],
+        }
+    }
+
+    /// Closest known algorithm to a mistyped name, if any is near enough.
+    /// Uses edit distance with transpositions, so `zpi` still finds `zip`.
+    pub fn suggest(input: &str) -> Option<Self> {
+        let input = input.to_lowercase();
+
+        Self::all()
+            .into_iter()
+            .flat_map(|algo| algo.aliases().iter().map(move |alias| (algo, alias)))
+            .map(|(algo, alias)| (algo, strsim::osa_distance(&input, alias)))
+            .filter(|(_, distance)| *distance <= 2)
+            .min_by_key(|(_, distance)| *distance)
+            .map(|(algo, _)| algo)
+    }
+}
+
+impl std::fmt::Display for Algorithm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Algorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let normalized = s.trim().to_lowercase();
+
+        Self::all()
+            .into_iter()
+            .find(|algo| algo.aliases().contains(&normalized.as_str()))
+            .ok_or_else(|| match Self::suggest(&normalized) {
+                Some(suggestion) => format!(
+                    "Unknown algorithm: {} (did you mean {}?)",
+                    s,
+                    suggestion.as_str()
+                ),
+                None => format!("Unknown algorithm: {}", s),
+            })
+    }
+}
+
+/// Value of `--level`: a fixed number or `auto` for content-adaptive selection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompressionLevel {
+    Fixed(i32),
+    Auto,
+}
+
+impl FromStr for CompressionLevel {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("auto") {
+            return Ok(Self::Auto);
+        }
+
+        s.parse::<i32>()
+            .map(Self::Fixed)
+            .map_err(|_| format!("expected a number or 'auto', got '{}'", s))
+    }
+}
+
+/// Header format of tar entries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum TarFormat {
+    /// GNU headers with long-name extension entries
+    Gnu,
+    /// POSIX ustar headers with PAX records for long or non-ASCII names
+    #[default]
+    Pax,
+    /// Plain POSIX ustar; names over its limits fall back to GNU long names
+    Ustar,
+}
+
+impl TarFormat {
+    fn header(self) -> tar::Header {
+        match self {
+            Self::Gnu => tar::Header::new_gnu(),
+            Self::Pax | Self::Ustar => tar::Header::new_ustar(),
+        }
+    }
+}
+
+/// Order of the entries written to the archive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum SortOrder {
+    /// Scan order, no sorting
+    None,
+    /// Largest files first, so the progress estimate settles early
+    #[default]
+    #[value(alias = "size")]
+    SizeDesc,
+    /// Smallest files first
+    SizeAsc,
+    /// By file name, then path
+    #[value(alias = "name")]
+    NameAsc,
+    /// By path relative to the source, keeping folders together
+    #[value(alias = "path")]
+    PathAsc,
+}
+
+/// What to do when two source files would be stored under the same entry
+/// name, which most extractors resolve by keeping only the last one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum CollisionPolicy {
+    /// Store the later file as `name (2).ext` and report it
+    #[default]
+    Suffix,
+    /// Abort the backup
+    Error,
+}
+
+/// Outcome of the adaptive pre-pass: one effective level for the whole archive.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AdaptiveLevel {
+    pub level: i32,
+    pub store_only: bool,
+    pub incompressible_ratio: f64,
+}
+
+impl AdaptiveLevel {
+    /// Weighs the content mix by size and picks a single level. Tar streams
+    /// can't switch codec per entry, so the decision covers the whole archive.
+    pub fn decide(files: &[PathBuf], algorithm: Algorithm) -> Self {
+        let (total, incompressible) = content_mix(files);
+
+        let incompressible_ratio = if total == 0 {
+            0.0
         } else {
-            self.num_threads
+            incompressible as f64 / total as f64
         };
+        let compressible = 1.0 - incompressible_ratio;
+        let mostly_incompressible = incompressible_ratio >= INCOMPRESSIBLE_THRESHOLD;
 
-        rayon::ThreadPoolBuilder::new()
-            .num_threads(num_threads)
-            .build_global()
-            .ok();
+        let (level, store_only) = match algorithm {
+            Algorithm::TarZst if mostly_incompressible => (1, false),
+            Algorithm::TarZst => (3 + (compressible * 9.0).round() as i32, false),
+            Algorithm::Zip if mostly_incompressible => (0, true),
+            _ if mostly_incompressible => (1, false),
+            _ => (1 + (compressible * 8.0).round() as i32, false),
+        };
+
+        Self {
+            level,
+            store_only,
+            incompressible_ratio,
+        }
+    }
 
-        crate::utils::print_info(&format!("Using {} threads", num_threads));
+    pub fn report(&self) {
+        crate::utils::print_info(&format!(
+            "Adaptive level: {:.0}% of data is already compressed",
+            self.incompressible_ratio * 100.0
+        ));
 
-        if let Some(level) = self.compression_level {
-            crate::utils::print_info(&format!("Compression level: {}", level));
+        if self.store_only {
+            crate::utils::print_info("Mostly incompressible content, storing zip entries without compression");
+        } else {
+            crate::utils::print_info(&format!("Selected compression level: {}", self.level));
         }
+    }
+}
 
-        let mut files = self.collect_files_parallel()?;
+/// Level chosen by `--time-budget`: the highest one projected to finish in time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BudgetLevel {
+    pub level: i32,
+    pub budget: Duration,
+    /// Projected compression time for the whole source at `level`.
+    pub projected: Duration,
+}
 
-        if self.sort_by_size {
-            crate::utils::print_info("Sorting files by size (largest first)...");
-            
-            let mut files_with_sizes: Vec<(PathBuf, u64)> = files
-                .par_iter()
-                .filter_map(|path| {
-                    fs::metadata(path)
-                        .ok()
-                        .map(|meta| (path.clone(), meta.len()))
-                })
-                .collect();
+impl BudgetLevel {
+    /// Candidate levels from fastest to strongest.
+    fn ladder(algorithm: Algorithm) -> &'static [i32] {
+        match algorithm {
+            Algorithm::TarZst => &[1, 3, 6, 9, 12, 15, 19],
+            Algorithm::TarGz | Algorithm::Zip => &[1, 3, 6, 9],
+        }
+    }
 
-            files_with_sizes.par_sort_by(|a, b| b.1.cmp(&a.1));
-            files = files_with_sizes.into_iter().map(|(path, _)| path).collect();
+    /// Compresses a sample of `files` at increasing levels and keeps the
+    /// highest one whose projected time for all of `files` fits in `budget`.
+    /// Falls back to the fastest level when none does.
+    pub fn decide(files: &[PathBuf], algorithm: Algorithm, budget: Duration) -> Result<Self> {
+        let (total, _) = content_mix(files);
+        let sample = Self::read_sample(files)?;
+        let ladder = Self::ladder(algorithm);
 
-            crate::utils::print_success("Files sorted by size");
+        let mut chosen: Option<Self> = None;
+        for &level in ladder {
+            let projected = Self::project(&sample, total, algorithm, level)?;
+            let fits = projected.as_secs_f64() <= budget.as_secs_f64() * BUDGET_HEADROOM;
+            if !fits && chosen.is_some() {
+                break;
+            }
+            chosen = Some(Self { level, budget, projected });
+            if !fits {
+                break;
+            }
         }
 
-        let total_files = files.len() as u64;
+        Ok(chosen.expect("level ladder is never empty"))
+    }
 
-        let pb = ProgressBar::new(total_files);
-        pb.set_style(
-            ProgressStyle::default_bar()
-                .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} files ({msg})")
-                .unwrap()
-                .progress_chars("#>-"),
-        );
+    fn read_sample(files: &[PathBuf]) -> Result<Vec<u8>> {
+        let mut sample = Vec::new();
+        for path in files {
+            let remaining = BUDGET_SAMPLE_BYTES - sample.len() as u64;
+            if remaining == 0 {
+                break;
+            }
+            File::open(path)
+                .with_context(|| format!("Failed to read {}", path.display()))?
+                .take(remaining)
+                .read_to_end(&mut sample)?;
+        }
+        Ok(sample)
+    }
 
-        let file_list = match self.algorithm.as_str() {
-            "tar.gz" | "gz" => {
-                if self.password.is_some() {
-                    crate::utils::print_warning(
-                        "tar.gz doesn't support built-in password protection",
-                    );
-                    crate::utils::print_warning("Use zip format for native encryption");
-                }
-                self.compress_tar_gz(&archive_path, &files, &pb)?
-            }
-            "tar.zst" | "zst" => {
-                if self.password.is_some() {
-                    crate::utils::print_warning(
-                        "tar.zst doesn't support built-in password protection",
-                    );
-                    crate::utils::print_warning("Use zip format for native encryption");
-                }
-                self.compress_tar_zst(&archive_path, &files, &pb)?
+    /// Time to compress `total` bytes at `level`, scaled from the sample.
+    fn project(sample: &[u8], total: u64, algorithm: Algorithm, level: i32) -> Result<Duration> {
+        if sample.is_empty() {
+            return Ok(Duration::ZERO);
+        }
+
+        let start = Instant::now();
+        match algorithm {
+            Algorithm::TarZst => {
+                zstd::bulk::compress(sample, level)?;
             }
-            "zip" => self.compress_zip(&archive_path, &files, &pb)?,
-            _ => return Err(anyhow::anyhow!("Unsupported algorithm: {}", self.algorithm)),
-        };
+            Algorithm::TarGz | Algorithm::Zip => {
+                let mut encoder = GzEncoder::new(io::sink(), Compression::new(level as u32));
+                encoder.write_all(sample)?;
+                encoder.finish()?;
+            }
+        }
+        let per_byte = start.elapsed().as_secs_f64() / sample.len() as f64;
+        Ok(Duration::from_secs_f64(per_byte * total as f64))
+    }
+
+    pub fn report(&self) {
+        crate::utils::print_info(&format!(
+            "Time budget {}: level {} projected to take {}",
+            crate::utils::format_duration(self.budget),
+            self.level,
+            crate::utils::format_duration(self.projected)
+        ));
+        if self.projected.as_secs_f64() > self.budget.as_secs_f64() * BUDGET_HEADROOM {
+            crate::utils::print_warning("Even the fastest level may not finish within the time budget");
+        }
+    }
+}
+
+/// Total bytes in `files` and how many of them are already compressed.
+fn content_mix(files: &[PathBuf]) -> (u64, u64) {
+    let mut total = 0u64;
+    let mut incompressible = 0u64;
 
-        pb.finish_with_message("Done!");
+    for path in files {
+        let size = fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+        total += size;
 
-        Ok((archive_path, file_list))
+        let is_compressed = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| INCOMPRESSIBLE_EXTENSIONS.contains(&e.to_lowercase().as_str()))
+            .unwrap_or(false);
+
+        if is_compressed {
+            incompressible += size;
+        }
     }
 
-    fn collect_files_parallel(&self) -> Result<Vec<PathBuf>> {
-        crate::utils::print_info("Scanning directory...");
+    (total, incompressible)
+}
 
-        let entries: Vec<_> = WalkDir::new(&self.source)
-            .into_iter()
-            .par_bridge()
-            .filter_map(|e| e.ok())
-            .filter(|e| e.file_type().is_file())
-            .map(|e| e.path().to_path_buf())
-            .collect();
+/// Rough upper estimate of the archive size for `files`.
+pub fn estimate_output_size(files: &[PathBuf]) -> u64 {
+    let (total, incompressible) = content_mix(files);
+    let compressible = total - incompressible;
 
-        crate::utils::print_success(&format!("Found {} files", entries.len()));
+    incompressible + (compressible as f64 * COMPRESSIBLE_OUTPUT_RATIO) as u64
+}
+
+/// Fails when `available` bytes can't hold `estimate` plus `margin`.
+fn ensure_free_space(estimate: u64, margin: u64, available: u64) -> Result<()> {
+    let required = estimate.saturating_add(margin);
 
-        Ok(entries)
+    if available < required {
+        return Err(anyhow::anyhow!(
+            "Not enough free space on destination: need about {} (estimated archive {} + margin {}), only {} available",
+            crate::utils::format_bytes(required),
+            crate::utils::format_bytes(estimate),
+            crate::utils::format_bytes(margin),
+            crate::utils::format_bytes(available)
+        ));
     }
 
-    fn compress_tar_gz(
-        &self,
-        archive_path: &Path,
-        files: &[PathBuf],
-        pb: &ProgressBar,
-    ) -> Result<Vec<String>> {
-        let tar_gz = File::create(archive_path)?;
-        let level = self.compression_level.unwrap_or(6);
-        let compression = Compression::new(level as u32);
-        let enc = GzEncoder::new(tar_gz, compression);
-        let mut tar = Builder::new(enc);
+    Ok(())
+}
 
-        let mut file_list = Vec::with_capacity(files.len());
+/// Reader that optionally hashes everything read through it.
+struct HashingReader<R: Read> {
+    inner: R,
+    hash: ContentHash,
+}
 
-        for file_path in files {
-            let relative = file_path.strip_prefix(&self.source)?;
-            tar.append_path_with_name(file_path, relative)?;
+/// Where `HashingReader` sends what it reads.
+enum ContentHash {
+    Off,
+    Inline(Sha256),
+    /// Chunks for the file's task on the rayon pool; see `BackgroundHasher`.
+    Background(mpsc::SyncSender<Vec<u8>>),
+}
+
+impl<R: Read> HashingReader<R> {
+    fn new(inner: R, hash: ContentHash) -> Self {
+        Self { inner, hash }
+    }
 
-            file_list.push(relative.to_string_lossy().to_string());
-            pb.inc(1);
-            pb.set_message(relative.to_string_lossy().to_string());
+    /// The SHA-256 of everything read, when hashed inline. A background
+    /// hash is reported by `BackgroundHasher::finish`.
+    fn finish(self) -> Option<String> {
+        match self.hash {
+            ContentHash::Inline(hasher) => Some(format!("{:x}", hasher.finalize())),
+            ContentHash::Off | ContentHash::Background(_) => None,
         }
+    }
+}
 
-        tar.finish()?;
-        Ok(file_list)
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let count = self.inner.read(buf)?;
+        match self.hash {
+            ContentHash::Off => {}
+            ContentHash::Inline(ref mut hasher) => hasher.update(&buf[..count]),
+            ContentHash::Background(ref sender) if count > 0 => {
+                let _ = sender.send(buf[..count].to_vec());
+            }
+            ContentHash::Background(_) => {}
+        }
+        Ok(count)
     }
+}
 
-    fn compress_tar_zst(
-        &self,
-        archive_path: &Path,
-        files: &[PathBuf],
-        pb: &ProgressBar,
-    ) -> Result<Vec<String>> {
-        let tar_zst = File::create(archive_path)?;
-        let level = self.compression_level.unwrap_or(3);
-        let encoder = ZstdEncoder::new(tar_zst, level)?;
-        let mut tar = Builder::new(encoder.auto_finish());
+/// Chunks queued for each file's hash task before archiving waits for it.
+const HASH_QUEUE_CHUNKS: usize = 64;
 
-        let mut file_list = Vec::with_capacity(files.len());
+/// `with_parallel_content_checksums`: each file is hashed by a task on the
+/// rayon pool (sized by `with_threads`) from the chunks read while it is
+/// archived, so files are read once and many small ones hash at the same
+/// time. Each file's queue is bounded, keeping memory small.
+struct BackgroundHasher {
+    results: mpsc::Receiver<(String, std::thread::Result<String>)>,
+    sender: mpsc::Sender<(String, std::thread::Result<String>)>,
+    started: usize,
+}
 
-        for file_path in files {
-            let relative = file_path.strip_prefix(&self.source)?;
-            tar.append_path_with_name(file_path, relative)?;
+impl BackgroundHasher {
+    fn start() -> Self {
+        let (sender, results) = mpsc::channel();
+        Self { results, sender, started: 0 }
+    }
+
+    /// Starts hashing the file stored as `name`; its contents go to the
+    /// returned sender, and dropping the sender completes the hash.
+    fn hash_file(&mut self, name: &str) -> mpsc::SyncSender<Vec<u8>> {
+        let (chunks, receiver) = mpsc::sync_channel::<Vec<u8>>(HASH_QUEUE_CHUNKS);
+        let results = self.sender.clone();
+        let name = name.to_string();
+        self.started += 1;
+        rayon::spawn(move || {
+            let hash = std::panic::catch_unwind(move || {
+                let mut hasher = Sha256::new();
+                for chunk in receiver {
+                    hasher.update(&chunk);
+                }
+                format!("{:x}", hasher.finalize())
+            });
+            let _ = results.send((name, hash));
+        });
+        chunks
+    }
 
-            file_list.push(relative.to_string_lossy().to_string());
-            pb.inc(1);
-            pb.set_message(relative.to_string_lossy().to_string());
+    /// `(entry name, SHA-256)` of every file started, once all are hashed.
+    fn finish(self) -> Result<Vec<(String, String)>> {
+        drop(self.sender);
+        let mut hashes = Vec::with_capacity(self.started);
+        for (name, hash) in self.results {
+            let hash = hash.map_err(|_| anyhow::anyhow!("Hashing {} for the content manifest failed", name))?;
+            hashes.push((name, hash));
         }
+        if hashes.len() != self.started {
+            anyhow::bail!("{} of {} content checksums are missing", self.started - hashes.len(), self.started);
+        }
+        Ok(hashes)
+    }
+}
 
-        tar.finish()?;
-        Ok(file_list)
+/// Retries failed opens and reads of a source file, for network mounts
+/// that drop out for a moment. A failed read is retried by reopening the
+/// file at the same offset, so nothing half-read reaches the archive.
+struct RetryingReader<R> {
+    inner: R,
+    reopen: Box<dyn FnMut(u64) -> io::Result<R>>,
+    offset: u64,
+    retries: u32,
+    label: String,
+}
+
+impl<R> RetryingReader<R> {
+    fn open(label: &str, retries: u32, mut reopen: impl FnMut(u64) -> io::Result<R> + 'static) -> io::Result<Self> {
+        let inner = retry_io(label, retries, || reopen(0))?;
+        Ok(Self {
+            inner,
+            reopen: Box::new(reopen),
+            offset: 0,
+            retries,
+            label: label.to_string(),
+        })
+    }
+}
+
+impl RetryingReader<File> {
+    fn open_file(path: &Path, retries: u32) -> io::Result<Self> {
+        let path = path.to_path_buf();
+        Self::open(&path.display().to_string(), retries, move |offset| {
+            let mut file = File::open(&path)?;
+            io::Seek::seek(&mut file, io::SeekFrom::Start(offset))?;
+            Ok(file)
+        })
     }
+}
 
-    fn compress_zip(
-        &self,
-        archive_path: &Path,
-        files: &[PathBuf],
-        pb: &ProgressBar,
-    ) -> Result<Vec<String>> {
-        let zip_file = File::create(archive_path)?;
-        let mut zip = zip::ZipWriter::new(zip_file);
+impl<R: Read> Read for RetryingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut attempt = 0;
+        loop {
+            match self.inner.read(buf) {
+                Ok(count) => {
+                    self.offset += count as u64;
+                    return Ok(count);
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) if attempt >= self.retries => return Err(e),
+                Err(e) => {
+                    attempt += 1;
+                    tracing::warn!(file = %self.label, attempt, error = %e, "read failed, retrying");
+                    std::thread::sleep(READ_RETRY_BACKOFF * attempt);
+                    match (self.reopen)(self.offset) {
+                        Ok(inner) => self.inner = inner,
+                        Err(e) if attempt >= self.retries => return Err(e),
+                        Err(_) => {}
+                    }
+                }
+            }
+        }
+    }
+}
 
-        let level = self.compression_level.unwrap_or(6);
-        let mut options: FileOptions<'_, ExtendedFileOptions> = FileOptions::default()
-            .compression_method(zip::CompressionMethod::Deflated)
-            .compression_level(Some(level as i64));
+/// Runs `op`, retrying up to `retries` times with a growing wait.
+fn retry_io<T>(label: &str, retries: u32, mut op: impl FnMut() -> io::Result<T>) -> io::Result<T> {
+    let mut attempt = 0;
+    loop {
+        match op() {
+            Err(e) if attempt < retries && e.kind() != io::ErrorKind::NotFound => {
+                attempt += 1;
+                tracing::warn!(file = label, attempt, error = %e, "open failed, retrying");
+                std::thread::sleep(READ_RETRY_BACKOFF * attempt);
+            }
+            result => return result,
+        }
+    }
+}
 
-        if let Some(ref password) = self.password {
-            crate::utils::print_info("Encrypting with AES-256 (ZIP native)..");
+/// Joins path components with `/`, the separator tar and zip readers expect,
+/// whatever the host OS uses.
+fn archive_entry_name(relative: &Path) -> String {
+    relative
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
 
-            options = options.with_deprecated_encryption(password.as_bytes());
+/// Drives a terminal progress bar from `ProgressEvent`s; the default when no
+/// callback is set.
+fn bar_progress(pb: ProgressBar) -> impl Fn(ProgressEvent) {
+    move |event| match event {
+        ProgressEvent::ScanFinished { total_bytes, .. } => pb.set_length(total_bytes),
+        ProgressEvent::Resumed { bytes } => pb.inc(bytes),
+        ProgressEvent::FileAdded { path, bytes } => {
+            pb.inc(bytes);
+            pb.set_message(path);
         }
-        let mut file_list = Vec::with_capacity(files.len());
+        ProgressEvent::Finished => pb.finish_with_message("Done!"),
+        ProgressEvent::ScanStarted => {}
+    }
+}
 
-        for file_path in files {
-            let relative = file_path.strip_prefix(&self.source)?;
-            let name = relative.to_string_lossy().to_string();
+/// Writes `ProgressEvent`s to `out` as NDJSON lines such as
+/// `{"stage":"compress","done":120,"total":500}`, counting source bytes.
+/// Lines come at most once per `interval`, except the first and last.
+pub fn ndjson_progress(stage: &'static str, out: impl Write + 'static, interval: Duration) -> impl Fn(ProgressEvent) {
+    let out = RefCell::new(out);
+    let state = std::cell::Cell::new((0u64, 0u64));
+    let last_emit: std::cell::Cell<Option<Instant>> = std::cell::Cell::new(None);
 
-            zip.start_file(&name, options.clone())?;
-            let mut f = File::open(file_path)?;
-            io::copy(&mut f, &mut zip)?;
+    move |event| {
+        let (mut done, mut total) = state.get();
+        let force = match event {
+            ProgressEvent::ScanStarted => return,
+            ProgressEvent::ScanFinished { total_bytes, .. } => {
+                total = total_bytes;
+                true
+            }
+            ProgressEvent::Resumed { bytes } | ProgressEvent::FileAdded { bytes, .. } => {
+                done += bytes;
+                false
+            }
+            ProgressEvent::Finished => true,
+        };
+        state.set((done, total));
 
-            file_list.push(name.clone());
-            pb.inc(1);
-            pb.set_message(name);
+        if !force && last_emit.get().is_some_and(|at| at.elapsed() < interval) {
+            return;
         }
+        last_emit.set(Some(Instant::now()));
+        let line = serde_json::json!({ "stage": stage, "done": done, "total": total });
+        // A frontend that went away must not fail the backup.
+        let mut out = out.borrow_mut();
+        writeln!(out, "{}", line).and_then(|_| out.flush()).ok();
+    }
+}
 
-        zip.finish()?;
+/// Size and modification time of each source file as seen at scan time.
+struct SourceSnapshot {
+    entries: HashMap<PathBuf, (u64, Option<SystemTime>)>,
+}
+
+impl SourceSnapshot {
+    fn capture(files: &[PathBuf]) -> Self {
+        let entries = files
+            .par_iter()
+            .filter_map(|path| {
+                fs::metadata(path)
+                    .ok()
+                    .map(|meta| (path.clone(), (meta.len(), meta.modified().ok())))
+            })
+            .collect();
+
+        Self { entries }
+    }
+
+    fn has_changed(&self, path: &Path) -> bool {
+        let Some(&(size, modified)) = self.entries.get(path) else {
+            return false;
+        };
+
+        match fs::metadata(path) {
+            Ok(meta) => meta.len() != size || meta.modified().ok() != modified,
+            Err(_) => true,
+        }
+    }
+}
+
+/// Outcome of `Archiver::compress`. The archiver doesn't print; callers
+/// decide how to present this.
+#[derive(Debug, Clone)]
+pub struct CompressResult {
+    pub path: PathBuf,
+    /// Archived paths relative to the source, in archive order.
+    pub files: Vec<String>,
+    pub bytes_written: u64,
+    pub duration: Duration,
+    /// Part of `duration` spent scanning the source.
+    pub scan_duration: Duration,
+    /// Source entries the scan could not read.
+    pub skipped: Vec<String>,
+    /// Files modified while the backup ran (see `with_source_verification`).
+    pub changed: Vec<String>,
+    /// Level chosen by adaptive mode, when enabled.
+    pub adaptive: Option<AdaptiveLevel>,
+    /// Level chosen by `with_time_budget`, when set.
+    pub budget: Option<BudgetLevel>,
+    /// `(entry name, SHA-256)` per file, with `with_content_checksums`.
+    pub content_checksums: Vec<(String, String)>,
+    /// Non-fatal problems worth showing the user.
+    pub warnings: Vec<String>,
+    /// `(source path, name it was stored as)` for files renamed because
+    /// their entry name was already taken.
+    pub collisions: Vec<(String, String)>,
+    /// Duplicates stored as hard links, with `with_dedup`.
+    pub dedup: DedupStats,
+    /// Size of the archived source files.
+    pub input_bytes: u64,
+    /// Source subfolders (relative, outermost only) that were scanned but
+    /// contributed no files, e.g. because an exclude caught all of them.
+    pub empty_dirs: Vec<String>,
+}
 
-        if self.password.is_some() {
-            crate::utils::print_success("✓ Archive encrypted with password");
+impl CompressResult {
+    /// Archive size as a fraction of the source size; 1.0 or more means
+    /// compression saved nothing.
+    pub fn ratio(&self) -> f64 {
+        if self.input_bytes == 0 {
+            return 0.0;
         }
+        self.bytes_written as f64 / self.input_bytes as f64
+    }
+}
 
-        Ok(file_list)
+/// Files `--dedup` stored as hard links to an identical earlier entry.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct DedupStats {
+    pub files: usize,
+    pub bytes_saved: u64,
+}
+
+/// Progress notifications emitted by `Archiver::compress`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProgressEvent {
+    ScanStarted,
+    /// The file list is final; `total_bytes` is the source size to archive.
+    ScanFinished { files: usize, total_bytes: u64 },
+    /// Bytes already archived by an earlier, interrupted run.
+    Resumed { bytes: u64 },
+    /// `path` is relative to the source; `bytes` is its source size.
+    FileAdded { path: String, bytes: u64 },
+    Finished,
+}
+
+type ProgressCallback = Box<dyn Fn(ProgressEvent)>;
+
+/// Findings collected during a `compress` run.
+#[derive(Default)]
+struct RunNotes {
+    skipped: Vec<String>,
+    changed: Vec<String>,
+    adaptive: Option<AdaptiveLevel>,
+    budget: Option<BudgetLevel>,
+    content_checksums: Vec<(String, String)>,
+    warnings: Vec<String>,
+    scan_duration: Duration,
+    stored_names: HashSet<String>,
+    collisions: Vec<(String, String)>,
+    /// Duplicate file to the earlier identical one, with `with_dedup`.
+    duplicates: HashMap<PathBuf, PathBuf>,
+    /// Entry name of each file written so far, for hard link targets.
+    written: HashMap<PathBuf, String>,
+    dedup: DedupStats,
+    input_bytes: u64,
+    empty_dirs: Vec<String>,
+    /// With `with_parallel_content_checksums`, while archiving.
+    hasher: Option<BackgroundHasher>,
+}
+
+pub struct Archiver {
+    source: PathBuf,
+    /// Shown as the source in comments, the README and messages when the
+    /// files are read from somewhere else, such as a snapshot.
+    display_source: Option<PathBuf>,
+    destination: PathBuf,
+    archive_name: String,
+    algorithm: Algorithm,
+    num_threads: usize,
+    compression_level: Option<i32>,
+    adaptive_level: bool,
+    time_budget: Option<Duration>,
+    password: Option<String>,
+    sort_order: SortOrder,
+    exclude_patterns: Vec<String>,
+    exclude_files: Vec<PathBuf>,
+    exclude_extensions: Vec<String>,
+    modified_since: Option<SystemTime>,
+    sample_limit: Option<u64>,
+    symlinks: SymlinkPolicy,
+    collisions: CollisionPolicy,
+    resume_dir: Option<PathBuf>,
+    resume_part_size: u64,
+    verify_source: bool,
+    strict_source: bool,
+    min_free_space: Option<u64>,
+    force: bool,
+    allow_empty: bool,
+    comment: Option<String>,
+    content_checksums: bool,
+    parallel_content_checksums: bool,
+    embed_readme: bool,
+    dedup: bool,
+    zstd_dictionary: Option<Vec<u8>>,
+    tar_format: TarFormat,
+    read_retries: u32,
+    progress_callback: Option<ProgressCallback>,
+    cancel: Option<CancelToken>,
+    source_snapshot: RefCell<Option<SourceSnapshot>>,
+    notes: RefCell<RunNotes>,
+}
+
+impl Archiver {
+    pub fn new(
+        source: impl AsRef<Path>,
+        destination: impl AsRef<Path>,
+        archive_name: String,
+        algorithm: Algorithm,
+    ) -> Self {
+        Self {
+            source: source.as_ref().to_path_buf(),
+            destination: destination.as_ref().to_path_buf(),
+            archive_name,
+            algorithm,
+            num_threads: 0,
+            compression_level: None,
+            adaptive_level: false,
+            time_budget: None,
+            password: None,
+            sort_order: SortOrder::default(),
+            exclude_patterns: Vec::new(),
+            exclude_files: Vec::new(),
+            exclude_extensions: Vec::new(),
+            modified_since: None,
+            sample_limit: None,
+            symlinks: SymlinkPolicy::default(),
+            collisions: CollisionPolicy::default(),
+            resume_dir: None,
+            resume_part_size: RESUME_PART_SIZE,
+            verify_source: false,
+            strict_source: false,
+            min_free_space: None,
+            force: false,
+            allow_empty: false,
+            comment: None,
+            display_source: None,
+            content_checksums: false,
+            parallel_content_checksums: false,
+            embed_readme: false,
+            dedup: false,
+            zstd_dictionary: None,
+            tar_format: TarFormat::default(),
+            read_retries: 0,
+            progress_callback: None,
+            cancel: None,
+            source_snapshot: RefCell::new(None),
+            notes: RefCell::new(RunNotes::default()),
+        }
+    }
+
+    /// Stops at the next file boundary once `token` is cancelled.
+    pub fn with_cancel(mut self, token: CancelToken) -> Self {
+        self.cancel = Some(token);
+        self
+    }
+
+    pub fn with_threads(mut self, threads: usize) -> Self {
+        self.num_threads = threads;
+        self
+    }
+
+    pub fn with_compression_level(mut self, level: i32) -> Self {
+        self.compression_level = Some(level);
+        self
+    }
+
+    /// Pick the level from the content mix instead of a fixed value.
+    pub fn with_adaptive_level(mut self, enabled: bool) -> Self {
+        self.adaptive_level = enabled;
+        self
+    }
+
+    /// Pick the strongest level projected to finish compressing within
+    /// `budget`, measured on a sample of the source. Overrides the fixed
+    /// and adaptive level.
+    pub fn with_time_budget(mut self, budget: Duration) -> Self {
+        self.time_budget = Some(budget);
+        self
+    }
+
+    pub fn with_password(mut self, password: String) -> Self {
+        self.password = Some(password);
+        self
+    }
+
+    /// Largest files first when `enabled`, otherwise scan order.
+    pub fn with_size_sorting(self, enabled: bool) -> Self {
+        self.with_sort_order(if enabled { SortOrder::SizeDesc } else { SortOrder::None })
+    }
+
+    pub fn with_sort_order(mut self, order: SortOrder) -> Self {
+        self.sort_order = order;
+        self
+    }
+
+    /// Extra gitignore-style patterns to skip, relative to the source root.
+    /// These are combined with the source's `.zencoreignore` (union of both).
+    pub fn with_excludes(mut self, patterns: Vec<String>) -> Self {
+        self.exclude_patterns = patterns;
+        self
+    }
+
+    /// Pattern files in `.zencoreignore` syntax kept outside the source
+    /// (`--exclude-from`), added to the other excludes.
+    pub fn with_exclude_files(mut self, files: Vec<PathBuf>) -> Self {
+        self.exclude_files = files;
+        self
+    }
+
+    /// Skip files with these extensions (`jpg`, `.PDF`, `tar.gz`), ignoring
+    /// case and any leading dot.
+    pub fn with_excluded_extensions(mut self, extensions: Vec<String>) -> Self {
+        self.exclude_extensions = extensions
+            .iter()
+            .map(|ext| ext.trim_start_matches('.').to_lowercase())
+            .filter(|ext| !ext.is_empty())
+            .collect();
+        self
+    }
+
+    /// Only archive files modified after `time`, for incremental backups.
+    pub fn with_modified_since(mut self, time: SystemTime) -> Self {
+        self.modified_since = Some(time);
+        self
+    }
+
+    /// Archive only the first files, in path order, up to about `bytes` of
+    /// source data. Used to benchmark settings on a sample.
+    pub fn with_sample_limit(mut self, bytes: u64) -> Self {
+        self.sample_limit = Some(bytes);
+        self
+    }
+
+    pub fn with_symlinks(mut self, policy: SymlinkPolicy) -> Self {
+        self.symlinks = policy;
+        self
+    }
+
+    pub fn with_collision_policy(mut self, policy: CollisionPolicy) -> Self {
+        self.collisions = policy;
+        self
+    }
+
+    /// Write tar archives in resumable parts, tracking progress in `resume_dir`.
+    pub fn with_resume(mut self, resume_dir: PathBuf) -> Self {
+        self.resume_dir = Some(resume_dir);
+        self
+    }
+
+    /// Re-stat each file after it is archived and flag any that changed since
+    /// the scan. With `strict`, the first change aborts the backup.
+    pub fn with_source_verification(mut self, enabled: bool, strict: bool) -> Self {
+        self.verify_source = enabled || strict;
+        self.strict_source = strict;
+        self
+    }
+
+    /// Check the destination has room for the estimated archive plus `margin`
+    /// bytes before writing. With `force`, a shortfall is only a warning.
+    pub fn with_min_free_space(mut self, margin: u64, force: bool) -> Self {
+        self.min_free_space = Some(margin);
+        self.force = force;
+        self
+    }
+
+    /// Write an archive even when no files are left to back up. By default
+    /// `compress` fails before creating anything.
+    pub fn with_allow_empty(mut self, allowed: bool) -> Self {
+        self.allow_empty = allowed;
+        self
+    }
+
+    /// Write a `ZENCORE_README.txt` describing the backup and how to verify
+    /// and extract it as the archive's first entry. Not done for resumable
+    /// backups, whose parts are written before the file count is final.
+    pub fn with_embedded_readme(mut self, enabled: bool) -> Self {
+        self.embed_readme = enabled;
+        self
+    }
+
+    /// The source folder to name in the comment, the README and messages
+    /// when the files are read from a copy of it (`--source-snapshot`). Set
+    /// it before `with_comment`.
+    pub fn with_display_source(mut self, source: impl AsRef<Path>) -> Self {
+        self.display_source = Some(source.as_ref().to_path_buf());
+        self
+    }
+
+    /// Embed a comment naming the backup, its date and source (plus `note`,
+    /// if not empty) in the archive. Zip stores it as the archive comment and
+    /// gzip in its header; zstd has no place for one.
+    pub fn with_comment(mut self, note: &str) -> Self {
+        let mut comment = format!(
+            "Zencore backup: {}\nCreated: {}\nSource: {}",
+            self.archive_name,
+            chrono::Local::now().format("%Y-%m-%d %H:%M:%S"),
+            self.shown_source().display()
+        );
+        if !note.trim().is_empty() {
+            comment.push_str(&format!("\n{}", note.trim()));
+        }
+
+        self.comment = Some(comment);
+        self
+    }
+
+    /// Hash every file with SHA-256 while it is archived, for a per-file
+    /// manifest (`CompressResult::content_checksums`).
+    pub fn with_content_checksums(mut self, enabled: bool) -> Self {
+        self.content_checksums = enabled;
+        self
+    }
+
+    /// Hash the files for the content manifest on the worker threads while
+    /// they are archived, instead of one after another on the archiving
+    /// thread.
+    pub fn with_parallel_content_checksums(mut self, enabled: bool) -> Self {
+        self.parallel_content_checksums = enabled;
+        self
+    }
+
+    /// Store files identical to an earlier one as hard links to it (tar
+    /// only; zip has no hard links). Same-size files are hashed after the
+    /// scan to find them.
+    pub fn with_dedup(mut self, enabled: bool) -> Self {
+        self.dedup = enabled;
+        self
+    }
+
+    /// Compress tar.zst archives with a trained zstd dictionary. The same
+    /// dictionary is needed to read them back.
+    pub fn with_zstd_dictionary(mut self, dictionary: Vec<u8>) -> Self {
+        self.zstd_dictionary = Some(dictionary);
+        self
+    }
+
+    /// Header format for tar archives, PAX unless set.
+    pub fn with_tar_format(mut self, format: TarFormat) -> Self {
+        self.tar_format = format;
+        self
+    }
+
+    /// Retry a source file this many times when opening or reading it
+    /// fails, before the backup gives up.
+    pub fn with_read_retries(mut self, retries: u32) -> Self {
+        self.read_retries = retries;
+        self
+    }
+
+    /// Report progress to `callback` instead of drawing the built-in
+    /// terminal progress bar.
+    pub fn with_progress_callback(mut self, callback: impl Fn(ProgressEvent) + 'static) -> Self {
+        self.progress_callback = Some(Box::new(callback));
+        self
+    }
+
+    /// The source as the user named it.
+    fn shown_source(&self) -> &Path {
+        self.display_source.as_deref().unwrap_or(&self.source)
+    }
+
+    /// Thread count `compress` will use, resolving 0 to the CPU count.
+    pub fn effective_threads(&self) -> usize {
+        if self.num_threads == 0 {
+            num_cpus::get()
+        } else {
+            self.num_threads
+        }
+    }
+
+    pub fn compress(&self) -> Result<CompressResult> {
+        let start = Instant::now();
+        let archive_path = self.destination.join(&self.archive_name);
+        *self.notes.borrow_mut() = RunNotes::default();
+        let _span = tracing::info_span!("compress", archive = %archive_path.display(), algorithm = %self.algorithm).entered();
+
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(self.effective_threads())
+            .build_global()
+            .ok();
+
+        let default_progress;
+        let progress: &dyn Fn(ProgressEvent) = match self.progress_callback {
+            Some(ref callback) => callback.as_ref(),
+            None => {
+                default_progress = bar_progress(Self::progress_bar());
+                &default_progress
+            }
+        };
+
+        if let Some(ref cancel) = self.cancel {
+            cancel.track_partial(&archive_path);
+        }
+
+        let files = match self.resume_dir {
+            Some(ref resume_dir) if self.algorithm.is_tar() => {
+                if self.embed_readme {
+                    self.warn("The README can't be embedded in a resumable backup, leaving it out");
+                }
+                self.compress_resumable(&archive_path, resume_dir, progress)?
+            }
+            _ => {
+                if self.resume_dir.is_some() {
+                    self.warn("Resume is only supported for tar formats, compressed normally");
+                }
+                self.compress_whole(&archive_path, progress)?
+            }
+        };
+
+        progress(ProgressEvent::Finished);
+        if let Some(ref cancel) = self.cancel {
+            cancel.finished_file(&archive_path);
+        }
+
+        let mut notes = self.notes.take();
+        if let Some(hasher) = notes.hasher.take() {
+            notes.content_checksums.extend(hasher.finish()?);
+        }
+        notes.content_checksums.sort();
+        tracing::info!(files = files.len(), elapsed = ?start.elapsed(), "archive written");
+
+        Ok(CompressResult {
+            bytes_written: fs::metadata(&archive_path)?.len(),
+            path: archive_path,
+            files,
+            duration: start.elapsed(),
+            scan_duration: notes.scan_duration,
+            skipped: notes.skipped,
+            changed: notes.changed,
+            adaptive: notes.adaptive,
+            budget: notes.budget,
+            content_checksums: notes.content_checksums,
+            warnings: notes.warnings,
+            collisions: notes.collisions,
+            dedup: notes.dedup,
+            input_bytes: notes.input_bytes,
+            empty_dirs: notes.empty_dirs,
+        })
+    }
+
+    fn compress_whole(&self, archive_path: &Path, progress: &dyn Fn(ProgressEvent)) -> Result<Vec<String>> {
+        let files = self.prepare_files(progress)?;
+        self.preflight_free_space(&files)?;
+        let (level, store_only) = self.resolve_level(&files)?;
+        self.snapshot_sources(&files);
+
+        if self.dedup {
+            if self.algorithm.is_tar() {
+                self.notes.borrow_mut().duplicates = Self::find_duplicates(&files)?;
+            } else {
+                self.warn("Zip has no hard links, --dedup is skipped");
+            }
+        }
+
+        if self.password.is_some() && !self.algorithm.supports_encryption() {
+            self.warn(&format!(
+                "{} doesn't support built-in password protection, archive is unencrypted",
+                self.algorithm
+            ));
+        }
+
+        self.start_background_hashing();
+        let total_bytes = Self::total_size(&files);
+        self.notes.borrow_mut().input_bytes = total_bytes;
+        progress(ProgressEvent::ScanFinished {
+            files: files.len(),
+            total_bytes,
+        });
+
+        let file_list = match self.algorithm {
+            Algorithm::TarGz => self.compress_tar_gz(archive_path, &files, progress, level)?,
+            Algorithm::TarZst => self.compress_tar_zst(archive_path, &files, progress, level)?,
+            Algorithm::Zip => self.compress_zip(archive_path, &files, progress, level, store_only)?,
+        };
+
+        Ok(file_list)
+    }
+
+    /// Name stored in the archive for a source file.
+    fn entry_name(&self, path: &Path) -> Result<String> {
+        Ok(archive_entry_name(path.strip_prefix(&self.source)?))
+    }
+
+    /// Maps each file whose content matches an earlier file in `files` to
+    /// that file. Only files sharing a size are hashed.
+    fn find_duplicates(files: &[PathBuf]) -> Result<HashMap<PathBuf, PathBuf>> {
+        let mut by_size: HashMap<u64, Vec<&PathBuf>> = HashMap::new();
+        for path in files {
+            let meta = fs::symlink_metadata(path)?;
+            if meta.is_file() && meta.len() > 0 {
+                by_size.entry(meta.len()).or_default().push(path);
+            }
+        }
+
+        let candidates: Vec<&PathBuf> = by_size.into_values().filter(|group| group.len() > 1).flatten().collect();
+        let hashes: HashMap<&PathBuf, String> = candidates
+            .into_par_iter()
+            .map(|path| {
+                let mut hasher = Sha256::new();
+                io::copy(&mut File::open(path)?, &mut hasher)?;
+                Ok((path, format!("{:x}", hasher.finalize())))
+            })
+            .collect::<Result<_>>()?;
+
+        let mut first_by_hash: HashMap<&str, &PathBuf> = HashMap::new();
+        let mut duplicates = HashMap::new();
+        for path in files {
+            if let Some(hash) = hashes.get(path) {
+                let first = *first_by_hash.entry(hash.as_str()).or_insert(path);
+                if first != path {
+                    duplicates.insert(path.clone(), first.clone());
+                }
+            }
+        }
+
+        Ok(duplicates)
+    }
+
+    /// Entry name for a file about to be written, made unique within this
+    /// archive according to the collision policy.
+    fn stored_name(&self, path: &Path) -> Result<String> {
+        let name = self.entry_name(path)?;
+        let mut notes = self.notes.borrow_mut();
+        if notes.stored_names.insert(name.clone()) {
+            return Ok(name);
+        }
+
+        if self.collisions == CollisionPolicy::Error {
+            return Err(anyhow::anyhow!(
+                "{} would be stored as {}, which another file already uses",
+                path.display(),
+                name
+            ));
+        }
+
+        let (stem, extension) = match name.rfind('.').filter(|&dot| dot > name.rfind('/').map_or(0, |s| s + 1)) {
+            Some(dot) => name.split_at(dot),
+            None => (name.as_str(), ""),
+        };
+        let unique = (2..)
+            .map(|n| format!("{} ({}){}", stem, n, extension))
+            .find(|candidate| !notes.stored_names.contains(candidate))
+            .unwrap();
+
+        notes.stored_names.insert(unique.clone());
+        notes.collisions.push((path.display().to_string(), unique.clone()));
+        Ok(unique)
+    }
+
+    fn warn(&self, message: &str) {
+        self.notes.borrow_mut().warnings.push(message.to_string());
+    }
+
+    /// Scans the source and applies the configured ordering.
+    fn prepare_files(&self, progress: &dyn Fn(ProgressEvent)) -> Result<Vec<PathBuf>> {
+        progress(ProgressEvent::ScanStarted);
+        let scan_start = Instant::now();
+        let mut files = self.collect_files_parallel()?;
+        tracing::debug!(source = %self.source.display(), files = files.len(), elapsed = ?scan_start.elapsed(), "scanned source");
+
+        if let Some(limit) = self.sample_limit {
+            files.sort();
+            let mut taken = 0u64;
+            files.retain(|path| {
+                let keep = taken < limit;
+                taken += fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+                keep
+            });
+        }
+
+        self.sort_files(&mut files);
+
+        self.notes.borrow_mut().scan_duration = scan_start.elapsed();
+
+        if files.is_empty() && !self.allow_empty {
+            return Err(anyhow::anyhow!(
+                "No files to back up in {}: the folder is empty or every file is excluded (use --allow-empty to archive anyway)",
+                self.shown_source().display()
+            ));
+        }
+
+        Ok(files)
+    }
+
+    fn sort_files(&self, files: &mut Vec<PathBuf>) {
+        match self.sort_order {
+            SortOrder::None => {}
+            SortOrder::SizeDesc | SortOrder::SizeAsc => {
+                let mut files_with_sizes: Vec<(PathBuf, u64)> = files
+                    .par_iter()
+                    .filter_map(|path| {
+                        fs::metadata(path)
+                            .or_else(|_| fs::symlink_metadata(path))
+                            .ok()
+                            .map(|meta| (path.clone(), meta.len()))
+                    })
+                    .collect();
+
+                if self.sort_order == SortOrder::SizeDesc {
+                    files_with_sizes.par_sort_by(|a, b| b.1.cmp(&a.1));
+                } else {
+                    files_with_sizes.par_sort_by(|a, b| a.1.cmp(&b.1));
+                }
+                *files = files_with_sizes.into_iter().map(|(path, _)| path).collect();
+            }
+            SortOrder::NameAsc => files.par_sort_by(|a, b| a.file_name().cmp(&b.file_name()).then_with(|| a.cmp(b))),
+            SortOrder::PathAsc => files.par_sort(),
+        }
+    }
+
+    fn preflight_free_space(&self, files: &[PathBuf]) -> Result<()> {
+        let Some(margin) = self.min_free_space else {
+            return Ok(());
+        };
+
+        let available = fs2::available_space(&self.destination)
+            .context("Failed to query free space on destination")?;
+        let estimate = estimate_output_size(files);
+
+        match ensure_free_space(estimate, margin, available) {
+            Err(e) if self.force => {
+                self.warn(&format!("{} (continued anyway)", e));
+                Ok(())
+            }
+            result => result,
+        }
+    }
+
+    /// Returns the effective level and whether zip entries should be stored.
+    fn resolve_level(&self, files: &[PathBuf]) -> Result<(Option<i32>, bool)> {
+        if let Some(budget) = self.time_budget {
+            let decision = BudgetLevel::decide(files, self.algorithm, budget)?;
+            self.notes.borrow_mut().budget = Some(decision);
+            Ok((Some(decision.level), false))
+        } else if self.adaptive_level {
+            let decision = AdaptiveLevel::decide(files, self.algorithm);
+            self.notes.borrow_mut().adaptive = Some(decision);
+            Ok((Some(decision.level), decision.store_only))
+        } else {
+            Ok((self.compression_level, false))
+        }
+    }
+
+    fn snapshot_sources(&self, files: &[PathBuf]) {
+        self.notes.borrow_mut().changed.clear();
+
+        if self.verify_source {
+            *self.source_snapshot.borrow_mut() = Some(SourceSnapshot::capture(files));
+        }
+    }
+
+    /// Compares a just-archived file against its scan-time snapshot, and
+    /// stops between files once the run is cancelled.
+    fn check_source(&self, path: &Path, name: &str) -> Result<()> {
+        if let Some(ref cancel) = self.cancel {
+            cancel.check()?;
+        }
+
+        let changed = match self.source_snapshot.borrow().as_ref() {
+            Some(snapshot) => snapshot.has_changed(path),
+            None => false,
+        };
+
+        if changed {
+            if self.strict_source {
+                return Err(anyhow::anyhow!("Source file changed during backup: {}", name));
+            }
+
+            self.notes.borrow_mut().changed.push(name.to_string());
+        }
+
+        Ok(())
+    }
+
+    /// Bar measured in source bytes, so large files advance it proportionally
+    /// and the speed and ETA reflect real throughput.
+    fn progress_bar() -> ProgressBar {
+        let pb = crate::utils::progress_bar(0);
+        pb.set_style(
+            ProgressStyle::default_bar()
+                .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({bytes_per_sec}, ETA {eta}) {msg}")
+                .unwrap()
+                .progress_chars("#>-"),
+        );
+        pb
+    }
+
+    fn file_size(path: &Path) -> u64 {
+        fs::metadata(path).map(|m| m.len()).unwrap_or(0)
+    }
+
+    fn total_size(files: &[PathBuf]) -> u64 {
+        files.par_iter().map(|path| Self::file_size(path)).sum()
+    }
+
+    fn file_added(progress: &dyn Fn(ProgressEvent), path: &Path, name: &str) {
+        progress(ProgressEvent::FileAdded {
+            path: name.to_string(),
+            bytes: Self::file_size(path),
+        });
+    }
+
+    fn compress_resumable(
+        &self,
+        archive_path: &Path,
+        resume_dir: &Path,
+        progress: &dyn Fn(ProgressEvent),
+    ) -> Result<Vec<String>> {
+        let state_path =
+            ResumeState::state_path(resume_dir, &self.source, &self.destination, self.algorithm.as_str());
+
+        let mut state = match ResumeState::load(&state_path)? {
+            Some(state) if state.archive_name == self.archive_name => state,
+            _ => {
+                let files = self.prepare_files(progress)?;
+                self.preflight_free_space(&files)?;
+                let mut planned = Vec::with_capacity(files.len());
+                for path in &files {
+                    let size = fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+                    planned.push((self.entry_name(path)?, size));
+                }
+
+                let state = ResumeState::plan(
+                    &self.archive_name,
+                    &self.source,
+                    &self.destination,
+                    self.algorithm.as_str(),
+                    &planned,
+                    self.resume_part_size,
+                );
+                state.save(&state_path)?;
+                state
+            }
+        };
+
+        let files: Vec<PathBuf> = state.files.iter().map(|f| self.source.join(f)).collect();
+        if self.dedup {
+            self.warn("Resumable backups can't link across parts, --dedup is skipped");
+        }
+        let (level, _) = self.resolve_level(&files)?;
+        self.snapshot_sources(&files);
+
+        self.start_background_hashing();
+        let total_bytes = Self::total_size(&files);
+        self.notes.borrow_mut().input_bytes = total_bytes;
+        progress(ProgressEvent::ScanFinished {
+            files: files.len(),
+            total_bytes,
+        });
+        if state.completed_parts > 0 {
+            if self.content_checksums {
+                self.warn("Content checksums only cover files written after resuming");
+            }
+            let done = state.part_ends[state.completed_parts - 1];
+            progress(ProgressEvent::Resumed {
+                bytes: Self::total_size(&files[..done]),
+            });
+        }
+
+        for index in state.completed_parts..state.part_count() {
+            self.write_part(&state, index, level, progress)?;
+            state.completed_parts = index + 1;
+            state.save(&state_path)?;
+        }
+
+        self.join_parts(&state, archive_path, level)?;
+
+        for index in 0..state.part_count() {
+            fs::remove_file(state.part_path(index)).ok();
+        }
+        fs::remove_file(&state_path).ok();
+
+        Ok(state.files)
+    }
+
+    /// Writes one part: its files as tar entries, minus the end-of-archive
+    /// blocks, in a standalone compressed frame.
+    fn write_part(
+        &self,
+        state: &ResumeState,
+        index: usize,
+        level: Option<i32>,
+        progress: &dyn Fn(ProgressEvent),
+    ) -> Result<()> {
+        let part_path = state.part_path(index);
+        let out = File::create(&part_path)
+            .with_context(|| format!("Failed to create {}", part_path.display()))?;
+
+        let out = self.write_compressed(out, level, |writer| {
+            let mut tar = self.tar_builder(TrailerStripper::new(writer));
+            for name in &state.files[state.part_range(index)] {
+                let path = self.source.join(name);
+                self.append_tar_entry(&mut tar, &path, name)?;
+                self.check_source(&path, name)?;
+                Self::file_added(progress, &path, name);
+            }
+            tar.into_inner()?;
+            Ok(())
+        })?;
+        out.sync_all()?;
+
+        Ok(())
+    }
+
+    fn join_parts(&self, state: &ResumeState, archive_path: &Path, level: Option<i32>) -> Result<()> {
+        let mut out = File::create(archive_path)?;
+
+        for index in 0..state.part_count() {
+            let mut part = File::open(state.part_path(index))
+                .with_context(|| format!("Missing part {} of {}", index + 1, state.part_count()))?;
+            io::copy(&mut part, &mut out)?;
+        }
+
+        let out = self.write_compressed(out, level, |writer| {
+            writer.write_all(&[0u8; TAR_TRAILER_LEN])?;
+            Ok(())
+        })?;
+        out.sync_all()?;
+
+        Ok(())
+    }
+
+    fn write_compressed(
+        &self,
+        out: File,
+        level: Option<i32>,
+        body: impl FnOnce(&mut dyn Write) -> Result<()>,
+    ) -> Result<File> {
+        let level = level.unwrap_or(self.algorithm.default_level());
+
+        if self.algorithm == Algorithm::TarGz {
+            let mut encoder = self.gz_encoder(out, level);
+            body(&mut encoder)?;
+            Ok(encoder.finish()?)
+        } else {
+            let mut encoder = self.zstd_encoder(out, level)?;
+            body(&mut encoder)?;
+            Ok(encoder.finish()?)
+        }
+    }
+
+    fn collect_files_parallel(&self) -> Result<Vec<PathBuf>> {
+        let matcher = self.build_ignore_matcher()?;
+        let output_dir = self.destination_in_source();
+        let is_own_output = |path: &Path| {
+            output_dir.as_deref().is_some_and(|dir| {
+                path.parent() == Some(dir)
+                    && path
+                        .file_name()
+                        .is_some_and(|name| name.to_string_lossy().starts_with(self.archive_name.as_str()))
+            })
+        };
+
+        // Folders an exclude skips whole, still reported as contributing nothing.
+        let pruned_dirs = std::sync::Mutex::new(Vec::new());
+        let (entries, errors): (Vec<_>, Vec<_>) = self
+            .symlinks
+            .walk(&self.source)
+            .into_iter()
+            .filter_entry(|e| {
+                let is_dir = e.file_type().is_dir();
+                if e.depth() == 0 {
+                    return true;
+                }
+                if is_dir && crate::snapshot::is_snapshot_dir(e.path()) {
+                    return false;
+                }
+                let excluded = matcher.matched_path_or_any_parents(e.path(), is_dir).is_ignore();
+                if excluded && is_dir {
+                    pruned_dirs.lock().unwrap_or_else(|e| e.into_inner()).push(e.path().to_path_buf());
+                }
+                !excluded
+            })
+            .par_bridge()
+            .partition_map(|entry| match entry {
+                Ok(e) => rayon::iter::Either::Left(e),
+                Err(err) => rayon::iter::Either::Right(err),
+            });
+
+        self.notes.borrow_mut().skipped = errors
+            .iter()
+            .map(|err| match err.path() {
+                Some(path) => format!("{}: {}", path.display(), err),
+                None => err.to_string(),
+            })
+            .collect();
+
+        let walked_dirs: Vec<PathBuf> = entries
+            .iter()
+            .filter(|e| e.depth() > 0 && e.file_type().is_dir())
+            .map(|e| e.path().to_path_buf())
+            .chain(pruned_dirs.into_inner().unwrap_or_else(|e| e.into_inner()))
+            .collect();
+
+        let files: Vec<PathBuf> = entries
+            .into_iter()
+            .filter(|e| self.symlinks.keeps_file(e, |message| self.warn(&message)))
+            .filter(|e| !is_own_output(e.path()))
+            .filter(|e| !self.has_excluded_extension(e.path()))
+            .filter(|e| match self.modified_since {
+                Some(since) => e.metadata().ok().and_then(|m| m.modified().ok()).is_none_or(|m| m > since),
+                None => true,
+            })
+            .map(|e| e.path().to_path_buf())
+            .collect();
+
+        let empty_dirs = self.empty_dirs(walked_dirs, &files);
+        for dir in &empty_dirs {
+            tracing::info!(folder = %dir, "source folder contributed no files");
+        }
+        self.notes.borrow_mut().empty_dirs = empty_dirs;
+
+        Ok(files)
+    }
+
+    /// The outermost of `walked` that hold none of `files`, relative to the
+    /// source and sorted.
+    fn empty_dirs(&self, walked: Vec<PathBuf>, files: &[PathBuf]) -> Vec<String> {
+        let contributing: HashSet<&Path> = files.iter().flat_map(|file| file.ancestors().skip(1)).collect();
+        let empty: HashSet<PathBuf> = walked.into_iter().filter(|dir| !contributing.contains(dir.as_path())).collect();
+
+        let mut outermost: Vec<String> = empty
+            .iter()
+            .filter(|dir| !dir.parent().is_some_and(|parent| empty.contains(parent)))
+            .filter_map(|dir| dir.strip_prefix(&self.source).ok().map(archive_entry_name))
+            .collect();
+        outermost.sort();
+        outermost
+    }
+
+    fn has_excluded_extension(&self, path: &Path) -> bool {
+        if self.exclude_extensions.is_empty() {
+            return false;
+        }
+        let Some(name) = path.file_name() else {
+            return false;
+        };
+        let name = name.to_string_lossy().to_lowercase();
+        self.exclude_extensions
+            .iter()
+            .any(|ext| name.strip_suffix(ext.as_str()).is_some_and(|stem| stem.len() > 1 && stem.ends_with('.')))
+    }
+
+    /// The destination as a path under the source, when it lies inside it.
+    /// The archive being written there (and its sidecars) must not be
+    /// picked up by the scan.
+    fn destination_in_source(&self) -> Option<PathBuf> {
+        let source = fs::canonicalize(&self.source).ok()?;
+        let destination = fs::canonicalize(&self.destination).ok()?;
+        let relative = destination.strip_prefix(&source).ok()?;
+
+        self.warn("Destination is inside the source; the new archive is left out of the scan");
+        Some(self.source.join(relative).components().collect())
+    }
+
+    /// Builds the exclude matcher from `.zencoreignore`, `--exclude-from`
+    /// files and CLI patterns. All of them use gitignore syntax (`#` comments, blank lines, `!` negation)
+    /// and are anchored at the source root.
+    fn build_ignore_matcher(&self) -> Result<Gitignore> {
+        let mut builder = GitignoreBuilder::new(&self.source);
+
+        let ignore_file = self.source.join(IGNORE_FILE_NAME);
+        if ignore_file.is_file()
+            && let Some(err) = builder.add(&ignore_file)
+        {
+            self.warn(&format!("Problem reading {}: {}", IGNORE_FILE_NAME, err));
+        }
+
+        for file in &self.exclude_files {
+            if !file.is_file() {
+                return Err(anyhow::anyhow!("Exclude file not found: {}", file.display()));
+            }
+            if let Some(err) = builder.add(file) {
+                return Err(anyhow::anyhow!("Problem reading {}: {}", file.display(), err));
+            }
+        }
+
+        for pattern in &self.exclude_patterns {
+            builder
+                .add_line(None, pattern)
+                .with_context(|| format!("Invalid exclude pattern: {}", pattern))?;
+        }
+
+        builder.build().context("Failed to build exclude rules")
+    }
+
+    /// Tar writer that keeps symlinks as link entries under `SymlinkPolicy::Store`.
+    fn tar_builder<W: Write>(&self, writer: W) -> Builder<W> {
+        let mut tar = Builder::new(writer);
+        tar.follow_symlinks(self.symlinks != SymlinkPolicy::Store);
+        tar
+    }
+
+    fn stores_link(&self, path: &Path) -> bool {
+        self.symlinks == SymlinkPolicy::Store && path.is_symlink()
+    }
+
+    /// Adds one file, hashing it on the way in when content checksums are on.
+    /// Duplicates found by `--dedup` become hard links to the first copy.
+    fn append_tar_entry<W: Write>(&self, tar: &mut Builder<W>, path: &Path, name: &str) -> Result<()> {
+        if self.dedup && self.append_dedup_link(tar, path, name)? {
+            return Ok(());
+        }
+
+        if self.tar_format == TarFormat::Gnu && self.stores_link(path) {
+            tar.append_path_with_name(path, name)?;
+            return Ok(());
+        }
+
+        if self.stores_link(path) {
+            let mut header = self.tar_format.header();
+            header.set_metadata(&fs::symlink_metadata(path)?);
+            let target = fs::read_link(path)?;
+            return self.append_link_entry(tar, &mut header, name, &target.to_string_lossy());
+        }
+
+        let file = RetryingReader::open_file(path, self.read_retries)?;
+        let mut header = self.tar_format.header();
+        header.set_metadata(&file.inner.metadata()?);
+
+        if self.content_checksums {
+            let mut reader = HashingReader::new(file, self.content_hash(name));
+            self.append_data_entry(tar, &mut header, name, &mut reader)?;
+            self.record_content_checksum(name, reader);
+        } else {
+            self.append_data_entry(tar, &mut header, name, file)?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes one entry in the chosen tar format. PAX puts names that are
+    /// too long or not ASCII in an extended header before the entry.
+    fn append_data_entry<W: Write, R: Read>(
+        &self,
+        tar: &mut Builder<W>,
+        header: &mut tar::Header,
+        name: &str,
+        data: R,
+    ) -> Result<()> {
+        match self.tar_format {
+            TarFormat::Gnu => tar.append_data(header, name, data)?,
+            TarFormat::Ustar => {
+                self.warn_beyond_ustar(name);
+                tar.append_data(header, name, data)?;
+            }
+            TarFormat::Pax => {
+                let mut records = Vec::new();
+                if !name.is_ascii() || !Self::fits_ustar_path(name) {
+                    records.push(("path", name));
+                }
+                Self::append_pax_records(tar, &records)?;
+
+                Self::set_header_path(header, name);
+                header.set_cksum();
+                tar.append(header, data)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Link counterpart of `append_data_entry`.
+    fn append_link_entry<W: Write>(
+        &self,
+        tar: &mut Builder<W>,
+        header: &mut tar::Header,
+        name: &str,
+        target: &str,
+    ) -> Result<()> {
+        match self.tar_format {
+            TarFormat::Gnu => tar.append_link(header, name, target)?,
+            TarFormat::Ustar => {
+                self.warn_beyond_ustar(name);
+                tar.append_link(header, name, target)?;
+            }
+            TarFormat::Pax => {
+                let mut records = Vec::new();
+                if !name.is_ascii() || !Self::fits_ustar_path(name) {
+                    records.push(("path", name));
+                }
+                if !target.is_ascii() || header.set_link_name(target).is_err() {
+                    records.push(("linkpath", target));
+                    let field = &mut header.as_old_mut().linkname;
+                    let len = target.len().min(field.len());
+                    field[..len].copy_from_slice(&target.as_bytes()[..len]);
+                }
+                Self::append_pax_records(tar, &records)?;
+
+                Self::set_header_path(header, name);
+                header.set_cksum();
+                tar.append(header, io::empty())?;
+            }
+        }
+        Ok(())
+    }
+
+    fn warn_beyond_ustar(&self, name: &str) {
+        if !Self::fits_ustar_path(name) {
+            self.warn(&format!("Path exceeds ustar limits, stored with a GNU long name: {}", name));
+        }
+    }
+
+    /// True if `name` fits ustar's 100-byte name plus 155-byte prefix fields.
+    fn fits_ustar_path(name: &str) -> bool {
+        tar::Header::new_ustar().set_path(name).is_ok()
+    }
+
+    /// Sets the header's own name field, cut short when a PAX `path`
+    /// record carries the full name.
+    fn set_header_path(header: &mut tar::Header, name: &str) {
+        if header.set_path(name).is_err() {
+            let field = &mut header.as_old_mut().name;
+            let len = name.len().min(field.len());
+            field[..len].copy_from_slice(&name.as_bytes()[..len]);
+        }
+    }
+
+    /// Writes a PAX extended header holding `records`, if there are any.
+    fn append_pax_records<W: Write>(tar: &mut Builder<W>, records: &[(&str, &str)]) -> Result<()> {
+        if records.is_empty() {
+            return Ok(());
+        }
+
+        let mut data = Vec::new();
+        for (key, value) in records {
+            // Each record is "<len> <key>=<value>\n", where len counts itself.
+            let rest = format!(" {}={}\n", key, value);
+            let mut len = rest.len() + 1;
+            while (len.to_string().len() + rest.len()) != len {
+                len = len.to_string().len() + rest.len();
+            }
+            data.extend_from_slice(format!("{}{}", len, rest).as_bytes());
+        }
+
+        let mut header = tar::Header::new_ustar();
+        header.set_path("PaxHeader")?;
+        header.set_entry_type(tar::EntryType::XHeader);
+        header.set_mode(0o644);
+        header.set_size(data.len() as u64);
+        header.set_cksum();
+        tar.append(&header, data.as_slice())?;
+        Ok(())
+    }
+
+    /// Writes `path` as a hard link if it duplicates a file already in the
+    /// archive; otherwise records its name as a possible link target.
+    fn append_dedup_link<W: Write>(&self, tar: &mut Builder<W>, path: &Path, name: &str) -> Result<bool> {
+        let target = {
+            let mut notes = self.notes.borrow_mut();
+            let target = notes
+                .duplicates
+                .get(path)
+                .and_then(|original| notes.written.get(original))
+                .cloned();
+            if target.is_none() {
+                notes.written.insert(path.to_path_buf(), name.to_string());
+            }
+            target
+        };
+        let Some(target) = target else {
+            return Ok(false);
+        };
+
+        let meta = fs::metadata(path)?;
+        let mut header = self.tar_format.header();
+        header.set_metadata(&meta);
+        header.set_entry_type(tar::EntryType::Link);
+        header.set_size(0);
+        self.append_link_entry(tar, &mut header, name, &target)?;
+
+        let mut notes = self.notes.borrow_mut();
+        notes.dedup.files += 1;
+        notes.dedup.bytes_saved += meta.len();
+        Ok(true)
+    }
+
+    fn record_content_checksum<R: Read>(&self, name: &str, reader: HashingReader<R>) {
+        if let Some(hash) = reader.finish() {
+            self.notes.borrow_mut().content_checksums.push((name.to_string(), hash));
+        }
+    }
+
+    /// How the file being archived is hashed for the content manifest.
+    fn content_hash(&self, name: &str) -> ContentHash {
+        if !self.content_checksums {
+            return ContentHash::Off;
+        }
+        match self.notes.borrow_mut().hasher {
+            Some(ref mut hasher) => ContentHash::Background(hasher.hash_file(name)),
+            None => ContentHash::Inline(Sha256::new()),
+        }
+    }
+
+    fn start_background_hashing(&self) {
+        if self.content_checksums && self.parallel_content_checksums {
+            self.notes.borrow_mut().hasher = Some(BackgroundHasher::start());
+        }
+    }
+
+    /// Gzip stream whose header records the tar's original file name and
+    /// the archive comment, if any.
+    fn gz_encoder<W: Write>(&self, out: W, level: i32) -> GzEncoder<W> {
+        let original_name = self
+            .archive_name
+            .strip_suffix(".gz")
+            .unwrap_or(&self.archive_name);
+
+        let mut builder = GzBuilder::new().filename(original_name);
+        if let Some(ref comment) = self.comment {
+            builder = builder.comment(comment.as_str());
+        }
+
+        builder.write(out, Compression::new(level as u32))
+    }
+
+    fn compress_tar_gz(
+        &self,
+        archive_path: &Path,
+        files: &[PathBuf],
+        progress: &dyn Fn(ProgressEvent),
+        level: Option<i32>,
+    ) -> Result<Vec<String>> {
+        let tar_gz = File::create(archive_path)?;
+        let level = level.unwrap_or(Algorithm::TarGz.default_level());
+        let enc = self.gz_encoder(tar_gz, level);
+        let mut tar = self.tar_builder(enc);
+        self.append_tar_readme(&mut tar, files)?;
+
+        let mut file_list = Vec::with_capacity(files.len());
+
+        for file_path in files {
+            let name = self.stored_name(file_path)?;
+            self.append_tar_entry(&mut tar, file_path, &name)?;
+
+            self.check_source(file_path, &name)?;
+            Self::file_added(progress, file_path, &name);
+            file_list.push(name);
+        }
+
+        tar.finish()?;
+        Ok(file_list)
+    }
+
+    fn compress_tar_zst(
+        &self,
+        archive_path: &Path,
+        files: &[PathBuf],
+        progress: &dyn Fn(ProgressEvent),
+        level: Option<i32>,
+    ) -> Result<Vec<String>> {
+        let tar_zst = File::create(archive_path)?;
+        let level = level.unwrap_or(Algorithm::TarZst.default_level());
+        let encoder = self.zstd_encoder(tar_zst, level)?;
+        let mut tar = self.tar_builder(encoder.auto_finish());
+        self.append_tar_readme(&mut tar, files)?;
+
+        let mut file_list = Vec::with_capacity(files.len());
+
+        for file_path in files {
+            let name = self.stored_name(file_path)?;
+            self.append_tar_entry(&mut tar, file_path, &name)?;
+
+            self.check_source(file_path, &name)?;
+            Self::file_added(progress, file_path, &name);
+            file_list.push(name);
+        }
+
+        tar.finish()?;
+        Ok(file_list)
+    }
+
+    /// Text of the embedded README, or `None` when it is off. Also reserves
+    /// its entry name so no source file is stored under it.
+    fn readme(&self, files: &[PathBuf]) -> Option<String> {
+        if !self.embed_readme {
+            return None;
+        }
+        self.notes.borrow_mut().stored_names.insert(README_ENTRY_NAME.to_string());
+
+        let mut readme = format!(
+            "{name}\n\
+             Created by Zencore on {created}\n\
+             \n\
+             Source:     {source}\n\
+             Format:     {algorithm}\n\
+             Files:      {count}\n",
+            name = self.archive_name,
+            created = chrono::Local::now().format("%Y-%m-%d %H:%M:%S"),
+            source = self.shown_source().display(),
+            algorithm = self.algorithm,
+            count = files.len(),
+        );
+        if self.password.is_some() && self.algorithm.supports_encryption() {
+            readme.push_str("Encrypted:  zip AES-256 (this README is not)\n");
+        }
+        readme.push_str(&format!(
+            "\n\
+             Verify:     zencore verify {name}\n\
+             \x20           or: sha256sum -c {name}.sha256\n",
+            name = self.archive_name,
+        ));
+        match &self.password {
+            Some(_) if self.algorithm.supports_encryption() => readme.push_str(&format!(
+                "Extract:    zencore decompress {name} (asks for the zip password)\n",
+                name = self.archive_name,
+            )),
+            // The tar is age-encrypted with the password after it is written,
+            // and decompress can't open it until it is decrypted.
+            Some(_) => readme.push_str(&format!(
+                "Decrypt:    age -d -o decrypted-{name} {name} (asks for the password)\n\
+                 Extract:    zencore decompress decrypted-{name}\n",
+                name = self.archive_name,
+            )),
+            None => readme.push_str(&format!("Extract:    zencore decompress {}\n", self.archive_name)),
+        }
+        Some(readme)
+    }
+
+    fn append_tar_readme<W: Write>(&self, tar: &mut Builder<W>, files: &[PathBuf]) -> Result<()> {
+        let Some(readme) = self.readme(files) else {
+            return Ok(());
+        };
+
+        let mut header = self.tar_format.header();
+        header.set_entry_type(tar::EntryType::Regular);
+        header.set_size(readme.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).map_or(0, |d| d.as_secs()));
+        self.append_data_entry(tar, &mut header, README_ENTRY_NAME, readme.as_bytes())
+    }
+
+    fn zstd_encoder<W: Write>(&self, out: W, level: i32) -> Result<ZstdEncoder<'static, W>> {
+        Ok(match self.zstd_dictionary {
+            Some(ref dictionary) => ZstdEncoder::with_dictionary(out, level, dictionary)?,
+            None => ZstdEncoder::new(out, level)?,
+        })
+    }
+
+    fn compress_zip(
+        &self,
+        archive_path: &Path,
+        files: &[PathBuf],
+        progress: &dyn Fn(ProgressEvent),
+        level: Option<i32>,
+        store_only: bool,
+    ) -> Result<Vec<String>> {
+        let zip_file = File::create(archive_path)?;
+        let mut zip = zip::ZipWriter::new(zip_file);
+        if let Some(ref comment) = self.comment {
+            zip.set_comment(comment.as_str());
+        }
+
+        let level = level.unwrap_or(Algorithm::Zip.default_level());
+        let mut options: FileOptions<'_, ExtendedFileOptions> = if store_only {
+            FileOptions::default().compression_method(zip::CompressionMethod::Stored)
+        } else {
+            FileOptions::default()
+                .compression_method(zip::CompressionMethod::Deflated)
+                .compression_level(Some(level as i64))
+        };
+
+        // Written before encryption is switched on, so it can be read without the password.
+        if let Some(readme) = self.readme(files) {
+            zip.start_file(README_ENTRY_NAME, options.clone())?;
+            zip.write_all(readme.as_bytes())?;
+        }
+
+        if let Some(ref password) = self.password {
+            options = options.with_aes_encryption(zip::AesMode::Aes256, password);
+        }
+        let mut file_list = Vec::with_capacity(files.len());
+
+        for file_path in files {
+            // Names are UTF-8; the zip crate sets the EFS flag (bit 11) on
+            // non-ASCII ones so extractors don't read them as CP437.
+            let name = self.stored_name(file_path)?;
+
+            if self.stores_link(file_path) {
+                let target = fs::read_link(file_path)?;
+                zip.add_symlink(&name, target.to_string_lossy(), options.clone())?;
+            } else {
+                zip.start_file(&name, options.clone())?;
+                let source = RetryingReader::open_file(file_path, self.read_retries)?;
+                let mut f = HashingReader::new(source, self.content_hash(&name));
+                io::copy(&mut f, &mut zip)?;
+                self.record_content_checksum(&name, f);
+            }
+            self.check_source(file_path, &name)?;
+
+            Self::file_added(progress, file_path, &name);
+            file_list.push(name);
+        }
+
+        zip.finish()?;
+
+        Ok(file_list)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_embedded_readme_is_the_first_entry() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let source = temp_dir.path().join("Music");
+        fs::create_dir_all(&source)?;
+        fs::write(source.join("track.flac"), b"audio")?;
+
+        for (name, algorithm) in [("music.tar.zst", Algorithm::TarZst), ("music.zip", Algorithm::Zip)] {
+            let result = Archiver::new(&source, temp_dir.path(), name.to_string(), algorithm)
+                .with_embedded_readme(true)
+                .compress()?;
+            assert_eq!(result.files, ["track.flac"]);
+
+            let reader = crate::extract::ArchiveReader::new(&result.path);
+            let entries = reader.list_contents()?;
+            assert_eq!(entries[0].name, README_ENTRY_NAME);
+
+            let mut readme = String::new();
+            reader.for_each_entry(|entry, data| {
+                if entry == README_ENTRY_NAME {
+                    data.read_to_string(&mut readme)?;
+                }
+                Ok(())
+            })?;
+            assert!(readme.starts_with(name), "{}", readme);
+            assert!(readme.contains("Files:      1"));
+            assert!(readme.contains(&format!("Extract:    zencore decompress {}\n", name)), "{}", readme);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_parallel_content_manifest_matches_serial() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let source = temp_dir.path().join("Music");
+        for album in ["A", "B", "C"] {
+            fs::create_dir_all(source.join(album))?;
+            for track in 0..20 {
+                fs::write(source.join(album).join(format!("{:02}.flac", track)), format!("{} {}", album, track))?;
+            }
+        }
+
+        let manifest = |name: &str, parallel: bool| -> Result<Vec<u8>> {
+            let result = Archiver::new(&source, temp_dir.path(), name.to_string(), Algorithm::TarZst)
+                .with_content_checksums(true)
+                .with_parallel_content_checksums(parallel)
+                .compress()?;
+            let path = crate::crypto::Checker::write_manifest(&result.path.to_string_lossy(), None, &result.content_checksums)?;
+            Ok(fs::read(path)?)
+        };
+
+        let serial = manifest("serial.tar.zst", false)?;
+        assert_eq!(manifest("parallel.tar.zst", true)?, serial);
+        assert_eq!(String::from_utf8(serial)?.lines().count(), 60);
+        Ok(())
+    }
+
+    #[test]
+    fn test_folders_emptied_by_excludes_are_reported() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let source = temp_dir.path().join("Music");
+        fs::create_dir_all(source.join("Album/Scans/Back"))?;
+        fs::create_dir_all(source.join("Empty"))?;
+        fs::write(source.join("Album/track.flac"), b"audio")?;
+        fs::write(source.join("Album/Scans/cover.jpg"), b"image")?;
+        fs::write(source.join("Album/Scans/Back/back.jpg"), b"image")?;
+        fs::create_dir_all(source.join("Live/Bootlegs"))?;
+        fs::write(source.join("Live/show.flac"), b"audio")?;
+        fs::write(source.join("Live/Bootlegs/show.flac"), b"audio")?;
+
+        let result = Archiver::new(&source, temp_dir.path(), "music.tar.zst".to_string(), Algorithm::TarZst)
+            .with_sort_order(SortOrder::PathAsc)
+            .with_excludes(vec!["*.jpg".to_string(), "Bootlegs/".to_string()])
+            .compress()?;
+
+        assert_eq!(result.files, ["Album/track.flac", "Live/show.flac"]);
+        // Bootlegs is never entered, but still reported.
+        assert_eq!(result.empty_dirs, ["Album/Scans", "Empty", "Live/Bootlegs"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_failed_read_is_retried_from_the_same_offset() -> Result<()> {
+        struct Flaky(io::Cursor<Vec<u8>>, bool);
+        impl Read for Flaky {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                if self.1 && self.0.position() > 0 {
+                    return Err(io::Error::other("mount went away"));
+                }
+                let len = buf.len().min(4);
+                self.0.read(&mut buf[..len])
+            }
+        }
+
+        let data = b"audio data from a network share".to_vec();
+        let opens = std::rc::Rc::new(std::cell::Cell::new(0));
+        let counter = opens.clone();
+        let source = data.clone();
+        let mut reader = RetryingReader::open("track.flac", 1, move |offset| {
+            counter.set(counter.get() + 1);
+            let mut cursor = io::Cursor::new(source.clone());
+            cursor.set_position(offset);
+            // Only the first handle fails, after its first read.
+            Ok(Flaky(cursor, counter.get() == 1))
+        })?;
+
+        let mut read = Vec::new();
+        reader.read_to_end(&mut read)?;
+        assert_eq!(read, data);
+        assert_eq!(opens.get(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_modified_since_keeps_only_newer_files() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let source = temp_dir.path();
+        let last_backup = SystemTime::now() - Duration::from_secs(3600);
+
+        fs::write(source.join("old.flac"), b"audio")?;
+        File::options()
+            .write(true)
+            .open(source.join("old.flac"))?
+            .set_modified(last_backup - Duration::from_secs(60))?;
+        fs::write(source.join("new.flac"), b"audio")?;
+
+        let archiver = Archiver::new(source, source, "out.tar.zst".to_string(), Algorithm::TarZst)
+            .with_modified_since(last_backup);
+
+        let files = archiver.collect_files_parallel()?;
+        assert_eq!(files, vec![source.join("new.flac")]);
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_broken_symlink_under_each_policy() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let source = temp_dir.path().join("Music");
+        fs::create_dir_all(&source)?;
+        fs::write(source.join("track.flac"), b"audio")?;
+        std::os::unix::fs::symlink(source.join("missing.flac"), source.join("gone.flac"))?;
+
+        let run = |policy: SymlinkPolicy| -> Result<(CompressResult, Vec<(String, tar::EntryType)>)> {
+            let result = Archiver::new(&source, temp_dir.path(), "out.tar.zst".to_string(), Algorithm::TarZst)
+                .with_symlinks(policy)
+                .compress()?;
+
+            let decoder = zstd::stream::read::Decoder::new(File::open(&result.path)?)?;
+            let mut archive = tar::Archive::new(decoder);
+            let mut entries = Vec::new();
+            for entry in archive.entries()? {
+                let entry = entry?;
+                entries.push((entry.path()?.to_string_lossy().to_string(), entry.header().entry_type()));
+            }
+            entries.sort_by(|a, b| a.0.cmp(&b.0));
+            Ok((result, entries))
+        };
+
+        let (followed, entries) = run(SymlinkPolicy::Follow)?;
+        assert_eq!(entries, vec![("track.flac".to_string(), tar::EntryType::Regular)]);
+        assert!(followed.skipped.iter().any(|s| s.contains("gone.flac")));
+
+        let (skipped, entries) = run(SymlinkPolicy::Skip)?;
+        assert_eq!(entries, vec![("track.flac".to_string(), tar::EntryType::Regular)]);
+        assert!(skipped.warnings.iter().any(|w| w.contains("Skipping broken symlink")));
+
+        let (stored, entries) = run(SymlinkPolicy::Store)?;
+        assert_eq!(
+            entries,
+            vec![
+                ("gone.flac".to_string(), tar::EntryType::Symlink),
+                ("track.flac".to_string(), tar::EntryType::Regular),
+            ]
+        );
+        assert!(stored.warnings.iter().any(|w| w.contains("Storing broken symlink")));
+
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_colliding_entry_names() -> Result<()> {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        // Both names are invalid UTF-8 and store as "\u{FFFD}.flac".
+        let temp_dir = tempfile::tempdir()?;
+        let source = temp_dir.path().join("Music");
+        fs::create_dir_all(&source)?;
+        fs::write(source.join(OsStr::from_bytes(b"\xff.flac")), b"first")?;
+        fs::write(source.join(OsStr::from_bytes(b"\xfe.flac")), b"second")?;
+
+        let result = Archiver::new(&source, temp_dir.path(), "out.zip".to_string(), Algorithm::Zip).compress()?;
+        let mut files = result.files.clone();
+        files.sort();
+        assert_eq!(files, ["\u{FFFD} (2).flac", "\u{FFFD}.flac"]);
+        assert_eq!(result.collisions.len(), 1);
+        assert_eq!(result.collisions[0].1, "\u{FFFD} (2).flac");
+
+        let err = Archiver::new(&source, temp_dir.path(), "out.tar.gz".to_string(), Algorithm::TarGz)
+            .with_collision_policy(CollisionPolicy::Error)
+            .compress()
+            .unwrap_err();
+        assert!(err.to_string().contains("which another file already uses"), "{}", err);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_destination_inside_source_skips_own_output() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let source = temp_dir.path().join("Music");
+        let destination = source.join("Backups");
+        fs::create_dir_all(&destination)?;
+        fs::write(source.join("track.flac"), b"audio")?;
+        fs::write(destination.join("notes.txt"), b"kept")?;
+        // Left over from an interrupted run with the same name.
+        fs::write(destination.join("music.tar.zst"), b"half an archive")?;
+        fs::write(destination.join("music.tar.zst.sha256"), b"stale")?;
+
+        let result = Archiver::new(&source, &destination, "music.tar.zst".to_string(), Algorithm::TarZst)
+            .with_size_sorting(false)
+            .compress()?;
+
+        let mut files = result.files.clone();
+        files.sort();
+        assert_eq!(files, ["Backups/notes.txt", "track.flac"]);
+        assert!(result.warnings.iter().any(|w| w.contains("Destination is inside the source")));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sort_orders() -> Result<()> {
+        let source_dir = tempfile::tempdir()?;
+        let dest_dir = tempfile::tempdir()?;
+        fs::create_dir(source_dir.path().join("b"))?;
+        fs::write(source_dir.path().join("b/a.txt"), "x".repeat(30))?;
+        fs::write(source_dir.path().join("c.txt"), "x".repeat(10))?;
+        fs::write(source_dir.path().join("a.txt"), "x".repeat(20))?;
+        fs::write(source_dir.path().join("b/0.txt"), "x".repeat(40))?;
+
+        let order = |sort: SortOrder| -> Result<Vec<String>> {
+            let result = Archiver::new(source_dir.path(), dest_dir.path(), "sorted.tar.gz".to_string(), Algorithm::TarGz)
+                .with_sort_order(sort)
+                .compress()?;
+            Ok(result.files)
+        };
+
+        assert_eq!(order(SortOrder::SizeDesc)?, ["b/0.txt", "b/a.txt", "a.txt", "c.txt"]);
+        assert_eq!(order(SortOrder::SizeAsc)?, ["c.txt", "a.txt", "b/a.txt", "b/0.txt"]);
+        assert_eq!(order(SortOrder::NameAsc)?, ["b/0.txt", "a.txt", "b/a.txt", "c.txt"]);
+        assert_eq!(order(SortOrder::PathAsc)?, ["a.txt", "b/0.txt", "b/a.txt", "c.txt"]);
+        let mut unsorted = order(SortOrder::None)?;
+        unsorted.sort();
+        assert_eq!(unsorted, ["a.txt", "b/0.txt", "b/a.txt", "c.txt"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_dedup_stores_identical_files_as_links() -> Result<()> {
+        let source_dir = tempfile::tempdir()?;
+        let dest_dir = tempfile::tempdir()?;
+        fs::create_dir(source_dir.path().join("Playlist"))?;
+        fs::write(source_dir.path().join("01.flac"), "same track")?;
+        fs::write(source_dir.path().join("Playlist/01.flac"), "same track")?;
+        fs::write(source_dir.path().join("02.flac"), "also track")?;
+
+        let result = Archiver::new(source_dir.path(), dest_dir.path(), "music.tar.gz".to_string(), Algorithm::TarGz)
+            .with_sort_order(SortOrder::PathAsc)
+            .with_dedup(true)
+            .compress()?;
+        assert_eq!(result.dedup, DedupStats { files: 1, bytes_saved: 10 });
+
+        let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(File::open(&result.path)?));
+        let mut entries = Vec::new();
+        for entry in archive.entries()? {
+            let entry = entry?;
+            let link = entry.link_name()?.map(|target| target.to_string_lossy().to_string());
+            entries.push((entry.path()?.to_string_lossy().to_string(), entry.header().entry_type(), link));
+        }
+        assert_eq!(
+            entries,
+            [
+                ("01.flac".to_string(), tar::EntryType::Regular, None),
+                ("02.flac".to_string(), tar::EntryType::Regular, None),
+                ("Playlist/01.flac".to_string(), tar::EntryType::Link, Some("01.flac".to_string())),
+            ]
+        );
+
+        let out = dest_dir.path().join("restored");
+        assert_eq!(crate::extract::ArchiveReader::new(&result.path).extract_to(&out)?, 3);
+        assert_eq!(fs::read_to_string(out.join("Playlist/01.flac"))?, "same track");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_zstd_dictionary_roundtrip() -> Result<()> {
+        let source_dir = tempfile::tempdir()?;
+        let dest_dir = tempfile::tempdir()?;
+        for i in 0..200 {
+            let sidecar = format!(
+                "{{\"title\": \"Track {i}\", \"artist\": \"Band {}\", \"album\": \"Album {}\", \"year\": {}}}",
+                i % 7,
+                i % 13,
+                1990 + i % 30
+            );
+            fs::write(source_dir.path().join(format!("{:03}.json", i)), sidecar)?;
+        }
+
+        let dictionary = crate::dictionary::train(source_dir.path(), 4096)?;
+        let result = Archiver::new(source_dir.path(), dest_dir.path(), "tags.tar.zst".to_string(), Algorithm::TarZst)
+            .with_zstd_dictionary(dictionary.clone())
+            .compress()?;
+
+        let out = dest_dir.path().join("restored");
+        let reader = crate::extract::ArchiveReader::new(&result.path).with_zstd_dictionary(dictionary.clone());
+        assert_eq!(reader.extract_to(&out)?, 200);
+        assert_eq!(
+            fs::read_to_string(out.join("042.json"))?,
+            fs::read_to_string(source_dir.path().join("042.json"))?
+        );
+        let entry_hash = crate::crypto::Checker::generate_checksum(&out.join("042.json").to_string_lossy())?;
+        let manifest = std::collections::BTreeMap::from([("042.json".to_string(), entry_hash)]);
+        assert!(reader.verify_contents_streaming(&manifest, false, |_, _| {})?.is_ok());
+
+        let err = crate::extract::ArchiveReader::new(&result.path).list_contents().unwrap_err();
+        assert!(err.to_string().contains(&format!("dictionary id {}", crate::dictionary::dictionary_id(&dictionary))));
+        assert_eq!(crate::state::ArchiveMetadata::from_archive_file(&result.path)?.file_count, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_long_paths_roundtrip_in_each_tar_format() -> Result<()> {
+        let source_dir = tempfile::tempdir()?;
+        let dest_dir = tempfile::tempdir()?;
+        let nested = "Artist With A Rather Long Name/Album With An Even Longer Title (Deluxe Edition)/Disc 1 Of 2/\
+                      Björk – Jóga/Live Recordings From The Tour Of The Same Name/Second Night At The Old Venue In The Rain/\
+                      Soundboard Transfer From The Original Reels";
+        fs::create_dir_all(source_dir.path().join(nested))?;
+        let name = format!("{}/01 - Trâck.flac", nested);
+        assert!(name.len() > 255);
+        fs::write(source_dir.path().join(&name), "audio")?;
+
+        for format in [TarFormat::Gnu, TarFormat::Pax, TarFormat::Ustar] {
+            let archive_name = format!("{:?}.tar.gz", format).to_lowercase();
+            let result = Archiver::new(source_dir.path(), dest_dir.path(), archive_name, Algorithm::TarGz)
+                .with_tar_format(format)
+                .compress()?;
+
+            let out = dest_dir.path().join(format!("{:?}", format));
+            assert_eq!(crate::extract::ArchiveReader::new(&result.path).extract_to(&out)?, 1);
+            assert_eq!(fs::read_to_string(out.join(&name))?, "audio");
+
+            let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(File::open(&result.path)?));
+            let kinds: Vec<tar::EntryType> = archive
+                .entries()?
+                .raw(true)
+                .map(|entry| Ok(entry?.header().entry_type()))
+                .collect::<Result<_>>()?;
+            let expected = match format {
+                TarFormat::Pax => tar::EntryType::XHeader,
+                TarFormat::Gnu | TarFormat::Ustar => tar::EntryType::GNULongName,
+            };
+            assert_eq!(kinds, [expected, tar::EntryType::Regular]);
+            assert_eq!(
+                result.warnings.iter().any(|w| w.contains("exceeds ustar limits")),
+                format == TarFormat::Ustar
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_zip_marks_non_ascii_names_as_utf8() -> Result<()> {
+        let source_dir = tempfile::tempdir()?;
+        let dest_dir = tempfile::tempdir()?;
+        fs::write(source_dir.path().join("Björk - Jóga.flac"), "audio")?;
+
+        let result = Archiver::new(source_dir.path(), dest_dir.path(), "music.zip".to_string(), Algorithm::Zip)
+            .compress()?;
+
+        let raw = fs::read(&result.path)?;
+        assert_eq!(&raw[..4], b"PK\x03\x04");
+        let flags = u16::from_le_bytes([raw[6], raw[7]]);
+        assert_ne!(flags & (1 << 11), 0, "local header lacks the UTF-8 flag");
+
+        let mut zip = zip::ZipArchive::new(File::open(&result.path)?)?;
+        assert_eq!(zip.by_index(0)?.name(), "Björk - Jóga.flac");
+
+        let out = dest_dir.path().join("restored");
+        crate::extract::ArchiveReader::new(&result.path).extract_to(&out)?;
+        assert_eq!(fs::read_to_string(out.join("Björk - Jóga.flac"))?, "audio");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_excluded_extensions_ignore_case() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let source = temp_dir.path();
+        for name in ["01.flac", "cover.jpg", "back.JPG", "booklet.pdf", "jpg", ".jpg"] {
+            fs::write(source.join(name), b"data")?;
+        }
+
+        let archiver = Archiver::new(source, source, "out.tar.zst".to_string(), Algorithm::TarZst)
+            .with_excluded_extensions(vec!["JPG".to_string(), ".pdf".to_string()]);
+        let mut names: Vec<String> = archiver
+            .collect_files_parallel()?
+            .iter()
+            .map(|p| p.file_name().unwrap().to_string_lossy().to_string())
+            .collect();
+        names.sort();
+        assert_eq!(names, [".jpg", "01.flac", "jpg"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_exclude_from_file() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let source = temp_dir.path().join("music");
+        fs::create_dir_all(source.join("Album"))?;
+        fs::write(source.join("Album/01.flac"), b"audio")?;
+        fs::write(source.join("Album/album.cue"), b"cue sheet")?;
+        fs::write(source.join("Album/cover.jpg"), b"image")?;
+        let patterns = temp_dir.path().join("patterns.txt");
+        fs::write(&patterns, "# artwork is kept elsewhere
+
+*.jpg
+")?;
+
+        let archiver = Archiver::new(&source, temp_dir.path(), "out.tar.zst".to_string(), Algorithm::TarZst)
+            .with_exclude_files(vec![patterns])
+            .with_excludes(vec!["*.cue".to_string()]);
+        let names: Vec<String> = archiver
+            .collect_files_parallel()?
+            .iter()
+            .map(|p| p.file_name().unwrap().to_string_lossy().to_string())
+            .collect();
+        assert_eq!(names, ["01.flac"]);
+
+        let missing = Archiver::new(&source, temp_dir.path(), "out.tar.zst".to_string(), Algorithm::TarZst)
+            .with_exclude_files(vec![temp_dir.path().join("nope.txt")]);
+        assert!(missing.collect_files_parallel().unwrap_err().to_string().contains("Exclude file not found"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_zencoreignore_excludes_matching_files() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let source = temp_dir.path();
+
+        fs::create_dir_all(source.join("Album"))?;
+        fs::write(source.join("Album/01.flac"), b"audio")?;
+        fs::write(source.join("Album/album.cue"), b"cue sheet")?;
+        fs::write(source.join("Album/cover.jpg"), b"image")?;
+        fs::create_dir_all(source.join(".zencore-snapshot-20240501-42/Album"))?;
+        fs::write(source.join(".zencore-snapshot-20240501-42/Album/01.flac"), b"stale")?;
+        fs::write(
+            source.join(IGNORE_FILE_NAME),
+            "# cue sheets are regenerated\n\n*.cue\n",
+        )?;
+
+        let archiver = Archiver::new(source, source, "out.tar.zst".to_string(), Algorithm::TarZst)
+            .with_excludes(vec!["*.jpg".to_string()]);
+
+        let files = archiver.collect_files_parallel()?;
+        let names: Vec<String> = files
+            .iter()
+            .map(|p| p.file_name().unwrap().to_string_lossy().to_string())
+            .collect();
+
+        assert!(names.contains(&"01.flac".to_string()));
+        assert!(!names.contains(&"album.cue".to_string()));
+        assert!(!names.contains(&"cover.jpg".to_string()));
+        assert_eq!(names.iter().filter(|name| *name == "01.flac").count(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_time_budget_picks_fast_level_when_tight() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let path = temp_dir.path().join("notes.txt");
+        fs::write(&path, "zencore time budget ".repeat(50_000))?;
+        let files = [path];
+
+        let tight = BudgetLevel::decide(&files, Algorithm::TarZst, Duration::from_nanos(1))?;
+        assert_eq!(tight.level, 1);
+        assert!(tight.projected > tight.budget);
+
+        let generous = BudgetLevel::decide(&files, Algorithm::TarGz, Duration::from_secs(3600))?;
+        assert_eq!(generous.level, 9);
+
+        let source = temp_dir.path().join("source");
+        fs::create_dir_all(&source)?;
+        fs::copy(&files[0], source.join("notes.txt"))?;
+        let result = Archiver::new(&source, temp_dir.path(), "budget".to_string(), Algorithm::TarZst)
+            .with_compression_level(19)
+            .with_time_budget(Duration::from_nanos(1))
+            .with_progress_callback(|_| {})
+            .compress()?;
+        assert_eq!(result.budget.map(|budget| budget.level), Some(1));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_adaptive_level_weighs_content_mix() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let dir = temp_dir.path();
+
+        let mp3 = dir.join("track.mp3");
+        let wav = dir.join("track.wav");
+        let cue = dir.join("album.cue");
+        fs::write(&mp3, vec![0u8; 9_500])?;
+        fs::write(&wav, vec![0u8; 9_000])?;
+        fs::write(&cue, vec![0u8; 500])?;
+
+        let mostly_mp3 = [mp3.clone(), cue.clone()];
+        let zst = AdaptiveLevel::decide(&mostly_mp3, Algorithm::TarZst);
+        assert_eq!(zst.level, 1);
+        assert!(!zst.store_only);
+
+        let zip = AdaptiveLevel::decide(&mostly_mp3, Algorithm::Zip);
+        assert!(zip.store_only);
+
+        let mixed = [mp3, wav, cue];
+        let mixed_zst = AdaptiveLevel::decide(&mixed, Algorithm::TarZst);
+        assert!(mixed_zst.level > 1 && mixed_zst.level < 12);
+
+        let raw_only = AdaptiveLevel::decide(&mixed[1..], Algorithm::TarZst);
+        assert_eq!(raw_only.level, 12);
+        assert!(raw_only.level > mixed_zst.level);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_resume_after_interrupted_run() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let source = temp_dir.path().join("music");
+        let destination = temp_dir.path().join("backups");
+        let resume_dir = temp_dir.path().join("resume");
+        fs::create_dir_all(&source)?;
+        fs::create_dir_all(&destination)?;
+
+        fs::write(source.join("a.flac"), vec![b'a'; 3000])?;
+        fs::write(source.join("b.flac"), vec![b'b'; 2000])?;
+        fs::write(source.join("c.flac"), vec![b'c'; 1000])?;
+
+        let mut archiver = Archiver::new(&source, &destination, "music.tar.zst".to_string(), Algorithm::TarZst)
+            .with_resume(resume_dir.clone());
+        archiver.resume_part_size = 1;
+
+        // Simulate a crash after the first part was written and recorded.
+        let files = archiver.prepare_files(&|_| {})?;
+        let planned: Vec<(String, u64)> = files
+            .iter()
+            .map(|p| {
+                let name = p.strip_prefix(&source).unwrap().to_string_lossy().to_string();
+                (name, fs::metadata(p).unwrap().len())
+            })
+            .collect();
+        let mut state = ResumeState::plan("music.tar.zst", &source, &destination, "tar.zst", &planned, 1);
+        assert_eq!(state.part_count(), 3);
+
+        let state_path = ResumeState::state_path(&resume_dir, &source, &destination, "tar.zst");
+        archiver.write_part(&state, 0, None, &|_| {})?;
+        state.completed_parts = 1;
+        state.save(&state_path)?;
+
+        // The first file is gone; resuming must not read it again.
+        fs::remove_file(source.join("a.flac"))?;
+
+        let result = archiver.compress()?;
+        let archive_path = result.path;
+        assert_eq!(result.files, vec!["a.flac", "b.flac", "c.flac"]);
+        assert!(!state_path.exists());
+        assert!(!state.part_path(0).exists());
+
+        let decoder = zstd::stream::read::Decoder::new(File::open(&archive_path)?)?;
+        let mut archive = tar::Archive::new(decoder);
+        let mut entries = Vec::new();
+        for entry in archive.entries()? {
+            let entry = entry?;
+            entries.push((entry.path()?.to_string_lossy().to_string(), entry.size()));
+        }
+
+        assert_eq!(
+            entries,
+            vec![
+                ("a.flac".to_string(), 3000),
+                ("b.flac".to_string(), 2000),
+                ("c.flac".to_string(), 1000),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_source_change_between_scan_and_append_is_detected() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let source = temp_dir.path();
+        let track = source.join("live.flac");
+        fs::write(&track, b"first take")?;
+
+        let archiver = Archiver::new(source, source, "out.tar.zst".to_string(), Algorithm::TarZst)
+            .with_source_verification(true, false);
+        archiver.snapshot_sources(std::slice::from_ref(&track));
+
+        archiver.check_source(&track, "live.flac")?;
+        assert!(archiver.notes.borrow().changed.is_empty());
+
+        fs::write(&track, b"second, longer take")?;
+        archiver.check_source(&track, "live.flac")?;
+        assert_eq!(archiver.notes.borrow().changed, vec!["live.flac"]);
+
+        let strict = Archiver::new(source, source, "out.tar.zst".to_string(), Algorithm::TarZst)
+            .with_source_verification(false, true);
+        strict.snapshot_sources(std::slice::from_ref(&track));
+        fs::write(&track, b"third")?;
+        assert!(strict.check_source(&track, "live.flac").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_algorithm_aliases() {
+        for alias in ["tar.gz", "gz", "gzip", "tgz", "TGZ"] {
+            assert_eq!(alias.parse::<Algorithm>(), Ok(Algorithm::TarGz), "{}", alias);
+        }
+        for alias in ["tar.zst", "zst", "zstd", "tzst"] {
+            assert_eq!(alias.parse::<Algorithm>(), Ok(Algorithm::TarZst), "{}", alias);
+        }
+        assert_eq!("zip".parse::<Algorithm>(), Ok(Algorithm::Zip));
+    }
+
+    #[test]
+    fn test_algorithm_properties() {
+        for algo in Algorithm::all() {
+            assert_eq!(algo.as_str().parse::<Algorithm>(), Ok(algo));
+            assert_eq!(algo.to_string(), algo.as_str());
+            assert!(algo.level_range().contains(&algo.default_level()));
+
+            match algo {
+                Algorithm::TarGz => {
+                    assert_eq!(algo.extension(), "tar.gz");
+                    assert_eq!(algo.level_range(), 0..=9);
+                    assert!(!algo.supports_encryption());
+                    assert!(algo.is_tar());
+                }
+                Algorithm::TarZst => {
+                    assert_eq!(algo.extension(), "tar.zst");
+                    assert_eq!(algo.level_range(), 1..=22);
+                    assert!(!algo.supports_encryption());
+                    assert!(algo.is_tar());
+                }
+                Algorithm::Zip => {
+                    assert_eq!(algo.extension(), "zip");
+                    assert_eq!(algo.level_range(), 0..=9);
+                    assert!(algo.supports_encryption());
+                    assert!(!algo.is_tar());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_level_is_clamped_to_algorithm_range() {
+        assert_eq!(
+            Algorithm::TarGz.clamp_level(19),
+            (9, Some("level 19 exceeds tar.gz max 9, using 9".to_string()))
+        );
+        assert_eq!(Algorithm::Zip.clamp_level(19), (9, Some("level 19 exceeds zip max 9, using 9".to_string())));
+        assert_eq!(
+            Algorithm::TarZst.clamp_level(30),
+            (22, Some("level 30 exceeds tar.zst max 22, using 22".to_string()))
+        );
+        assert_eq!(
+            Algorithm::TarZst.clamp_level(0),
+            (1, Some("level 0 is below tar.zst min 1, using 1".to_string()))
+        );
+        assert_eq!(Algorithm::TarZst.clamp_level(19), (19, None));
+    }
+
+    #[test]
+    fn test_algorithm_typo_suggestion() {
+        assert_eq!(Algorithm::suggest("targz"), Some(Algorithm::TarGz));
+        assert_eq!(Algorithm::suggest("tar.zts"), Some(Algorithm::TarZst));
+        assert_eq!(Algorithm::suggest("zpi"), Some(Algorithm::Zip));
+        assert_eq!(Algorithm::suggest("rar5archive"), None);
+
+        let err = "targz".parse::<Algorithm>().unwrap_err();
+        assert!(err.contains("did you mean tar.gz?"));
+    }
+
+    #[test]
+    fn test_free_space_preflight() -> Result<()> {
+        assert!(ensure_free_space(900, 100, 1000).is_ok());
+        assert!(ensure_free_space(900, 101, 1000).is_err());
+
+        let source_dir = tempfile::tempdir()?;
+        let dest_dir = tempfile::tempdir()?;
+        fs::write(source_dir.path().join("mix.wav"), vec![0u8; 4096])?;
+
+        // No real filesystem has u64::MAX / 2 bytes free.
+        let archiver = Archiver::new(source_dir.path(), dest_dir.path(), "out.tar.zst".to_string(), Algorithm::TarZst)
+            .with_min_free_space(u64::MAX / 2, false);
+        let err = archiver.compress().unwrap_err();
+        assert!(err.to_string().contains("Not enough free space"));
+        assert!(!dest_dir.path().join("out.tar.zst").exists());
+
+        let forced = Archiver::new(source_dir.path(), dest_dir.path(), "out.tar.zst".to_string(), Algorithm::TarZst)
+            .with_min_free_space(u64::MAX / 2, true);
+        let result = forced.compress()?;
+        assert!(result.path.exists());
+        assert_eq!(result.warnings.len(), 1);
+        assert!(result.warnings[0].contains("Not enough free space"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_compress_result_fields() -> Result<()> {
+        let source_dir = tempfile::tempdir()?;
+        let dest_dir = tempfile::tempdir()?;
+        fs::create_dir(source_dir.path().join("album"))?;
+        fs::write(source_dir.path().join("album/01.wav"), vec![7u8; 8192])?;
+        fs::write(source_dir.path().join("cover.jpg"), vec![1u8; 512])?;
+
+        let archiver = Archiver::new(source_dir.path(), dest_dir.path(), "out.zip".to_string(), Algorithm::Zip)
+            .with_adaptive_level(true);
+        let result = archiver.compress()?;
+
+        assert_eq!(result.path, dest_dir.path().join("out.zip"));
+        assert_eq!(result.files, vec!["album/01.wav", "cover.jpg"]);
+        assert_eq!(result.bytes_written, fs::metadata(&result.path)?.len());
+        assert!(result.bytes_written > 0);
+        assert!(result.skipped.is_empty());
+        assert!(result.changed.is_empty());
+        assert!(result.warnings.is_empty());
+        assert!(!result.adaptive.expect("adaptive level recorded").store_only);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_ndjson_progress_lines_count_up() -> Result<()> {
+        let source_dir = tempfile::tempdir()?;
+        let dest_dir = tempfile::tempdir()?;
+        for name in ["01.flac", "02.flac", "03.flac"] {
+            fs::write(source_dir.path().join(name), name.repeat(100))?;
+        }
+
+        let log_path = dest_dir.path().join("progress.ndjson");
+        Archiver::new(source_dir.path(), dest_dir.path(), "out.tar.zst".to_string(), Algorithm::TarZst)
+            .with_progress_callback(ndjson_progress("compress", File::create(&log_path)?, Duration::ZERO))
+            .compress()?;
+
+        let events: Vec<serde_json::Value> = fs::read_to_string(&log_path)?
+            .lines()
+            .map(serde_json::from_str)
+            .collect::<std::result::Result<_, _>>()?;
+        assert_eq!(events.len(), 5);
+        assert!(events.iter().all(|event| event["stage"] == "compress" && event["total"] == 2100));
+        let done: Vec<u64> = events.iter().map(|event| event["done"].as_u64().unwrap()).collect();
+        assert!(done.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert_eq!(done.last(), Some(&2100));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_progress_callback_reports_each_file() -> Result<()> {
+        let source_dir = tempfile::tempdir()?;
+        let dest_dir = tempfile::tempdir()?;
+        for name in ["01.flac", "02.flac", "03.flac"] {
+            fs::write(source_dir.path().join(name), name.as_bytes())?;
+        }
+
+        let events = std::rc::Rc::new(RefCell::new(Vec::new()));
+        let recorder = events.clone();
+        let archiver = Archiver::new(source_dir.path(), dest_dir.path(), "out.tar.gz".to_string(), Algorithm::TarGz)
+            .with_progress_callback(move |event| recorder.borrow_mut().push(event));
+        let result = archiver.compress()?;
+
+        let events = events.borrow();
+        let added = events
+            .iter()
+            .filter(|e| matches!(e, ProgressEvent::FileAdded { .. }))
+            .count();
+        assert_eq!(added, result.files.len());
+        assert_eq!(events.first(), Some(&ProgressEvent::ScanStarted));
+        assert_eq!(events.last(), Some(&ProgressEvent::Finished));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_all_excluded_source_is_rejected() -> Result<()> {
+        let source_dir = tempfile::tempdir()?;
+        let dest_dir = tempfile::tempdir()?;
+        fs::write(source_dir.path().join("notes.tmp"), b"scratch")?;
+
+        let archiver = Archiver::new(source_dir.path(), dest_dir.path(), "out.tar.zst".to_string(), Algorithm::TarZst)
+            .with_excludes(vec!["*.tmp".to_string()]);
+        let err = archiver.compress().unwrap_err();
+        assert!(err.to_string().contains("No files to back up"));
+        assert!(!dest_dir.path().join("out.tar.zst").exists());
+
+        let allowed = Archiver::new(source_dir.path(), dest_dir.path(), "out.tar.zst".to_string(), Algorithm::TarZst)
+            .with_excludes(vec!["*.tmp".to_string()])
+            .with_allow_empty(true);
+        let result = allowed.compress()?;
+        assert!(result.files.is_empty());
+        assert!(result.path.exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_entry_names_use_forward_slashes() {
+        let nested = Path::new("Artist").join("Album").join("01.flac");
+        assert_eq!(archive_entry_name(&nested), "Artist/Album/01.flac");
+        assert_eq!(archive_entry_name(Path::new("cover.jpg")), "cover.jpg");
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_windows_entry_names_in_zip() -> Result<()> {
+        let source_dir = tempfile::tempdir()?;
+        let dest_dir = tempfile::tempdir()?;
+        fs::create_dir(source_dir.path().join("Album"))?;
+        fs::write(source_dir.path().join("Album\\01.flac"), b"fLaC")?;
+
+        let archiver = Archiver::new(source_dir.path(), dest_dir.path(), "out.zip".to_string(), Algorithm::Zip);
+        let result = archiver.compress()?;
+
+        let zip = zip::ZipArchive::new(File::open(&result.path)?)?;
+        assert_eq!(zip.file_names().collect::<Vec<_>>(), vec!["Album/01.flac"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_zip_password_uses_aes() -> Result<()> {
+        let source_dir = tempfile::tempdir()?;
+        let dest_dir = tempfile::tempdir()?;
+        fs::write(source_dir.path().join("01.flac"), b"fLaC secret")?;
+
+        let result = Archiver::new(source_dir.path(), dest_dir.path(), "secret.zip".to_string(), Algorithm::Zip)
+            .with_password("correct horse battery".to_string())
+            .compress()?;
+
+        // Local header: encrypted flag set, compression method 99 (WinZip AES).
+        let raw = fs::read(&result.path)?;
+        assert_eq!(&raw[..4], b"PK\x03\x04");
+        assert_eq!(raw[6] & 1, 1);
+        assert_eq!(u16::from_le_bytes([raw[8], raw[9]]), 99);
+
+        let mut zip = zip::ZipArchive::new(File::open(&result.path)?)?;
+        assert!(zip.by_index(0).is_err());
+        assert!(zip.by_index_decrypt(0, b"wrong password").is_err());
+
+        let mut content = String::new();
+        zip.by_index_decrypt(0, b"correct horse battery")?.read_to_string(&mut content)?;
+        assert_eq!(content, "fLaC secret");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_archive_comment_is_embedded() -> Result<()> {
+        let source_dir = tempfile::tempdir()?;
+        let dest_dir = tempfile::tempdir()?;
+        fs::write(source_dir.path().join("01.flac"), b"fLaC")?;
+
+        let zip_result = Archiver::new(source_dir.path(), dest_dir.path(), "music.zip".to_string(), Algorithm::Zip)
+            .with_comment("before the move")
+            .compress()?;
+        let zip = zip::ZipArchive::new(File::open(&zip_result.path)?)?;
+        let comment = String::from_utf8_lossy(zip.comment()).to_string();
+        assert!(comment.starts_with("Zencore backup: music.zip\n"));
+        assert!(comment.contains(&format!("Source: {}", source_dir.path().display())));
+        assert!(comment.ends_with("\nbefore the move"));
+
+        // Read from a snapshot, the comment still names the user's folder.
+        let gz_result = Archiver::new(source_dir.path(), dest_dir.path(), "music.tar.gz".to_string(), Algorithm::TarGz)
+            .with_display_source("/home/user/Music")
+            .with_comment("")
+            .compress()?;
+        let gz = flate2::read::GzDecoder::new(File::open(&gz_result.path)?);
+        let header = gz.header().expect("gzip header");
+        assert_eq!(header.filename(), Some(&b"music.tar"[..]));
+        let comment = String::from_utf8_lossy(header.comment().expect("gzip comment")).to_string();
+        assert!(comment.starts_with("Zencore backup: music.tar.gz"));
+        assert!(comment.contains(&format!("Source: {}", Path::new("/home/user/Music").display())), "{}", comment);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_progress_bar_counts_bytes() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let big = temp_dir.path().join("big.flac");
+        let small = temp_dir.path().join("small.cue");
+        fs::write(&big, vec![0u8; 3000])?;
+        fs::write(&small, vec![0u8; 100])?;
+
+        let pb = ProgressBar::hidden();
+        let progress = bar_progress(pb.clone());
+        progress(ProgressEvent::ScanFinished {
+            files: 2,
+            total_bytes: Archiver::total_size(&[big.clone(), small]),
+        });
+        assert_eq!(pb.length(), Some(3100));
+
+        Archiver::file_added(&progress, &big, "big.flac");
+        assert_eq!(pb.position(), 3000);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_compression_level_parsing() {
+        assert_eq!("auto".parse::<CompressionLevel>(), Ok(CompressionLevel::Auto));
+        assert_eq!("AUTO".parse::<CompressionLevel>(), Ok(CompressionLevel::Auto));
+        assert_eq!("19".parse::<CompressionLevel>(), Ok(CompressionLevel::Fixed(19)));
+        assert!("fast".parse::<CompressionLevel>().is_err());
     }
 }
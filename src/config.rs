@@ -1,13 +1,29 @@
 use anyhow::{Context, Result};
 use directories::ProjectDirs;
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use crate::fuzzer::FuzzerConfig;
+use crate::path_utils::SymlinkPolicy;
+use crate::path_utils::PathUtils;
+use crate::state::{ContentsMode, StateBackend};
+
+/// Version of the config layout this build writes. Files without a
+/// `schema_version` predate versioning and count as 0.
+pub const CONFIG_SCHEMA_VERSION: u32 = 1;
+
+/// Keys that moved, as (schema version that moved them, old dotted key,
+/// new dotted key). `Config::migrate` carries values over from older files.
+const RENAMED_KEYS: &[(u32, &str, &str)] = &[];
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Config {
+    /// Layout version, bumped when keys are renamed or removed.
+    #[serde(default)]
+    pub schema_version: u32,
+
     #[serde(default = "default_algorithm")]
     pub default_algorithm: String,
 
@@ -41,9 +57,17 @@ pub struct Config {
     #[serde(default = "default_true")]
     pub generate_checksum_file: bool,
 
+    /// Overwrite an existing `<archive>.sha256` that holds something else.
+    #[serde(default)]
+    pub replace_existing_checksum_file: bool,
+
     #[serde(default = "default_true")]
     pub verify_after_backup: bool,
 
+    /// Re-check the archive against its checksum right before uploading it.
+    #[serde(default)]
+    pub verify_before_upload: bool,
+
     #[serde(default)]
     pub remote: Option<RemoteConfig>,
 
@@ -55,6 +79,146 @@ pub struct Config {
 
     #[serde(default)]
     pub naming_presets: Vec<NamingPreset>,
+
+    /// Store the archive catalog as `archives.json.zst`.
+    #[serde(default)]
+    pub compress_state: bool,
+
+    /// Keep the archive catalog in `archives.json` or in SQLite
+    /// (`archives.db`), which loads faster once it holds many archives.
+    #[serde(default)]
+    pub state_backend: StateBackend,
+
+    #[serde(default)]
+    pub contents_mode: ContentsMode,
+
+    /// ASCII-armor age-encrypted tars (larger, but text-safe).
+    #[serde(default)]
+    pub age_armor: bool,
+
+    /// `age1...` public keys checked by `backup --verify-identity`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub age_recipients: Vec<String>,
+
+    /// age identity (private key) file that should open archives for
+    /// `age_recipients`, proven with a test roundtrip by `--verify-identity`.
+    #[serde(default)]
+    pub age_identity_file: Option<String>,
+
+    #[serde(default = "default_password_min_length")]
+    pub password_min_length: usize,
+
+    /// Uploads larger than this many bytes ask for confirmation first
+    /// (skipped with `--yes`).
+    #[serde(default = "default_large_upload_threshold")]
+    pub large_upload_threshold: u64,
+
+    /// Sources with more files than this ask for confirmation before the
+    /// backup starts (skipped with `--force`).
+    #[serde(default = "default_warn_file_count")]
+    pub warn_file_count: usize,
+
+    /// Sources larger than this many bytes ask for confirmation before the
+    /// backup starts (skipped with `--force`).
+    #[serde(default = "default_warn_total_size")]
+    pub warn_total_size: u64,
+
+    /// What scans and archives do with symbolic links: follow, skip or store.
+    #[serde(default)]
+    pub symlinks: SymlinkPolicy,
+
+    /// How many times a source file is reopened after a failed open or
+    /// read (flaky network mounts) before the backup gives up.
+    #[serde(default = "default_read_retries")]
+    pub read_retries: u32,
+
+    /// Archives bigger than this fraction of their source get a tip about
+    /// storing already-compressed media instead.
+    #[serde(default = "default_min_compression_ratio")]
+    pub min_compression_ratio: f64,
+
+    /// Named option sets for `zencore backup --profile <name>`.
+    #[serde(default)]
+    pub profiles: HashMap<String, BackupProfile>,
+
+    /// Top-level keys this build doesn't know (say, from a newer one),
+    /// written back as they were so saving doesn't drop them.
+    #[serde(flatten)]
+    pub unknown_keys: toml::Table,
+}
+
+/// One field edited by `zencore config set`, as TOML text.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigChange {
+    pub key: String,
+    /// `None` when the field was unset.
+    pub before: Option<String>,
+    pub after: String,
+}
+
+/// Backup options saved under a name. Anything left unset falls back to the
+/// command line or the usual defaults.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct BackupProfile {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub destination: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub algorithm: Option<String>,
+
+    /// A number or `auto`, as for `--level`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub level: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub threads: Option<usize>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encrypt: Option<bool>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upload: Option<bool>,
+
+    /// Upload target such as `s3://bucket/prefix` or `gdrive:Music`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remote: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub checksums: Option<Vec<String>>,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude: Vec<String>,
+}
+
+impl BackupProfile {
+    /// The options of the most recent successful backup, if any.
+    pub fn load_last() -> Result<Option<Self>> {
+        let path = Self::last_path()?;
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let content = fs::read_to_string(&path).context("Failed to read last backup options")?;
+        Ok(Some(serde_json::from_str(&content).context("Failed to parse last backup options")?))
+    }
+
+    pub fn save_last(&self) -> Result<()> {
+        let path = Self::last_path()?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        crate::utils::write_atomic(&path, serde_json::to_string_pretty(self)?.as_bytes())
+            .context("Failed to write last backup options")?;
+        Ok(())
+    }
+
+    fn last_path() -> Result<PathBuf> {
+        Ok(Config::state_dir()?.join("last_backup.json"))
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -79,6 +243,12 @@ pub struct RcloneConfig {
 
     #[serde(default = "default_true")]
     pub verify_after_upload: bool,
+
+    /// Extra flags passed to every rclone call, e.g. `--transfers=8` or
+    /// `--config=/etc/rclone.conf`. They are never logged, so they may hold
+    /// credentials, but prefer rclone's own config or environment for those.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub extra_args: Vec<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -137,17 +307,35 @@ fn default_date_format() -> String {
 }
 
 fn default_music_folders() -> Vec<String> {
-    vec![
-        "~/Music".to_string(),
-        "~/Documents/Music".to_string(),
-    ]
+    PathUtils::default_music_folders()
 }
 
 fn default_backup_folders() -> Vec<String> {
-    vec![
-        "~/Backups".to_string(),
-        "~/Documents/Backups".to_string(),
-    ]
+    PathUtils::default_backup_folders()
+}
+
+fn default_password_min_length() -> usize {
+    8
+}
+
+fn default_large_upload_threshold() -> u64 {
+    1024 * 1024 * 1024
+}
+
+fn default_warn_file_count() -> usize {
+    200_000
+}
+
+fn default_read_retries() -> u32 {
+    3
+}
+
+fn default_min_compression_ratio() -> f64 {
+    0.98
+}
+
+fn default_warn_total_size() -> u64 {
+    100 * 1024 * 1024 * 1024
 }
 
 fn default_cipher() -> String {
@@ -162,11 +350,11 @@ fn default_true() -> bool {
     true
 }
 
-fn default_mysql_port() -> u16 {
+pub(crate) fn default_mysql_port() -> u16 {
     3306
 }
 
-fn default_table_name() -> String {
+pub(crate) fn default_table_name() -> String {
     "backups".to_string()
 }
 
@@ -191,6 +379,7 @@ fn default_exclude_patterns() -> Vec<String> {
 impl Default for Config {
     fn default() -> Self {
         Self {
+            schema_version: CONFIG_SCHEMA_VERSION,
             default_algorithm: default_algorithm(),
             date_format: default_date_format(),
             music_folders: default_music_folders(),
@@ -202,10 +391,27 @@ impl Default for Config {
             num_threads: 0,
             compression_level: None,
             generate_checksum_file: true,
+            replace_existing_checksum_file: false,
             verify_after_backup: true,
+            verify_before_upload: false,
             remote: None,
             fuzzer: FuzzerSettings::default(),
             sort_files_by_size: true,
+            compress_state: false,
+            state_backend: StateBackend::default(),
+            contents_mode: ContentsMode::Full,
+            age_armor: false,
+            age_recipients: Vec::new(),
+            age_identity_file: None,
+            password_min_length: default_password_min_length(),
+            large_upload_threshold: default_large_upload_threshold(),
+            warn_file_count: default_warn_file_count(),
+            warn_total_size: default_warn_total_size(),
+            symlinks: SymlinkPolicy::default(),
+            read_retries: default_read_retries(),
+            min_compression_ratio: default_min_compression_ratio(),
+            profiles: HashMap::new(),
+            unknown_keys: toml::Table::new(),
             naming_presets: vec![
                 NamingPreset {
                     name: "Daily Backup".to_string(),
@@ -228,6 +434,15 @@ impl Default for Config {
 }
 
 impl Config {
+    /// `extra_args` of the configured rclone remote, if any.
+    pub fn rclone_extra_args(&self) -> &[String] {
+        self.remote
+            .as_ref()
+            .and_then(|remote| remote.rclone.as_ref())
+            .map(|rclone| rclone.extra_args.as_slice())
+            .unwrap_or_default()
+    }
+
     pub fn load() -> Result<Self> {
         let config_path = Self::config_path()?;
 
@@ -237,29 +452,222 @@ impl Config {
             return Ok(config);
         }
 
-        let content = fs::read_to_string(&config_path)
+        Self::load_from(&config_path)
+    }
+
+    pub fn load_from(config_path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(config_path)
             .context("Failed to read config file")?;
 
         if config_path.extension().and_then(|s| s.to_str()) == Some("json") {
-            serde_json::from_str(&content).context("Failed to parse JSON config")
-        } else {
-            toml::from_str(&content).context("Failed to parse TOML config")
+            return serde_json::from_str(&content).context("Failed to parse JSON config");
+        }
+
+        // Upgraded in memory only; the file is rewritten by the next save.
+        let mut table: toml::Table = toml::from_str(&content).context("Failed to parse TOML config")?;
+        if Self::migrate(&mut table) {
+            tracing::debug!(path = %config_path.display(), "config upgraded to schema {}", CONFIG_SCHEMA_VERSION);
+        }
+        table.try_into().context("Failed to parse TOML config")
+    }
+
+    /// Copies a config file from an older schema to `<file>.bak` before it
+    /// is first overwritten, since saving drops its comments.
+    fn backup_before_upgrade(config_path: &Path) -> Result<()> {
+        let Ok(content) = fs::read_to_string(config_path) else {
+            return Ok(());
+        };
+        let Ok(table) = toml::from_str::<toml::Table>(&content) else {
+            return Ok(());
+        };
+        let version = table.get("schema_version").and_then(toml::Value::as_integer).unwrap_or(0);
+        if version >= CONFIG_SCHEMA_VERSION as i64 {
+            return Ok(());
+        }
+
+        let mut backup = config_path.as_os_str().to_owned();
+        backup.push(".bak");
+        fs::copy(config_path, &backup).context("Failed to back up config file before upgrading it")?;
+        crate::utils::print_info(&format!(
+            "Upgraded {} to config schema {}; the previous file is kept as {}",
+            config_path.display(),
+            CONFIG_SCHEMA_VERSION,
+            Path::new(&backup).display()
+        ));
+        Ok(())
+    }
+
+    /// Brings a config table from an older schema up to
+    /// `CONFIG_SCHEMA_VERSION`, moving renamed keys. Returns whether
+    /// anything changed.
+    pub fn migrate(table: &mut toml::Table) -> bool {
+        Self::migrate_with(table, RENAMED_KEYS)
+    }
+
+    fn migrate_with(table: &mut toml::Table, renames: &[(u32, &str, &str)]) -> bool {
+        let version = table
+            .get("schema_version")
+            .and_then(toml::Value::as_integer)
+            .unwrap_or(0);
+        if version >= CONFIG_SCHEMA_VERSION as i64 {
+            return false;
+        }
+
+        for (_, old, new) in renames.iter().filter(|(since, _, _)| *since as i64 > version) {
+            let Some(value) = Self::take_key(table, old) else {
+                continue;
+            };
+            // A file that already has the new key keeps it.
+            if Self::lookup_table(table, new).is_none() {
+                Self::insert_key(table, new, value);
+            }
+        }
+
+        table.insert("schema_version".to_string(), toml::Value::Integer(CONFIG_SCHEMA_VERSION as i64));
+        true
+    }
+
+    fn take_key(table: &mut toml::Table, key: &str) -> Option<toml::Value> {
+        match key.split_once('.') {
+            Some((section, rest)) => Self::take_key(table.get_mut(section)?.as_table_mut()?, rest),
+            None => table.remove(key),
+        }
+    }
+
+    fn lookup_table<'a>(table: &'a toml::Table, key: &str) -> Option<&'a toml::Value> {
+        match key.split_once('.') {
+            Some((section, rest)) => Self::lookup_table(table.get(section)?.as_table()?, rest),
+            None => table.get(key),
+        }
+    }
+
+    fn insert_key(table: &mut toml::Table, key: &str, value: toml::Value) {
+        match key.split_once('.') {
+            Some((section, rest)) => {
+                let section = table
+                    .entry(section)
+                    .or_insert_with(|| toml::Value::Table(toml::Table::new()));
+                if let Some(section) = section.as_table_mut() {
+                    Self::insert_key(section, rest, value);
+                }
+            }
+            None => {
+                table.insert(key.to_string(), value);
+            }
         }
     }
 
     pub fn save(&self) -> Result<()> {
-        let config_path = Self::config_path()?;
+        self.save_to(&Self::config_path()?)
+    }
 
+    pub fn save_to(&self, config_path: &Path) -> Result<()> {
         if let Some(parent) = config_path.parent() {
             fs::create_dir_all(parent)?;
         }
 
-        let content = toml::to_string_pretty(self)?;
-        fs::write(&config_path, content)?;
+        Self::backup_before_upgrade(config_path)?;
+        let content = self.to_toml()?;
+        crate::utils::write_atomic(config_path, content.as_bytes())
+            .context("Failed to write config file")?;
 
         Ok(())
     }
 
+    /// Every field as TOML, defaults included; unset optional fields are
+    /// listed commented out under their section.
+    pub fn to_toml(&self) -> Result<String> {
+        // TOML has no null, so find the unset fields in the JSON form.
+        let mut unset = BTreeMap::new();
+        Self::collect_unset(&serde_json::to_value(self)?, "", &mut unset);
+
+        let commented = |header: &str| {
+            unset
+                .get(header)
+                .map(|keys: &Vec<String>| keys.iter().map(|key| format!("# {} =\n", key)).collect::<String>())
+                .unwrap_or_default()
+        };
+        let mut out = commented("");
+        for line in toml::to_string_pretty(self)?.lines() {
+            out.push_str(line);
+            out.push('\n');
+            out.push_str(&commented(line.trim()));
+        }
+        Ok(out)
+    }
+
+    /// Adds the null fields of `value` under the `[section]` header line
+    /// they belong to, `""` for the top level. Arrays are not searched.
+    fn collect_unset(value: &serde_json::Value, section: &str, unset: &mut BTreeMap<String, Vec<String>>) {
+        let Some(fields) = value.as_object() else {
+            return;
+        };
+        for (key, field) in fields {
+            let key = if key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+                key.clone()
+            } else {
+                format!("{:?}", key)
+            };
+            let path = if section.is_empty() { key.clone() } else { format!("{}.{}", section, key) };
+            if field.is_null() {
+                let header = if section.is_empty() { String::new() } else { format!("[{}]", section) };
+                unset.entry(header).or_default().push(key);
+            } else {
+                Self::collect_unset(field, &path, unset);
+            }
+        }
+    }
+
+    /// A copy of this config with the dotted `key` (e.g. `remote.enabled`)
+    /// set to `value`, which is read as a TOML value or else as a string.
+    pub fn with_value(&self, key: &str, value: &str) -> Result<(Self, ConfigChange)> {
+        let mut root = toml::Value::try_from(self)?;
+        let before = Self::lookup(&root, key).map(|v| v.to_string());
+
+        let parsed = match toml::from_str::<toml::Table>(&format!("v = {}", value)) {
+            Ok(mut table) if !matches!(before.as_deref(), Some(b) if b.starts_with('"')) => {
+                table.remove("v").expect("parsed key")
+            }
+            _ => toml::Value::String(value.to_string()),
+        };
+
+        let (parents, field) = key.rsplit_once('.').map_or((None, key), |(p, f)| (Some(p), f));
+        let mut table = root.as_table_mut().expect("config serializes to a table");
+        for part in parents.into_iter().flat_map(|p| p.split('.')) {
+            table = table
+                .entry(part)
+                .or_insert_with(|| toml::Value::Table(toml::Table::new()))
+                .as_table_mut()
+                .with_context(|| format!("'{}' is not a section", part))?;
+        }
+        table.insert(field.to_string(), parsed);
+
+        let updated: Config = root
+            .try_into()
+            .with_context(|| format!("Invalid value for {}: {}", key, value))?;
+
+        // Unknown keys are dropped by serde, or kept aside at the top
+        // level, so check the key survived as a field.
+        let top_level = key.split('.').next().unwrap_or(key);
+        let after = Self::lookup(&toml::Value::try_from(&updated)?, key)
+            .filter(|_| !updated.unknown_keys.contains_key(top_level))
+            .map(|v| v.to_string())
+            .with_context(|| format!("Unknown config key: {}", key))?;
+
+        Ok((
+            updated,
+            ConfigChange {
+                key: key.to_string(),
+                before,
+                after,
+            },
+        ))
+    }
+
+    fn lookup<'a>(root: &'a toml::Value, key: &str) -> Option<&'a toml::Value> {
+        key.split('.').try_fold(root, |value, part| value.get(part))
+    }
+
     pub fn config_path() -> Result<PathBuf> {
         let proj_dirs = ProjectDirs::from("com", "Blues24", "zencore")
             .context("Failed to determine config dir")?;
@@ -279,6 +687,7 @@ impl Config {
             max_depth: self.fuzzer.max_depth,
             exclude_patterns: self.fuzzer.exclude_patterns.clone(),
             case_sensitive: self.fuzzer.case_sensitive,
+            symlinks: self.symlinks,
         }
     }
 
@@ -296,3 +705,81 @@ impl Config {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unversioned_config_is_upgraded_on_save() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let config_path = dir.path().join("config.toml");
+        let original = "# my settings\ndefault_algorithm = \"zip\"\nfrom_a_newer_build = \"kept\"\n\n[newer_section]\nflag = true\n";
+        fs::write(&config_path, original)?;
+
+        // Loading never touches the file.
+        let config = Config::load_from(&config_path)?;
+        assert_eq!(config.schema_version, CONFIG_SCHEMA_VERSION);
+        assert_eq!(config.default_algorithm, "zip");
+        assert_eq!(fs::read_to_string(&config_path)?, original);
+
+        config.save_to(&config_path)?;
+        let backup = dir.path().join("config.toml.bak");
+        assert_eq!(fs::read_to_string(&backup)?, original);
+        let upgraded = fs::read_to_string(&config_path)?;
+        assert!(upgraded.contains(&format!("schema_version = {}", CONFIG_SCHEMA_VERSION)));
+        assert!(upgraded.contains("from_a_newer_build = \"kept\""), "{}", upgraded);
+        assert!(upgraded.contains("[newer_section]\nflag = true\n"), "{}", upgraded);
+
+        // Saving an up-to-date file leaves the backup alone.
+        fs::remove_file(&backup)?;
+        Config::load_from(&config_path)?.save_to(&config_path)?;
+        assert!(!backup.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_unset_fields_are_written_commented_out() -> Result<()> {
+        let mut config = Config::default();
+        let toml = config.to_toml()?;
+        // Top-level keys go before the first section.
+        let top_level = &toml[..toml.find("\n[").unwrap()];
+        for key in ["compression_level", "remote", "age_identity_file"] {
+            assert!(top_level.contains(&format!("# {} =\n", key)), "{}", toml);
+        }
+
+        config.compression_level = Some(19);
+        config.remote = Some(RemoteConfig {
+            enabled: true,
+            auto_upload: false,
+            rclone: None,
+            database: None,
+        });
+        let toml = config.to_toml()?;
+        assert!(!toml.contains("# compression_level"));
+        assert!(toml.contains("[remote]\n# database =\n# rclone =\n"), "{}", toml);
+
+        let reloaded: Config = toml::from_str(&toml)?;
+        assert_eq!(reloaded.compression_level, Some(19));
+        assert!(reloaded.remote.is_some_and(|remote| remote.enabled && remote.rclone.is_none()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_migrate_moves_renamed_keys() -> Result<()> {
+        let mut table: toml::Table = toml::from_str(
+            "algorithm_name = \"tar.gz\"\n[remote]\nold_flag = true\n",
+        )?;
+        let renames = [
+            (1, "algorithm_name", "default_algorithm"),
+            (1, "remote.old_flag", "remote.enabled"),
+        ];
+
+        assert!(Config::migrate_with(&mut table, &renames));
+        assert_eq!(table["default_algorithm"].as_str(), Some("tar.gz"));
+        assert!(!table.contains_key("algorithm_name"));
+        assert_eq!(table["remote"]["enabled"].as_bool(), Some(true));
+        assert!(!Config::migrate_with(&mut table, &renames));
+        Ok(())
+    }
+}
@@ -7,13 +7,129 @@ use argon2::{
     password_hash::{rand_core::RngCore, PasswordHasher, SaltString},
     Argon2, Params, Version,
 };
+use blake2::Blake2b512;
 use blake3;
 use indicatif::{ProgressBar, ProgressStyle};
-use sha2::{Digest as Sha2Digest, Sha256};
+use sha2::{Digest as Sha2Digest, Sha256, Sha512};
 use sha3::Sha3_256;
 use std::fs::{self, File};
-use std::io::{BufReader, Read, Write};
+use std::io::{BufRead, BufReader, Read, Write};
 use std::path::Path;
+use std::str::FromStr;
+
+use crate::error::ErrorKind;
+
+/// Length of a hex-encoded SHA-256 digest, as written to `.sha256` files.
+const SHA256_HEX_LEN: usize = 64;
+
+/// Files smaller than this are hashed in one read without a progress bar,
+/// which would only flicker.
+const SMALL_FILE_BYTES: u64 = 1024 * 1024;
+
+/// Estimated entropy below which a password is flagged as weak.
+const WEAK_PASSWORD_BITS: f64 = 50.0;
+
+/// Rules applied to encryption passwords before use.
+pub struct PasswordPolicy {
+    pub min_length: usize,
+}
+
+impl PasswordPolicy {
+    pub fn new(min_length: usize) -> Self {
+        Self { min_length }
+    }
+
+    /// Hard requirement: rejects passwords shorter than `min_length` characters.
+    pub fn check(&self, password: &str) -> Result<()> {
+        let length = password.chars().count();
+        if length < self.min_length {
+            return Err(ErrorKind::Usage.error(format!(
+                "Password too short: {} characters, at least {} required",
+                length,
+                self.min_length
+            )));
+        }
+        Ok(())
+    }
+
+    /// Soft check: a reason the password looks weak, based on a simple
+    /// character-pool entropy estimate.
+    pub fn weakness(&self, password: &str) -> Option<String> {
+        let mut pool = 0u32;
+        if password.chars().any(|c| c.is_ascii_lowercase()) {
+            pool += 26;
+        }
+        if password.chars().any(|c| c.is_ascii_uppercase()) {
+            pool += 26;
+        }
+        if password.chars().any(|c| c.is_ascii_digit()) {
+            pool += 10;
+        }
+        if password.chars().any(|c| !c.is_ascii_alphanumeric()) {
+            pool += 33;
+        }
+
+        let mut distinct: Vec<char> = password.chars().collect();
+        distinct.sort_unstable();
+        distinct.dedup();
+
+        if distinct.len() <= 2 {
+            return Some("it repeats the same characters".to_string());
+        }
+
+        let bits = password.chars().count() as f64 * (pool.max(1) as f64).log2();
+        if bits < WEAK_PASSWORD_BITS {
+            return Some(format!(
+                "about {:.0} bits of entropy, {:.0}+ recommended; use a longer password or mix character types",
+                bits, WEAK_PASSWORD_BITS
+            ));
+        }
+
+        None
+    }
+
+    /// Reads a password from the first line of `reader` (`--password-stdin`)
+    /// and applies the length check. Weak passwords only warn, since there is
+    /// nobody to confirm them.
+    pub fn read_from(&self, mut reader: impl BufRead) -> Result<String> {
+        let mut line = String::new();
+        reader.read_line(&mut line).context("Failed to read password from stdin")?;
+        let password = line.trim_end_matches(['\r', '\n']).to_string();
+
+        self.accept(password)
+    }
+
+    /// Reads a password from the environment variable `name`
+    /// (`--password-from-env`), with the same checks as `read_from`.
+    pub fn read_from_env(&self, name: &str) -> Result<String> {
+        self.read_from_var(name, std::env::var(name))
+    }
+
+    /// `read_from_env` with the variable already looked up.
+    pub fn read_from_var(&self, name: &str, value: Result<String, std::env::VarError>) -> Result<String> {
+        let password = match value {
+            Ok(value) if value.is_empty() => return Err(ErrorKind::Usage.error(format!("Environment variable {} is empty", name))),
+            Ok(value) => value,
+            Err(std::env::VarError::NotPresent) => {
+                return Err(ErrorKind::Usage.error(format!("Environment variable {} is not set", name)));
+            }
+            Err(std::env::VarError::NotUnicode(_)) => {
+                return Err(ErrorKind::Usage.error(format!("Environment variable {} is not valid UTF-8", name)));
+            }
+        };
+
+        self.accept(password)
+    }
+
+    fn accept(&self, password: String) -> Result<String> {
+        self.check(&password)?;
+        if let Some(reason) = self.weakness(&password) {
+            crate::utils::print_warning(&format!("Weak password: {}", reason));
+        }
+
+        Ok(password)
+    }
+}
 
 pub struct Encryptor {
     password: String,
@@ -30,13 +146,13 @@ impl Encryptor {
         let salt = SaltString::generate(&mut OsRng);
         
         let params = Params::new(32768, 3, 1, None)
-            .map_err(|e| anyhow::anyhow!("Failed to create Argon2 params: {}", e))?;
+            .map_err(|e| ErrorKind::Encryption.error(format!("Failed to create Argon2 params: {}", e)))?;
         
         let argon2 = Argon2::new(argon2::Algorithm::Argon2id, Version::V0x13, params);
 
         let password_hash = argon2
             .hash_password(self.password.as_bytes(), &salt)
-            .map_err(|e| anyhow::anyhow!("Password hashing failed: {}", e))?;
+            .map_err(|e| ErrorKind::Encryption.error(format!("Password hashing failed: {}", e)))?;
 
         let hash_string = password_hash.hash.context("Failed to extract hash")?;
         let hash_bytes = hash_string.as_bytes();
@@ -54,7 +170,7 @@ impl Encryptor {
 
         let file_size = fs::metadata(file_path)?.len();
         
-        let pb = ProgressBar::new(100);
+        let pb = crate::utils::progress_bar(100);
         pb.set_style(
             ProgressStyle::default_bar()
                 .template("{spinner:.green} [{bar:40.cyan/blue}] {pos}% {msg}")
@@ -78,7 +194,7 @@ impl Encryptor {
         
         let ciphertext = cipher
             .encrypt(nonce, plaintext.as_ref())
-            .map_err(|e| anyhow::anyhow!("Encryption failed: {}", e))?;
+            .map_err(|e| ErrorKind::Encryption.error(format!("Encryption failed: {}", e)))?;
         
         pb.set_position(80);
         pb.set_message("Writing...");
@@ -105,30 +221,149 @@ impl Encryptor {
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum HashAlgorithm {
     Sha256,
+    Sha512,
     Sha3_256,
+    Blake2b,
     Blake3,
+    Crc32,
+    Xxh3,
 }
 
-impl HashAlgorithm {
-    pub fn from_str(s: &str) -> Result<Self> {
+impl FromStr for HashAlgorithm {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
         match s.to_lowercase().as_str() {
             "sha256" | "sha-256" => Ok(Self::Sha256),
+            "sha512" | "sha-512" => Ok(Self::Sha512),
             "sha3" | "sha3-256" => Ok(Self::Sha3_256),
+            "blake2b" | "blake2b-512" | "blake2" => Ok(Self::Blake2b),
             "blake3" => Ok(Self::Blake3),
+            "crc32" => Ok(Self::Crc32),
+            "xxh3" | "xxhash" | "xxh3-64" => Ok(Self::Xxh3),
             _ => Err(anyhow::anyhow!("Unknown algorithm: {}", s)),
         }
     }
+}
 
+impl HashAlgorithm {
+    /// Human-readable label, for display only; the catalog stores `key`.
     pub fn name(&self) -> &str {
         match self {
             Self::Sha256 => "SHA-256",
+            Self::Sha512 => "SHA-512",
             Self::Sha3_256 => "SHA3-256",
+            Self::Blake2b => "BLAKE2b",
             Self::Blake3 => "BLAKE3",
+            Self::Crc32 => "CRC32 (non-cryptographic)",
+            Self::Xxh3 => "XXH3 (non-cryptographic)",
+        }
+    }
+
+    /// Stable identifier the catalog stores checksums under. Never change
+    /// these; labels from `name` may change freely.
+    pub fn key(&self) -> &'static str {
+        match self {
+            Self::Sha256 => "sha256",
+            Self::Sha512 => "sha512",
+            Self::Sha3_256 => "sha3-256",
+            Self::Blake2b => "blake2b",
+            Self::Blake3 => "blake3",
+            Self::Crc32 => "crc32",
+            Self::Xxh3 => "xxh3",
+        }
+    }
+
+    /// Inverse of `key`, for checksums read back from the catalog. Older
+    /// catalogs stored upper-cased `name` labels, which are accepted too, as
+    /// are plain aliases like `sha-256`.
+    pub fn from_stored_name(name: &str) -> Option<Self> {
+        Self::all()
+            .into_iter()
+            .find(|algorithm| algorithm.key().eq_ignore_ascii_case(name) || algorithm.name().eq_ignore_ascii_case(name))
+            .or_else(|| name.parse().ok())
+    }
+
+    /// Extension of the sidecar file (`archive.<ext>`) holding this checksum.
+    pub fn sidecar_extension(&self) -> &'static str {
+        match self {
+            Self::Sha256 => "sha256",
+            Self::Sha512 => "sha512",
+            Self::Sha3_256 => "sha3",
+            Self::Blake2b => "b2",
+            Self::Blake3 => "b3",
+            Self::Crc32 => "crc32",
+            Self::Xxh3 => "xxh3",
         }
     }
 
+    /// CRC32 and XXH3 only catch accidental corruption such as bit rot;
+    /// they offer no protection against deliberate tampering.
+    pub fn is_cryptographic(&self) -> bool {
+        !matches!(self, Self::Crc32 | Self::Xxh3)
+    }
+
     pub fn all() -> Vec<Self> {
-        vec![Self::Sha256, Self::Sha3_256, Self::Blake3]
+        vec![
+            Self::Sha256,
+            Self::Sha512,
+            Self::Sha3_256,
+            Self::Blake2b,
+            Self::Blake3,
+            Self::Crc32,
+            Self::Xxh3,
+        ]
+    }
+}
+
+/// Incremental state for any supported algorithm.
+enum StreamHasher {
+    Sha256(Sha256),
+    Sha512(Sha512),
+    Sha3_256(Sha3_256),
+    Blake2b(Blake2b512),
+    Blake3(Box<blake3::Hasher>),
+    Crc32(crc32fast::Hasher),
+    Xxh3(Box<xxhash_rust::xxh3::Xxh3>),
+}
+
+impl StreamHasher {
+    fn new(algorithm: HashAlgorithm) -> Self {
+        match algorithm {
+            HashAlgorithm::Sha256 => Self::Sha256(Sha256::new()),
+            HashAlgorithm::Sha512 => Self::Sha512(Sha512::new()),
+            HashAlgorithm::Sha3_256 => Self::Sha3_256(sha3::Digest::new()),
+            HashAlgorithm::Blake2b => Self::Blake2b(Blake2b512::new()),
+            HashAlgorithm::Blake3 => Self::Blake3(Box::new(blake3::Hasher::new())),
+            HashAlgorithm::Crc32 => Self::Crc32(crc32fast::Hasher::new()),
+            HashAlgorithm::Xxh3 => Self::Xxh3(Box::new(xxhash_rust::xxh3::Xxh3::new())),
+        }
+    }
+
+    fn update(&mut self, chunk: &[u8]) {
+        match self {
+            Self::Sha256(h) => h.update(chunk),
+            Self::Sha512(h) => h.update(chunk),
+            Self::Sha3_256(h) => sha3::Digest::update(h, chunk),
+            Self::Blake2b(h) => h.update(chunk),
+            Self::Blake3(h) => {
+                h.update(chunk);
+            }
+            Self::Crc32(h) => h.update(chunk),
+            Self::Xxh3(h) => h.update(chunk),
+        }
+    }
+
+    fn finish(self) -> String {
+        match self {
+            Self::Sha256(h) => format!("{:x}", h.finalize()),
+            Self::Sha512(h) => format!("{:x}", h.finalize()),
+            Self::Sha3_256(h) => format!("{:x}", sha3::Digest::finalize(h)),
+            Self::Blake2b(h) => format!("{:x}", h.finalize()),
+            Self::Blake3(h) => h.finalize().to_hex().to_string(),
+            Self::Crc32(h) => format!("{:08x}", h.finalize()),
+            Self::Xxh3(h) => format!("{:016x}", h.digest()),
+        }
     }
 }
 
@@ -145,156 +380,597 @@ impl Checker {
     ) -> Result<String> {
         let file = File::open(file_path)?;
         let file_size = file.metadata()?.len();
-        let mut reader = BufReader::with_capacity(65536, file);
 
-        let pb = ProgressBar::new(file_size);
+        let pb = Self::hash_progress_bar(file_size);
+        pb.set_message(format!("Calculating {}", algorithm.name()));
+
+        let mut hasher = StreamHasher::new(algorithm);
+        Self::stream(file, file_size, &pb, |chunk| hasher.update(chunk))?;
+
+        pb.finish_with_message(format!("✓ {} done", algorithm.name()));
+        Ok(hasher.finish())
+    }
+
+    /// Hidden for files under `SMALL_FILE_BYTES`.
+    fn hash_progress_bar(file_size: u64) -> ProgressBar {
+        if file_size < SMALL_FILE_BYTES {
+            return ProgressBar::hidden();
+        }
+
+        let pb = crate::utils::progress_bar(file_size);
         pb.set_style(
             ProgressStyle::default_bar()
-                .template("{spinner:.green} [{bar:40.cyan/blue}] {bytes}/{total_bytes} {msg}")
+                .template("{spinner:.green} [{bar:40.cyan/blue}] {bytes}/{total_bytes} {bytes_per_sec} ETA {eta} {msg}")
                 .unwrap()
                 .progress_chars("█▓░"),
         );
-        pb.set_message(format!("Calculating {}", algorithm.name()));
+        pb
+    }
 
+    /// Feeds the file through `update` in 64 KiB chunks, advancing `pb`;
+    /// small files go through in a single read.
+    fn stream(mut file: File, file_size: u64, pb: &ProgressBar, mut update: impl FnMut(&[u8])) -> Result<()> {
+        if file_size < SMALL_FILE_BYTES {
+            let mut contents = Vec::with_capacity(file_size as usize);
+            file.read_to_end(&mut contents)?;
+            update(&contents);
+            return Ok(());
+        }
+
+        let mut reader = BufReader::with_capacity(65536, file);
+        let mut buffer = [0u8; 65536];
         let mut total_read = 0u64;
 
-        match algorithm {
-            HashAlgorithm::Sha256 => {
-                let mut hasher = Sha256::new();
-                let mut buffer = [0u8; 65536];
-
-                loop {
-                    let count = reader.read(&mut buffer)?;
-                    if count == 0 {
-                        break;
-                    }
-                    hasher.update(&buffer[..count]);
-                    total_read += count as u64;
-                    pb.set_position(total_read);
-                }
-
-                pb.finish_with_message("✓ SHA-256 done");
-                Ok(format!("{:x}", hasher.finalize()))
-            }
-            HashAlgorithm::Sha3_256 => {
-                use sha3::Digest;
-                
-                let mut hasher = Sha3_256::new();
-                let mut buffer = [0u8; 65536];
-
-                loop {
-                    let count = reader.read(&mut buffer)?;
-                    if count == 0 {
-                        break;
-                    }
-                    hasher.update(&buffer[..count]);
-                    total_read += count as u64;
-                    pb.set_position(total_read);
-                }
-
-                pb.finish_with_message("✓ SHA3-256 done");
-                Ok(format!("{:x}", hasher.finalize()))
-            }
-            HashAlgorithm::Blake3 => {
-                let mut hasher = blake3::Hasher::new();
-                let mut buffer = [0u8; 65536];
-
-                loop {
-                    let count = reader.read(&mut buffer)?;
-                    if count == 0 {
-                        break;
-                    }
-                    hasher.update(&buffer[..count]);
-                    total_read += count as u64;
-                    pb.set_position(total_read);
-                }
-
-                pb.finish_with_message("✓ BLAKE3 done");
-                Ok(hasher.finalize().to_hex().to_string())
+        loop {
+            let count = reader.read(&mut buffer)?;
+            if count == 0 {
+                break;
             }
+            update(&buffer[..count]);
+            total_read += count as u64;
+            pb.set_position(total_read);
         }
+
+        Ok(())
     }
 
+    /// Computes every algorithm in a single read of the file.
     pub fn generate_multiple_checksums(
         file_path: &str,
         algorithms: &[HashAlgorithm],
     ) -> Result<Vec<(HashAlgorithm, String)>> {
-        let mut results = Vec::with_capacity(algorithms.len());
+        let file = File::open(file_path)?;
+        let file_size = file.metadata()?.len();
+
+        let pb = Self::hash_progress_bar(file_size);
+        pb.set_message(format!("Calculating {} checksum(s)", algorithms.len()));
+
+        let mut hashers: Vec<StreamHasher> = algorithms.iter().map(|a| StreamHasher::new(*a)).collect();
+        Self::stream(file, file_size, &pb, |chunk| {
+            for hasher in &mut hashers {
+                hasher.update(chunk);
+            }
+        })?;
+        pb.finish_and_clear();
+
+        Ok(algorithms
+            .iter()
+            .copied()
+            .zip(hashers.into_iter().map(StreamHasher::finish))
+            .collect())
+    }
 
-        for algo in algorithms {
-            let checksum = Self::generate_checksum_with_algorithm(file_path, *algo)?;
-            results.push((*algo, checksum));
+    /// Recomputes each `(algorithm name, expected hash)` pair from the catalog
+    /// in one pass and reports whether it still matches. Names no known
+    /// algorithm produces are skipped with a warning.
+    pub fn verify_stored_checksums(file_path: &str, stored: &[(String, String)]) -> Result<Vec<(String, bool)>> {
+        let mut known = Vec::new();
+        for (name, expected) in stored {
+            match HashAlgorithm::from_stored_name(name) {
+                Some(algorithm) => known.push((algorithm, name, expected)),
+                None => crate::utils::print_warning(&format!("Skipping unknown checksum algorithm: {}", name)),
+            }
         }
 
-        Ok(results)
+        let algorithms: Vec<HashAlgorithm> = known.iter().map(|(algorithm, _, _)| *algorithm).collect();
+        let actual = Self::generate_multiple_checksums(file_path, &algorithms)?;
+
+        Ok(known
+            .iter()
+            .zip(actual)
+            .map(|((_, name, expected), (_, hash))| (name.to_string(), Self::hashes_match(&hash, expected)))
+            .collect())
     }
 
     pub fn verify_checksum(file_path: &str, expected: &str) -> Result<bool> {
-        let actual = Self::generate_checksum(file_path)?;
-        Ok(actual.eq_ignore_ascii_case(expected))
+        Self::verify_checksum_with_algorithm(file_path, expected, HashAlgorithm::Sha256)
     }
 
+    /// Recomputes `algorithm` over the file and compares it with `expected`,
+    /// ignoring hex case and surrounding whitespace.
     pub fn verify_checksum_with_algorithm(
         file_path: &str,
         expected: &str,
         algorithm: HashAlgorithm,
     ) -> Result<bool> {
         let actual = Self::generate_checksum_with_algorithm(file_path, algorithm)?;
-        Ok(actual.eq_ignore_ascii_case(expected))
+        Ok(Self::hashes_match(&actual, expected))
+    }
+
+    /// Whether a computed hash equals a stored one, ignoring case and
+    /// surrounding whitespace.
+    pub fn hashes_match(actual: &str, expected: &str) -> bool {
+        actual.eq_ignore_ascii_case(expected.trim())
+    }
+
+    /// Where the `<archive>.<suffix>` sidecar lives: next to the archive, or
+    /// in `meta_dir` when the metadata is kept apart from archives.
+    pub fn sidecar_path(archive_path: &str, meta_dir: Option<&Path>, suffix: &str) -> String {
+        match meta_dir {
+            Some(dir) => {
+                let archive_name = Path::new(archive_path)
+                    .file_name()
+                    .map(|n| n.to_string_lossy())
+                    .unwrap_or_default();
+                dir.join(format!("{}.{}", archive_name, suffix)).to_string_lossy().to_string()
+            }
+            None => format!("{}.{}", archive_path, suffix),
+        }
     }
 
-    pub fn generate_checksum_file(archive_path: &str) -> Result<String> {
+    /// Writes `<archive>.sha256`. An existing sidecar with other contents,
+    /// such as a hand-maintained one, is only replaced with `replace_existing`.
+    pub fn generate_checksum_file(archive_path: &str, meta_dir: Option<&Path>, replace_existing: bool) -> Result<String> {
         crate::utils::print_info("Generating .sha256 file...");
 
         let checksum = Self::generate_checksum(archive_path)?;
-        let archive_name = Path::new(archive_path)
-            .file_name()
-            .and_then(|n| n.to_str())
-            .context("Invalid path")?;
+        let checksum_path = Self::sidecar_path(archive_path, meta_dir, HashAlgorithm::Sha256.sidecar_extension());
+        if !replace_existing
+            && let Ok(existing) = fs::read_to_string(&checksum_path)
+            && existing.trim_end() != Self::checksum_line(archive_path, &checksum)?
+        {
+            return Err(Self::sidecar_conflict(&checksum_path));
+        }
+
+        Self::write_checksum_file(archive_path, meta_dir, HashAlgorithm::Sha256, &checksum)
+    }
+
+    /// Fails when `<archive>.sha256` exists but doesn't describe the file now
+    /// at `archive_path`, such as a hand-maintained one. A backup checks this
+    /// before writing the archive, so a conflict leaves nothing behind.
+    pub fn check_sidecar_replaceable(archive_path: &str, meta_dir: Option<&Path>) -> Result<()> {
+        let checksum_path = Self::sidecar_path(archive_path, meta_dir, HashAlgorithm::Sha256.sidecar_extension());
+        let Ok(existing) = fs::read_to_string(&checksum_path) else {
+            return Ok(());
+        };
+        if Path::new(archive_path).is_file()
+            && existing.trim_end() == Self::checksum_line(archive_path, &Self::generate_checksum(archive_path)?)?
+        {
+            return Ok(());
+        }
+        Err(Self::sidecar_conflict(&checksum_path))
+    }
 
-        let checksum_path = format!("{}.sha256", archive_path);
+    fn sidecar_conflict(checksum_path: &str) -> anyhow::Error {
+        ErrorKind::Usage.error(format!(
+            "{} already exists with different contents; use --force or --replace-existing-checksum-file to overwrite it",
+            checksum_path
+        ))
+    }
+
+    /// Writes an already computed hash to `<archive>.<ext>` in GNU format.
+    pub fn write_checksum_file(
+        archive_path: &str,
+        meta_dir: Option<&Path>,
+        algorithm: HashAlgorithm,
+        checksum: &str,
+    ) -> Result<String> {
+        if !Path::new(archive_path).is_file() {
+            return Err(ErrorKind::Usage.error(format!(
+                "Not writing a checksum file for {}, which is not a file",
+                archive_path
+            )));
+        }
+
+        let checksum_path = Self::sidecar_path(archive_path, meta_dir, algorithm.sidecar_extension());
         let mut checksum_file = File::create(&checksum_path)?;
-        writeln!(checksum_file, "{}  {}", checksum, archive_name)?;
+        writeln!(checksum_file, "{}", Self::checksum_line(archive_path, checksum)?)?;
 
         crate::utils::print_success(&format!(
             "Created: {}",
             Path::new(&checksum_path)
                 .file_name()
                 .and_then(|n| n.to_str())
-                .unwrap_or("checksum.sha256")
+                .unwrap_or("checksum")
         ));
 
         Ok(checksum_path)
     }
 
-    pub fn verify_from_checksum_file(archive_path: &str) -> Result<bool> {
-        let checksum_path = format!("{}.sha256", archive_path);
+    /// `<hash>  <file name>`, the GNU format line of a checksum sidecar.
+    fn checksum_line(archive_path: &str, checksum: &str) -> Result<String> {
+        let archive_name = Path::new(archive_path)
+            .file_name()
+            .and_then(|n| n.to_str())
+            .context("Invalid path")?;
+        Ok(format!("{}  {}", checksum, archive_name))
+    }
+
+    /// Path of the per-file manifest written for an archive.
+    pub fn manifest_path(archive_path: &str, meta_dir: Option<&Path>) -> String {
+        Self::sidecar_path(archive_path, meta_dir, "manifest.sha256")
+    }
+
+    /// Writes `<hash>  <entry>` per archived file, in GNU format.
+    pub fn write_manifest(archive_path: &str, meta_dir: Option<&Path>, entries: &[(String, String)]) -> Result<String> {
+        let manifest_path = Self::manifest_path(archive_path, meta_dir);
+        let mut manifest = File::create(&manifest_path)?;
+
+        for (name, hash) in entries {
+            writeln!(manifest, "{}  {}", hash, name)?;
+        }
+
+        Ok(manifest_path)
+    }
+
+    /// Reads a manifest back as entry name to hash.
+    pub fn read_manifest(manifest_path: &str) -> Result<std::collections::BTreeMap<String, String>> {
+        let content = fs::read_to_string(manifest_path)
+            .with_context(|| format!("Failed to read {}", manifest_path))?;
+
+        content
+            .lines()
+            .filter(|l| !l.trim().is_empty())
+            .map(|line| {
+                let (hash, name) = Self::parse_checksum_line(line, SHA256_HEX_LEN)?;
+                Ok((name, hash))
+            })
+            .collect()
+    }
+
+    pub fn verify_from_checksum_file(archive_path: &str, meta_dir: Option<&Path>) -> Result<bool> {
+        let checksum_path = Self::sidecar_path(archive_path, meta_dir, "sha256");
 
         if !Path::new(&checksum_path).exists() {
             return Err(anyhow::anyhow!("Checksum file not found"));
         }
 
         let content = fs::read_to_string(&checksum_path)?;
-        let parts: Vec<&str> = content.trim().split_whitespace().collect();
+        let line = content
+            .lines()
+            .find(|l| !l.trim().is_empty())
+            .context("Invalid checksum file: no entries")?;
+
+        let (expected, _) = Self::parse_checksum_line(line, SHA256_HEX_LEN)?;
+        let actual = Self::generate_checksum(archive_path)?;
+
+        Ok(Self::hashes_match(&actual, &expected))
+    }
 
-        if parts.len() < 2 {
-            return Err(anyhow::anyhow!("Invalid checksum file"));
+    /// Parses one GNU coreutils line: `<hash>  <name>`, or `<hash> *<name>` in
+    /// binary mode. Everything after the separator is the name, so spaces in
+    /// file names survive. The hash must be `hex_len` hex digits.
+    pub fn parse_checksum_line(line: &str, hex_len: usize) -> Result<(String, String)> {
+        let line = line.trim_end_matches(['\r', '\n']);
+
+        let (hash, rest) = line
+            .split_once(' ')
+            .context("Invalid checksum line: missing separator")?;
+
+        let name = rest
+            .strip_prefix(' ')
+            .or_else(|| rest.strip_prefix('*'))
+            .context("Invalid checksum line: expected two spaces or ' *' after the hash")?;
+
+        if hash.len() != hex_len || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(anyhow::anyhow!(
+                "Invalid checksum line: expected {} hex digits, got '{}'",
+                hex_len,
+                hash
+            ));
         }
 
-        let expected = parts[0];
-        let actual = Self::generate_checksum(archive_path)?;
+        if name.is_empty() {
+            return Err(anyhow::anyhow!("Invalid checksum line: missing file name"));
+        }
 
-        Ok(actual.eq_ignore_ascii_case(expected))
+        Ok((hash.to_lowercase(), name.to_string()))
     }
 
-    pub fn auto_verify(archive_path: &str) -> Result<bool> {
-        let checksum_path = format!("{}.sha256", archive_path);
+    pub fn auto_verify(archive_path: &str, meta_dir: Option<&Path>) -> Result<bool> {
+        let checksum_path = Self::sidecar_path(archive_path, meta_dir, "sha256");
 
         if Path::new(&checksum_path).exists() {
-            Self::verify_from_checksum_file(archive_path)
+            Self::verify_from_checksum_file(archive_path, meta_dir)
         } else {
             crate::utils::print_warning("No checksum file, skipping verification");
             Ok(true)
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn checksum_of(data: &[u8], algorithm: HashAlgorithm) -> Result<String> {
+        let temp_dir = tempfile::tempdir()?;
+        let path = temp_dir.path().join("data.bin");
+        fs::write(&path, data)?;
+        Checker::generate_checksum_with_algorithm(path.to_str().unwrap(), algorithm)
+    }
+
+    #[test]
+    fn test_small_file_hashes_without_progress_bar() -> Result<()> {
+        assert_eq!(
+            checksum_of(b"0123456789", HashAlgorithm::Sha256)?,
+            "84d89877f0d4041efb6bf91a16f0248f2fd573e6af05c19f96bedb9f882f7882"
+        );
+        assert!(Checker::hash_progress_bar(10).is_hidden());
+        Ok(())
+    }
+
+    #[test]
+    fn test_sha512_known_vector() -> Result<()> {
+        assert_eq!(
+            checksum_of(b"abc", HashAlgorithm::Sha512)?,
+            "ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a\
+             2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_blake2b_known_vector() -> Result<()> {
+        assert_eq!(
+            checksum_of(b"abc", HashAlgorithm::Blake2b)?,
+            "ba80a53f981c4d0d6a2797b69f12f6e94c212f14685ac4b74b12bb6fdbffa2d1\
+             7d87c5392aab792dc252d5de4533cc9518d38aa8dbf1925ab92386edd4009923"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_with_each_algorithm() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let path = temp_dir.path().join("abc.bin");
+        fs::write(&path, b"abc")?;
+        let path = path.to_str().unwrap();
+
+        let known = [
+            (HashAlgorithm::Sha3_256, "3a985da74fe225b2045c172d6bd390bd855f086e3e9d525b46bfe24511431532"),
+            (HashAlgorithm::Blake3, "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85"),
+        ];
+        for (algorithm, expected) in known {
+            assert_eq!(Checker::generate_checksum_with_algorithm(path, algorithm)?, expected);
+        }
+
+        for algorithm in HashAlgorithm::all() {
+            let good = Checker::generate_checksum_with_algorithm(path, algorithm)?;
+            let wrong = "0".repeat(good.len());
+            assert!(Checker::verify_checksum_with_algorithm(path, &good, algorithm)?, "{}", algorithm.name());
+            assert!(Checker::verify_checksum_with_algorithm(path, &format!(" {}\n", good.to_uppercase()), algorithm)?);
+            assert!(!Checker::verify_checksum_with_algorithm(path, &wrong, algorithm)?, "{}", algorithm.name());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_crc32_and_xxh3_known_vectors() -> Result<()> {
+        assert_eq!(checksum_of(b"123456789", HashAlgorithm::Crc32)?, "cbf43926");
+        assert_eq!(checksum_of(b"", HashAlgorithm::Xxh3)?, "2d06800538d394c2");
+        assert!(!HashAlgorithm::Crc32.is_cryptographic());
+        assert!(!HashAlgorithm::Xxh3.is_cryptographic());
+        assert!(HashAlgorithm::Sha256.is_cryptographic());
+        Ok(())
+    }
+
+    #[test]
+    fn test_multiple_checksums_and_sidecars() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let path = temp_dir.path().join("abc.bin");
+        fs::write(&path, b"abc")?;
+        let path = path.to_str().unwrap();
+
+        let results = Checker::generate_multiple_checksums(
+            path,
+            &[HashAlgorithm::Sha256, HashAlgorithm::Crc32],
+        )?;
+        assert_eq!(
+            results,
+            vec![
+                (
+                    HashAlgorithm::Sha256,
+                    "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad".to_string()
+                ),
+                (HashAlgorithm::Crc32, "352441c2".to_string()),
+            ]
+        );
+
+        for (algo, hash) in &results {
+            let sidecar = Checker::write_checksum_file(path, None, *algo, hash)?;
+            let line = fs::read_to_string(&sidecar)?;
+            assert_eq!(line, format!("{}  abc.bin\n", hash));
+        }
+        assert!(Checker::verify_from_checksum_file(path, None)?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_stored_checksums_flags_wrong_one() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let path = temp_dir.path().join("archive.tar.zst");
+        fs::write(&path, b"zencore")?;
+        let path = path.to_str().unwrap();
+
+        let sha256 = Checker::generate_checksum(path)?;
+        let stored = vec![
+            ("SHA-256".to_string(), sha256.to_uppercase()),
+            ("CRC32 (NON-CRYPTOGRAPHIC)".to_string(), "00000000".to_string()),
+        ];
+
+        let results = Checker::verify_stored_checksums(path, &stored)?;
+        assert_eq!(
+            results,
+            vec![
+                ("SHA-256".to_string(), true),
+                ("CRC32 (NON-CRYPTOGRAPHIC)".to_string(), false),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_password_stdin_length_check() -> Result<()> {
+        let policy = PasswordPolicy::new(8);
+
+        assert!(policy.read_from("short\n".as_bytes()).is_err());
+        assert!(policy.read_from("".as_bytes()).is_err());
+        assert_eq!(policy.read_from("correct horse\r\n".as_bytes())?, "correct horse");
+
+        assert!(policy.weakness("aaaaaaaaaaaa").is_some());
+        assert!(policy.weakness("password").is_some());
+        assert!(policy.weakness("T4k3-the-A-train!").is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_manifest_roundtrip() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let archive = temp_dir.path().join("music.tar.zst");
+        let archive = archive.to_str().unwrap();
+        let entries = vec![
+            ("Album A/01 Intro.flac".to_string(), "a".repeat(SHA256_HEX_LEN)),
+            ("cover.jpg".to_string(), "b".repeat(SHA256_HEX_LEN)),
+        ];
+
+        let manifest_path = Checker::write_manifest(archive, None, &entries)?;
+        assert!(manifest_path.ends_with("music.tar.zst.manifest.sha256"));
+
+        let manifest = Checker::read_manifest(&manifest_path)?;
+        assert_eq!(manifest.into_iter().collect::<Vec<_>>(), entries);
+        Ok(())
+    }
+
+    #[test]
+    fn test_different_sidecar_is_kept_unless_replacing() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let archive = dir.path().join("music.tar.zst");
+        fs::write(&archive, b"archive bytes")?;
+        let sidecar = dir.path().join("music.tar.zst.sha256");
+        fs::write(&sidecar, "hand-maintained notes\n")?;
+        let archive = archive.to_str().unwrap();
+
+        let err = Checker::generate_checksum_file(archive, None, false).unwrap_err();
+        assert!(err.to_string().contains("already exists with different contents"));
+        assert_eq!(fs::read_to_string(&sidecar)?, "hand-maintained notes\n");
+
+        Checker::generate_checksum_file(archive, None, true)?;
+        assert!(Checker::verify_from_checksum_file(archive, None)?);
+        // An identical sidecar is not a conflict.
+        Checker::generate_checksum_file(archive, None, false)?;
+
+        let missing = dir.path().join("missing.tar.zst");
+        assert!(Checker::generate_checksum_file(missing.to_str().unwrap(), None, true).is_err());
+        assert!(!dir.path().join("missing.tar.zst.sha256").exists());
+
+        // Before a backup: the sidecar of the archive about to be replaced is
+        // fine, a stray one is not.
+        Checker::check_sidecar_replaceable(archive, None)?;
+        fs::write(&sidecar, "hand-maintained notes\n")?;
+        assert!(Checker::check_sidecar_replaceable(archive, None).is_err());
+        fs::write(dir.path().join("missing.tar.zst.sha256"), "notes\n")?;
+        assert!(Checker::check_sidecar_replaceable(missing.to_str().unwrap(), None).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sidecars_in_separate_meta_dir() -> Result<()> {
+        let archive_dir = tempfile::tempdir()?;
+        let meta_dir = tempfile::tempdir()?;
+        let archive = archive_dir.path().join("music.tar.zst");
+        fs::write(&archive, b"archive bytes")?;
+        let archive = archive.to_str().unwrap();
+
+        let sidecar = Checker::generate_checksum_file(archive, Some(meta_dir.path()), false)?;
+        assert_eq!(Path::new(&sidecar), meta_dir.path().join("music.tar.zst.sha256"));
+        assert!(!Path::new(&format!("{}.sha256", archive)).exists());
+
+        assert!(Checker::verify_from_checksum_file(archive, Some(meta_dir.path()))?);
+        assert!(Checker::verify_from_checksum_file(archive, None).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_fast_hashes_stream_large_input() -> Result<()> {
+        // Spans many 64 KiB read chunks, like a large media archive.
+        let data: Vec<u8> = (0..8 * 1024 * 1024u32).map(|i| (i % 251) as u8).collect();
+
+        assert_eq!(
+            checksum_of(&data, HashAlgorithm::Crc32)?,
+            format!("{:08x}", crc32fast::hash(&data))
+        );
+        assert_eq!(
+            checksum_of(&data, HashAlgorithm::Xxh3)?,
+            format!("{:016x}", xxhash_rust::xxh3::xxh3_64(&data))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_checksum_line_keeps_spaces_in_name() -> Result<()> {
+        let hash = "a".repeat(64);
+
+        let (parsed, name) =
+            Checker::parse_checksum_line(&format!("{}  01 - Song Name.flac\r\n", hash), 64)?;
+        assert_eq!(parsed, hash);
+        assert_eq!(name, "01 - Song Name.flac");
+
+        let (_, name) = Checker::parse_checksum_line(&format!("{} *My Album.tar.zst", hash), 64)?;
+        assert_eq!(name, "My Album.tar.zst");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_checksum_line_rejects_malformed() {
+        let hash = "b".repeat(64);
+
+        assert!(Checker::parse_checksum_line("deadbeef  short.tar.zst", 64).is_err());
+        assert!(Checker::parse_checksum_line(&format!("{}  ", hash), 64).is_err());
+        assert!(Checker::parse_checksum_line(&format!("{}-name", hash), 64).is_err());
+        assert!(Checker::parse_checksum_line(&format!("{}zz  x.zip", &hash[2..]), 64).is_err());
+    }
+
+    #[test]
+    fn test_verify_from_checksum_file_tolerates_trailing_newlines() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let archive = temp_dir.path().join("My Music.tar.zst");
+        fs::write(&archive, b"archive bytes")?;
+
+        let archive_str = archive.to_str().unwrap();
+        let checksum = Checker::generate_checksum(archive_str)?;
+        fs::write(
+            format!("{}.sha256", archive_str),
+            format!("{}  My Music.tar.zst\n\n\n", checksum),
+        )?;
+
+        assert!(Checker::verify_from_checksum_file(archive_str, None)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_new_algorithms_parse() -> Result<()> {
+        assert_eq!(HashAlgorithm::from_str("sha512")?, HashAlgorithm::Sha512);
+        assert_eq!(HashAlgorithm::from_str("SHA-512")?, HashAlgorithm::Sha512);
+        assert_eq!(HashAlgorithm::from_str("blake2b")?, HashAlgorithm::Blake2b);
+        assert_eq!(HashAlgorithm::Blake2b.name(), "BLAKE2b");
+        assert_eq!(HashAlgorithm::from_str("crc32")?, HashAlgorithm::Crc32);
+        assert_eq!(HashAlgorithm::from_str("xxh3")?, HashAlgorithm::Xxh3);
+        Ok(())
+    }
+}
@@ -0,0 +1,97 @@
+use anyhow::{Context, Result};
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::Path;
+use walkdir::WalkDir;
+
+use crate::error::ErrorKind;
+
+/// Dictionary size `zencore train-dict` aims for, zstd's own default.
+pub const DEFAULT_DICT_SIZE: usize = 110 * 1024;
+
+/// Bytes read from the start of each sample file; dictionaries help with
+/// small files, so the head of a large one is all that matters.
+const SAMPLE_BYTES: u64 = 128 * 1024;
+
+/// Magic number every zstd dictionary starts with.
+const DICT_MAGIC: [u8; 4] = [0x37, 0xA4, 0x30, 0xEC];
+
+/// Trains a zstd dictionary of at most `max_size` bytes from the files
+/// under `source`, reading up to 100 times `max_size` of samples.
+pub fn train(source: &Path, max_size: usize) -> Result<Vec<u8>> {
+    let budget = max_size as u64 * 100;
+    let mut samples = Vec::new();
+    let mut total = 0u64;
+
+    for entry in WalkDir::new(source).sort_by_file_name() {
+        let entry = entry?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        if total >= budget {
+            break;
+        }
+
+        let mut sample = Vec::new();
+        File::open(entry.path())
+            .with_context(|| format!("Failed to read {}", entry.path().display()))?
+            .take(SAMPLE_BYTES.min(budget - total))
+            .read_to_end(&mut sample)?;
+        if !sample.is_empty() {
+            total += sample.len() as u64;
+            samples.push(sample);
+        }
+    }
+
+    if samples.is_empty() {
+        return Err(ErrorKind::Usage.error(format!("No sample files found in {}", source.display())));
+    }
+
+    zstd::dict::from_samples(&samples, max_size)
+        .with_context(|| format!("Not enough sample data in {} to train a dictionary", source.display()))
+}
+
+/// Reads a dictionary written by `train` (or `zstd --train`).
+pub fn load(path: &Path) -> Result<Vec<u8>> {
+    let dict = fs::read(path).with_context(|| format!("Failed to read dictionary {}", path.display()))?;
+    if !dict.starts_with(&DICT_MAGIC) {
+        return Err(ErrorKind::Usage.error(format!("{} is not a zstd dictionary", path.display())));
+    }
+    Ok(dict)
+}
+
+/// The ID zstd stores in the dictionary header and in every frame
+/// compressed with it.
+pub fn dictionary_id(dict: &[u8]) -> u32 {
+    dict.get(4..8)
+        .map(|id| u32::from_le_bytes([id[0], id[1], id[2], id[3]]))
+        .unwrap_or(0)
+}
+
+/// The dictionary ID in the header of the zstd file at `path`, if it was
+/// compressed with a dictionary.
+pub fn frame_dictionary_id(path: &Path) -> Result<Option<u32>> {
+    // A frame header is at most 18 bytes.
+    let mut header = Vec::with_capacity(18);
+    File::open(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?
+        .take(18)
+        .read_to_end(&mut header)?;
+    Ok(zstd::zstd_safe::get_dict_id_from_frame(&header).map(|id| id.get()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_rejects_non_dictionaries() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let path = temp_dir.path().join("notes.txt");
+        fs::write(&path, b"not a dictionary")?;
+
+        let err = load(&path).unwrap_err();
+        assert!(err.to_string().contains("is not a zstd dictionary"));
+        Ok(())
+    }
+}
@@ -0,0 +1,130 @@
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::path_utils::SymlinkPolicy;
+
+/// Names at least this similar (normalized Levenshtein, 0 to 1) count as
+/// the same track in `zencore analyze-dupes`.
+pub const DEFAULT_SIMILARITY: f64 = 0.9;
+
+/// Only these are compared; artwork and playlists repeat in every album.
+const AUDIO_EXTENSIONS: &[&str] = &[
+    "mp3", "flac", "wav", "aiff", "aif", "m4a", "aac", "alac", "ogg", "opus", "wma", "ape", "wv", "dsf", "dff",
+];
+
+/// Files that look like copies of one track, e.g. `Song.mp3` and `Song.flac`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DuplicateGroup {
+    /// Normalized name of the first file in the group.
+    pub name: String,
+    /// Paths relative to the scanned source, with their sizes.
+    pub files: Vec<(PathBuf, u64)>,
+}
+
+impl DuplicateGroup {
+    pub fn total_size(&self) -> u64 {
+        self.files.iter().map(|(_, size)| size).sum()
+    }
+}
+
+/// File name without extension, lowercased, with `_` read as a space and
+/// runs of whitespace collapsed.
+pub fn normalize_name(path: &Path) -> String {
+    let stem = path.file_stem().map(|s| s.to_string_lossy().to_lowercase()).unwrap_or_default();
+    stem.replace('_', " ").split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Groups the audio files under `source` whose normalized names are equal
+/// or at least `similarity` alike. Read-only; groups of one are left out.
+pub fn find_duplicates(source: &Path, symlinks: SymlinkPolicy, similarity: f64) -> Result<Vec<DuplicateGroup>> {
+    let mut by_name: BTreeMap<String, Vec<(PathBuf, u64)>> = BTreeMap::new();
+    for entry in symlinks.walk(source).sort_by_file_name() {
+        let entry = entry?;
+        if !symlinks.keeps_file(&entry, |_| {}) || !is_audio(entry.path()) {
+            continue;
+        }
+        let size = entry.metadata().map(|meta| meta.len()).unwrap_or(0);
+        let relative = entry.path().strip_prefix(source).unwrap_or(entry.path()).to_path_buf();
+        by_name.entry(normalize_name(entry.path())).or_default().push((relative, size));
+    }
+
+    let names: Vec<String> = by_name.keys().cloned().collect();
+    let mut parent: Vec<usize> = (0..names.len()).collect();
+    fn root(parent: &mut [usize], mut i: usize) -> usize {
+        while parent[i] != i {
+            parent[i] = parent[parent[i]];
+            i = parent[i];
+        }
+        i
+    }
+
+    if similarity < 1.0 {
+        // Near matches only among names with the same first character and
+        // similar length, which keeps large libraries from going quadratic.
+        for i in 0..names.len() {
+            for j in i + 1..names.len() {
+                if names[i].chars().next() != names[j].chars().next() {
+                    break;
+                }
+                let (short, long) = (names[i].len().min(names[j].len()), names[i].len().max(names[j].len()));
+                if (short as f64) < long as f64 * similarity {
+                    continue;
+                }
+                if strsim::normalized_levenshtein(&names[i], &names[j]) >= similarity {
+                    let (a, b) = (root(&mut parent, i), root(&mut parent, j));
+                    parent[b] = a;
+                }
+            }
+        }
+    }
+
+    let mut groups: BTreeMap<usize, DuplicateGroup> = BTreeMap::new();
+    for (i, (name, files)) in by_name.into_iter().enumerate() {
+        let group = groups.entry(root(&mut parent, i)).or_insert_with(|| DuplicateGroup {
+            name,
+            files: Vec::new(),
+        });
+        group.files.extend(files);
+    }
+
+    Ok(groups.into_values().filter(|group| group.files.len() > 1).collect())
+}
+
+fn is_audio(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| AUDIO_EXTENSIONS.contains(&ext.to_string_lossy().to_lowercase().as_str()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_same_song_in_two_formats_is_grouped() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let source = temp_dir.path();
+        fs::create_dir_all(source.join("FLAC"))?;
+        fs::create_dir_all(source.join("MP3"))?;
+        fs::write(source.join("FLAC/Song.flac"), vec![0u8; 3000])?;
+        fs::write(source.join("MP3/Song.mp3"), vec![0u8; 300])?;
+        fs::write(source.join("MP3/Other_Song  Remix.mp3"), b"x")?;
+        fs::write(source.join("FLAC/other song remix!.flac"), b"x")?;
+        fs::write(source.join("MP3/Unrelated.mp3"), b"x")?;
+        fs::write(source.join("FLAC/cover.jpg"), b"x")?;
+        fs::write(source.join("MP3/cover.jpg"), b"x")?;
+        fs::write(source.join("MP3/song.txt"), b"x")?;
+
+        let groups = find_duplicates(source, SymlinkPolicy::default(), DEFAULT_SIMILARITY)?;
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0].name, "other song remix");
+        assert_eq!(groups[1].name, "song");
+        assert_eq!(
+            groups[1].files,
+            [(PathBuf::from("FLAC/Song.flac"), 3000), (PathBuf::from("MP3/Song.mp3"), 300)]
+        );
+        assert_eq!(groups[1].total_size(), 3300);
+        Ok(())
+    }
+}
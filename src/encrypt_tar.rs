@@ -1,24 +1,52 @@
 use anyhow::{Context, Result};
-use indicatif::{ProgressBar, ProgressStyle};
+use indicatif::ProgressStyle;
 use std::fs::{self, File};
 use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::error::ErrorKind;
+
+/// First bytes of a binary age file (the header version line).
+const AGE_BINARY_MAGIC: &[u8] = b"age-encryption.org/v1";
+
+/// First line of an ASCII-armored age file.
+const AGE_ARMOR_HEADER: &[u8] = b"-----BEGIN AGE ENCRYPTED FILE-----";
 
 pub struct TarEncryptor {
     password: String,
+    armor: bool,
 }
 
 impl TarEncryptor {
     pub fn new(password: String) -> Self {
-        Self { password }
+        Self {
+            password,
+            armor: false,
+        }
+    }
+
+    /// ASCII armor makes the output text-safe at the cost of ~33% extra size,
+    /// so it is off by default.
+    pub fn with_armor(mut self, armor: bool) -> Self {
+        self.armor = armor;
+        self
+    }
+
+    /// Where `encrypt_file` writes the encrypted copy before it replaces
+    /// the archive.
+    pub fn encrypted_temp_path(tar_path: &str) -> String {
+        format!("{}.age", tar_path)
     }
 
     pub fn encrypt_file(&self, tar_path: &str) -> Result<String> {
         crate::utils::print_info("🔒 Encrypting TAR with age...");
 
         let file_size = fs::metadata(tar_path)?.len();
-        let encrypted_path = format!("{}.age", tar_path);
+        let encrypted_path = Self::encrypted_temp_path(tar_path);
+        let _span = tracing::debug_span!("encrypt", path = tar_path, bytes = file_size, armor = self.armor).entered();
 
-        let pb = ProgressBar::new(file_size);
+        let pb = crate::utils::progress_bar(file_size);
         pb.set_style(
             ProgressStyle::default_bar()
                 .template("{spinner:.green} [{bar:40.cyan/blue}] {bytes}/{total_bytes} {msg}")
@@ -36,10 +64,13 @@ impl TarEncryptor {
 
         let mut input = BufReader::new(input_file);
         
-        let armor_output = age::armor::ArmoredWriter::wrap_output(
-            output_file,
+        let format = if self.armor {
             age::armor::Format::AsciiArmor
-            )
+        } else {
+            age::armor::Format::Binary
+        };
+
+        let armor_output = age::armor::ArmoredWriter::wrap_output(output_file, format)
             .context("Failed to create armored writer")?;
         
         let mut encrypted_writer = encryptor
@@ -66,10 +97,12 @@ impl TarEncryptor {
             .context("Failed to finalize encryption")?;
         
         pb.finish_with_message("✓ Encrypted");
+        tracing::debug!(bytes = total_read, "encrypted stream finished");
 
         let backup_path = format!("{}.bak", tar_path);
         fs::rename(tar_path, &backup_path)?;
         fs::rename(&encrypted_path, tar_path)?;
+        tracing::debug!(backup = %backup_path, "replaced archive with encrypted copy");
 
         crate::utils::print_success(&format!(
             "Encrypted: {:.2} MB",
@@ -85,7 +118,7 @@ impl TarEncryptor {
         let file_size = fs::metadata(encrypted_path)?.len();
         let decrypted_path = encrypted_path.trim_end_matches(".age");
 
-        let pb = ProgressBar::new(file_size);
+        let pb = crate::utils::progress_bar(file_size);
         pb.set_style(
             ProgressStyle::default_bar()
                 .template("{spinner:.green} [{bar:40.cyan/blue}] {bytes}/{total_bytes} {msg}")
@@ -95,16 +128,21 @@ impl TarEncryptor {
         pb.set_message("Decrypting...");
 
         let input_file = File::open(encrypted_path)?;
-        let input = BufReader::new(input_file);
+        // ArmoredReader passes binary age files through untouched.
+        let input = age::armor::ArmoredReader::new(BufReader::new(input_file));
 
-        let decryptor = age::Decryptor::new(input)?;
+        let decryptor = age::Decryptor::new(input).map_err(|e| {
+            ErrorKind::Encryption.error(format!("{} is not a readable age file ({})", encrypted_path, e))
+        })?;
 
         let passphrase = secrecy::SecretString::from(self.password.clone());
         let identity = age::scrypt::Identity::new(passphrase);
 
         let mut decrypted_reader = decryptor
             .decrypt(std::iter::once(&identity as &dyn age::Identity))
-            .context("Decryption failed - wrong password or corrupted file")?;
+            .map_err(|e| {
+                ErrorKind::Encryption.error(format!("Decryption failed - wrong password or corrupted file ({})", e))
+            })?;
 
         let output_file = File::create(decrypted_path)?;
         let mut output = BufWriter::new(output_file);
@@ -131,20 +169,86 @@ impl TarEncryptor {
         Ok(decrypted_path.to_string())
     }
 
+    /// `--verify-identity`: parses every `age1...` recipient, reporting
+    /// each, and with `identity_file` encrypts a few bytes to the recipients
+    /// to prove the identity can open the result. Fails before anything is
+    /// encrypted if a recipient is malformed or no recipient matches.
+    pub fn verify_recipients(recipients: &[String], identity_file: Option<&Path>) -> Result<Vec<age::x25519::Recipient>> {
+        if recipients.is_empty() {
+            return Err(ErrorKind::Usage.error("No age recipients configured (age_recipients)"));
+        }
+
+        let mut parsed = Vec::new();
+        let mut invalid = 0;
+        for recipient in recipients {
+            match age::x25519::Recipient::from_str(recipient.trim()) {
+                Ok(key) => {
+                    crate::utils::print_success(&format!("Recipient {}", key));
+                    parsed.push(key);
+                }
+                Err(e) => {
+                    crate::utils::print_error(&format!("Recipient {:?} is not a valid age key: {}", recipient, e));
+                    invalid += 1;
+                }
+            }
+        }
+        if invalid > 0 {
+            return Err(ErrorKind::Encryption.error(format!("{} of {} age recipient(s) are invalid", invalid, recipients.len())));
+        }
+
+        if let Some(identity_file) = identity_file {
+            Self::roundtrip(&parsed, identity_file)?;
+            crate::utils::print_success(&format!("{} opens archives for these recipients", identity_file.display()));
+        }
+        Ok(parsed)
+    }
+
+    fn roundtrip(recipients: &[age::x25519::Recipient], identity_file: &Path) -> Result<()> {
+        const PROBE: &[u8] = b"zencore identity check";
+
+        let identities = age::IdentityFile::from_file(identity_file.to_string_lossy().to_string())
+            .with_context(|| format!("Failed to read identity file {}", identity_file.display()))?
+            .into_identities()
+            .map_err(|e| ErrorKind::Encryption.error(format!("{}: {}", identity_file.display(), e)))?;
+
+        let encryptor = age::Encryptor::with_recipients(recipients.iter().map(|r| r as &dyn age::Recipient))?;
+        let mut ciphertext = Vec::new();
+        let mut writer = encryptor.wrap_output(&mut ciphertext)?;
+        writer.write_all(PROBE)?;
+        writer.finish()?;
+
+        let mut plaintext = Vec::new();
+        let opened = age::Decryptor::new(&ciphertext[..])
+            .and_then(|decryptor| decryptor.decrypt(identities.iter().map(|i| i.as_ref() as &dyn age::Identity)))
+            .map(|mut reader| reader.read_to_end(&mut plaintext));
+        if !matches!(opened, Ok(Ok(_))) || plaintext != PROBE {
+            return Err(ErrorKind::Encryption.error(format!(
+                "{} can't decrypt for any configured recipient; the key pair doesn't match",
+                identity_file.display()
+            )));
+        }
+        Ok(())
+    }
+
     pub fn is_age_encrypted(file_path: &str) -> bool {
         if file_path.ends_with(".age") {
             return true;
         }
 
-        if let Ok(mut file) = File::open(file_path) {
-            let mut header = [0u8; 32];
-            if file.read_exact(&mut header).is_ok() {
-                let header_str = String::from_utf8_lossy(&header);
-                return header_str.contains("age-encryption.org");
-            }
+        let Ok(file) = File::open(file_path) else {
+            return false;
+        };
+
+        let mut header = Vec::with_capacity(AGE_ARMOR_HEADER.len());
+        if file
+            .take(AGE_ARMOR_HEADER.len() as u64)
+            .read_to_end(&mut header)
+            .is_err()
+        {
+            return false;
         }
 
-        false
+        header.starts_with(AGE_BINARY_MAGIC) || header.starts_with(AGE_ARMOR_HEADER)
     }
 }
 
@@ -153,6 +257,28 @@ mod tests {
     use super::*;
     use std::io::Write;
 
+    #[test]
+    fn test_verify_recipients_rejects_typos_and_foreign_identities() -> Result<()> {
+        use age::secrecy::ExposeSecret;
+
+        let temp_dir = tempfile::tempdir()?;
+        let mine = age::x25519::Identity::generate();
+        let identity_file = temp_dir.path().join("key.txt");
+        fs::write(&identity_file, format!("{}\n", mine.to_string().expose_secret()))?;
+
+        let valid = mine.to_public().to_string();
+        assert_eq!(TarEncryptor::verify_recipients(std::slice::from_ref(&valid), Some(&identity_file))?.len(), 1);
+
+        let last = if valid.ends_with('q') { 'p' } else { 'q' };
+        let typo = format!("{}{}", &valid[..valid.len() - 1], last);
+        let err = TarEncryptor::verify_recipients(&[valid, typo], None).unwrap_err();
+        assert_eq!(crate::error::exit_code(&err), 5);
+
+        let someone_else = age::x25519::Identity::generate().to_public().to_string();
+        assert!(TarEncryptor::verify_recipients(&[someone_else], Some(&identity_file)).is_err());
+        Ok(())
+    }
+
     #[test]
     fn test_encrypt_decrypt_roundtrip() -> Result<()> {
         let temp_dir = tempfile::tempdir()?;
@@ -172,6 +298,74 @@ mod tests {
         
         assert_eq!(content, "Test data for encryption");
 
+        let encrypted = encryptor.encrypt_file(&decrypted)?;
+        let err = TarEncryptor::new("not_the_password".to_string()).decrypt_file(&encrypted).unwrap_err();
+        assert_eq!(crate::error::exit_code(&err), 5);
+        assert!(err.to_string().contains("wrong password"), "{}", err);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_binary_encrypt_decrypt_roundtrip() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let test_file = temp_dir.path().join("music.tar.zst");
+        fs::write(&test_file, b"binary mode payload")?;
+
+        let encryptor = TarEncryptor::new("test_password_123".to_string()).with_armor(false);
+        let encrypted = encryptor.encrypt_file(test_file.to_str().unwrap())?;
+
+        let header = fs::read(&encrypted)?;
+        assert!(header.starts_with(b"age-encryption.org/v1"));
+
+        let decrypted = encryptor.decrypt_file(&encrypted)?;
+        assert_eq!(fs::read(&decrypted)?, b"binary mode payload");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_is_age_encrypted_detects_content() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+
+        let armored = temp_dir.path().join("armored.tar.zst");
+        fs::write(&armored, b"armored payload")?;
+        TarEncryptor::new("pw".to_string())
+            .with_armor(true)
+            .encrypt_file(armored.to_str().unwrap())?;
+        assert!(TarEncryptor::is_age_encrypted(armored.to_str().unwrap()));
+
+        let binary = temp_dir.path().join("binary.tar.zst");
+        fs::write(&binary, b"binary payload")?;
+        TarEncryptor::new("pw".to_string())
+            .with_armor(false)
+            .encrypt_file(binary.to_str().unwrap())?;
+        assert!(TarEncryptor::is_age_encrypted(binary.to_str().unwrap()));
+
+        let plain = temp_dir.path().join("plain.tar.zst");
+        fs::write(&plain, vec![0x28, 0xb5, 0x2f, 0xfd, 0x00, 0x00])?;
+        assert!(!TarEncryptor::is_age_encrypted(plain.to_str().unwrap()));
+
+        let tiny = temp_dir.path().join("tiny.tar");
+        fs::write(&tiny, b"age")?;
+        assert!(!TarEncryptor::is_age_encrypted(tiny.to_str().unwrap()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_armored_encrypt_decrypt_roundtrip() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let test_file = temp_dir.path().join("music.tar.gz");
+        fs::write(&test_file, b"armored mode payload")?;
+
+        let encryptor = TarEncryptor::new("test_password_123".to_string()).with_armor(true);
+        let encrypted = encryptor.encrypt_file(test_file.to_str().unwrap())?;
+        assert!(fs::read(&encrypted)?.starts_with(AGE_ARMOR_HEADER));
+
+        let decrypted = encryptor.decrypt_file(&encrypted)?;
+        assert_eq!(fs::read(&decrypted)?, b"armored mode payload");
+
         Ok(())
     }
 
@@ -0,0 +1,81 @@
+use std::fmt;
+
+/// Exit code for failures without a more specific kind.
+pub const EXIT_FAILURE: i32 = 1;
+
+/// Failure categories scripts can tell apart by exit code. Errors that
+/// carry none of these exit with `EXIT_FAILURE`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// Bad arguments or input, such as a missing file (2, like clap's own
+    /// usage errors)
+    Usage,
+    /// A checksum or content manifest no longer matches (3)
+    Verification,
+    /// Listing or uploading to a remote failed (4)
+    Remote,
+    /// Encrypting or decrypting failed, including a wrong password (5)
+    Encryption,
+}
+
+impl ErrorKind {
+    pub fn exit_code(self) -> i32 {
+        match self {
+            Self::Usage => 2,
+            Self::Verification => 3,
+            Self::Remote => 4,
+            Self::Encryption => 5,
+        }
+    }
+
+    /// An error of this kind. Context added on top keeps the kind.
+    pub fn error(self, message: impl Into<String>) -> anyhow::Error {
+        anyhow::Error::new(KindError {
+            kind: self,
+            message: message.into(),
+        })
+    }
+}
+
+#[derive(Debug)]
+struct KindError {
+    kind: ErrorKind,
+    message: String,
+}
+
+impl fmt::Display for KindError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for KindError {}
+
+/// The kind of the first tagged error in `error`'s chain, if any.
+pub fn kind_of(error: &anyhow::Error) -> Option<ErrorKind> {
+    error
+        .chain()
+        .find_map(|cause| cause.downcast_ref::<KindError>().map(|tagged| tagged.kind))
+}
+
+/// Process exit code for a failed command.
+pub fn exit_code(error: &anyhow::Error) -> i32 {
+    kind_of(error).map_or(EXIT_FAILURE, ErrorKind::exit_code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    #[test]
+    fn test_kind_survives_added_context() {
+        let error = Err::<(), _>(ErrorKind::Remote.error("Rclone upload failed"))
+            .context("Backup of music failed")
+            .unwrap_err();
+        assert_eq!(exit_code(&error), 4);
+        assert_eq!(format!("{:#}", error), "Backup of music failed: Rclone upload failed");
+
+        assert_eq!(exit_code(&anyhow::anyhow!("disk full")), EXIT_FAILURE);
+    }
+}
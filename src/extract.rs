@@ -0,0 +1,793 @@
+use anyhow::{Context, Result};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::{Component, Path, PathBuf};
+use zip::result::ZipError;
+
+use crate::compress::Algorithm;
+use crate::error::ErrorKind;
+
+/// One file entry as listed by `ArchiveReader::list_contents`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EntryInfo {
+    pub name: String,
+    pub size: u64,
+    /// Password protected (zip only).
+    pub encrypted: bool,
+}
+
+/// What `ArchiveReader::list_contents_report` could read of a damaged archive.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ContentListing {
+    pub entries: Vec<EntryInfo>,
+    /// Zip entries left out because their records are damaged.
+    pub unreadable: usize,
+    /// The tar stream broke off at an unreadable block; `entries` holds
+    /// everything before it.
+    pub truncated: bool,
+}
+
+/// Signatures of the zip records `scan_zip_directory` reads.
+const ZIP_CENTRAL_HEADER: &[u8; 4] = b"PK\x01\x02";
+const ZIP_LOCAL_HEADER: &[u8; 4] = b"PK\x03\x04";
+const ZIP_END_OF_DIRECTORY: &[u8; 4] = b"PK\x05\x06";
+
+/// What `ArchiveReader::extract` wrote.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExtractSummary {
+    pub extracted: usize,
+    /// File entries in the archive, extracted or not.
+    pub total: usize,
+}
+
+/// Reads tar.gz, tar.zst and zip archives, including AES-encrypted zips
+/// when given the password.
+pub struct ArchiveReader {
+    path: PathBuf,
+    password: Option<String>,
+    zstd_dictionary: Option<Vec<u8>>,
+    includes: Vec<String>,
+}
+
+impl ArchiveReader {
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            password: None,
+            zstd_dictionary: None,
+            includes: Vec::new(),
+        }
+    }
+
+    pub fn with_password(mut self, password: impl Into<String>) -> Self {
+        self.password = Some(password.into());
+        self
+    }
+
+    /// The dictionary a tar.zst archive was compressed with.
+    pub fn with_zstd_dictionary(mut self, dictionary: Vec<u8>) -> Self {
+        self.zstd_dictionary = Some(dictionary);
+        self
+    }
+
+    /// Only extract entries matching one of these gitignore-style globs,
+    /// matched against the stored entry name (`*.flac`, `Album/**`).
+    pub fn with_includes(mut self, patterns: Vec<String>) -> Self {
+        self.includes = patterns;
+        self
+    }
+
+    /// True for age-encrypted archives and zips with a password-protected
+    /// entry, judged from the file itself. Only reads the age header and,
+    /// for zips, the central directory.
+    pub fn is_encrypted(&self) -> Result<bool> {
+        if crate::encrypt_tar::TarEncryptor::is_age_encrypted(&self.path.to_string_lossy()) {
+            return Ok(true);
+        }
+        let algorithm = Algorithm::from_archive_path(&self.path)
+            .with_context(|| format!("Unrecognized archive format: {}", self.path.display()))?;
+        if algorithm != Algorithm::Zip {
+            // Tar archives have no encryption of their own besides age.
+            return Ok(false);
+        }
+        Ok(self.list_contents()?.iter().any(|entry| entry.encrypted))
+    }
+
+    /// Names and sizes of the file entries, read from the headers only, so
+    /// no password is needed. Fails if any entry is damaged or the listing
+    /// breaks off; `list_contents_report` lists what is readable instead.
+    pub fn list_contents(&self) -> Result<Vec<EntryInfo>> {
+        let listing = self.list_contents_report()?;
+        if listing.truncated {
+            return Err(ErrorKind::Verification.error(format!(
+                "{} is truncated after {} entries",
+                self.path.display(),
+                listing.entries.len()
+            )));
+        }
+        if listing.unreadable > 0 {
+            return Err(ErrorKind::Verification.error(format!(
+                "{} has {} damaged entries",
+                self.path.display(),
+                listing.unreadable
+            )));
+        }
+        Ok(listing.entries)
+    }
+
+    /// `list_contents` that also counts what it could not read: zip entries
+    /// with damaged records are skipped, and a tar listing stops at the first
+    /// unreadable block. Fails only when nothing at all can be read.
+    pub fn list_contents_report(&self) -> Result<ContentListing> {
+        let mut listing = ContentListing::default();
+
+        let opened = match self.open() {
+            Ok(opened) => opened,
+            Err(e) if Algorithm::from_archive_path(&self.path) == Some(Algorithm::Zip) => {
+                return Self::scan_zip_directory(&self.path).map_err(|_| e);
+            }
+            Err(e) => return Err(e),
+        };
+
+        match opened {
+            Opened::Tar(mut archive) => {
+                for entry in archive.entries()? {
+                    let entry = match entry {
+                        Ok(entry) => entry,
+                        Err(_) if !listing.entries.is_empty() => {
+                            listing.truncated = true;
+                            break;
+                        }
+                        Err(e) => return Err(e.into()),
+                    };
+                    let entry_type = entry.header().entry_type();
+                    if entry_type.is_file() || entry_type.is_hard_link() {
+                        listing.entries.push(EntryInfo {
+                            name: entry.path()?.to_string_lossy().to_string(),
+                            size: entry.size(),
+                            encrypted: false,
+                        });
+                    }
+                }
+            }
+            Opened::Zip(mut zip) => {
+                for index in 0..zip.len() {
+                    let Ok(entry) = zip.by_index_raw(index) else {
+                        listing.unreadable += 1;
+                        continue;
+                    };
+                    if entry.is_file() {
+                        listing.entries.push(EntryInfo {
+                            name: entry.name().to_string(),
+                            size: entry.size(),
+                            encrypted: entry.encrypted(),
+                        });
+                    }
+                }
+            }
+        }
+
+        Ok(listing)
+    }
+
+    /// Lists a zip the zip crate refused to open by walking its central
+    /// directory record by record. A damaged record is skipped by searching
+    /// for the next record signature; one whose local header is gone is
+    /// left out too. Zip64 archives are not handled.
+    fn scan_zip_directory(path: &Path) -> Result<ContentListing> {
+        let mut file = File::open(path)?;
+        let len = file.metadata()?.len();
+
+        // The end-of-directory record is the last 22 bytes plus a comment
+        // of up to 64 KiB.
+        let tail_len = len.min(22 + u16::MAX as u64);
+        let mut tail = vec![0u8; tail_len as usize];
+        file.seek(SeekFrom::Start(len - tail_len))?;
+        file.read_exact(&mut tail)?;
+        let eocd = tail
+            .windows(4)
+            .rposition(|window| window == ZIP_END_OF_DIRECTORY)
+            .filter(|&at| at + 22 <= tail.len())
+            .context("No zip end-of-directory record")?;
+        let u16_at = |bytes: &[u8], at: usize| u16::from_le_bytes([bytes[at], bytes[at + 1]]) as usize;
+        let u32_at = |bytes: &[u8], at: usize| u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]]);
+        let directory_size = u32_at(&tail, eocd + 12) as u64;
+        let directory_start = u32_at(&tail, eocd + 16) as u64;
+        if directory_start.saturating_add(directory_size) > len {
+            anyhow::bail!("Zip central directory lies outside the file");
+        }
+
+        let mut directory = vec![0u8; directory_size as usize];
+        file.seek(SeekFrom::Start(directory_start))?;
+        file.read_exact(&mut directory)?;
+
+        let mut listing = ContentListing::default();
+        let mut at = 0;
+        while at + 46 <= directory.len() {
+            if &directory[at..at + 4] != ZIP_CENTRAL_HEADER {
+                listing.unreadable += 1;
+                match directory[at + 1..].windows(4).position(|window| window == ZIP_CENTRAL_HEADER) {
+                    Some(next) => at += 1 + next,
+                    None => break,
+                }
+                continue;
+            }
+
+            let name_end = at + 46 + u16_at(&directory, at + 28);
+            let record_end = name_end + u16_at(&directory, at + 30) + u16_at(&directory, at + 32);
+            if record_end > directory.len() {
+                listing.unreadable += 1;
+                break;
+            }
+            let name = String::from_utf8_lossy(&directory[at + 46..name_end]).to_string();
+            let local_header = u32_at(&directory, at + 42) as u64;
+
+            let mut signature = [0u8; 4];
+            let local_ok = file.seek(SeekFrom::Start(local_header)).is_ok()
+                && file.read_exact(&mut signature).is_ok()
+                && &signature == ZIP_LOCAL_HEADER;
+            if !local_ok {
+                listing.unreadable += 1;
+            } else if !name.ends_with('/') {
+                listing.entries.push(EntryInfo {
+                    name,
+                    size: u32_at(&directory, at + 24) as u64,
+                    encrypted: u16_at(&directory, at + 8) & 1 == 1,
+                });
+            }
+            at = record_end;
+        }
+
+        if listing.entries.is_empty() {
+            anyhow::bail!("No readable entries in the zip central directory");
+        }
+        Ok(listing)
+    }
+
+    /// Calls `visit` with the name and content of every file entry in the
+    /// archive, streaming so nothing is written to disk.
+    pub fn for_each_entry(&self, visit: impl FnMut(&str, &mut dyn Read) -> Result<()>) -> Result<()> {
+        self.walk_entries(visit, |_, _| Ok(()))
+    }
+
+    /// Like `for_each_entry`, also calling `link` with the name and target
+    /// of each tar hard link (written by `--dedup`).
+    fn walk_entries(
+        &self,
+        mut visit: impl FnMut(&str, &mut dyn Read) -> Result<()>,
+        mut link: impl FnMut(&str, &str) -> Result<()>,
+    ) -> Result<()> {
+        match self.open()? {
+            Opened::Tar(mut archive) => {
+                for entry in archive.entries()? {
+                    let mut entry = entry?;
+                    let entry_type = entry.header().entry_type();
+                    let name = entry.path()?.to_string_lossy().to_string();
+                    if entry_type.is_file() {
+                        visit(&name, &mut entry)?;
+                    } else if entry_type.is_hard_link()
+                        && let Some(target) = entry.link_name()?
+                    {
+                        link(&name, &target.to_string_lossy())?;
+                    }
+                }
+            }
+            Opened::Zip(mut zip) => {
+                for index in 0..zip.len() {
+                    let entry = match self.password {
+                        Some(ref password) => zip.by_index_decrypt(index, password.as_bytes()),
+                        None => zip.by_index(index),
+                    };
+                    let mut entry = entry.map_err(|e| self.zip_error(e))?;
+                    if entry.is_file() {
+                        let name = entry.name().to_string();
+                        visit(&name, &mut entry)?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Writes every file entry under `output`, returning how many were
+    /// extracted. Entries that would land outside `output` are refused.
+    /// Hard links are extracted as copies of the file they point to.
+    pub fn extract_to(&self, output: &Path) -> Result<usize> {
+        Ok(self.extract(output)?.extracted)
+    }
+
+    /// `extract_to`, limited to the `with_includes` patterns if any, also
+    /// counting the entries left out. A hard link whose target the patterns
+    /// leave out is still extracted, from a second read of the archive.
+    pub fn extract(&self, output: &Path) -> Result<ExtractSummary> {
+        let includes = self.include_matcher()?;
+        let wanted = |name: &str| {
+            // Patterns match relative paths; absolute names are refused by `output_path`.
+            let relative = name.trim_start_matches(['/', '\\']);
+            includes.as_ref().is_none_or(|matcher| {
+                !relative.is_empty() && matcher.matched_path_or_any_parents(relative, false).is_ignore()
+            })
+        };
+        let extracted = std::cell::Cell::new(0);
+        let total = std::cell::Cell::new(0);
+        // (link, target) pairs whose target was left out.
+        let unresolved = std::cell::RefCell::new(Vec::new());
+
+        self.walk_entries(
+            |name, reader| {
+                total.set(total.get() + 1);
+                if !wanted(name) {
+                    return Ok(());
+                }
+                let target = Self::output_path(output, name)?;
+                io::copy(reader, &mut File::create(&target)?)
+                    .with_context(|| format!("Failed to extract {}", name))?;
+                extracted.set(extracted.get() + 1);
+                Ok(())
+            },
+            |name, link_target| {
+                total.set(total.get() + 1);
+                if !wanted(name) {
+                    return Ok(());
+                }
+                let source = Self::output_path(output, link_target)?;
+                if !source.exists() {
+                    unresolved.borrow_mut().push((name.to_string(), link_target.to_string()));
+                    return Ok(());
+                }
+                fs::copy(&source, Self::output_path(output, name)?)
+                    .with_context(|| format!("Failed to extract {} (a copy of {})", name, link_target))?;
+                extracted.set(extracted.get() + 1);
+                Ok(())
+            },
+        )?;
+
+        let mut unresolved = unresolved.into_inner();
+        if !unresolved.is_empty() {
+            self.walk_entries(
+                |name, reader| {
+                    let links: Vec<String> = unresolved
+                        .iter()
+                        .filter(|(_, target)| target == name)
+                        .map(|(link, _)| link.clone())
+                        .collect();
+                    let Some((first, rest)) = links.split_first() else {
+                        return Ok(());
+                    };
+                    let first_path = Self::output_path(output, first)?;
+                    io::copy(reader, &mut File::create(&first_path)?)
+                        .with_context(|| format!("Failed to extract {} (a copy of {})", first, name))?;
+                    for link in rest {
+                        fs::copy(&first_path, Self::output_path(output, link)?)
+                            .with_context(|| format!("Failed to extract {} (a copy of {})", link, name))?;
+                    }
+                    extracted.set(extracted.get() + links.len());
+                    unresolved.retain(|(_, target)| target != name);
+                    Ok(())
+                },
+                |_, _| Ok(()),
+            )?;
+        }
+        for (link, target) in &unresolved {
+            crate::utils::print_warning(&format!("Skipped {}: its target {} is not in the archive", link, target));
+        }
+
+        Ok(ExtractSummary {
+            extracted: extracted.get(),
+            total: total.get(),
+        })
+    }
+
+    fn include_matcher(&self) -> Result<Option<Gitignore>> {
+        if self.includes.is_empty() {
+            return Ok(None);
+        }
+
+        let mut builder = GitignoreBuilder::new("");
+        for pattern in &self.includes {
+            builder
+                .add_line(None, pattern)
+                .with_context(|| format!("Invalid include pattern: {}", pattern))?;
+        }
+        Ok(Some(builder.build().context("Failed to build include patterns")?))
+    }
+
+    /// Where entry `name` goes under `output`, with its parent folders
+    /// created. Names that would escape `output` are refused.
+    fn output_path(output: &Path, name: &str) -> Result<PathBuf> {
+        let relative = Path::new(name);
+        if !relative.components().all(|c| matches!(c, Component::Normal(_))) {
+            return Err(anyhow::anyhow!("Refusing to extract unsafe entry name: {}", name));
+        }
+
+        let target = output.join(relative);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        Ok(target)
+    }
+
+    fn open(&self) -> Result<Opened> {
+        if crate::encrypt_tar::TarEncryptor::is_age_encrypted(&self.path.to_string_lossy()) {
+            return Err(ErrorKind::Encryption.error("Archive is age-encrypted, decrypt it first"));
+        }
+
+        let algorithm = Algorithm::from_archive_path(&self.path)
+            .with_context(|| format!("Unrecognized archive format: {}", self.path.display()))?;
+        let file = BufReader::new(File::open(&self.path)?);
+
+        Ok(match algorithm {
+            Algorithm::TarGz => Opened::Tar(tar::Archive::new(Box::new(flate2::read::MultiGzDecoder::new(file)))),
+            Algorithm::TarZst => {
+                let decoder = match self.zstd_dictionary {
+                    Some(ref dictionary) => zstd::stream::read::Decoder::with_dictionary(file, dictionary)?,
+                    None => {
+                        if let Some(id) = crate::dictionary::frame_dictionary_id(&self.path)? {
+                            return Err(ErrorKind::Usage.error(format!(
+                                "{} was compressed with zstd dictionary id {}, pass it with --zstd-dict",
+                                self.path.display(),
+                                id
+                            )));
+                        }
+                        zstd::stream::read::Decoder::with_buffer(file)?
+                    }
+                };
+                Opened::Tar(tar::Archive::new(Box::new(decoder)))
+            }
+            Algorithm::Zip => Opened::Zip(zip::ZipArchive::new(file)?),
+        })
+    }
+
+    fn zip_error(&self, error: ZipError) -> anyhow::Error {
+        match error {
+            ZipError::InvalidPassword => ErrorKind::Encryption.error(format!("Wrong password for {}", self.path.display())),
+            ZipError::UnsupportedArchive(message) if message == ZipError::PASSWORD_REQUIRED => ErrorKind::Encryption
+                .error(format!("{} is password-protected, a password is needed", self.path.display())),
+            other => other.into(),
+        }
+    }
+}
+
+enum Opened {
+    Tar(tar::Archive<Box<dyn Read>>),
+    Zip(zip::ZipArchive<BufReader<File>>),
+}
+
+/// Calls `visit` with the name and content of every file entry in the
+/// archive, streaming so nothing is written to disk. Archives compressed
+/// with a zstd dictionary need `ArchiveReader::for_each_entry` instead.
+pub fn for_each_entry(
+    archive_path: &Path,
+    visit: impl FnMut(&str, &mut dyn Read) -> Result<()>,
+) -> Result<()> {
+    ArchiveReader::new(archive_path).for_each_entry(visit)
+}
+
+/// SHA-256 of every file entry, keyed by entry name.
+pub fn hash_entries(archive_path: &Path) -> Result<BTreeMap<String, String>> {
+    let mut hashes = BTreeMap::new();
+
+    for_each_entry(archive_path, |name, reader| {
+        let mut hasher = Sha256::new();
+        io::copy(reader, &mut hasher)?;
+        hashes.insert(name.to_string(), format!("{:x}", hasher.finalize()));
+        Ok(())
+    })?;
+
+    Ok(hashes)
+}
+
+/// Entries whose content no longer matches the manifest.
+#[derive(Debug, Default, PartialEq)]
+pub struct ContentReport {
+    pub checked: usize,
+    pub corrupted: Vec<String>,
+    pub missing: Vec<String>,
+}
+
+impl ContentReport {
+    pub fn is_ok(&self) -> bool {
+        self.corrupted.is_empty() && self.missing.is_empty()
+    }
+}
+
+/// Re-hashes the archive's entries and compares them to `manifest`
+/// (entry name to SHA-256).
+pub fn verify_contents(archive_path: &Path, manifest: &BTreeMap<String, String>) -> Result<ContentReport> {
+    ArchiveReader::new(archive_path).verify_contents_streaming(manifest, false, |_, _| {})
+}
+
+/// Ends an entry walk early without it counting as a failure.
+#[derive(Debug)]
+struct StopWalk;
+
+impl std::fmt::Display for StopWalk {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("entry walk stopped")
+    }
+}
+
+impl std::error::Error for StopWalk {}
+
+impl ArchiveReader {
+    /// `verify_contents` that checks each entry as it is read, calling
+    /// `on_file` with its name and whether it matched. With `stop_early` the
+    /// walk ends at the first corrupted entry, leaving `missing` empty.
+    pub fn verify_contents_streaming(
+        &self,
+        manifest: &BTreeMap<String, String>,
+        stop_early: bool,
+        mut on_file: impl FnMut(&str, bool),
+    ) -> Result<ContentReport> {
+        let mut report = ContentReport::default();
+        let mut seen = std::collections::HashSet::new();
+
+        let walked = self.for_each_entry(|name, reader| {
+            let Some(expected) = manifest.get(name) else {
+                return Ok(());
+            };
+            let mut hasher = Sha256::new();
+            io::copy(reader, &mut hasher)?;
+            let matches = format!("{:x}", hasher.finalize()).eq_ignore_ascii_case(expected);
+
+            seen.insert(name.to_string());
+            report.checked += 1;
+            if !matches {
+                report.corrupted.push(name.to_string());
+            }
+            on_file(name, matches);
+
+            if !matches && stop_early {
+                return Err(StopWalk.into());
+            }
+            Ok(())
+        });
+        match walked {
+            Err(e) if e.is::<StopWalk>() => return Ok(report),
+            other => other?,
+        }
+
+        report.corrupted.sort();
+        report.missing = manifest.keys().filter(|name| !seen.contains(*name)).cloned().collect();
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compress::Archiver;
+
+    #[test]
+    fn test_corrupted_file_is_pinpointed() -> Result<()> {
+        let source_dir = tempfile::tempdir()?;
+        let dest_dir = tempfile::tempdir()?;
+        for (name, data) in [("01.flac", "one"), ("02.flac", "two"), ("03.flac", "three")] {
+            fs::write(source_dir.path().join(name), data)?;
+        }
+
+        let original = Archiver::new(source_dir.path(), dest_dir.path(), "music.tar.zst".to_string(), Algorithm::TarZst)
+            .with_content_checksums(true)
+            .compress()?;
+        let manifest: BTreeMap<String, String> = original.content_checksums.into_iter().collect();
+        assert_eq!(manifest.len(), 3);
+        assert!(verify_contents(&original.path, &manifest)?.is_ok());
+
+        // Rebuild the archive with one track damaged.
+        fs::write(source_dir.path().join("02.flac"), "tw0")?;
+        Archiver::new(source_dir.path(), dest_dir.path(), "music.tar.zst".to_string(), Algorithm::TarZst).compress()?;
+
+        let report = verify_contents(&original.path, &manifest)?;
+        assert_eq!(report.checked, 3);
+        assert_eq!(report.corrupted, vec!["02.flac"]);
+        assert!(report.missing.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_streaming_verify_reports_each_file_and_stops_early() -> Result<()> {
+        let source_dir = tempfile::tempdir()?;
+        let dest_dir = tempfile::tempdir()?;
+        for (name, data) in [("01.flac", "one"), ("02.flac", "two"), ("03.flac", "three")] {
+            fs::write(source_dir.path().join(name), data)?;
+        }
+        let archive = |name: &str| {
+            Archiver::new(source_dir.path(), dest_dir.path(), name.to_string(), Algorithm::TarZst)
+                .with_sort_order(crate::compress::SortOrder::NameAsc)
+                .with_content_checksums(true)
+                .compress()
+        };
+
+        let original = archive("music.tar.zst")?;
+        let manifest: BTreeMap<String, String> = original.content_checksums.into_iter().collect();
+        let mut progress = Vec::new();
+        let report = ArchiveReader::new(&original.path).verify_contents_streaming(&manifest, true, |name, ok| {
+            progress.push((name.to_string(), ok))
+        })?;
+        assert!(report.is_ok());
+        assert_eq!(progress.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>(), ["01.flac", "02.flac", "03.flac"]);
+
+        fs::write(source_dir.path().join("02.flac"), "tw0")?;
+        archive("music.tar.zst")?;
+        progress.clear();
+        let report = ArchiveReader::new(&original.path).verify_contents_streaming(&manifest, true, |name, ok| {
+            progress.push((name.to_string(), ok))
+        })?;
+        assert_eq!(progress, [("01.flac".to_string(), true), ("02.flac".to_string(), false)]);
+        assert_eq!(report.corrupted, ["02.flac"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_listing_skips_damaged_entries() -> Result<()> {
+        let source_dir = tempfile::tempdir()?;
+        let dest_dir = tempfile::tempdir()?;
+        for name in ["01.flac", "02.flac", "03.flac"] {
+            fs::write(source_dir.path().join(name), name.repeat(1000))?;
+        }
+
+        let zip = Archiver::new(source_dir.path(), dest_dir.path(), "music.zip".to_string(), Algorithm::Zip)
+            .with_sort_order(crate::compress::SortOrder::NameAsc)
+            .compress()?
+            .path;
+        let mut bytes = fs::read(&zip)?;
+        let records: Vec<usize> = bytes
+            .windows(4)
+            .enumerate()
+            .filter(|(_, window)| window == ZIP_CENTRAL_HEADER)
+            .map(|(at, _)| at)
+            .collect();
+        assert_eq!(records.len(), 3);
+        bytes[records[1] + 3] = 0xff;
+        fs::write(&zip, &bytes)?;
+
+        let listing = ArchiveReader::new(&zip).list_contents_report()?;
+        let names: Vec<&str> = listing.entries.iter().map(|entry| entry.name.as_str()).collect();
+        assert_eq!(names, ["01.flac", "03.flac"]);
+        assert_eq!(listing.entries[1].size, 7000);
+        assert_eq!(listing.unreadable, 1);
+        let err = ArchiveReader::new(&zip).list_contents().unwrap_err();
+        assert_eq!(crate::error::exit_code(&err), 3);
+
+        // Tar: everything before the cut is still listed.
+        let noise: Vec<u8> = (0..200_000u32).flat_map(|i| i.wrapping_mul(2_654_435_761).to_le_bytes()).collect();
+        for name in ["01.flac", "02.flac", "03.flac"] {
+            fs::write(source_dir.path().join(name), &noise)?;
+        }
+        let tar = Archiver::new(source_dir.path(), dest_dir.path(), "music.tar.gz".to_string(), Algorithm::TarGz)
+            .with_sort_order(crate::compress::SortOrder::NameAsc)
+            .compress()?
+            .path;
+        let bytes = fs::read(&tar)?;
+        fs::write(&tar, &bytes[..bytes.len() / 2])?;
+
+        let listing = ArchiveReader::new(&tar).list_contents_report()?;
+        assert!(listing.truncated);
+        assert_eq!(listing.entries.len(), 2);
+        assert!(ArchiveReader::new(&tar).list_contents().is_err());
+        // repair-state must not catalogue it as a healthy archive.
+        assert!(crate::state::ArchiveMetadata::from_archive_file(&tar).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_include_extracts_only_matching_entries() -> Result<()> {
+        let source_dir = tempfile::tempdir()?;
+        let dest_dir = tempfile::tempdir()?;
+        fs::create_dir(source_dir.path().join("Album"))?;
+        for name in ["Album/01.flac", "Album/02.FLAC", "Album/cover.jpg", "notes.txt", "bonus.flac"] {
+            fs::write(source_dir.path().join(name), name)?;
+        }
+
+        for (archive_name, algorithm) in [("mixed.tar.gz", Algorithm::TarGz), ("mixed.zip", Algorithm::Zip)] {
+            let archive = Archiver::new(source_dir.path(), dest_dir.path(), archive_name.to_string(), algorithm)
+                .compress()?
+                .path;
+
+            let out = dest_dir.path().join(format!("{}.out", archive_name));
+            let summary = ArchiveReader::new(&archive)
+                .with_includes(vec!["*.flac".to_string()])
+                .extract(&out)?;
+            assert_eq!(summary, ExtractSummary { extracted: 2, total: 5 });
+            assert!(out.join("Album/01.flac").is_file());
+            assert!(out.join("bonus.flac").is_file());
+            assert!(!out.join("Album/cover.jpg").exists());
+            assert!(!out.join("notes.txt").exists());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_include_keeps_links_to_left_out_files_and_refuses_absolute_names() -> Result<()> {
+        let source_dir = tempfile::tempdir()?;
+        let dest_dir = tempfile::tempdir()?;
+        fs::create_dir(source_dir.path().join("Playlist"))?;
+        fs::write(source_dir.path().join("01.wav"), "same track")?;
+        fs::write(source_dir.path().join("Playlist/01.flac"), "same track")?;
+
+        let archive = Archiver::new(source_dir.path(), dest_dir.path(), "music.tar.gz".to_string(), Algorithm::TarGz)
+            .with_sort_order(crate::compress::SortOrder::PathAsc)
+            .with_dedup(true)
+            .compress()?
+            .path;
+        let out = dest_dir.path().join("flac");
+        let summary = ArchiveReader::new(&archive)
+            .with_includes(vec!["*.flac".to_string()])
+            .extract(&out)?;
+        assert_eq!(summary, ExtractSummary { extracted: 1, total: 2 });
+        assert_eq!(fs::read_to_string(out.join("Playlist/01.flac"))?, "same track");
+        assert!(!out.join("01.wav").exists());
+
+        // tar::Builder won't write an absolute name, so set the raw header field.
+        let absolute = dest_dir.path().join("absolute.tar.gz");
+        let mut builder = tar::Builder::new(flate2::write::GzEncoder::new(
+            File::create(&absolute)?,
+            flate2::Compression::default(),
+        ));
+        let mut header = tar::Header::new_gnu();
+        header.as_gnu_mut().unwrap().name[..12].copy_from_slice(b"/tmp/x.flac\0");
+        header.set_size(4);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder.append(&header, &b"data"[..])?;
+        builder.into_inner()?.finish()?;
+
+        let err = ArchiveReader::new(&absolute)
+            .with_includes(vec!["*.flac".to_string()])
+            .extract(&dest_dir.path().join("absolute"))
+            .unwrap_err();
+        assert!(err.to_string().contains("unsafe entry name"), "{}", err);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_encrypted_zip_roundtrip() -> Result<()> {
+        let source_dir = tempfile::tempdir()?;
+        let dest_dir = tempfile::tempdir()?;
+        fs::create_dir(source_dir.path().join("Album"))?;
+        fs::write(source_dir.path().join("Album/01.flac"), "track one")?;
+
+        let archive = Archiver::new(source_dir.path(), dest_dir.path(), "secret.zip".to_string(), Algorithm::Zip)
+            .with_password("correct horse battery".to_string())
+            .compress()?
+            .path;
+
+        let reader = ArchiveReader::new(&archive);
+        assert!(reader.is_encrypted()?);
+        assert_eq!(reader.list_contents()?[0].name, "Album/01.flac");
+        let err = reader.extract_to(&dest_dir.path().join("none")).unwrap_err();
+        assert!(err.to_string().contains("password-protected"), "{}", err);
+
+        let err = ArchiveReader::new(&archive)
+            .with_password("wrong")
+            .extract_to(&dest_dir.path().join("wrong"))
+            .unwrap_err();
+        assert!(err.to_string().contains("Wrong password"), "{}", err);
+
+        let out = dest_dir.path().join("restored");
+        let extracted = ArchiveReader::new(&archive)
+            .with_password("correct horse battery")
+            .extract_to(&out)?;
+        assert_eq!(extracted, 1);
+        assert_eq!(fs::read_to_string(out.join("Album/01.flac"))?, "track one");
+
+        // A password given for an unencrypted archive is simply not needed.
+        let plain = Archiver::new(source_dir.path(), dest_dir.path(), "plain.zip".to_string(), Algorithm::Zip)
+            .compress()?
+            .path;
+        let reader = ArchiveReader::new(&plain).with_password("unused");
+        assert!(!reader.is_encrypted()?);
+        assert_eq!(reader.extract_to(&dest_dir.path().join("plain"))?, 1);
+
+        Ok(())
+    }
+}
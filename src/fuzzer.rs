@@ -1,13 +1,14 @@
 use anyhow::{Context, Result};
 use dialoguer::{theme::ColorfulTheme, FuzzySelect};
 use std::path::PathBuf;
-use walkdir::WalkDir;
+use crate::path_utils::{PathUtils, SymlinkPolicy};
 
 #[derive(Clone)]
 pub struct FuzzerConfig {
     pub max_depth: usize,
     pub exclude_patterns: Vec<String>,
     pub case_sensitive: bool,
+    pub symlinks: SymlinkPolicy,
 }
 
 impl Default for FuzzerConfig {
@@ -26,6 +27,7 @@ impl Default for FuzzerConfig {
                 "venv".to_string(),
             ],
             case_sensitive: false,
+            symlinks: SymlinkPolicy::default(),
         }
     }
 }
@@ -47,7 +49,13 @@ impl Fuzzer {
         let mut folders_found = Vec::new();
 
         for base in base_paths {
-            let expanded = shellexpand::tilde(base).to_string();
+            let expanded = match PathUtils::expand_path(base) {
+                Ok(expanded) => expanded,
+                Err(e) => {
+                    crate::utils::print_warning(&format!("Skipping {}: {}", base, e));
+                    continue;
+                }
+            };
             let folders = Self::find_target_folders_with_config(&expanded, target, &config);
             folders_found.extend(folders);
         }
@@ -93,7 +101,9 @@ impl Fuzzer {
         target: &str,
         config: &FuzzerConfig,
     ) -> Vec<PathBuf> {
-        WalkDir::new(base)
+        config
+            .symlinks
+            .walk(base)
             .max_depth(config.max_depth)
             .into_iter()
             .filter_map(|e| e.ok())
@@ -123,23 +133,25 @@ impl Fuzzer {
             .collect()
     }
 
-    pub fn count_files(path: &str) -> Result<usize> {
-        let count = WalkDir::new(path)
+    pub fn count_files(path: &str, symlinks: SymlinkPolicy) -> Result<usize> {
+        let count = symlinks
+            .walk(path)
             .into_iter()
             .filter_map(|e| e.ok())
-            .filter(|e| e.file_type().is_file())
+            .filter(|e| symlinks.keeps_file(e, |message| crate::utils::print_warning(&message)))
             .count();
 
         Ok(count)
     }
 
-    pub fn estimate_size(path: &str) -> Result<u64> {
+    pub fn estimate_size(path: &str, symlinks: SymlinkPolicy) -> Result<u64> {
         let mut total_size = 0u64;
 
-        for entry in WalkDir::new(path)
+        for entry in symlinks
+            .walk(path)
             .into_iter()
             .filter_map(|e| e.ok())
-            .filter(|e| e.file_type().is_file())
+            .filter(|e| symlinks.keeps_file(e, |_| {}))
         {
             if let Ok(metadata) = entry.metadata() {
                 total_size += metadata.len();
@@ -149,9 +161,9 @@ impl Fuzzer {
         Ok(total_size)
     }
 
-    pub fn get_folder_info(path: &str) -> Result<FolderInfo> {
-        let file_count = Self::count_files(path)?;
-        let total_size = Self::estimate_size(path)?;
+    pub fn get_folder_info(path: &str, symlinks: SymlinkPolicy) -> Result<FolderInfo> {
+        let file_count = Self::count_files(path, symlinks)?;
+        let total_size = Self::estimate_size(path, symlinks)?;
 
         Ok(FolderInfo {
             path: path.to_string(),
@@ -189,6 +201,7 @@ mod tests {
             max_depth: 3,
             exclude_patterns: vec![".git".to_string(), "node_modules".to_string()],
             case_sensitive: false,
+            symlinks: SymlinkPolicy::Follow,
         };
 
         assert_eq!(config.exclude_patterns.len(), 2);
@@ -0,0 +1,50 @@
+//! Zencore's backup engine: archiving, checksums, encryption and the archive
+//! catalog, usable without the `zencore` command line.
+//!
+//! ```
+//! use zencore::{Algorithm, Archiver, Checker};
+//!
+//! # fn main() -> anyhow::Result<()> {
+//! # let music = tempfile::tempdir()?;
+//! # let backups = tempfile::tempdir()?;
+//! # std::fs::write(music.path().join("track.flac"), b"fLaC")?;
+//! let archiver = Archiver::new(music.path(), backups.path(), "music.tar.zst".to_string(), Algorithm::TarZst)
+//!     .with_excludes(vec!["*.tmp".to_string()]);
+//!
+//! let result = archiver.compress()?;
+//! let sha256 = Checker::generate_checksum(result.path.to_str().unwrap())?;
+//! println!("{} {}", sha256, result.path.display());
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod archive_name;
+pub mod bench;
+pub mod cancel;
+#[cfg(feature = "sqlite")]
+pub mod catalog_db;
+pub mod cli;
+pub mod compress;
+pub mod config;
+pub mod crypto;
+pub mod dictionary;
+pub mod dupes;
+pub mod error;
+pub mod fuzzer;
+pub mod logging;
+pub mod state;
+pub mod utils;
+pub mod remote;
+pub mod encrypt_tar;
+pub mod resume;
+pub mod run_log;
+pub mod snapshot;
+pub mod reveal;
+pub mod path_utils;
+pub mod extract;
+
+pub use compress::{Algorithm, Archiver, CompressResult, CompressionLevel, ProgressEvent};
+pub use crypto::{Checker, HashAlgorithm};
+pub use encrypt_tar::TarEncryptor;
+pub use extract::ArchiveReader;
+pub use state::{ArchiveMetadata, StateTracker};
@@ -0,0 +1,56 @@
+use tracing_subscriber::EnvFilter;
+
+/// Environment variable with a filter directive (e.g. `zencore=debug`), used
+/// when no `-v` flag is given.
+pub const LOG_ENV: &str = "ZENCORE_LOG";
+
+/// Filter for the given number of `-v` flags: off, info, debug, then trace.
+/// Without `-v`, `ZENCORE_LOG` applies if set.
+pub fn filter_for(verbosity: u8) -> EnvFilter {
+    let level = match verbosity {
+        0 => return EnvFilter::try_from_env(LOG_ENV).unwrap_or_else(|_| EnvFilter::new("off")),
+        1 => "info",
+        2 => "debug",
+        _ => "trace",
+    };
+    EnvFilter::new(format!("zencore={}", level))
+}
+
+/// Sends diagnostic logs to stderr, leaving stdout to the normal output.
+pub fn init(verbosity: u8) {
+    tracing_subscriber::fmt()
+        .with_env_filter(filter_for(verbosity))
+        .with_writer(std::io::stderr)
+        .with_target(false)
+        .try_init()
+        .ok();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tracing::Level;
+
+    fn enabled_at(verbosity: u8, level: Level) -> bool {
+        let subscriber = tracing_subscriber::fmt()
+            .with_env_filter(filter_for(verbosity))
+            .with_writer(std::io::sink)
+            .finish();
+
+        let disabled = tracing::subscriber::with_default(subscriber, || match level {
+            Level::INFO => tracing::info_span!("scan").is_disabled(),
+            Level::DEBUG => tracing::debug_span!("scan").is_disabled(),
+            _ => tracing::trace_span!("scan").is_disabled(),
+        });
+        !disabled
+    }
+
+    #[test]
+    fn test_verbosity_maps_to_levels() {
+        assert!(enabled_at(2, Level::DEBUG));
+        assert!(!enabled_at(2, Level::TRACE));
+        assert!(enabled_at(1, Level::INFO));
+        assert!(!enabled_at(1, Level::DEBUG));
+        assert!(enabled_at(3, Level::TRACE));
+    }
+}
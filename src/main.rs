@@ -1,21 +1,20 @@
-use anyhow::Result;
 use clap::Parser;
 
-mod archive_name;
-mod cli;
-mod compress;
-mod config;
-mod crypto;
-mod fuzzer;
-mod state;
-mod utils;
-mod remote;
-mod encrypt_tar;
+use zencore::cli::Cli;
+use zencore::error;
 
-use cli::Cli;
-
-fn main() -> Result<()> {
+/// Exit codes:
+/// - 0: success
+/// - 1: any other failure
+/// - 2: usage error (bad arguments, missing files)
+/// - 3: verification mismatch
+/// - 4: remote or upload failure
+/// - 5: encryption or decryption failure
+fn main() {
     let cli = Cli::parse();
-    utils::show_banner();
-    cli.run()
+
+    if let Err(e) = cli.run() {
+        eprintln!("Error: {:?}", e);
+        std::process::exit(error::exit_code(&e));
+    }
 }
@@ -0,0 +1,245 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use walkdir::WalkDir;
+
+/// What folder scans and the archiver do with symbolic links.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum SymlinkPolicy {
+    /// Archive what the link points to
+    #[default]
+    Follow,
+    /// Leave links out
+    Skip,
+    /// Keep links as links (tar link entries, zip symlinks)
+    Store,
+}
+
+impl SymlinkPolicy {
+    /// A directory walk that follows links only under `Follow`. Broken
+    /// links then show up as entries instead of errors.
+    pub fn walk(self, root: impl AsRef<std::path::Path>) -> WalkDir {
+        WalkDir::new(root).follow_links(self == SymlinkPolicy::Follow)
+    }
+
+    /// Whether a walked entry is a file to archive. Symlinks only count
+    /// under `Store`; broken ones are warned about either way.
+    pub fn keeps_file(self, entry: &walkdir::DirEntry, warn: impl FnOnce(String)) -> bool {
+        if !entry.path_is_symlink() || self == SymlinkPolicy::Follow {
+            return entry.file_type().is_file();
+        }
+        let broken = std::fs::metadata(entry.path()).is_err();
+        match self {
+            SymlinkPolicy::Store => {
+                if broken {
+                    warn(format!("Storing broken symlink {}", entry.path().display()));
+                }
+                true
+            }
+            _ => {
+                if broken {
+                    warn(format!("Skipping broken symlink {}", entry.path().display()));
+                }
+                false
+            }
+        }
+    }
+}
+
+pub struct PathUtils;
+
+impl PathUtils {
+    /// Expands `~`, `$VAR`/`${VAR}` and Windows-style `%VAR%` references.
+    /// An unset variable is left in the path as written, with a warning.
+    pub fn expand_path(path: &str) -> Result<String> {
+        Self::expand_path_with(path, |name| std::env::var(name).ok())
+    }
+
+    /// `expand_path` reading variables through `lookup`.
+    pub fn expand_path_with(path: &str, lookup: impl Fn(&str) -> Option<String>) -> Result<String> {
+        let lookup = |name: &str| {
+            let value = lookup(name);
+            if value.is_none() {
+                crate::utils::print_warning(&format!("Environment variable {} is not set in path: {}", name, path));
+            }
+            value
+        };
+        let expanded = Self::expand_percent_vars(path, lookup);
+        let home = || dirs::home_dir().map(|home| home.to_string_lossy().into_owned());
+        let expanded = shellexpand::full_with_context(&expanded, home, |name| {
+            Ok::<_, std::convert::Infallible>(lookup(name))
+        })
+        .with_context(|| format!("Cannot expand path: {}", path))?;
+        Ok(expanded.into_owned())
+    }
+
+    /// Absolute form of an existing path with `.`, `..`, symlinks and
+    /// trailing separators resolved, so `~/Music/`, `./Music` and a link to
+    /// it all name the same folder. Paths that can't be resolved only lose
+    /// their trailing separators.
+    pub fn canonical(path: &str) -> String {
+        match std::fs::canonicalize(path) {
+            Ok(resolved) => {
+                let resolved = resolved.to_string_lossy().into_owned();
+                // Windows returns verbatim `\\?\C:\...` paths, which other tools choke on.
+                match resolved.strip_prefix(r"\\?\") {
+                    Some(plain) if !plain.starts_with("UNC") => plain.to_string(),
+                    _ => resolved,
+                }
+            }
+            Err(_) => {
+                let trimmed = path.trim_end_matches(['/', std::path::MAIN_SEPARATOR]);
+                if trimmed.is_empty() { path.to_string() } else { trimmed.to_string() }
+            }
+        }
+    }
+
+    /// Where to look for music by default on this OS.
+    pub fn default_music_folders() -> Vec<String> {
+        Self::platform_folders("Music")
+    }
+
+    /// Where to look for backup destinations by default on this OS.
+    pub fn default_backup_folders() -> Vec<String> {
+        Self::platform_folders("Backups")
+    }
+
+    /// Home and Documents subfolders, plus where external drives usually show up.
+    fn platform_folders(name: &str) -> Vec<String> {
+        if cfg!(windows) {
+            vec![
+                format!("%USERPROFILE%\\{}", name),
+                format!("%USERPROFILE%\\Documents\\{}", name),
+                format!("D:\\{}", name),
+                format!("E:\\{}", name),
+            ]
+        } else if cfg!(target_os = "macos") {
+            vec![
+                format!("~/{}", name),
+                format!("~/Documents/{}", name),
+                "/Volumes".to_string(),
+            ]
+        } else {
+            vec![format!("~/{}", name), format!("~/Documents/{}", name)]
+        }
+    }
+
+    /// Replaces `%NAME%` with the variable's value. A lone `%` or one not
+    /// followed by a valid name and closing `%` is kept as is.
+    fn expand_percent_vars(path: &str, lookup: impl Fn(&str) -> Option<String>) -> String {
+        let mut result = String::with_capacity(path.len());
+        let mut rest = path;
+
+        while let Some(start) = rest.find('%') {
+            result.push_str(&rest[..start]);
+            let after = &rest[start + 1..];
+
+            let name = after
+                .find('%')
+                .map(|end| &after[..end])
+                .filter(|name| {
+                    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+                });
+
+            match name {
+                Some(name) => {
+                    match lookup(name) {
+                        Some(value) => result.push_str(&value),
+                        None => result.push_str(&rest[start..start + name.len() + 2]),
+                    }
+                    rest = &after[name.len() + 1..];
+                }
+                None => {
+                    result.push('%');
+                    rest = after;
+                }
+            }
+        }
+
+        result.push_str(rest);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand_tilde() -> Result<()> {
+        let home = dirs::home_dir().expect("home directory");
+        let expanded = PathUtils::expand_path("~/Music")?;
+        assert_eq!(expanded, home.join("Music").to_string_lossy());
+        Ok(())
+    }
+
+    #[test]
+    fn test_expand_env_vars() -> Result<()> {
+        let lookup = |name: &str| (name == "PROFILE").then(|| "/home/tester".to_string());
+        let expand = |path: &str| PathUtils::expand_path_with(path, lookup);
+
+        assert_eq!(expand("%PROFILE%/Music")?, "/home/tester/Music");
+        assert_eq!(expand("$PROFILE/Music")?, "/home/tester/Music");
+        assert_eq!(expand("${PROFILE}/Music")?, "/home/tester/Music");
+        assert_eq!(expand("/mnt/100%/Music")?, "/mnt/100%/Music");
+        assert_eq!(expand("%UNSET%/Music")?, "%UNSET%/Music");
+        assert_eq!(expand("$UNSET/Music")?, "$UNSET/Music");
+        assert_eq!(expand("/mnt/${UNSET}/Music")?, "/mnt/${UNSET}/Music");
+        Ok(())
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_windows_default_folders() {
+        assert!(PathUtils::default_music_folders().contains(&"%USERPROFILE%\\Music".to_string()));
+        assert!(PathUtils::default_backup_folders().contains(&"%USERPROFILE%\\Backups".to_string()));
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn test_unix_default_folders() {
+        assert!(PathUtils::default_music_folders().contains(&"~/Music".to_string()));
+        assert!(PathUtils::default_backup_folders().contains(&"~/Backups".to_string()));
+    }
+
+    #[test]
+    fn test_equivalent_sources_get_the_same_archive_name() -> Result<()> {
+        use crate::archive_name::ArchiveNamer;
+        use crate::compress::Algorithm;
+
+        let temp_dir = tempfile::tempdir()?;
+        let music = temp_dir.path().join("Music");
+        std::fs::create_dir(&music)?;
+        // Also named relative to the working directory, by climbing to the root.
+        let depth = std::env::current_dir()?.components().count() - 1;
+        let relative = format!("{}{}", "../".repeat(depth), music.strip_prefix("/")?.display());
+
+        let name_for = |source: &str| {
+            ArchiveNamer::new(Some("{source}_{algo}".to_string()), "/nonexistent".to_string(), Algorithm::TarZst, "%Y".to_string())
+                .with_source_path(PathUtils::canonical(source))
+                .generate()
+        };
+
+        let absolute = music.to_string_lossy().to_string();
+        let expected = PathUtils::canonical(&absolute);
+        assert!(std::path::Path::new(&expected).is_absolute());
+        for input in [
+            format!("{}/", absolute),
+            format!("./{}", relative),
+            format!("{}/../Music", relative),
+        ] {
+            assert_eq!(PathUtils::canonical(&input), expected, "{}", input);
+            assert_eq!(name_for(&input)?, "Music_tar.zst.tar.zst");
+        }
+
+        assert_eq!(PathUtils::canonical("/nonexistent/Music/"), "/nonexistent/Music");
+        assert_eq!(PathUtils::canonical("/"), "/");
+        Ok(())
+    }
+
+    #[test]
+    fn test_plain_absolute_path_unchanged() -> Result<()> {
+        assert_eq!(PathUtils::expand_path("/srv/backups")?, "/srv/backups");
+        Ok(())
+    }
+}
@@ -1,9 +1,13 @@
 use anyhow::{Context, Result};
-use indicatif::{ProgressBar, ProgressStyle};
+use indicatif::ProgressStyle;
 use std::io::{BufRead, BufReader};
 use std::path::Path;
 use std::process::{Command, Stdio};
 
+use crate::cancel::CancelToken;
+use crate::config::{default_mysql_port, default_table_name, RemoteConfig};
+use crate::error::ErrorKind;
+
 #[derive(Debug, Clone)]
 pub enum RemoteDestination {
     Rclone { remote: String, path: String },
@@ -14,6 +18,13 @@ pub enum RemoteDestination {
         database: String,
         table: String,
     },
+    S3 { bucket: String, prefix: String },
+    Sftp {
+        user: Option<String>,
+        host: String,
+        port: Option<u16>,
+        path: String,
+    },
 }
 
 impl RemoteDestination {
@@ -39,6 +50,159 @@ impl RemoteDestination {
             table: table.to_string(),
         }
     }
+
+    /// Parses a destination given on the command line: `s3://bucket/prefix`,
+    /// `sftp://[user@]host[:port]/dir`, `mysql://user@host[:port]/database[/table]`
+    /// or an rclone `remote:path`.
+    pub fn parse(uri: &str) -> Result<Self> {
+        let Some((scheme, rest)) = uri.split_once("://") else {
+            return match uri.split_once(':') {
+                Some((remote, path)) if !remote.is_empty() => Ok(Self::from_rclone(remote, path)),
+                _ => Err(anyhow::anyhow!(
+                    "Invalid destination '{}'. Use an rclone remote:path or a URI such as s3://bucket/prefix",
+                    uri
+                )),
+            };
+        };
+
+        let (authority, path) = rest.split_once('/').unwrap_or((rest, ""));
+        let (user, host_port) = match authority.rsplit_once('@') {
+            Some((user, host_port)) => (Some(user.to_string()), host_port),
+            None => (None, authority),
+        };
+        let (host, port) = match host_port.split_once(':') {
+            Some((host, port)) => (
+                host,
+                Some(port.parse::<u16>().with_context(|| format!("Invalid port in '{}'", uri))?),
+            ),
+            None => (host_port, None),
+        };
+        if host.is_empty() {
+            return Err(anyhow::anyhow!("Missing host or bucket in '{}'", uri));
+        }
+
+        match scheme.to_ascii_lowercase().as_str() {
+            "s3" => Ok(RemoteDestination::S3 {
+                bucket: host.to_string(),
+                prefix: path.trim_end_matches('/').to_string(),
+            }),
+            "sftp" => Ok(RemoteDestination::Sftp {
+                user,
+                host: host.to_string(),
+                port,
+                path: format!("/{}", path),
+            }),
+            "mysql" => {
+                let username = user
+                    .with_context(|| format!("mysql destination needs a user, e.g. mysql://user@{}", host_port))?;
+                let (database, table) = match path.split_once('/') {
+                    Some((database, table)) => (database.to_string(), table.trim_end_matches('/').to_string()),
+                    None => (path.to_string(), default_table_name()),
+                };
+                if database.is_empty() {
+                    return Err(anyhow::anyhow!("mysql destination needs a database: mysql://user@host/database"));
+                }
+
+                Ok(Self::from_database(
+                    host,
+                    port.unwrap_or_else(default_mysql_port),
+                    &username,
+                    &database,
+                    &table,
+                ))
+            }
+            other => Err(anyhow::anyhow!(
+                "Unknown destination scheme '{}://'. Supported: s3://, sftp://, mysql://, or an rclone remote:path",
+                other
+            )),
+        }
+    }
+
+    /// Every destination in the remote config, in the order uploads try
+    /// them; the first one is where auto-upload goes.
+    pub fn all_from_config(config: &RemoteConfig) -> Vec<Self> {
+        let mut destinations = Vec::new();
+
+        if let Some(ref rclone) = config.rclone {
+            destinations.push(Self::from_rclone(&rclone.remote_name, &rclone.remote_path));
+        }
+
+        if let Some(ref db) = config.database {
+            destinations.push(Self::from_database(&db.host, db.port, &db.username, &db.database, &db.table));
+        }
+
+        destinations
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            RemoteDestination::Rclone { .. } => "rclone",
+            RemoteDestination::Database { .. } => "database",
+            RemoteDestination::S3 { .. } => "s3",
+            RemoteDestination::Sftp { .. } => "sftp",
+        }
+    }
+
+    /// Where uploads land, e.g. `gdrive:Backups` or `user@host:3306/db.table`.
+    pub fn target(&self) -> String {
+        match self {
+            RemoteDestination::Rclone { remote, path } => format!("{}:{}", remote, path),
+            RemoteDestination::Database {
+                host,
+                port,
+                username,
+                database,
+                table,
+            } => format!("{}@{}:{}/{}.{}", username, host, port, database, table),
+            RemoteDestination::S3 { bucket, prefix } => format!("s3://{}/{}", bucket, prefix),
+            RemoteDestination::Sftp { user, host, port, path } => {
+                let user = user.as_ref().map(|u| format!("{}@", u)).unwrap_or_default();
+                let port = port.map(|p| format!(":{}", p)).unwrap_or_default();
+                format!("sftp://{}{}{}{}", user, host, port, path)
+            }
+        }
+    }
+
+    /// `[user@]host:path` as scp expects it.
+    fn scp_target(&self) -> String {
+        match self {
+            RemoteDestination::Sftp { user, host, path, .. } => match user {
+                Some(user) => format!("{}@{}:{}", user, host, path),
+                None => format!("{}:{}", host, path),
+            },
+            _ => self.target(),
+        }
+    }
+}
+
+/// Flags Zencore sets itself; passing them again through `extra_args`
+/// would fight with its own output parsing and filtering.
+const RESERVED_RCLONE_FLAGS: &[&str] = &["--progress", "-P", "--stats", "--include", "--max-depth"];
+
+/// `rclone <args>` followed by the configured extra flags. Only the built-in
+/// arguments are logged, since extra flags may carry secrets.
+fn rclone_command(args: &[String], extra_args: &[String]) -> Result<Command> {
+    if let Some(flag) = extra_args.iter().find(|arg| {
+        RESERVED_RCLONE_FLAGS
+            .iter()
+            .any(|reserved| arg.as_str() == *reserved || arg.starts_with(&format!("{}=", reserved)))
+    }) {
+        return Err(anyhow::anyhow!(
+            "rclone extra_args can't include {}, Zencore sets it itself",
+            flag.split('=').next().unwrap_or(flag)
+        ));
+    }
+
+    let mut command = Command::new("rclone");
+    command.args(args);
+    tracing::debug!(command = ?command, extra_args = extra_args.len(), "running rclone");
+    command.args(extra_args);
+    Ok(command)
+}
+
+/// A remote tool that could not be started, most likely not installed.
+fn not_installed(program: &str, error: std::io::Error) -> anyhow::Error {
+    ErrorKind::Remote.error(format!("Failed to run {}. Is it installed? ({})", program, error))
 }
 
 pub struct RemoteTransfer;
@@ -51,14 +215,13 @@ impl RemoteTransfer {
         }
     }
 
-    pub fn list_rclone_remotes() -> Result<Vec<String>> {
-        let output = Command::new("rclone")
-            .arg("listremotes")
+    pub fn list_rclone_remotes(extra_args: &[String]) -> Result<Vec<String>> {
+        let output = rclone_command(&["listremotes".to_string()], extra_args)?
             .output()
-            .context("Failed to run rclone. Is it installed?")?;
+            .map_err(|e| not_installed("rclone", e))?;
 
         if !output.status.success() {
-            return Err(anyhow::anyhow!("Rclone command failed"));
+            return Err(ErrorKind::Remote.error("Rclone command failed"));
         }
 
         let remotes = String::from_utf8_lossy(&output.stdout)
@@ -69,7 +232,13 @@ impl RemoteTransfer {
         Ok(remotes)
     }
 
-    pub fn upload_to_rclone(local_path: &str, remote: &str, remote_path: &str) -> Result<()> {
+    pub fn upload_to_rclone(
+        local_path: &str,
+        remote: &str,
+        remote_path: &str,
+        extra_args: &[String],
+        cancel: &CancelToken,
+    ) -> Result<()> {
         crate::utils::print_info(&format!("📤 Uploading to {}:{}...", remote, remote_path));
 
         let file_size = std::fs::metadata(local_path)?.len();
@@ -78,7 +247,7 @@ impl RemoteTransfer {
             .and_then(|n| n.to_str())
             .unwrap_or("archive");
 
-        let pb = ProgressBar::new(100);
+        let pb = crate::utils::progress_bar(100);
         pb.set_style(
             ProgressStyle::default_bar()
                 .template(
@@ -91,19 +260,24 @@ impl RemoteTransfer {
 
         let destination = format!("{}:{}", remote, remote_path);
 
-        let mut child = Command::new("rclone")
-            .arg("copy")
-            .arg(local_path)
-            .arg(&destination)
-            .arg("--progress")
-            .arg("--stats")
-            .arg("1s")
-            .stdout(Stdio::piped())
+        let args = [
+            "copy".to_string(),
+            local_path.to_string(),
+            destination,
+            "--progress".to_string(),
+            "--stats".to_string(),
+            "1s".to_string(),
+        ];
+
+        let mut child = rclone_command(&args, extra_args)?
+            .stdout(Stdio::null())
             .stderr(Stdio::piped())
             .spawn()
             .context("Failed to start rclone")?;
+        let stderr = child.stderr.take();
+        let child = cancel.track_child(child);
 
-        if let Some(stderr) = child.stderr.take() {
+        if let Some(stderr) = stderr {
             let reader = BufReader::new(stderr);
             for line in reader.lines().map_while(Result::ok) {
                 if line.contains("Transferred:") {
@@ -127,10 +301,104 @@ impl RemoteTransfer {
             Ok(())
         } else {
             pb.finish_with_message("✗ Upload failed");
-            Err(anyhow::anyhow!("Rclone upload failed"))
+            Err(ErrorKind::Remote.error("Rclone upload failed"))
         }
     }
 
+    /// Copies the file into `s3://bucket/prefix/` with the AWS CLI, which
+    /// picks up credentials from its usual config and environment.
+    pub fn upload_to_s3(local_path: &str, bucket: &str, prefix: &str, cancel: &CancelToken) -> Result<()> {
+        let file_name = Path::new(local_path)
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or("archive");
+        let key = if prefix.is_empty() {
+            file_name.to_string()
+        } else {
+            format!("{}/{}", prefix, file_name)
+        };
+        let target = format!("s3://{}/{}", bucket, key);
+
+        let args = ["s3".to_string(), "cp".to_string(), local_path.to_string(), target.clone()];
+        Self::run_copy_command("aws", &args, &target, cancel)
+    }
+
+    /// Copies the file over SSH with scp, using the user's SSH keys and config.
+    pub fn upload_to_sftp(local_path: &str, destination: &RemoteDestination, cancel: &CancelToken) -> Result<()> {
+        let RemoteDestination::Sftp { port, .. } = destination else {
+            return Err(anyhow::anyhow!("Not an sftp destination: {}", destination.target()));
+        };
+
+        let mut args = Vec::new();
+        if let Some(port) = port {
+            args.push("-P".to_string());
+            args.push(port.to_string());
+        }
+        args.push(local_path.to_string());
+        args.push(destination.scp_target());
+
+        Self::run_copy_command("scp", &args, &destination.target(), cancel)
+    }
+
+    fn run_copy_command(program: &str, args: &[String], target: &str, cancel: &CancelToken) -> Result<()> {
+        crate::utils::print_info(&format!("📤 Uploading to {}...", target));
+
+        let mut command = Command::new(program);
+        command.args(args);
+        tracing::debug!(command = ?command, "running uploader");
+
+        let child = command
+            .spawn()
+            .map_err(|e| not_installed(program, e))?;
+        let status = cancel.track_child(child).wait()?;
+
+        if status.success() {
+            crate::utils::print_success(&format!("Uploaded to {}", target));
+            Ok(())
+        } else {
+            Err(ErrorKind::Remote.error(format!("{} upload failed", program)))
+        }
+    }
+
+    /// Whether `remote:path` already holds `name` with this SHA-256, going by
+    /// `rclone hashsum`. Backends that can't report SHA-256 count as missing.
+    pub fn remote_has(remote: &str, path: &str, name: &str, checksum: &str, extra_args: &[String]) -> Result<bool> {
+        Self::remote_has_with(remote, path, name, checksum, |args| {
+            let output = rclone_command(args, extra_args)?
+                .output()
+                .map_err(|e| not_installed("rclone", e))?;
+            if !output.status.success() {
+                return Err(ErrorKind::Remote.error(format!(
+                    "rclone hashsum failed: {}",
+                    String::from_utf8_lossy(&output.stderr).trim()
+                )));
+            }
+            Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+        })
+    }
+
+    fn remote_has_with(
+        remote: &str,
+        path: &str,
+        name: &str,
+        checksum: &str,
+        hashsum: impl FnOnce(&[String]) -> Result<String>,
+    ) -> Result<bool> {
+        let args = [
+            "hashsum".to_string(),
+            "sha256".to_string(),
+            format!("{}:{}", remote, path),
+            "--include".to_string(),
+            format!("/{}", name),
+        ];
+        let listing = hashsum(&args)?;
+
+        Ok(listing.lines().any(|line| {
+            line.split_once("  ")
+                .is_some_and(|(hash, file)| file == name && hash.eq_ignore_ascii_case(checksum))
+        }))
+    }
+
     fn extract_progress(line: &str) -> Option<u8> {
         line.split(',')
             .find(|s| s.contains('%'))
@@ -155,7 +423,7 @@ impl RemoteTransfer {
 
         let file_size = std::fs::metadata(local_path)?.len();
 
-        let pb = ProgressBar::new(100);
+        let pb = crate::utils::progress_bar(100);
         pb.set_style(
             ProgressStyle::default_bar()
                 .template("{spinner:.green} [{bar:40.cyan/blue}] {pos}% {msg}")
@@ -191,14 +459,11 @@ impl RemoteTransfer {
         Ok(())
     }
 
-    pub fn test_rclone_connection(remote: &str) -> Result<bool> {
+    pub fn test_rclone_connection(remote: &str, extra_args: &[String]) -> Result<bool> {
         crate::utils::print_info(&format!("Testing connection to {}...", remote));
 
-        let output = Command::new("rclone")
-            .arg("lsd")
-            .arg(format!("{}:", remote))
-            .arg("--max-depth")
-            .arg("1")
+        let args = ["lsd".to_string(), format!("{}:", remote), "--max-depth".to_string(), "1".to_string()];
+        let output = rclone_command(&args, extra_args)?
             .output()
             .context("Failed to test rclone connection")?;
 
@@ -212,3 +477,107 @@ impl RemoteTransfer {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{DatabaseConfig, RcloneConfig};
+
+    #[test]
+    fn test_all_destinations_from_config() {
+        let config = RemoteConfig {
+            enabled: true,
+            auto_upload: true,
+            rclone: Some(RcloneConfig {
+                remote_name: "gdrive".to_string(),
+                remote_path: "Backups/Music".to_string(),
+                verify_after_upload: true,
+                extra_args: Vec::new(),
+            }),
+            database: Some(DatabaseConfig {
+                host: "db.local".to_string(),
+                port: 3306,
+                username: "zen".to_string(),
+                password: None,
+                database: "backups".to_string(),
+                table: "archives".to_string(),
+            }),
+        };
+
+        let listed: Vec<(&str, String)> = RemoteDestination::all_from_config(&config)
+            .iter()
+            .map(|d| (d.kind(), d.target()))
+            .collect();
+
+        assert_eq!(
+            listed,
+            vec![
+                ("rclone", "gdrive:Backups/Music".to_string()),
+                ("database", "zen@db.local:3306/backups.archives".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_extra_args_are_appended_to_rclone_commands() -> Result<()> {
+        let extra = vec!["--transfers=8".to_string(), "--config".to_string(), "/etc/rclone.conf".to_string()];
+        let command = rclone_command(&["listremotes".to_string()], &extra)?;
+        let args: Vec<_> = command.get_args().map(|arg| arg.to_string_lossy().into_owned()).collect();
+        assert_eq!(args, ["listremotes", "--transfers=8", "--config", "/etc/rclone.conf"]);
+
+        for reserved in ["--progress", "--stats=5s", "--include"] {
+            let err = rclone_command(&["lsd".to_string()], &[reserved.to_string()]).unwrap_err();
+            assert!(err.to_string().contains("can't include"), "{}", err);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_missing_uploader_is_a_remote_error() {
+        let err = RemoteTransfer::run_copy_command("zencore-no-such-uploader", &[], "nowhere", &CancelToken::new())
+            .unwrap_err();
+        assert_eq!(crate::error::exit_code(&err), 4);
+        assert!(err.to_string().contains("Is it installed?"), "{}", err);
+    }
+
+    #[test]
+    fn test_remote_has_matches_name_and_hash() -> Result<()> {
+        let listing = "aaaa  other.tar.zst\nBEEF  music.tar.zst\n";
+        let hashsum = |args: &[String]| {
+            assert_eq!(args[2], "gdrive:Backups");
+            Ok(listing.to_string())
+        };
+
+        assert!(RemoteTransfer::remote_has_with("gdrive", "Backups", "music.tar.zst", "beef", hashsum)?);
+        assert!(!RemoteTransfer::remote_has_with("gdrive", "Backups", "music.tar.zst", "aaaa", hashsum)?);
+        assert!(!RemoteTransfer::remote_has_with("gdrive", "Backups", "new.tar.zst", "beef", hashsum)?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_destination_uris() -> Result<()> {
+        let cases = [
+            ("s3://bucket/backups/music/", "s3", "s3://bucket/backups/music"),
+            ("sftp://zen@nas.local/srv/backups", "sftp", "sftp://zen@nas.local/srv/backups"),
+            ("mysql://zen@db.local/media", "database", "zen@db.local:3306/media.backups"),
+            ("mysql://zen@db.local:3307/media/archives", "database", "zen@db.local:3307/media.archives"),
+            ("gdrive:Backups/Music", "rclone", "gdrive:Backups/Music"),
+        ];
+
+        for (uri, kind, target) in cases {
+            let destination = RemoteDestination::parse(uri)?;
+            assert_eq!((destination.kind(), destination.target()), (kind, target.to_string()), "{}", uri);
+        }
+
+        let sftp = RemoteDestination::parse("sftp://nas:2222/backups")?;
+        assert!(matches!(sftp, RemoteDestination::Sftp { user: None, port: Some(2222), .. }));
+
+        let err = RemoteDestination::parse("ftp://host/dir").unwrap_err();
+        assert!(err.to_string().contains("Unknown destination scheme 'ftp://'"));
+        assert!(RemoteDestination::parse("backups").is_err());
+        assert!(RemoteDestination::parse("mysql://db.local/media").is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,188 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// Progress of an interrupted `--resume-backup` run.
+///
+/// The archive is written as a series of compressed parts, each holding a
+/// slice of `files` as tar entries without the end-of-archive marker. Once
+/// every part is done they are concatenated (zstd frames and gzip members
+/// chain cleanly) and a final trailer frame closes the tar stream.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ResumeState {
+    pub archive_name: String,
+    pub source: String,
+    pub destination: String,
+    pub algorithm: String,
+
+    /// Paths relative to `source`, in archive order.
+    pub files: Vec<String>,
+
+    /// Exclusive end index into `files` for each planned part.
+    pub part_ends: Vec<usize>,
+
+    pub completed_parts: usize,
+}
+
+impl ResumeState {
+    /// Splits `files` into parts of roughly `part_size` bytes each.
+    pub fn plan(
+        archive_name: &str,
+        source: &Path,
+        destination: &Path,
+        algorithm: &str,
+        files: &[(String, u64)],
+        part_size: u64,
+    ) -> Self {
+        let mut part_ends = Vec::new();
+        let mut current = 0u64;
+
+        for (i, (_, size)) in files.iter().enumerate() {
+            current += size;
+            if current >= part_size {
+                part_ends.push(i + 1);
+                current = 0;
+            }
+        }
+
+        if part_ends.last() != Some(&files.len()) {
+            part_ends.push(files.len());
+        }
+
+        Self {
+            archive_name: archive_name.to_string(),
+            source: source.to_string_lossy().to_string(),
+            destination: destination.to_string_lossy().to_string(),
+            algorithm: algorithm.to_string(),
+            files: files.iter().map(|(name, _)| name.clone()).collect(),
+            part_ends,
+            completed_parts: 0,
+        }
+    }
+
+    /// Location of the resume record for a source/destination/algorithm triple.
+    pub fn state_path(resume_dir: &Path, source: &Path, destination: &Path, algorithm: &str) -> PathBuf {
+        let mut hasher = Sha256::new();
+        hasher.update(source.to_string_lossy().as_bytes());
+        hasher.update([0]);
+        hasher.update(destination.to_string_lossy().as_bytes());
+        hasher.update([0]);
+        hasher.update(algorithm.as_bytes());
+        let key = format!("{:x}", hasher.finalize());
+
+        resume_dir.join(format!("{}.json", &key[..16]))
+    }
+
+    pub fn find(
+        resume_dir: &Path,
+        source: &Path,
+        destination: &Path,
+        algorithm: &str,
+    ) -> Result<Option<Self>> {
+        Self::load(&Self::state_path(resume_dir, source, destination, algorithm))
+    }
+
+    pub fn load(path: &Path) -> Result<Option<Self>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let content = fs::read(path)?;
+        let state = serde_json::from_slice(&content).context("Failed to parse resume state")?;
+        Ok(Some(state))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let content = serde_json::to_string_pretty(self)?;
+        crate::utils::write_atomic(path, content.as_bytes()).context("Failed to write resume state")?;
+        Ok(())
+    }
+
+    pub fn part_count(&self) -> usize {
+        self.part_ends.len()
+    }
+
+    pub fn part_range(&self, index: usize) -> std::ops::Range<usize> {
+        let start = if index == 0 { 0 } else { self.part_ends[index - 1] };
+        start..self.part_ends[index]
+    }
+
+    pub fn part_path(&self, index: usize) -> PathBuf {
+        Path::new(&self.destination).join(format!("{}.part{:03}", self.archive_name, index + 1))
+    }
+}
+
+/// Writer that withholds the final `TAR_TRAILER_LEN` bytes written to it, used
+/// to drop the end-of-archive blocks from each part so parts can be chained.
+pub struct TrailerStripper<W: Write> {
+    inner: W,
+    tail: Vec<u8>,
+}
+
+pub const TAR_TRAILER_LEN: usize = 1024;
+
+impl<W: Write> TrailerStripper<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            tail: Vec::with_capacity(TAR_TRAILER_LEN * 2),
+        }
+    }
+}
+
+impl<W: Write> Write for TrailerStripper<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.tail.extend_from_slice(buf);
+
+        if self.tail.len() > TAR_TRAILER_LEN {
+            let flush_len = self.tail.len() - TAR_TRAILER_LEN;
+            self.inner.write_all(&self.tail[..flush_len])?;
+            self.tail.drain(..flush_len);
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plan_splits_by_size() {
+        let files = vec![
+            ("a.flac".to_string(), 600),
+            ("b.flac".to_string(), 500),
+            ("c.flac".to_string(), 100),
+            ("d.flac".to_string(), 50),
+        ];
+
+        let state = ResumeState::plan("x.tar.zst", Path::new("/src"), Path::new("/dst"), "tar.zst", &files, 1000);
+        assert_eq!(state.part_ends, vec![2, 4]);
+        assert_eq!(state.part_range(1), 2..4);
+        assert_eq!(state.part_path(0), Path::new("/dst/x.tar.zst.part001"));
+    }
+
+    #[test]
+    fn test_trailer_stripper_drops_last_block() -> io::Result<()> {
+        let mut out = Vec::new();
+        {
+            let mut writer = TrailerStripper::new(&mut out);
+            writer.write_all(&[1u8; 700])?;
+            writer.write_all(&[0u8; TAR_TRAILER_LEN])?;
+        }
+        assert_eq!(out, vec![1u8; 700]);
+        Ok(())
+    }
+}
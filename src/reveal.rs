@@ -0,0 +1,146 @@
+use anyhow::{Context, Result};
+use std::path::Path;
+use std::process::Command;
+
+/// Desktop platforms with a known file manager invocation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Platform {
+    Linux,
+    MacOs,
+    Windows,
+}
+
+impl Platform {
+    pub fn current() -> Self {
+        if cfg!(target_os = "macos") {
+            Self::MacOs
+        } else if cfg!(target_os = "windows") {
+            Self::Windows
+        } else {
+            Self::Linux
+        }
+    }
+
+    /// Program and arguments that show `archive` in the file manager. macOS
+    /// and Windows can select the file; xdg-open only opens its folder.
+    pub fn reveal_command(&self, archive: &Path) -> (String, Vec<String>) {
+        match self {
+            Self::Linux => {
+                let folder = archive.parent().unwrap_or(Path::new("."));
+                ("xdg-open".to_string(), vec![folder.to_string_lossy().to_string()])
+            }
+            Self::MacOs => (
+                "open".to_string(),
+                vec!["-R".to_string(), archive.to_string_lossy().to_string()],
+            ),
+            Self::Windows => (
+                "explorer".to_string(),
+                vec![format!("/select,{}", archive.display())],
+            ),
+        }
+    }
+}
+
+/// Launches external programs; swapped out in tests.
+pub trait Launcher {
+    fn platform(&self) -> Platform;
+
+    /// True when there is no desktop session to open a window in.
+    fn is_headless(&self) -> bool;
+
+    fn spawn(&self, program: &str, args: &[String]) -> Result<()>;
+}
+
+pub struct SystemLauncher;
+
+impl Launcher for SystemLauncher {
+    fn platform(&self) -> Platform {
+        Platform::current()
+    }
+
+    fn is_headless(&self) -> bool {
+        match self.platform() {
+            Platform::Linux => {
+                std::env::var_os("DISPLAY").is_none() && std::env::var_os("WAYLAND_DISPLAY").is_none()
+            }
+            Platform::MacOs | Platform::Windows => false,
+        }
+    }
+
+    fn spawn(&self, program: &str, args: &[String]) -> Result<()> {
+        Command::new(program)
+            .args(args)
+            .spawn()
+            .with_context(|| format!("Failed to launch {}", program))?;
+        Ok(())
+    }
+}
+
+/// Opens the folder containing `archive`, selecting it where supported.
+/// Does nothing but warn on headless systems.
+pub fn reveal_archive(launcher: &dyn Launcher, archive: &Path) -> Result<()> {
+    if launcher.is_headless() {
+        crate::utils::print_warning("No desktop session found, skipping --open-after");
+        return Ok(());
+    }
+
+    let (program, args) = launcher.platform().reveal_command(archive);
+    launcher.spawn(&program, &args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    struct RecordingLauncher {
+        platform: Platform,
+        headless: bool,
+        calls: RefCell<Vec<(String, Vec<String>)>>,
+    }
+
+    impl Launcher for RecordingLauncher {
+        fn platform(&self) -> Platform {
+            self.platform
+        }
+
+        fn is_headless(&self) -> bool {
+            self.headless
+        }
+
+        fn spawn(&self, program: &str, args: &[String]) -> Result<()> {
+            self.calls.borrow_mut().push((program.to_string(), args.to_vec()));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_reveal_command_per_platform() -> Result<()> {
+        let archive = Path::new("/backups/music.tar.zst");
+        let expected = [
+            (Platform::Linux, "xdg-open", vec!["/backups".to_string()]),
+            (Platform::MacOs, "open", vec!["-R".to_string(), "/backups/music.tar.zst".to_string()]),
+            (Platform::Windows, "explorer", vec!["/select,/backups/music.tar.zst".to_string()]),
+        ];
+
+        for (platform, program, args) in expected {
+            let launcher = RecordingLauncher {
+                platform,
+                headless: false,
+                calls: RefCell::new(Vec::new()),
+            };
+            reveal_archive(&launcher, archive)?;
+            assert_eq!(launcher.calls.into_inner(), vec![(program.to_string(), args)]);
+        }
+
+        let headless = RecordingLauncher {
+            platform: Platform::Linux,
+            headless: true,
+            calls: RefCell::new(Vec::new()),
+        };
+        reveal_archive(&headless, archive)?;
+        assert!(headless.calls.into_inner().is_empty());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,69 @@
+use anyhow::{Context, Result};
+use chrono::Local;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// A log file bigger than this is moved to `<path>.1` before a new run.
+const ROTATE_BYTES: u64 = 10 * 1024 * 1024;
+
+/// Timestamped, line-based audit trail of backup runs (`--log-file`).
+/// Each line is written out straight away, so a stalled run still shows
+/// how far it got. The default log records nothing.
+#[derive(Clone, Default)]
+pub struct RunLog {
+    file: Option<Arc<Mutex<File>>>,
+}
+
+impl RunLog {
+    /// Appends to `path`, rotating it first if it has grown too large.
+    pub fn open(path: &Path) -> Result<Self> {
+        if fs::metadata(path).is_ok_and(|meta| meta.len() > ROTATE_BYTES) {
+            let mut rotated = PathBuf::from(path).into_os_string();
+            rotated.push(".1");
+            fs::rename(path, &rotated).with_context(|| format!("Failed to rotate {}", path.display()))?;
+        }
+
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open log file {}", path.display()))?;
+
+        Ok(Self {
+            file: Some(Arc::new(Mutex::new(file))),
+        })
+    }
+
+    /// Writes one line. Failing to log never fails the backup.
+    pub fn record(&self, message: &str) {
+        if let Some(ref file) = self.file {
+            let line = format!("{} {}\n", Local::now().format("%Y-%m-%d %H:%M:%S"), message);
+            let _ = file.lock().unwrap().write_all(line.as_bytes());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_large_log_is_rotated() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let path = temp_dir.path().join("zencore.log");
+        fs::write(&path, vec![b'x'; ROTATE_BYTES as usize + 1])?;
+
+        RunLog::open(&path)?.record("backup started");
+
+        assert_eq!(fs::metadata(temp_dir.path().join("zencore.log.1"))?.len(), ROTATE_BYTES + 1);
+        let content = fs::read_to_string(&path)?;
+        assert_eq!(content.lines().count(), 1);
+        assert!(content.ends_with(" backup started\n"));
+        Ok(())
+    }
+}
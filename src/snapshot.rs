@@ -0,0 +1,426 @@
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Name prefix of the Btrfs snapshot folders made inside the source's
+/// subvolume. Ones left behind by an interrupted run are never archived.
+pub const SNAPSHOT_DIR_PREFIX: &str = ".zencore-snapshot-";
+
+/// Where macOS mounts the writable data volume; `/Users` and the other
+/// firmlinked folders are reached through it.
+const APFS_DATA_VOLUME: &str = "/System/Volumes/Data";
+
+/// A mounted filesystem, as listed in `/proc/self/mountinfo`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mount {
+    pub point: PathBuf,
+    pub fstype: String,
+    /// Device or dataset, e.g. `/dev/sda2` or `tank/music`.
+    pub device: String,
+}
+
+/// Read-only snapshots on one kind of filesystem.
+pub trait SnapshotProvider {
+    fn name(&self) -> &'static str;
+
+    /// Creates a snapshot of the filesystem mounted at `mount`, tagged with
+    /// `id`, and returns the folder where its copy of `mount.point` is visible.
+    fn create(&self, mount: &Mount, id: &str) -> Result<PathBuf>;
+
+    fn remove(&self, mount: &Mount, id: &str, root: &Path) -> Result<()>;
+
+    /// Narrows `mount` to what has to be snapshotted for `source`. Fails
+    /// when a snapshot of it would not show everything under `source`.
+    fn scope(&self, mount: Mount, _source: &Path) -> Result<Mount> {
+        Ok(mount)
+    }
+}
+
+/// `btrfs subvolume snapshot -r` of the subvolume holding the source,
+/// placed inside that subvolume.
+pub struct Btrfs;
+
+impl Btrfs {
+    /// Inode number of every Btrfs subvolume root.
+    const SUBVOLUME_ROOT_INODE: u64 = 256;
+
+    #[cfg(unix)]
+    fn is_subvolume_root(path: &Path) -> bool {
+        use std::os::unix::fs::MetadataExt;
+        std::fs::symlink_metadata(path).is_ok_and(|meta| meta.is_dir() && meta.ino() == Self::SUBVOLUME_ROOT_INODE)
+    }
+
+    #[cfg(not(unix))]
+    fn is_subvolume_root(_path: &Path) -> bool {
+        false
+    }
+
+    /// Subvolumes below `root`, which a snapshot of `root` shows as empty
+    /// folders. Our own leftover snapshots are skipped; the scan leaves
+    /// them out anyway.
+    fn nested_subvolumes(root: &Path) -> Vec<PathBuf> {
+        walkdir::WalkDir::new(root)
+            .min_depth(1)
+            .into_iter()
+            .filter_entry(|entry| !is_snapshot_dir(entry.path()))
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_type().is_dir() && Self::is_subvolume_root(entry.path()))
+            .map(|entry| entry.into_path())
+            .collect()
+    }
+}
+
+impl SnapshotProvider for Btrfs {
+    fn name(&self) -> &'static str {
+        "Btrfs"
+    }
+
+    fn scope(&self, mount: Mount, source: &Path) -> Result<Mount> {
+        let subvolume = source
+            .ancestors()
+            .take_while(|dir| dir.starts_with(&mount.point))
+            .find(|dir| Self::is_subvolume_root(dir))
+            .map_or_else(|| mount.point.clone(), Path::to_path_buf);
+
+        let nested = Self::nested_subvolumes(source);
+        if let Some(first) = nested.first() {
+            anyhow::bail!(
+                "{} holds {} nested subvolume(s), e.g. {}, which a snapshot would show empty",
+                source.display(),
+                nested.len(),
+                first.display()
+            );
+        }
+
+        Ok(Mount { point: subvolume, ..mount })
+    }
+
+    fn create(&self, mount: &Mount, id: &str) -> Result<PathBuf> {
+        let root = mount.point.join(format!("{}{}", SNAPSHOT_DIR_PREFIX, id));
+        run("btrfs", &["subvolume".as_ref(), "snapshot".as_ref(), "-r".as_ref(), mount.point.as_os_str(), root.as_os_str()])?;
+        Ok(root)
+    }
+
+    fn remove(&self, _mount: &Mount, _id: &str, root: &Path) -> Result<()> {
+        run("btrfs", &["subvolume".as_ref(), "delete".as_ref(), root.as_os_str()])
+    }
+}
+
+/// `zfs snapshot`, read through the dataset's `.zfs/snapshot` folder.
+pub struct Zfs;
+
+impl SnapshotProvider for Zfs {
+    fn name(&self) -> &'static str {
+        "ZFS"
+    }
+
+    fn create(&self, mount: &Mount, id: &str) -> Result<PathBuf> {
+        let name = format!("zencore-{}", id);
+        run("zfs", &["snapshot".as_ref(), format!("{}@{}", mount.device, name).as_ref()])?;
+        Ok(mount.point.join(".zfs/snapshot").join(name))
+    }
+
+    fn remove(&self, mount: &Mount, id: &str, _root: &Path) -> Result<()> {
+        run("zfs", &["destroy".as_ref(), format!("{}@zencore-{}", mount.device, id).as_ref()])
+    }
+}
+
+/// `tmutil localsnapshot` of the data volume, mounted read-only with
+/// `mount_apfs -s` under the temp folder.
+pub struct Apfs;
+
+impl Apfs {
+    /// Local snapshots are named after their date; `tmutil` prints it as
+    /// `Created local snapshot with date: 2024-05-01-101500`.
+    fn created_date(output: &str) -> Option<&str> {
+        output.lines().find_map(|line| line.split_once("date: ")).map(|(_, date)| date.trim())
+    }
+}
+
+impl SnapshotProvider for Apfs {
+    fn name(&self) -> &'static str {
+        "APFS"
+    }
+
+    fn create(&self, mount: &Mount, id: &str) -> Result<PathBuf> {
+        let output = Command::new("tmutil")
+            .arg("localsnapshot")
+            .output()
+            .context("Failed to run tmutil")?;
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let date = Self::created_date(&stdout)
+            .filter(|_| output.status.success())
+            .with_context(|| format!("tmutil localsnapshot failed: {}", String::from_utf8_lossy(&output.stderr).trim()))?;
+
+        let root = std::env::temp_dir().join(format!("zencore-snapshot-{}", id));
+        std::fs::create_dir_all(&root)?;
+        let snapshot = format!("com.apple.TimeMachine.{}.local", date);
+        let mounted = run(
+            "mount_apfs",
+            &["-s".as_ref(), snapshot.as_ref(), mount.point.as_os_str(), root.as_os_str()],
+        );
+        if let Err(e) = mounted {
+            run("tmutil", &["deletelocalsnapshots".as_ref(), date.as_ref()]).ok();
+            return Err(e);
+        }
+        std::fs::write(root.with_extension("date"), date)?;
+        Ok(root)
+    }
+
+    fn remove(&self, _mount: &Mount, _id: &str, root: &Path) -> Result<()> {
+        run("umount", &[root.as_os_str()])?;
+        std::fs::remove_dir(root).ok();
+        let date_file = root.with_extension("date");
+        let date = std::fs::read_to_string(&date_file)?;
+        std::fs::remove_file(&date_file).ok();
+        run("tmutil", &["deletelocalsnapshots".as_ref(), date.trim().as_ref()])
+    }
+}
+
+fn run(program: &str, args: &[&std::ffi::OsStr]) -> Result<()> {
+    let output = Command::new(program)
+        .args(args)
+        .output()
+        .with_context(|| format!("Failed to run {}", program))?;
+    if !output.status.success() {
+        anyhow::bail!("{} failed: {}", program, String::from_utf8_lossy(&output.stderr).trim());
+    }
+    Ok(())
+}
+
+/// A snapshot taken for one backup; removed again when dropped.
+pub struct Snapshot {
+    provider: Box<dyn SnapshotProvider>,
+    mount: Mount,
+    id: String,
+    root: PathBuf,
+    source: PathBuf,
+}
+
+impl Snapshot {
+    /// Snapshots the filesystem holding `source`. Returns `None`, after a
+    /// warning, when the filesystem has no snapshot support here or the
+    /// snapshot could not be made, so the caller backs up the live source.
+    pub fn take(source: &Path) -> Result<Option<Self>> {
+        let source = source
+            .canonicalize()
+            .with_context(|| format!("Failed to resolve {}", source.display()))?;
+
+        let Some((mount, provider, source)) = Self::provider_for(&source) else {
+            crate::utils::print_warning("No snapshot support for this filesystem, backing up the live source");
+            return Ok(None);
+        };
+        let mount = match provider.scope(mount, &source) {
+            Ok(mount) => mount,
+            Err(e) => {
+                crate::utils::print_warning(&format!(
+                    "Cannot use a {} snapshot ({}), backing up the live source",
+                    provider.name(),
+                    e
+                ));
+                return Ok(None);
+            }
+        };
+
+        let id = format!("{}-{}", chrono::Local::now().format("%Y%m%d%H%M%S"), std::process::id());
+        let root = match provider.create(&mount, &id) {
+            Ok(root) => root,
+            Err(e) => {
+                crate::utils::print_warning(&format!(
+                    "Could not create a {} snapshot ({}), backing up the live source",
+                    provider.name(),
+                    e
+                ));
+                return Ok(None);
+            }
+        };
+
+        let relative = source.strip_prefix(&mount.point).unwrap_or(Path::new(""));
+        crate::utils::print_info(&format!("Backing up from a read-only {} snapshot", provider.name()));
+        Ok(Some(Self {
+            source: root.join(relative),
+            provider,
+            mount,
+            id,
+            root,
+        }))
+    }
+
+    /// The backup source as seen inside the snapshot.
+    pub fn source(&self) -> &Path {
+        &self.source
+    }
+
+    /// The mount and provider for `source`, and `source` as a path under
+    /// that mount.
+    fn provider_for(source: &Path) -> Option<(Mount, Box<dyn SnapshotProvider>, PathBuf)> {
+        if cfg!(target_os = "macos") {
+            let mount = Mount {
+                point: PathBuf::from(APFS_DATA_VOLUME),
+                fstype: "apfs".to_string(),
+                device: String::new(),
+            };
+            let source = through_firmlink(&mount.point, source)?;
+            return Some((mount, Box::new(Apfs) as _, source));
+        }
+
+        let mountinfo = std::fs::read_to_string("/proc/self/mountinfo").ok()?;
+        let mount = mount_for(&parse_mountinfo(&mountinfo), source)?.clone();
+        let provider: Box<dyn SnapshotProvider> = match mount.fstype.as_str() {
+            "btrfs" => Box::new(Btrfs),
+            "zfs" => Box::new(Zfs),
+            _ => return None,
+        };
+        Some((mount, provider, source.to_path_buf()))
+    }
+}
+
+impl Drop for Snapshot {
+    fn drop(&mut self) {
+        if let Err(e) = self.provider.remove(&self.mount, &self.id, &self.root) {
+            crate::utils::print_warning(&format!(
+                "Failed to remove {} snapshot {}: {}",
+                self.provider.name(),
+                self.root.display(),
+                e
+            ));
+        }
+    }
+}
+
+/// Whether `path` is one of our Btrfs snapshot folders.
+pub fn is_snapshot_dir(path: &Path) -> bool {
+    path.file_name().is_some_and(|name| name.to_string_lossy().starts_with(SNAPSHOT_DIR_PREFIX))
+}
+
+/// `source` as a path under `data_volume`. Canonical paths like
+/// `/Users/me/Music` reach the data volume through a firmlink, so they are
+/// matched by looking for the same folder under the volume's mount point.
+fn through_firmlink(data_volume: &Path, source: &Path) -> Option<PathBuf> {
+    if source.starts_with(data_volume) {
+        return Some(source.to_path_buf());
+    }
+    let candidate = data_volume.join(source.strip_prefix("/").ok()?);
+    same_file(&candidate, source).then_some(candidate)
+}
+
+#[cfg(unix)]
+fn same_file(a: &Path, b: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    match (std::fs::metadata(a), std::fs::metadata(b)) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+        _ => false,
+    }
+}
+
+#[cfg(not(unix))]
+fn same_file(_a: &Path, _b: &Path) -> bool {
+    false
+}
+
+/// Mounts from `/proc/self/mountinfo`. Spaces and other special characters
+/// in mount points are octal escapes there, e.g. `\040`.
+pub fn parse_mountinfo(text: &str) -> Vec<Mount> {
+    text.lines()
+        .filter_map(|line| {
+            let (before, after) = line.split_once(" - ")?;
+            let point = before.split(' ').nth(4)?;
+            let mut after = after.split(' ');
+            Some(Mount {
+                point: PathBuf::from(unescape_octal(point)),
+                fstype: after.next()?.to_string(),
+                device: unescape_octal(after.next()?),
+            })
+        })
+        .collect()
+}
+
+fn unescape_octal(field: &str) -> String {
+    let bytes = field.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'\\'
+            && let Some(code) = field.get(i + 1..i + 4).and_then(|oct| u8::from_str_radix(oct, 8).ok())
+        {
+            out.push(code);
+            i += 4;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// The innermost mount containing `path`; later entries win on ties, since
+/// they are mounted over earlier ones.
+pub fn mount_for<'a>(mounts: &'a [Mount], path: &Path) -> Option<&'a Mount> {
+    mounts
+        .iter()
+        .rev()
+        .filter(|mount| path.starts_with(&mount.point))
+        .max_by_key(|mount| mount.point.components().count())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mountinfo_finds_innermost_filesystem() {
+        let mountinfo = "\
+22 1 8:2 / / rw,relatime shared:1 - ext4 /dev/sda2 rw
+40 22 0:35 /@music /home/user/My\\040Music rw,relatime shared:20 - btrfs /dev/sdb1 rw,subvol=/@music
+41 22 0:36 / /tank rw shared:21 - zfs tank/music rw,xattr
+";
+        let mounts = parse_mountinfo(mountinfo);
+        assert_eq!(mounts.len(), 3);
+
+        let music = mount_for(&mounts, Path::new("/home/user/My Music/Albums")).unwrap();
+        assert_eq!(music.fstype, "btrfs");
+        assert_eq!(music.point, PathBuf::from("/home/user/My Music"));
+
+        assert_eq!(mount_for(&mounts, Path::new("/tank/flac")).unwrap().device, "tank/music");
+        assert_eq!(mount_for(&mounts, Path::new("/home/user/Musicals")).unwrap().fstype, "ext4");
+        assert_eq!(Apfs::created_date("Created local snapshot with date: 2024-05-01-101500\n"), Some("2024-05-01-101500"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_firmlinked_source_resolves_to_data_volume() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        std::fs::create_dir_all(temp_dir.path().join("Users/me/Music"))?;
+        let source = temp_dir.path().join("Users/me/Music").canonicalize()?;
+
+        // Stand-in for the data volume: the source's folder, reached under it
+        // the way a firmlink would.
+        let data_volume = temp_dir.path().join("Data");
+        let linked = data_volume.join(source.strip_prefix("/")?);
+        std::fs::create_dir_all(linked.parent().unwrap())?;
+        std::os::unix::fs::symlink(&source, &linked)?;
+
+        assert_eq!(through_firmlink(&data_volume, &source), Some(linked.clone()));
+        assert_eq!(through_firmlink(&data_volume, &linked), Some(linked));
+        assert_eq!(through_firmlink(&data_volume, temp_dir.path()), None);
+        assert!(is_snapshot_dir(Path::new("/music/.zencore-snapshot-20240501-42")));
+        assert!(!is_snapshot_dir(Path::new("/music/.zencore")));
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_take_falls_back_without_snapshot_support() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        std::fs::write(temp_dir.path().join("track.flac"), b"fLaC")?;
+
+        // Temp folders sit on tmpfs or ext4 almost everywhere; on Btrfs the
+        // snapshot needs privileges a test run rarely has. Either way the
+        // backup must be able to go on.
+        match Snapshot::take(temp_dir.path())? {
+            None => {}
+            Some(snapshot) => assert!(snapshot.source().join("track.flac").exists()),
+        }
+        Ok(())
+    }
+}
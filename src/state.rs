@@ -1,9 +1,41 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
+use chrono::Local;
+use fs2::FileExt;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::fs;
-use std::path::PathBuf;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
 use crate::config::Config;
+use crate::crypto::HashAlgorithm;
+
+/// How long `StateTracker::update` waits for another run to release the
+/// catalog before giving up.
+pub const STATE_LOCK_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How much of an archive's file list is kept in the catalog.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ContentsMode {
+    /// Don't store file names at all
+    None,
+    /// Store file counts per top-level folder
+    Summary,
+    /// Store every file name
+    #[default]
+    Full,
+}
+
+/// Where the archive catalog is stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StateBackend {
+    /// `archives.json`, or `archives.json.zst` with `compress_state`
+    #[default]
+    Json,
+    /// `archives.db`, migrating an existing `archives.json` on first use
+    Sqlite,
+}
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ArchiveMetadata {
@@ -20,44 +52,215 @@ pub struct ArchiveMetadata {
     pub size_bytes: u64,
     pub file_count: usize,
     pub encrypted: bool,
+
+    #[serde(default)]
     pub contents: Vec<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub contents_summary: BTreeMap<String, usize>,
+    #[serde(default)]
+    pub contents_mode: ContentsMode,
+
+    /// SHA-256 per archived file, from `--content-checksums`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub content_checksums: BTreeMap<String, String>,
+
+    /// Folder the archive was made from; empty for older entries.
+    #[serde(default)]
+    pub source: String,
+
+    /// Where the checksum and manifest sidecars went, when `--meta-dir`
+    /// kept them apart from the archive.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub meta_dir: Option<String>,
+
+    /// Where the archive was written; empty for older entries.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub path: String,
+
+    /// The earlier archive a `--since-last` backup only adds changes to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent: Option<String>,
+
+    /// The `--zstd-dict` dictionary the archive was compressed with, which
+    /// decompressing it needs.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub zstd_dictionary: Option<String>,
+
+    /// When the source scan began. Files changed after this but before the
+    /// archive was finished may be missing, so a later `--since-last`
+    /// backup starts from here rather than from `created_at`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scan_started_at: Option<String>,
+
+    /// Name of the archive file when the entry is catalogued under another
+    /// one, as `import-state --strategy keep-both` does on a collision.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_name: Option<String>,
 }
 
 impl ArchiveMetadata {
-    pub fn get_checksum(&self, algorithm: &str) -> Option<&String> {
-        let algo_upper = algorithm.to_uppercase();
+    /// Catalog entry for an archive found on disk, with what can be read
+    /// back from the file: format, size, modification time, SHA-256 and,
+    /// unless it is age-encrypted or needs a zstd dictionary, the file list.
+    pub fn from_archive_file(path: &Path) -> Result<Self> {
+        let algorithm = crate::compress::Algorithm::from_archive_path(path)
+            .with_context(|| format!("{} is not a tar.gz, tar.zst or zip archive", path.display()))?;
+        let file_meta = fs::metadata(path)?;
+        let reader = crate::extract::ArchiveReader::new(path);
+        let encrypted = reader.is_encrypted().unwrap_or(false);
+        // Without the key or the zstd dictionary the file list can't be read.
+        let needs_dictionary = algorithm == crate::compress::Algorithm::TarZst
+            && crate::dictionary::frame_dictionary_id(path)?.is_some();
+        let files: Vec<String> = if needs_dictionary
+            || crate::encrypt_tar::TarEncryptor::is_age_encrypted(&path.to_string_lossy())
+        {
+            Vec::new()
+        } else {
+            reader.list_contents()?.into_iter().map(|entry| entry.name).collect()
+        };
+        let created_at = file_meta.modified().map(chrono::DateTime::<Local>::from).unwrap_or_else(|_| Local::now());
 
-        self.checksums.get(&algo_upper).or_else(|| {
-            if algo_upper == "SHA-256" || algo_upper == "SHA256" {
-                if !self.checksum.is_empty() {
-                    Some(&self.checksum)
-                } else {
-                    None
+        let mut metadata = Self {
+            name: path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default(),
+            created_at: created_at.to_rfc3339(),
+            checksum: String::new(),
+            checksums: HashMap::new(),
+            algorithm: algorithm.to_string(),
+            size_bytes: file_meta.len(),
+            file_count: files.len(),
+            encrypted,
+            contents: Vec::new(),
+            contents_summary: BTreeMap::new(),
+            contents_mode: ContentsMode::Full,
+            content_checksums: BTreeMap::new(),
+            source: String::new(),
+            meta_dir: None,
+            path: path.to_string_lossy().to_string(),
+            parent: None,
+            zstd_dictionary: None,
+            scan_started_at: None,
+            file_name: None,
+        };
+        metadata.set_contents(files, ContentsMode::Full);
+        metadata.add_checksum(HashAlgorithm::Sha256.key(), crate::crypto::Checker::generate_checksum(&path.to_string_lossy())?);
+        Ok(metadata)
+    }
+
+    /// `created_at` as a point in time, if it parses.
+    pub fn created_time(&self) -> Option<SystemTime> {
+        chrono::DateTime::parse_from_rfc3339(&self.created_at).ok().map(SystemTime::from)
+    }
+
+    /// `created_at` as UTC seconds, which unlike the RFC 3339 text sort
+    /// across UTC offsets; unreadable timestamps sort oldest.
+    pub fn created_epoch(&self) -> i64 {
+        chrono::DateTime::parse_from_rfc3339(&self.created_at).map_or(0, |time| time.timestamp())
+    }
+
+    /// Cutoff for a `--since-last` backup on top of this archive: the scan
+    /// start, or `created_at` for entries that predate it.
+    pub fn changes_since(&self) -> Option<SystemTime> {
+        match &self.scan_started_at {
+            Some(started) => chrono::DateTime::parse_from_rfc3339(started).ok().map(SystemTime::from),
+            None => self.created_time(),
+        }
+    }
+
+    /// Name of the archive file on disk, usually the catalog name.
+    pub fn file_name(&self) -> &str {
+        self.file_name.as_deref().unwrap_or(&self.name)
+    }
+
+    pub fn archive_path(&self) -> Option<&Path> {
+        (!self.path.is_empty()).then(|| Path::new(&self.path))
+    }
+
+    pub fn meta_dir(&self) -> Option<&Path> {
+        self.meta_dir.as_deref().map(Path::new)
+    }
+
+    /// Recomputes every stored checksum from `path`, returning whether each
+    /// one still matches.
+    pub fn verify_against(&self, path: &Path) -> Result<Vec<(String, bool)>> {
+        let stored = self.list_checksums();
+        if stored.is_empty() {
+            return Err(anyhow::anyhow!("No checksums stored for {}", self.name));
+        }
+        crate::crypto::Checker::verify_stored_checksums(&path.to_string_lossy(), &stored)
+    }
+
+    /// Name of the source folder (e.g. `Music`), used to group archives of
+    /// the same source.
+    pub fn source_label(&self) -> Option<&str> {
+        Path::new(&self.source).file_name().and_then(|n| n.to_str())
+    }
+
+    /// True if `query` is the source folder's name or part of its path,
+    /// ignoring case.
+    pub fn matches_source(&self, query: &str) -> bool {
+        let query = query.to_lowercase();
+        !self.source.is_empty() && self.source.to_lowercase().contains(&query)
+    }
+
+    /// Records the archive's file list according to `mode`. Summary groups by
+    /// top-level folder; files at the archive root are counted under `.`.
+    pub fn set_contents(&mut self, files: Vec<String>, mode: ContentsMode) {
+        self.contents_mode = mode;
+        self.contents_summary.clear();
+
+        match mode {
+            ContentsMode::None => self.contents = Vec::new(),
+            ContentsMode::Summary => {
+                for file in &files {
+                    let mut components = Path::new(file).components();
+                    let first = components.next();
+                    let folder = match (first, components.next()) {
+                        (Some(top), Some(_)) => top.as_os_str().to_string_lossy().to_string(),
+                        _ => ".".to_string(),
+                    };
+                    *self.contents_summary.entry(folder).or_insert(0) += 1;
                 }
-            } else {
-                None
+                self.contents = Vec::new();
             }
+            ContentsMode::Full => self.contents = files,
+        }
+    }
+
+    /// The catalog key for `algorithm`: `HashAlgorithm::key` for any name
+    /// or alias of a known algorithm, else the name lowercased.
+    fn checksum_key(algorithm: &str) -> String {
+        HashAlgorithm::from_stored_name(algorithm)
+            .map(|known| known.key().to_string())
+            .unwrap_or_else(|| algorithm.to_lowercase())
+    }
+
+    pub fn get_checksum(&self, algorithm: &str) -> Option<&String> {
+        let key = Self::checksum_key(algorithm);
+
+        self.checksums.get(&key).or_else(|| {
+            (key == HashAlgorithm::Sha256.key() && !self.checksum.is_empty()).then_some(&self.checksum)
         })
     }
 
     pub fn add_checksum(&mut self, algorithm: &str, hash: String) {
-        let algo_upper = algorithm.to_uppercase();
-        self.checksums.insert(algo_upper.clone(), hash.clone());
-
-        if algo_upper == "SHA256" || algo_upper == "SHA-256" {
-            self.checksum = hash;
+        let key = Self::checksum_key(algorithm);
+        if key == HashAlgorithm::Sha256.key() {
+            self.checksum = hash.clone();
         }
+        self.checksums.insert(key, hash);
     }
 
+    /// `(label, hash)` for display, labelled with `HashAlgorithm::name`.
     pub fn list_checksums(&self) -> Vec<(String, String)> {
-        let mut result: Vec<(String, String)> = self
-            .checksums
-            .iter()
-            .map(|(k, v)| (k.clone(), v.clone()))
-            .collect();
+        let label = |key: &str| {
+            HashAlgorithm::from_stored_name(key)
+                .map(|known| known.name().to_string())
+                .unwrap_or_else(|| key.to_string())
+        };
+        let mut result: Vec<(String, String)> = self.checksums.iter().map(|(k, v)| (label(k), v.clone())).collect();
 
-        if !self.checksum.is_empty() && !self.checksums.contains_key("SHA-256") {
-            result.push(("SHA-256".to_string(), self.checksum.clone()));
+        if !self.checksum.is_empty() && !self.checksums.contains_key(HashAlgorithm::Sha256.key()) {
+            result.push((HashAlgorithm::Sha256.name().to_string(), self.checksum.clone()));
         }
 
         result.sort_by(|a, b| a.0.cmp(&b.0));
@@ -65,73 +268,471 @@ impl ArchiveMetadata {
     }
 }
 
+/// How `import-state` resolves an imported entry whose name is already in
+/// the local catalog.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum MergeStrategy {
+    /// Keep whichever entry was created later
+    #[default]
+    KeepNewer,
+    /// Keep both, adding the imported one under a suffixed name
+    KeepBoth,
+    /// Always take the imported entry
+    Overwrite,
+}
+
+/// What `StateTracker::merge` did with the imported entries.
+#[derive(Debug, Default, PartialEq)]
+pub struct MergeReport {
+    pub added: usize,
+    pub replaced: usize,
+    /// Colliding entries where the local one was kept.
+    pub kept: usize,
+    /// Entries identical to the local ones.
+    pub unchanged: usize,
+    /// Imported names stored under a new one, as (original, new).
+    pub renamed: Vec<(String, String)>,
+}
+
+/// What `StateTracker::repair` changed.
+#[derive(Debug, Default, PartialEq)]
+pub struct RepairReport {
+    /// Entries dropped because their archive file is gone.
+    pub removed: Vec<String>,
+    /// Entries whose file was found in the scan directory instead, as (name, new path).
+    pub relocated: Vec<(String, String)>,
+    /// Archives in the scan directory that the catalog didn't know about.
+    pub imported: Vec<String>,
+    /// Entries whose recorded size was out of date, as (name, old, new).
+    pub resized: Vec<(String, u64, u64)>,
+    /// Entries left alone because their folder is missing, as on an
+    /// unmounted drive.
+    pub unreachable: Vec<String>,
+}
+
+impl RepairReport {
+    /// True when nothing needs changing; `unreachable` entries are only
+    /// reported, never changed.
+    pub fn is_empty(&self) -> bool {
+        self.removed.is_empty()
+            && self.relocated.is_empty()
+            && self.imported.is_empty()
+            && self.resized.is_empty()
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct StateTracker {
     archives: HashMap<String, ArchiveMetadata>,
+
+    /// Destination of the last successful backup of each source, offered
+    /// again by the interactive destination prompt.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    last_destinations: HashMap<String, String>,
+
+    /// Whether `save` writes the zstd-compressed catalog (`compress_state`).
+    #[serde(skip)]
+    compress: bool,
+
+    #[serde(skip)]
+    backend: StateBackend,
+
+    /// Names of the entries added, changed or removed since loading, so the
+    /// SQLite backend only writes those rows.
+    #[serde(skip)]
+    changed: BTreeSet<String>,
+
+    #[serde(skip)]
+    destinations_changed: bool,
 }
 
 impl Default for StateTracker {
     fn default() -> Self {
         Self {
             archives: HashMap::new(),
+            last_destinations: HashMap::new(),
+            compress: false,
+            backend: StateBackend::Json,
+            changed: BTreeSet::new(),
+            destinations_changed: false,
         }
     }
 }
 
 impl StateTracker {
+    /// Loads the catalog, applies `change` and saves it while holding the
+    /// state lock, so runs finishing together don't drop each other's entries.
+    pub fn update<T>(change: impl FnOnce(&mut Self) -> T) -> Result<T> {
+        let _lock = StateLock::acquire(&Config::state_dir()?, STATE_LOCK_TIMEOUT)?;
+        let mut state = Self::load()?;
+        let result = change(&mut state);
+        state.save()?;
+        Ok(result)
+    }
+
     pub fn load() -> Result<Self> {
-        let state_path = Self::state_file()?;
+        let config = Config::load().ok();
+        let compress = config.as_ref().is_some_and(|c| c.compress_state);
 
-        if !state_path.exists() {
-            return Ok(Self::default());
+        match config.map(|c| c.state_backend).unwrap_or_default() {
+            StateBackend::Json => Ok(Self::load_from(&Self::state_file()?)?.with_compression(compress)),
+            StateBackend::Sqlite => Self::load_from_db(&Self::db_file()?, &Self::state_file()?),
         }
+    }
 
-        let content = fs::read_to_string(state_path)?;
-        let mut tracker: Self = serde_json::from_str(&content)?;
+    /// Archives whose source contains `source` and made at or after `since`
+    /// (UTC seconds), newest first. With the SQLite backend this is a query
+    /// instead of loading the whole catalog.
+    pub fn search(source: Option<&str>, since: Option<i64>) -> Result<Vec<ArchiveMetadata>> {
+        let backend = Config::load().map(|c| c.state_backend).unwrap_or_default();
+        if backend == StateBackend::Sqlite {
+            return Self::search_db(&Self::db_file()?, &Self::state_file()?, source, since);
+        }
+
+        Ok(Self::load()?
+            .list_archives()
+            .into_iter()
+            .filter(|archive| source.is_none_or(|query| archive.matches_source(query)))
+            .filter(|archive| since.is_none_or(|since| archive.created_epoch() >= since))
+            .cloned()
+            .collect())
+    }
+
+    #[cfg(feature = "sqlite")]
+    fn search_db(db_path: &Path, json_path: &Path, source: Option<&str>, since: Option<i64>) -> Result<Vec<ArchiveMetadata>> {
+        Self::open_db(db_path, json_path)?.search(source, since)
+    }
+
+    #[cfg(not(feature = "sqlite"))]
+    fn search_db(_db_path: &Path, _json_path: &Path, _source: Option<&str>, _since: Option<i64>) -> Result<Vec<ArchiveMetadata>> {
+        Err(anyhow::anyhow!("state_backend = \"sqlite\" needs zencore built with the sqlite feature"))
+    }
+
+    /// Opens the SQLite catalog at `db_path`, first importing the JSON
+    /// catalog at `json_path` if the database is new.
+    #[cfg(feature = "sqlite")]
+    fn open_db(db_path: &Path, json_path: &Path) -> Result<crate::catalog_db::CatalogDb> {
+        let mut db = crate::catalog_db::CatalogDb::open(db_path)?;
+        if let Some(count) = db.migrate_from_json(json_path)? {
+            crate::utils::print_info(&format!(
+                "Moved {} archive(s) from {} into {}",
+                count,
+                json_path.display(),
+                db_path.display()
+            ));
+        }
+        Ok(db)
+    }
+
+    /// Loads the SQLite catalog at `db_path`, first importing the JSON
+    /// catalog at `json_path` if the database is new.
+    #[cfg(feature = "sqlite")]
+    pub fn load_from_db(db_path: &Path, json_path: &Path) -> Result<Self> {
+        let db = Self::open_db(db_path, json_path)?;
+        let mut tracker = Self {
+            backend: StateBackend::Sqlite,
+            ..Self::default()
+        };
+        tracker.archives = db.list_archives()?.into_iter().map(|metadata| (metadata.name.clone(), metadata)).collect();
+        tracker.last_destinations = db.last_destinations()?;
+        tracker.migrate_old_format();
+        Ok(tracker)
+    }
+
+    #[cfg(not(feature = "sqlite"))]
+    pub fn load_from_db(_db_path: &Path, _json_path: &Path) -> Result<Self> {
+        Err(anyhow::anyhow!("state_backend = \"sqlite\" needs zencore built with the sqlite feature"))
+    }
+
+    /// Writes the entries changed since loading to the SQLite database at
+    /// `db_path`, leaving the other rows alone.
+    #[cfg(feature = "sqlite")]
+    pub fn save_to_db(&self, db_path: &Path) -> Result<()> {
+        let mut db = crate::catalog_db::CatalogDb::open(db_path)?;
+        let (present, removed): (Vec<&String>, Vec<&String>) =
+            self.changed.iter().partition(|name| self.archives.contains_key(*name));
+        let changed: Vec<&ArchiveMetadata> = present.into_iter().map(|name| &self.archives[name]).collect();
+        let removed: Vec<&str> = removed.into_iter().map(String::as_str).collect();
+        db.apply_changes(&changed, &removed)?;
+        if self.destinations_changed {
+            db.replace_last_destinations(&self.last_destinations)?;
+        }
+        Ok(())
+    }
+
+    #[cfg(not(feature = "sqlite"))]
+    pub fn save_to_db(&self, _db_path: &Path) -> Result<()> {
+        Err(anyhow::anyhow!("state_backend = \"sqlite\" needs zencore built with the sqlite feature"))
+    }
+
+    /// Loads the catalog, quarantining an unreadable file instead of failing
+    /// so a damaged `archives.json` never blocks new backups. A compressed
+    /// `archives.json.zst` takes precedence over the plain file; one that
+    /// doesn't decompress is an error, left in place.
+    pub fn load_from(state_path: &Path) -> Result<Self> {
+        let compressed_path = Self::compressed_path(state_path);
+
+        let (source_path, content) = if compressed_path.exists() {
+            let raw = fs::read(&compressed_path)?;
+            let content = zstd::decode_all(raw.as_slice())
+                .with_context(|| format!("Failed to decompress {}", compressed_path.display()))?;
+            (compressed_path, content)
+        } else if state_path.exists() {
+            (state_path.to_path_buf(), fs::read(state_path)?)
+        } else {
+            return Ok(Self::default());
+        };
+
+        let mut tracker: Self = match serde_json::from_slice(&content) {
+            Ok(tracker) => tracker,
+            Err(e) => {
+                let quarantined = Self::quarantine(&source_path)?;
+                crate::utils::print_warning(&format!("State file is corrupted: {}", e));
+                crate::utils::print_warning(&format!(
+                    "Moved it to {} and started a fresh catalog",
+                    quarantined.display()
+                ));
+                return Ok(Self::default());
+            }
+        };
 
         tracker.migrate_old_format();
 
         Ok(tracker)
     }
 
+    /// Loads another machine's catalog for `import-state`. Unlike `load_from`
+    /// a missing or unreadable file is an error, and it is never moved.
+    pub fn read_catalog(path: &Path) -> Result<Self> {
+        let raw = fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        let content = if path.extension().is_some_and(|ext| ext == "zst") {
+            zstd::decode_all(raw.as_slice()).with_context(|| format!("Failed to decompress {}", path.display()))?
+        } else {
+            raw
+        };
+
+        let mut tracker: Self = serde_json::from_slice(&content)
+            .with_context(|| format!("{} is not an archive catalog", path.display()))?;
+        tracker.migrate_old_format();
+        Ok(tracker)
+    }
+
+    /// Adds `other`'s entries, resolving name collisions with `strategy`.
+    /// Entries with the same creation time and checksum are the same archive
+    /// and are left alone.
+    pub fn merge(&mut self, other: StateTracker, strategy: MergeStrategy) -> MergeReport {
+        let mut report = MergeReport::default();
+        let mut imported: Vec<ArchiveMetadata> = other.archives.into_values().collect();
+        imported.sort_by(|a, b| a.name.cmp(&b.name));
+
+        for mut metadata in imported {
+            let Some(local) = self.archives.get(&metadata.name) else {
+                report.added += 1;
+                self.add_archive(metadata);
+                continue;
+            };
+
+            if local.created_at == metadata.created_at && local.checksum == metadata.checksum {
+                report.unchanged += 1;
+                continue;
+            }
+
+            match strategy {
+                MergeStrategy::Overwrite => {
+                    report.replaced += 1;
+                    self.add_archive(metadata);
+                }
+                MergeStrategy::KeepNewer => {
+                    let newer = match (metadata.created_time(), local.created_time()) {
+                        (Some(theirs), Some(ours)) => theirs > ours,
+                        _ => metadata.created_at > local.created_at,
+                    };
+                    if newer {
+                        report.replaced += 1;
+                        self.add_archive(metadata);
+                    } else {
+                        report.kept += 1;
+                    }
+                }
+                MergeStrategy::KeepBoth => {
+                    let original = metadata.name.clone();
+                    let renamed = (2..)
+                        .map(|n| format!("{} ({})", original, n))
+                        .find(|name| !self.archives.contains_key(name))
+                        .unwrap();
+                    // Only the catalog name changes; the file keeps its own.
+                    metadata.file_name = Some(metadata.file_name().to_string());
+                    metadata.name = renamed.clone();
+                    report.renamed.push((original, renamed));
+                    self.add_archive(metadata);
+                }
+            }
+        }
+
+        report
+    }
+
+    fn quarantine(state_path: &Path) -> Result<PathBuf> {
+        let file_name = state_path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .context("Invalid state file path")?;
+
+        let quarantined = state_path.with_file_name(format!(
+            "{}.corrupt.{}",
+            file_name,
+            Local::now().format("%Y%m%d_%H%M%S")
+        ));
+
+        fs::rename(state_path, &quarantined)
+            .context("Failed to move corrupted state file aside")?;
+
+        Ok(quarantined)
+    }
+
     fn migrate_old_format(&mut self) {
         for metadata in self.archives.values_mut() {
+            let before = metadata.checksums.clone();
             if !metadata.checksum.is_empty() && metadata.checksums.is_empty() {
                 metadata
                     .checksums
-                    .insert("SHA-256".to_string(), metadata.checksum.clone());
+                    .insert(HashAlgorithm::Sha256.key().to_string(), metadata.checksum.clone());
+            }
+
+            // Older catalogs keyed checksums by display label ("SHA-256").
+            metadata.checksums = std::mem::take(&mut metadata.checksums)
+                .into_iter()
+                .map(|(name, hash)| (ArchiveMetadata::checksum_key(&name), hash))
+                .collect();
+            if metadata.checksums != before {
+                self.changed.insert(metadata.name.clone());
             }
         }
     }
 
     pub fn save(&self) -> Result<()> {
-        let state_path = Self::state_file()?;
+        match self.backend {
+            StateBackend::Json => self.save_to(&Self::state_file()?),
+            StateBackend::Sqlite => self.save_to_db(&Self::db_file()?),
+        }
+    }
 
+    pub fn save_to(&self, state_path: &Path) -> Result<()> {
         if let Some(parent) = state_path.parent() {
             fs::create_dir_all(parent)?;
         }
 
         let content = serde_json::to_string_pretty(self)?;
-        fs::write(state_path, content)?;
+        let compressed_path = Self::compressed_path(state_path);
+
+        // Only one form is kept on disk, so switching `compress_state` migrates it.
+        let (target, stale) = if self.compress {
+            let packed = zstd::encode_all(content.as_bytes(), 3)?;
+            crate::utils::write_atomic(&compressed_path, &packed)
+                .context("Failed to write state file")?;
+            (compressed_path, state_path.to_path_buf())
+        } else {
+            crate::utils::write_atomic(state_path, content.as_bytes())
+                .context("Failed to write state file")?;
+            (state_path.to_path_buf(), compressed_path)
+        };
+
+        if stale.exists() && stale != target {
+            fs::remove_file(&stale)?;
+        }
 
         Ok(())
     }
 
+    pub fn with_compression(mut self, enabled: bool) -> Self {
+        self.compress = enabled;
+        self
+    }
+
+    fn compressed_path(state_path: &Path) -> PathBuf {
+        let mut name = state_path.as_os_str().to_owned();
+        name.push(".zst");
+        PathBuf::from(name)
+    }
+
     pub fn add_archive(&mut self, metadata: ArchiveMetadata) {
+        self.changed.insert(metadata.name.clone());
         self.archives.insert(metadata.name.clone(), metadata);
     }
 
+    /// Records `destination` as where `source` was last backed up to.
+    pub fn remember_destination(&mut self, source: &str, destination: &str) {
+        self.destinations_changed = true;
+        self.last_destinations.insert(source.to_string(), destination.to_string());
+    }
+
+    pub fn last_destination(&self, source: &str) -> Option<&str> {
+        self.last_destinations.get(source).map(String::as_str)
+    }
+
+    pub fn last_destinations(&self) -> &HashMap<String, String> {
+        &self.last_destinations
+    }
+
     pub fn get_archive(&self, name: &str) -> Option<&ArchiveMetadata> {
         self.archives.get(name)
     }
 
+    /// The entry for the archive file at `path`: the one recorded at that
+    /// path, else the one catalogued under the file's name.
+    pub fn find_archive_file(&self, path: &Path) -> Option<&ArchiveMetadata> {
+        let file_name = path.file_name()?.to_string_lossy();
+        let canonical = fs::canonicalize(path).ok();
+        let recorded_here = |archive: &&ArchiveMetadata| {
+            archive.archive_path().is_some_and(|recorded| {
+                recorded == path || (canonical.is_some() && fs::canonicalize(recorded).ok() == canonical)
+            })
+        };
+
+        self.archives
+            .values()
+            .filter(|archive| archive.file_name() == file_name)
+            .find(recorded_here)
+            .or_else(|| self.get_archive(&file_name))
+    }
+
     pub fn list_archives(&self) -> Vec<&ArchiveMetadata> {
         let mut archives: Vec<&ArchiveMetadata> = self.archives.values().collect();
         archives.sort_by(|a, b| b.created_at.cmp(&a.created_at));
         archives
     }
 
+    /// Archives to drop so that only the newest `keep` remain for each source
+    /// folder name. Entries without a recorded source are never selected.
+    pub fn prune_per_source(&self, keep: usize) -> Vec<&ArchiveMetadata> {
+        let mut by_source: BTreeMap<&str, Vec<&ArchiveMetadata>> = BTreeMap::new();
+        for archive in self.list_archives() {
+            if let Some(label) = archive.source_label() {
+                by_source.entry(label).or_default().push(archive);
+            }
+        }
+
+        // list_archives is newest first, so everything past `keep` is older.
+        by_source
+            .into_values()
+            .flat_map(|archives| archives.into_iter().skip(keep))
+            .collect()
+    }
+
+    /// The newest archive made from exactly this source folder.
+    pub fn latest_for_source(&self, source: &str) -> Option<&ArchiveMetadata> {
+        self.list_archives().into_iter().find(|archive| archive.source == source)
+    }
+
+    /// The archive whose recorded SHA-256 is `hash`, ignoring case.
+    pub fn find_by_checksum(&self, hash: &str) -> Option<&ArchiveMetadata> {
+        self.archives
+            .values()
+            .find(|archive| archive.get_checksum(HashAlgorithm::Sha256.key()).is_some_and(|stored| stored.eq_ignore_ascii_case(hash)))
+    }
+
     pub fn remove_archive(&mut self, name: &str) -> Option<ArchiveMetadata> {
+        self.changed.insert(name.to_string());
         self.archives.remove(name)
     }
 
@@ -139,7 +740,556 @@ impl StateTracker {
         self.archives.len()
     }
 
+    /// Reconciles the catalog with the disk: entries whose recorded file is
+    /// gone are dropped (or pointed at a same-named file in `scan_dir`),
+    /// sizes are refreshed, and `untracked` archives the catalog still
+    /// doesn't know are added. Entries without a recorded path, or whose
+    /// folder is missing, are left alone.
+    pub fn repair(&mut self, scan_dir: Option<&Path>, untracked: Vec<ArchiveMetadata>) -> Result<RepairReport> {
+        let mut report = RepairReport::default();
+
+        let mut names: Vec<String> = self.archives.keys().cloned().collect();
+        names.sort();
+        for name in names {
+            let archive = self.archives.get_mut(&name).expect("name taken from the catalog");
+            let Some(path) = archive.archive_path().map(Path::to_path_buf) else {
+                continue;
+            };
+
+            if !path.is_file() {
+                match scan_dir.map(|dir| dir.join(&name)).filter(|moved| moved.is_file()) {
+                    Some(moved) => {
+                        archive.path = moved.to_string_lossy().to_string();
+                        report.relocated.push((name.clone(), archive.path.clone()));
+                        self.changed.insert(name.clone());
+                    }
+                    None if path.parent().is_some_and(|dir| !dir.is_dir()) => {
+                        report.unreachable.push(name);
+                        continue;
+                    }
+                    None => {
+                        self.remove_archive(&name);
+                        report.removed.push(name);
+                        continue;
+                    }
+                }
+            }
+
+            let size = fs::metadata(&archive.path)?.len();
+            if size != archive.size_bytes {
+                report.resized.push((name.clone(), archive.size_bytes, size));
+                archive.size_bytes = size;
+                self.changed.insert(name);
+            }
+        }
+
+        for metadata in untracked {
+            if !self.archives.contains_key(&metadata.name) {
+                report.imported.push(metadata.name.clone());
+                self.add_archive(metadata);
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Reads the archives in `dir` the catalog doesn't know, for `repair`.
+    /// Archives that can't be read are skipped with a warning.
+    pub fn read_untracked(&self, dir: &Path) -> Result<Vec<ArchiveMetadata>> {
+        let mut untracked = Vec::new();
+        for entry in fs::read_dir(dir).with_context(|| format!("Failed to read {}", dir.display()))? {
+            let path = entry?.path();
+            let name = path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+            if path.is_file()
+                && crate::compress::Algorithm::from_archive_path(&path).is_some()
+                && !self.archives.contains_key(&name)
+            {
+                untracked.push(path);
+            }
+        }
+        untracked.sort();
+
+        Ok(untracked
+            .into_iter()
+            .filter_map(|path| match ArchiveMetadata::from_archive_file(&path) {
+                Ok(metadata) => Some(metadata),
+                Err(e) => {
+                    crate::utils::print_warning(&format!("Skipping {}: {:#}", path.display(), e));
+                    None
+                }
+            })
+            .collect())
+    }
+
     fn state_file() -> Result<PathBuf> {
         Ok(Config::state_dir()?.join("archives.json"))
     }
+
+    fn db_file() -> Result<PathBuf> {
+        Ok(Config::state_dir()?.join("archives.db"))
+    }
+}
+
+/// Exclusive lock on `state.lock` in the state folder, released on drop.
+/// The holder writes its process id into the file for error messages.
+pub struct StateLock {
+    file: File,
+}
+
+impl StateLock {
+    /// Waits up to `timeout` for other holders to let go.
+    pub fn acquire(state_dir: &Path, timeout: Duration) -> Result<Self> {
+        fs::create_dir_all(state_dir)?;
+        let path = state_dir.join("state.lock");
+        let file = File::options()
+            .create(true)
+            .write(true)
+            .truncate(false)
+            .open(&path)
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+
+        let start = Instant::now();
+        loop {
+            match file.try_lock_exclusive() {
+                Ok(()) => {
+                    use std::io::Write;
+                    file.set_len(0)?;
+                    write!(&file, "{}", std::process::id())?;
+                    return Ok(Self { file });
+                }
+                Err(e) if e.raw_os_error() == fs2::lock_contended_error().raw_os_error() => {}
+                Err(e) => return Err(e).with_context(|| format!("Failed to lock {}", path.display())),
+            }
+
+            if start.elapsed() >= timeout {
+                let holder = fs::read_to_string(&path)
+                    .ok()
+                    .and_then(|pid| pid.trim().parse::<u32>().ok())
+                    .map_or_else(|| "Another process".to_string(), |pid| format!("Zencore process {}", pid));
+                return Err(anyhow::anyhow!(
+                    "{} has been updating the catalog for over {}s (it holds the lock on {}); wait for it to finish or stop it",
+                    holder,
+                    timeout.as_secs_f32(),
+                    path.display()
+                ));
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        }
+    }
+}
+
+impl Drop for StateLock {
+    fn drop(&mut self) {
+        FileExt::unlock(&self.file).ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_metadata(name: &str) -> ArchiveMetadata {
+        ArchiveMetadata {
+            name: name.to_string(),
+            created_at: "2024-01-01T00:00:00+00:00".to_string(),
+            checksum: String::new(),
+            checksums: HashMap::new(),
+            algorithm: "tar.zst".to_string(),
+            size_bytes: 1024,
+            file_count: 1,
+            encrypted: false,
+            contents: vec!["track.flac".to_string()],
+            contents_summary: BTreeMap::new(),
+            contents_mode: ContentsMode::Full,
+            content_checksums: BTreeMap::new(),
+            source: String::new(),
+            meta_dir: None,
+            path: String::new(),
+            parent: None,
+            zstd_dictionary: None,
+            scan_started_at: None,
+            file_name: None,
+        }
+    }
+
+    #[test]
+    fn test_repair_drops_entries_whose_file_is_gone() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let kept_path = temp_dir.path().join("kept.tar.zst");
+        fs::write(&kept_path, b"0123456789")?;
+
+        let mut state = StateTracker::default();
+        let mut kept = sample_metadata("kept.tar.zst");
+        kept.path = kept_path.to_string_lossy().to_string();
+        let mut gone = sample_metadata("gone.tar.zst");
+        gone.path = temp_dir.path().join("gone.tar.zst").to_string_lossy().to_string();
+        state.add_archive(kept);
+        state.add_archive(gone);
+        state.add_archive(sample_metadata("unknown_location.tar.zst"));
+        let mut unmounted = sample_metadata("unmounted.tar.zst");
+        unmounted.path = temp_dir.path().join("drive/unmounted.tar.zst").to_string_lossy().to_string();
+        state.add_archive(unmounted);
+
+        let report = state.repair(None, Vec::new())?;
+        assert_eq!(report.removed, ["gone.tar.zst"]);
+        assert_eq!(report.unreachable, ["unmounted.tar.zst"]);
+        assert!(state.get_archive("unmounted.tar.zst").is_some());
+        assert_eq!(report.resized, [("kept.tar.zst".to_string(), 1024, 10)]);
+        assert!(state.get_archive("gone.tar.zst").is_none());
+        assert!(state.get_archive("unknown_location.tar.zst").is_some());
+        assert_eq!(state.get_archive("kept.tar.zst").unwrap().size_bytes, 10);
+
+        // Once repaired, the unmounted entry alone is nothing to repair.
+        let report = state.repair(None, Vec::new())?;
+        assert_eq!(report.unreachable, ["unmounted.tar.zst"]);
+        assert!(report.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_repair_imports_untracked_archives() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let source = temp_dir.path().join("Music");
+        fs::create_dir_all(&source)?;
+        fs::write(source.join("track.flac"), b"audio")?;
+        let archives = temp_dir.path().join("archives");
+        fs::create_dir_all(&archives)?;
+        crate::compress::Archiver::new(&source, &archives, "music.tar.zst".to_string(), crate::compress::Algorithm::TarZst)
+            .compress()?;
+        fs::write(archives.join("notes.txt"), b"not an archive")?;
+        fs::write(archives.join("broken.tar.zst"), b"not zstd")?;
+
+        let mut state = StateTracker::default();
+        let untracked = state.read_untracked(&archives)?;
+        let report = state.repair(Some(&archives), untracked)?;
+        assert_eq!(report.imported, ["music.tar.zst"]);
+
+        let imported = state.get_archive("music.tar.zst").unwrap();
+        assert_eq!(imported.algorithm, "tar.zst");
+        assert_eq!(imported.file_count, 1);
+        assert!(imported.get_checksum("SHA-256").is_some());
+        assert!(state.read_untracked(&archives)?.is_empty());
+        assert!(state.repair(Some(&archives), Vec::new())?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_source_roundtrip_and_legacy_entries() -> Result<()> {
+        let mut metadata = sample_metadata("music.tar.zst");
+        metadata.source = "/home/user/Music".to_string();
+
+        let json = serde_json::to_string(&metadata)?;
+        let loaded: ArchiveMetadata = serde_json::from_str(&json)?;
+        assert_eq!(loaded.source, "/home/user/Music");
+        assert_eq!(loaded.source_label(), Some("Music"));
+        assert!(loaded.matches_source("music"));
+        assert!(!loaded.matches_source("Podcasts"));
+
+        let mut legacy: serde_json::Value = serde_json::from_str(&json)?;
+        legacy.as_object_mut().unwrap().remove("source");
+        let loaded: ArchiveMetadata = serde_json::from_value(legacy)?;
+        assert_eq!(loaded.source, "");
+        assert!(!loaded.matches_source("music"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_checksums_are_keyed_by_stable_algorithm_key() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let state_path = temp_dir.path().join("state.json");
+
+        // Older catalogs keyed checksums by the upper-cased display label.
+        let mut tracker = StateTracker::default();
+        let mut metadata = sample_metadata("album.tar.zst");
+        metadata.checksums.insert("CRC32 (NON-CRYPTOGRAPHIC)".to_string(), "cbf43926".to_string());
+        tracker.add_archive(metadata);
+        tracker.save_to(&state_path)?;
+
+        let loaded = StateTracker::load_from(&state_path)?;
+        let archive = loaded.get_archive("album.tar.zst").unwrap();
+        assert!(archive.checksums.contains_key("crc32"));
+        assert_eq!(archive.get_checksum(HashAlgorithm::Crc32.key()).map(String::as_str), Some("cbf43926"));
+        assert!(archive.list_checksums().iter().any(|(label, _)| label == HashAlgorithm::Crc32.name()));
+
+        let mut metadata = sample_metadata("new.tar.zst");
+        metadata.add_checksum("SHA-256", "abc".to_string());
+        assert_eq!(metadata.checksums.get("sha256").map(String::as_str), Some("abc"));
+        assert_eq!(metadata.get_checksum("sha256").map(String::as_str), Some("abc"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_prune_keeps_last_n_per_source() {
+        let mut tracker = StateTracker::default();
+        for (source, day) in [("Music", 1), ("Music", 2), ("Music", 3), ("Podcasts", 1), ("Podcasts", 2)] {
+            let mut metadata = sample_metadata(&format!("{}_{}.tar.zst", source, day));
+            metadata.source = format!("/home/user/{}", source);
+            metadata.created_at = format!("2024-01-0{}T00:00:00+00:00", day);
+            tracker.add_archive(metadata);
+        }
+        tracker.add_archive(sample_metadata("legacy.tar.zst"));
+
+        let mut pruned: Vec<&str> = tracker.prune_per_source(1).iter().map(|a| a.name.as_str()).collect();
+        pruned.sort();
+        assert_eq!(pruned, ["Music_1.tar.zst", "Music_2.tar.zst", "Podcasts_1.tar.zst"]);
+
+        assert!(tracker.prune_per_source(3).is_empty());
+    }
+
+    #[test]
+    fn test_latest_for_source() {
+        let mut tracker = StateTracker::default();
+        for (name, source, day) in [("a", "/music", 1), ("b", "/music", 2), ("c", "/podcasts", 3)] {
+            let mut metadata = sample_metadata(name);
+            metadata.source = source.to_string();
+            metadata.created_at = format!("2024-01-0{}T00:00:00+00:00", day);
+            tracker.add_archive(metadata);
+        }
+
+        let latest = tracker.latest_for_source("/music").unwrap();
+        assert_eq!(latest.name, "b");
+        assert!(latest.created_time().unwrap() < tracker.get_archive("c").unwrap().created_time().unwrap());
+        assert!(tracker.latest_for_source("/videos").is_none());
+
+        // `--since-last` counts from when the scan began, not when it ended.
+        assert_eq!(latest.changes_since(), latest.created_time());
+        let mut scanned = latest.clone();
+        scanned.scan_started_at = Some("2024-01-01T23:00:00+00:00".to_string());
+        assert!(scanned.changes_since().unwrap() < scanned.created_time().unwrap());
+    }
+
+    #[test]
+    fn test_merge_strategies_with_colliding_names() {
+        let entry = |name: &str, day: u32, checksum: &str| {
+            let mut metadata = sample_metadata(name);
+            metadata.created_at = format!("2024-01-0{}T00:00:00+00:00", day);
+            metadata.checksum = checksum.to_string();
+            metadata
+        };
+        let local = || {
+            let mut tracker = StateTracker::default();
+            tracker.add_archive(entry("music.tar.zst", 2, "aa"));
+            tracker.add_archive(entry("podcasts.tar.zst", 2, "bb"));
+            tracker.add_archive(entry("same.tar.zst", 1, "cc"));
+            tracker
+        };
+        let imported = || {
+            let mut tracker = StateTracker::default();
+            tracker.add_archive(entry("music.tar.zst", 3, "dd"));
+            tracker.add_archive(entry("podcasts.tar.zst", 1, "ee"));
+            tracker.add_archive(entry("same.tar.zst", 1, "cc"));
+            tracker.add_archive(entry("laptop.tar.zst", 1, "ff"));
+            tracker
+        };
+        let checksum = |tracker: &StateTracker, name: &str| tracker.get_archive(name).unwrap().checksum.clone();
+
+        let mut tracker = local();
+        let report = tracker.merge(imported(), MergeStrategy::KeepNewer);
+        assert_eq!((report.added, report.replaced, report.kept, report.unchanged), (1, 1, 1, 1));
+        assert_eq!(checksum(&tracker, "music.tar.zst"), "dd");
+        assert_eq!(checksum(&tracker, "podcasts.tar.zst"), "bb");
+
+        let mut tracker = local();
+        let report = tracker.merge(imported(), MergeStrategy::Overwrite);
+        assert_eq!((report.added, report.replaced, report.unchanged), (1, 2, 1));
+        assert_eq!(checksum(&tracker, "podcasts.tar.zst"), "ee");
+
+        let mut tracker = local();
+        tracker.add_archive(entry("music.tar.zst (2)", 1, "11"));
+        let report = tracker.merge(imported(), MergeStrategy::KeepBoth);
+        assert_eq!(report.added, 1);
+        assert_eq!(
+            report.renamed,
+            [
+                ("music.tar.zst".to_string(), "music.tar.zst (3)".to_string()),
+                ("podcasts.tar.zst".to_string(), "podcasts.tar.zst (2)".to_string()),
+            ]
+        );
+        assert_eq!(checksum(&tracker, "music.tar.zst"), "aa");
+        assert_eq!(checksum(&tracker, "music.tar.zst (3)"), "dd");
+        assert_eq!(tracker.archive_count(), 7);
+
+        // The renamed entry still points at its file.
+        let renamed = tracker.get_archive("music.tar.zst (3)").unwrap();
+        assert_eq!(renamed.file_name(), "music.tar.zst");
+        let mut moved = renamed.clone();
+        moved.path = "/mnt/laptop/music.tar.zst".to_string();
+        tracker.add_archive(moved);
+        let find = |path: &str| tracker.find_archive_file(Path::new(path)).map(|a| a.checksum.clone());
+        assert_eq!(find("/mnt/laptop/music.tar.zst").as_deref(), Some("dd"));
+        assert_eq!(find("/elsewhere/music.tar.zst").as_deref(), Some("aa"));
+    }
+
+    #[test]
+    fn test_verify_against_detects_corruption() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("music.tar.zst");
+        fs::write(&path, b"archive bytes")?;
+
+        let mut metadata = sample_metadata("music.tar.zst");
+        assert!(metadata.verify_against(&path).is_err());
+        metadata.add_checksum("SHA-256", crate::crypto::Checker::generate_checksum(&path.to_string_lossy())?);
+        assert_eq!(metadata.verify_against(&path)?, [("SHA-256".to_string(), true)]);
+
+        fs::write(&path, b"archive bytez")?;
+        assert_eq!(metadata.verify_against(&path)?, [("SHA-256".to_string(), false)]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_find_by_checksum() {
+        let hash = "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08";
+        let mut tracker = StateTracker::default();
+        tracker.add_archive(sample_metadata("other.tar.zst"));
+
+        let mut metadata = sample_metadata("music.tar.zst");
+        metadata.add_checksum("SHA-256", hash.to_string());
+        tracker.add_archive(metadata);
+
+        let found = tracker.find_by_checksum(&hash.to_uppercase()).map(|a| a.name.as_str());
+        assert_eq!(found, Some("music.tar.zst"));
+        assert!(tracker.find_by_checksum(&"0".repeat(64)).is_none());
+    }
+
+    #[test]
+    fn test_corrupted_state_is_quarantined() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let state_path = temp_dir.path().join("archives.json");
+        fs::write(&state_path, b"{\"archives\": {\"trunc")?;
+
+        let tracker = StateTracker::load_from(&state_path)?;
+        assert_eq!(tracker.archive_count(), 0);
+        assert!(!state_path.exists());
+
+        let quarantined: Vec<String> = fs::read_dir(temp_dir.path())?
+            .filter_map(|e| e.ok())
+            .map(|e| e.file_name().to_string_lossy().to_string())
+            .filter(|n| n.starts_with("archives.json.corrupt."))
+            .collect();
+        assert_eq!(quarantined.len(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_save_and_load_roundtrip() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let state_path = temp_dir.path().join("archives.json");
+
+        let mut tracker = StateTracker::default();
+        tracker.add_archive(sample_metadata("music.tar.zst"));
+        tracker.save_to(&state_path)?;
+
+        let loaded = StateTracker::load_from(&state_path)?;
+        assert!(loaded.get_archive("music.tar.zst").is_some());
+        assert!(!temp_dir.path().join("archives.json.tmp").exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_contents_modes() -> Result<()> {
+        let files = vec![
+            "Album A/01.flac".to_string(),
+            "Album A/02.flac".to_string(),
+            "Album B/01.flac".to_string(),
+            "playlist.m3u".to_string(),
+        ];
+
+        let mut full = sample_metadata("full.tar.zst");
+        full.set_contents(files.clone(), ContentsMode::Full);
+        assert_eq!(full.contents.len(), 4);
+        assert!(full.contents_summary.is_empty());
+
+        let mut summary = sample_metadata("summary.tar.zst");
+        summary.set_contents(files.clone(), ContentsMode::Summary);
+        assert!(summary.contents.is_empty());
+        assert_eq!(summary.contents_summary.get("Album A"), Some(&2));
+        assert_eq!(summary.contents_summary.get("Album B"), Some(&1));
+        assert_eq!(summary.contents_summary.get("."), Some(&1));
+
+        let mut none = sample_metadata("none.tar.zst");
+        none.set_contents(files, ContentsMode::None);
+        assert!(none.contents.is_empty());
+        assert!(none.contents_summary.is_empty());
+
+        let full_json = serde_json::to_string(&full)?;
+        let summary_json = serde_json::to_string(&summary)?;
+        let none_json = serde_json::to_string(&none)?;
+        assert!(none_json.len() < summary_json.len());
+        assert!(none_json.len() < full_json.len());
+        assert!(none_json.contains("\"contents_mode\":\"none\""));
+        assert!(!none_json.contains("contents_summary"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_compressed_state_roundtrip_and_migration() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let state_path = temp_dir.path().join("archives.json");
+        let compressed_path = temp_dir.path().join("archives.json.zst");
+
+        let mut tracker = StateTracker::default();
+        tracker.add_archive(sample_metadata("plain.tar.zst"));
+        tracker.save_to(&state_path)?;
+        assert!(state_path.exists());
+
+        let mut tracker = StateTracker::load_from(&state_path)?.with_compression(true);
+        tracker.add_archive(sample_metadata("packed.tar.zst"));
+        tracker.save_to(&state_path)?;
+
+        assert!(compressed_path.exists());
+        assert!(!state_path.exists());
+
+        let loaded = StateTracker::load_from(&state_path)?;
+        assert_eq!(loaded.archive_count(), 2);
+        assert!(loaded.get_archive("packed.tar.zst").is_some());
+
+        fs::write(&compressed_path, b"not zstd")?;
+        let err = StateTracker::load_from(&state_path).unwrap_err();
+        assert!(err.to_string().contains("Failed to decompress"), "{}", err);
+        assert!(compressed_path.exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_locked_updates_from_two_threads_keep_every_archive() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let state_dir = temp_dir.path().to_path_buf();
+
+        let writers: Vec<_> = ["a", "b"]
+            .into_iter()
+            .map(|writer| {
+                let state_dir = state_dir.clone();
+                std::thread::spawn(move || -> Result<()> {
+                    let state_path = state_dir.join("archives.json");
+                    for i in 0..10 {
+                        let _lock = StateLock::acquire(&state_dir, Duration::from_secs(10))?;
+                        let mut state = StateTracker::load_from(&state_path)?;
+                        state.add_archive(sample_metadata(&format!("{}_{}.tar.zst", writer, i)));
+                        state.save_to(&state_path)?;
+                    }
+                    Ok(())
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap()?;
+        }
+
+        assert_eq!(StateTracker::load_from(&state_dir.join("archives.json"))?.archive_count(), 20);
+
+        let _held = StateLock::acquire(&state_dir, Duration::from_secs(1))?;
+        let err = StateLock::acquire(&state_dir, Duration::from_millis(100)).err().unwrap();
+        assert!(err.to_string().contains("updating the catalog"));
+        assert!(err.to_string().starts_with(&format!("Zencore process {} ", std::process::id())), "{}", err);
+
+        Ok(())
+    }
 }
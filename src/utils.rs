@@ -1,19 +1,95 @@
 use colored::*;
+use indicatif::ProgressBar;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, MutexGuard, PoisonError};
 use std::time::Duration;
 
+// Process-wide rather than per thread: `--timeout` runs the backup on a
+// worker thread.
+
+/// Normal output held back by `--quiet-success` until something goes wrong.
+static HELD_OUTPUT: Mutex<Option<Vec<String>>> = Mutex::new(None);
+static PROBLEM_REPORTED: AtomicBool = AtomicBool::new(false);
+/// `--json-progress`: progress goes out as NDJSON instead of bars.
+static JSON_PROGRESS: AtomicBool = AtomicBool::new(false);
+
+fn held_output() -> MutexGuard<'static, Option<Vec<String>>> {
+    HELD_OUTPUT.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// `--json-progress`: hide progress bars; stages that report progress
+/// emit NDJSON events instead.
+pub fn enable_json_progress() {
+    JSON_PROGRESS.store(true, Ordering::Relaxed);
+}
+
+pub fn json_progress_enabled() -> bool {
+    JSON_PROGRESS.load(Ordering::Relaxed)
+}
+
+/// `--quiet-success`: hold back normal output. The first warning or error
+/// prints what was held, and everything after prints as usual.
+pub fn hold_output_until_problem() {
+    *held_output() = Some(Vec::new());
+    PROBLEM_REPORTED.store(false, Ordering::Relaxed);
+}
+
+/// True once a warning or error was printed since `hold_output_until_problem`.
+pub fn problem_reported() -> bool {
+    PROBLEM_REPORTED.load(Ordering::Relaxed)
+}
+
+pub fn is_holding_output() -> bool {
+    held_output().is_some()
+}
+
+/// Prints whatever `--quiet-success` held back and stops holding.
+pub fn release_held_output() {
+    let mut held = held_output();
+    if let Some(lines) = held.take() {
+        for line in lines {
+            println!("{}", line);
+        }
+        PROBLEM_REPORTED.store(true, Ordering::Relaxed);
+    }
+}
+
+/// A progress bar of `len` steps, hidden while `--quiet-success` holds
+/// output and with `--json-progress`.
+pub fn progress_bar(len: u64) -> ProgressBar {
+    if is_holding_output() || json_progress_enabled() {
+        ProgressBar::hidden()
+    } else {
+        ProgressBar::new(len)
+    }
+}
+
+/// Prints one line of normal output, unless `--quiet-success` holds it back.
+pub fn print_line(line: &str) {
+    match held_output().as_mut() {
+        Some(lines) => lines.push(line.to_string()),
+        None => println!("{}", line),
+    }
+}
+
 pub fn print_info(message: &str) {
-    println!("{} {}", "[•]".cyan(), message);
+    print_line(&format!("{} {}", "[•]".cyan(), message));
 }
 
 pub fn print_success(message: &str) {
-    println!("{} {}", "[✓]".green(), message);
+    print_line(&format!("{} {}", "[✓]".green(), message));
 }
 
 pub fn print_warning(message: &str) {
+    release_held_output();
     println!("{} {}", "[!]".yellow(), message);
 }
 
 pub fn print_error(message: &str) {
+    release_held_output();
     eprintln!("{} {}", "[✗]".red(), message);
 }
 
@@ -37,8 +113,8 @@ pub fn show_banner() {
               v1.3.1 - Rust Edition - Codename Oswin Oswald                                                                                                                                                                                                                                        
     "#;
 
-    println!("{}", banner.bright_blue());
-    println!("Ready to serve!");
+    print_line(&banner.bright_blue().to_string());
+    print_line("Ready to serve!");
 }
 
 pub fn format_bytes(bytes: u64) -> String {
@@ -50,12 +126,56 @@ pub fn format_bytes(bytes: u64) -> String {
 
     let base: f64 = 1024.0;
     let exp = (bytes as f64).log(base).floor() as usize;
-    let exp = exp.min(UNITS.len() - 1);
+    let mut exp = exp.min(UNITS.len() - 1);
 
-    let size = bytes as f64 / base.powi(exp as i32);
+    let mut size = bytes as f64 / base.powi(exp as i32);
+    // 1048575 B is 1023.999 KB, which would print as "1024.00 KB".
+    if (size * 100.0).round() >= base * 100.0 && exp < UNITS.len() - 1 {
+        exp += 1;
+        size /= base;
+    }
     format!("{:.2} {}", size, UNITS[exp])
 }
 
+/// Parses a human size such as `500M`, `2G` or `1.5GB` (1024-based) into bytes.
+pub fn parse_size(text: &str) -> Result<u64, String> {
+    let text = text.trim();
+    let split = text
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+
+    let value: f64 = number
+        .parse()
+        .map_err(|_| format!("invalid size '{}'", text))?;
+
+    let exp = match unit.trim().to_uppercase().trim_end_matches('B').trim_end_matches('I') {
+        "" => 0,
+        "K" => 1,
+        "M" => 2,
+        "G" => 3,
+        "T" => 4,
+        _ => return Err(format!("invalid size unit in '{}' (use K, M, G or T)", text)),
+    };
+
+    Ok((value * 1024f64.powi(exp)) as u64)
+}
+
+/// Parses an RFC 3339 timestamp or a `YYYY-MM-DD` date (local midnight)
+/// into UTC seconds.
+pub fn parse_since(text: &str) -> Result<i64, String> {
+    let text = text.trim();
+    if let Ok(time) = chrono::DateTime::parse_from_rfc3339(text) {
+        return Ok(time.timestamp());
+    }
+
+    chrono::NaiveDate::parse_from_str(text, "%Y-%m-%d")
+        .ok()
+        .and_then(|date| date.and_hms_opt(0, 0, 0)?.and_local_timezone(chrono::Local).earliest())
+        .map(|time| time.timestamp())
+        .ok_or_else(|| format!("invalid date '{}' (use YYYY-MM-DD or an RFC 3339 timestamp)", text))
+}
+
 pub fn format_duration(duration: Duration) -> String {
     let secs = duration.as_secs();
 
@@ -68,9 +188,11 @@ pub fn format_duration(duration: Duration) -> String {
     }
 }
 
+/// Sub-second runs still get a speed; only a zero duration or empty archive
+/// has none, shown as `-`.
 pub fn format_speed(bytes: u64, duration: Duration) -> String {
-    if duration.as_secs() == 0 {
-        return "N/A".to_string();
+    if bytes == 0 || duration.is_zero() {
+        return "-".to_string();
     }
 
     let bytes_per_sec = bytes as f64 / duration.as_secs_f64();
@@ -102,19 +224,19 @@ pub fn print_table_header(columns: &[&str], widths: &[usize]) {
             header.push_str(" ");
         }
     }
-    println!("{}", header.bold());
-    println!("{}", "─".repeat(widths.iter().sum::<usize>() + widths.len() - 1));
+    print_line(&header.bold().to_string());
+    print_line(&"─".repeat(widths.iter().sum::<usize>() + widths.len() - 1));
 }
 
 pub fn print_separator(length: usize) {
-    println!("{}", "─".repeat(length).bright_black());
+    print_line(&"─".repeat(length).bright_black().to_string());
 }
 
 pub fn print_header(title: &str) {
     let width = title.len() + 4;
-    println!("\n{}", "═".repeat(width).bright_blue());
-    println!("  {}", title.bold().bright_white());
-    println!("{}", "═".repeat(width).bright_blue());
+    print_line(&format!("\n{}", "═".repeat(width).bright_blue()));
+    print_line(&format!("  {}", title.bold().bright_white()));
+    print_line(&"═".repeat(width).bright_blue().to_string());
 }
 
 pub fn confirm_action(question: &str, default: bool) -> bool {
@@ -128,6 +250,10 @@ pub fn confirm_action(question: &str, default: bool) -> bool {
 }
 
 pub fn print_progress(current: u64, total: u64, prefix: &str) {
+    if is_holding_output() {
+        return;
+    }
+
     let percentage = if total > 0 {
         (current as f64 / total as f64 * 100.0) as u8
     } else {
@@ -170,7 +296,7 @@ pub fn format_number(num: usize) -> String {
 
 pub fn format_compression_ratio(original: u64, compressed: u64) -> String {
     if original == 0 {
-        return "N/A".to_string();
+        return "-".to_string();
     }
 
     let ratio = (compressed as f64 / original as f64) * 100.0;
@@ -180,9 +306,31 @@ pub fn format_compression_ratio(original: u64, compressed: u64) -> String {
 pub fn print_summary(items: &[(&str, String)]) {
     print_header("Summary");
     for (key, value) in items {
-        println!("  {}: {}", key.bright_yellow(), value.white());
+        print_line(&format!("  {}: {}", key.bright_yellow(), value.white()));
     }
-    println!();
+    print_line("");
+}
+
+/// Replaces `path` with `contents` atomically: the data is written and fsynced
+/// to a sibling temp file, then renamed over the target. A crash at any point
+/// leaves either the old or the new file, never a truncated one.
+pub fn write_atomic(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let tmp_path = stage_atomic_write(path, contents)?;
+    fs::rename(&tmp_path, path)
+}
+
+fn stage_atomic_write(path: &Path, contents: &[u8]) -> io::Result<PathBuf> {
+    let file_name = path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "invalid file path"))?;
+    let tmp_path = path.with_file_name(format!("{}.tmp", file_name));
+
+    let mut file = File::create(&tmp_path)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+
+    Ok(tmp_path)
 }
 
 #[cfg(test)]
@@ -197,6 +345,37 @@ mod tests {
         assert_eq!(format_bytes(1_073_741_824), "1.00 GB");
     }
 
+    #[test]
+    fn test_format_bytes_just_under_a_unit() {
+        assert_eq!(format_bytes(1023), "1023.00 B");
+        assert_eq!(format_bytes(1_047_552), "1023.00 KB");
+        assert_eq!(format_bytes(1_048_570), "1023.99 KB");
+        assert_eq!(format_bytes(1_048_575), "1.00 MB");
+        assert_eq!(format_bytes(1_073_741_823), "1.00 GB");
+        assert_eq!(format_bytes(1_099_511_627_775), "1.00 TB");
+        assert_eq!(format_bytes(1_125_899_906_842_623), "1.00 PB");
+        assert_eq!(format_bytes(u64::MAX), "16384.00 PB");
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("512"), Ok(512));
+        assert_eq!(parse_size("500M"), Ok(500 * 1_048_576));
+        assert_eq!(parse_size("2GB"), Ok(2 * 1_073_741_824));
+        assert_eq!(parse_size("1.5g"), Ok(1_610_612_736));
+        assert!(parse_size("10X").is_err());
+        assert!(parse_size("lots").is_err());
+    }
+
+    #[test]
+    fn test_parse_since() {
+        assert_eq!(parse_since("2024-04-01T08:00:00+00:00"), Ok(1711958400));
+        assert_eq!(parse_since("2024-04-01T10:00:00+05:00"), Ok(1711947600));
+        let midnight = parse_since("2024-04-01").unwrap();
+        assert!((midnight - 1711929600).abs() <= 14 * 3600);
+        assert!(parse_since("yesterday").is_err());
+    }
+
     #[test]
     fn test_format_duration() {
         assert_eq!(format_duration(Duration::from_secs(30)), "30s");
@@ -229,6 +408,32 @@ mod tests {
     fn test_compression_ratio() {
         assert_eq!(format_compression_ratio(1000, 500), "50.0%");
         assert_eq!(format_compression_ratio(1000, 750), "75.0%");
-        assert_eq!(format_compression_ratio(0, 100), "N/A");
+        assert_eq!(format_compression_ratio(0, 100), "-");
+    }
+
+    #[test]
+    fn test_format_speed() {
+        assert_eq!(format_speed(2048, Duration::from_secs(2)), "1.00 KB/s");
+        assert_eq!(format_speed(512, Duration::from_millis(500)), "1.00 KB/s");
+        assert_eq!(format_speed(0, Duration::from_secs(5)), "-");
+        assert_eq!(format_speed(100, Duration::ZERO), "-");
+    }
+
+    #[test]
+    fn test_write_atomic_interrupted_keeps_original() -> io::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let path = temp_dir.path().join("archives.json");
+        fs::write(&path, "original")?;
+
+        // Crash after staging but before the rename.
+        let tmp_path = stage_atomic_write(&path, b"updated")?;
+        assert_eq!(fs::read_to_string(&path)?, "original");
+        assert_eq!(fs::read_to_string(&tmp_path)?, "updated");
+
+        write_atomic(&path, b"updated")?;
+        assert_eq!(fs::read_to_string(&path)?, "updated");
+        assert!(!tmp_path.exists());
+
+        Ok(())
     }
 }
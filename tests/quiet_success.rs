@@ -0,0 +1,91 @@
+//! `--quiet-success` and `--json-progress` are process-wide switches, so
+//! they get their own test binary instead of racing the unit tests. The
+//! backups run the `zencore` binary itself, with a temporary home.
+
+use std::path::Path;
+use std::time::Duration;
+use zencore::cancel::run_with_timeout;
+use zencore::{Algorithm, Archiver, utils};
+
+/// `zencore --quiet-success backup` from `source` into `dest`, with config
+/// and catalog kept under `home`, plus `extra` arguments.
+fn quiet_backup(home: &Path, source: &Path, dest: &Path, extra: &[&str]) -> assert_cmd::assert::Assert {
+    assert_cmd::Command::cargo_bin("zencore")
+        .unwrap()
+        .env("HOME", home)
+        .env("XDG_CONFIG_HOME", home.join("config"))
+        .env("XDG_DATA_HOME", home.join("data"))
+        .args(["--quiet-success", "backup", "--algorithm", "tar.zst"])
+        .arg("--source")
+        .arg(source)
+        .arg("--destination")
+        .arg(dest)
+        .args(extra)
+        .write_stdin("")
+        .timeout(Duration::from_secs(120))
+        .assert()
+}
+
+#[test]
+fn test_quiet_success_backup_prints_nothing() -> anyhow::Result<()> {
+    let home = tempfile::tempdir()?;
+    let source_dir = tempfile::tempdir()?;
+    let dest_dir = tempfile::tempdir()?;
+    std::fs::write(source_dir.path().join("track.flac"), b"audio")?;
+
+    quiet_backup(home.path(), source_dir.path(), dest_dir.path(), &["--name", "music"])
+        .success()
+        .stdout("");
+    assert!(dest_dir.path().join("music.tar.zst").is_file());
+    Ok(())
+}
+
+#[test]
+fn test_quiet_success_warning_releases_output_and_fails() -> anyhow::Result<()> {
+    let home = tempfile::tempdir()?;
+    let source_dir = tempfile::tempdir()?;
+    let dest_dir = tempfile::tempdir()?;
+    std::fs::write(source_dir.path().join("track.flac"), b"audio")?;
+
+    // Without a terminal, --interactive-exclude is ignored with a warning.
+    let extra = ["--name", "music", "--interactive-exclude"];
+    let output = quiet_backup(home.path(), source_dir.path(), dest_dir.path(), &extra)
+        .failure()
+        .get_output()
+        .clone();
+    let stdout = String::from_utf8(output.stdout)?;
+    let held = stdout.find("Ready to serve!").expect("held output is printed");
+    let warning = stdout.find("--interactive-exclude needs a terminal").expect("warning is printed");
+    assert!(held < warning, "{}", stdout);
+    assert!(String::from_utf8(output.stderr)?.contains("Completed with warnings"));
+    Ok(())
+}
+
+#[test]
+fn test_quiet_success_holds_output_from_timeout_worker() -> anyhow::Result<()> {
+    let source_dir = tempfile::tempdir()?;
+    let dest_dir = tempfile::tempdir()?;
+    std::fs::write(source_dir.path().join("track.flac"), b"audio")?;
+
+    utils::hold_output_until_problem();
+    utils::enable_json_progress();
+
+    // `--timeout` runs the backup on a worker thread.
+    let (source, dest) = (source_dir.path().to_path_buf(), dest_dir.path().to_path_buf());
+    let on_worker = run_with_timeout(Duration::from_secs(60), move |_| {
+        utils::print_info("compressing");
+        Archiver::new(&source, &dest, "music.tar.zst".to_string(), Algorithm::TarZst).compress()?;
+        Ok((utils::is_holding_output(), utils::json_progress_enabled()))
+    })?;
+    assert_eq!(on_worker, (true, true));
+
+    // Still holding means nothing reached stdout.
+    assert!(utils::is_holding_output());
+    assert!(!utils::problem_reported());
+
+    std::thread::spawn(|| utils::print_warning("disk almost full")).join().unwrap();
+    assert!(!utils::is_holding_output());
+    assert!(utils::problem_reported());
+
+    Ok(())
+}